@@ -31,7 +31,7 @@ use codec::{Encode, Decode};
 use kvdb::{KeyValueDB, DBTransaction};
 use kvdb_rocksdb::{Database, DatabaseConfig};
 use polkadot_primitives::Hash;
-use polkadot_primitives::parachain::{Id as ParaId, BlockData, Extrinsic};
+use polkadot_primitives::parachain::{Id as ParaId, BlockData, CandidateHash, Extrinsic};
 
 use std::collections::HashSet;
 use std::path::PathBuf;
@@ -41,7 +41,8 @@ use std::io;
 mod columns {
 	pub const DATA: Option<u32> = Some(0);
 	pub const META: Option<u32> = Some(1);
-	pub const NUM_COLUMNS: u32 = 2;
+	pub const SEEN: Option<u32> = Some(2);
+	pub const NUM_COLUMNS: u32 = 3;
 }
 
 /// Configuration for the availability store.
@@ -59,21 +60,40 @@ pub struct Data {
 	/// The parachain index for this candidate.
 	pub parachain_id: ParaId,
 	/// Unique candidate receipt hash.
-	pub candidate_hash: Hash,
+	pub candidate_hash: CandidateHash,
 	/// Block data.
 	pub block_data: BlockData,
 	/// Extrinsic data.
 	pub extrinsic: Option<Extrinsic>,
 }
 
-fn block_data_key(relay_parent: &Hash, candidate_hash: &Hash) -> Vec<u8> {
+/// A compact digest of the gossip state on a relay parent, persisted so that
+/// it survives short restarts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SeenDigest {
+	/// Hashes of gossip messages which have been seen and checked.
+	pub statements: Vec<Hash>,
+	/// Hashes of candidates which have been validated locally.
+	pub candidates: Vec<CandidateHash>,
+}
+
+fn block_data_key(relay_parent: &Hash, candidate_hash: &CandidateHash) -> Vec<u8> {
 	(relay_parent, candidate_hash, 0i8).encode()
 }
 
-fn extrinsic_key(relay_parent: &Hash, candidate_hash: &Hash) -> Vec<u8> {
+fn extrinsic_key(relay_parent: &Hash, candidate_hash: &CandidateHash) -> Vec<u8> {
 	(relay_parent, candidate_hash, 1i8).encode()
 }
 
+// each entry of a seen digest is stored under its own key, prefixed by the relay parent.
+fn seen_statement_key(relay_parent: &Hash, message_hash: &Hash) -> Vec<u8> {
+	(relay_parent, 0i8, message_hash).encode()
+}
+
+fn seen_candidate_key(relay_parent: &Hash, candidate_hash: &CandidateHash) -> Vec<u8> {
+	(relay_parent, 1i8, candidate_hash).encode()
+}
+
 /// Handle to the availability store.
 #[derive(Clone)]
 pub struct Store {
@@ -140,7 +160,7 @@ impl Store {
 	}
 
 	/// Note that a set of candidates have been included in a finalized block with given hash and parent hash.
-	pub fn candidates_finalized(&self, parent: Hash, finalized_candidates: HashSet<Hash>) -> io::Result<()> {
+	pub fn candidates_finalized(&self, parent: Hash, finalized_candidates: HashSet<CandidateHash>) -> io::Result<()> {
 		let mut tx = DBTransaction::new();
 
 		let v = match self.inner.get(columns::META, &parent[..]) {
@@ -152,6 +172,9 @@ impl Store {
 			}
 		};
 		tx.delete(columns::META, &parent[..]);
+		for (key, _) in self.inner.iter_from_prefix(columns::SEEN, &parent[..]) {
+			tx.delete(columns::SEEN, &key);
+		}
 
 		for candidate_hash in v {
 			if !finalized_candidates.contains(&candidate_hash) {
@@ -163,8 +186,13 @@ impl Store {
 		self.inner.write(tx)
 	}
 
+	/// Flush all buffered writes to disk.
+	pub fn flush(&self) -> io::Result<()> {
+		self.inner.flush()
+	}
+
 	/// Query block data.
-	pub fn block_data(&self, relay_parent: Hash, candidate_hash: Hash) -> Option<BlockData> {
+	pub fn block_data(&self, relay_parent: Hash, candidate_hash: CandidateHash) -> Option<BlockData> {
 		let encoded_key = block_data_key(&relay_parent, &candidate_hash);
 		match self.inner.get(columns::DATA, &encoded_key[..]) {
 			Ok(Some(raw)) => Some(
@@ -178,8 +206,49 @@ impl Store {
 		}
 	}
 
+	/// Add to the persisted digest of gossip state on a relay parent. Entries persisted
+	/// earlier are kept, so only the new ones need to be given.
+	///
+	/// This is pruned along with other data on the relay parent when candidates are finalized.
+	pub fn note_seen(&self, relay_parent: Hash, digest: &SeenDigest) -> io::Result<()> {
+		if digest.statements.is_empty() && digest.candidates.is_empty() {
+			return Ok(());
+		}
+
+		let mut tx = DBTransaction::new();
+		for message_hash in &digest.statements {
+			tx.put_vec(columns::SEEN, &seen_statement_key(&relay_parent, message_hash), Vec::new());
+		}
+		for candidate_hash in &digest.candidates {
+			tx.put_vec(columns::SEEN, &seen_candidate_key(&relay_parent, candidate_hash), Vec::new());
+		}
+
+		self.inner.write(tx)
+	}
+
+	/// Query the persisted digest of gossip state on a relay parent.
+	pub fn seen(&self, relay_parent: Hash) -> Option<SeenDigest> {
+		let mut digest = SeenDigest::default();
+		let mut found = false;
+
+		for (key, _) in self.inner.iter_from_prefix(columns::SEEN, &relay_parent[..]) {
+			found = true;
+			let mut input = &key[..];
+			let decoded = match <(Hash, i8)>::decode(&mut input) {
+				Some((_, 0)) => Hash::decode(&mut input).map(|hash| digest.statements.push(hash)),
+				Some((_, 1)) => CandidateHash::decode(&mut input).map(|hash| digest.candidates.push(hash)),
+				_ => None,
+			};
+			if decoded.is_none() {
+				warn!(target: "availability", "Malformed seen digest entry in availability store");
+			}
+		}
+
+		if found { Some(digest) } else { None }
+	}
+
 	/// Query extrinsic data.
-	pub fn extrinsic(&self, relay_parent: Hash, candidate_hash: Hash) -> Option<Extrinsic> {
+	pub fn extrinsic(&self, relay_parent: Hash, candidate_hash: CandidateHash) -> Option<Extrinsic> {
 		let encoded_key = extrinsic_key(&relay_parent, &candidate_hash);
 		match self.inner.get(columns::DATA, &encoded_key[..]) {
 			Ok(Some(raw)) => Some(
@@ -198,6 +267,10 @@ impl Store {
 mod tests {
 	use super::*;
 
+	fn candidate_hash(byte: u8) -> CandidateHash {
+		CandidateHash::decode(&mut &[byte; 32][..]).unwrap()
+	}
+
 	#[test]
 	fn finalization_removes_unneeded() {
 		let relay_parent = [1; 32].into();
@@ -205,8 +278,8 @@ mod tests {
 		let para_id_1 = 5.into();
 		let para_id_2 = 6.into();
 
-		let candidate_1 = [2; 32].into();
-		let candidate_2 = [3; 32].into();
+		let candidate_1 = candidate_hash(2);
+		let candidate_2 = candidate_hash(3);
 
 		let block_data_1 = BlockData(vec![1, 2, 3]);
 		let block_data_2 = BlockData(vec![4, 5, 6]);
@@ -242,4 +315,38 @@ mod tests {
 		assert!(store.extrinsic(relay_parent, candidate_1).is_some());
 		assert!(store.extrinsic(relay_parent, candidate_2).is_none());
 	}
+
+	#[test]
+	fn seen_digest_is_pruned_on_finalization() {
+		let relay_parent = [1; 32].into();
+		let other_parent = [2; 32].into();
+
+		let digest = SeenDigest {
+			statements: vec![[3; 32].into(), [4; 32].into()],
+			candidates: vec![candidate_hash(5)],
+		};
+		let other_digest = SeenDigest {
+			statements: vec![[6; 32].into()],
+			candidates: Vec::new(),
+		};
+
+		let store = Store::new_in_memory();
+		assert!(store.seen(relay_parent).is_none());
+
+		store.note_seen(relay_parent, &digest).unwrap();
+		store.note_seen(other_parent, &other_digest).unwrap();
+		assert_eq!(store.seen(relay_parent).unwrap(), digest);
+
+		// later entries are added to the digest.
+		store.note_seen(relay_parent, &SeenDigest {
+			statements: vec![[7; 32].into()],
+			candidates: Vec::new(),
+		}).unwrap();
+		assert_eq!(store.seen(relay_parent).unwrap().statements.len(), 3);
+		assert_eq!(store.seen(relay_parent).unwrap().candidates, digest.candidates);
+
+		store.candidates_finalized(relay_parent, HashSet::new()).unwrap();
+		assert!(store.seen(relay_parent).is_none());
+		assert_eq!(store.seen(other_parent).unwrap(), other_digest);
+	}
 }
@@ -30,6 +30,10 @@ pub enum ChainSpec {
 	Alexander,
 	/// Whatever the current runtime is with the "global testnet" defaults.
 	StagingTestnet,
+	/// The Kusama network.
+	Kusama,
+	/// Whatever the current runtime is, with Alice/Bob auths and test parachains.
+	WestendLocal,
 }
 
 impl Default for ChainSpec {
@@ -46,6 +50,8 @@ impl ChainSpec {
 			ChainSpec::Development => service::chain_spec::development_config(),
 			ChainSpec::LocalTestnet => service::chain_spec::local_testnet_config(),
 			ChainSpec::StagingTestnet => service::chain_spec::staging_testnet_config(),
+			ChainSpec::Kusama => service::chain_spec::kusama_config(),
+			ChainSpec::WestendLocal => service::chain_spec::westend_local_config(),
 		})
 	}
 
@@ -55,9 +61,24 @@ impl ChainSpec {
 			"local" => Some(ChainSpec::LocalTestnet),
 			"poc-3" | "alex" | "alexander" => Some(ChainSpec::Alexander),
 			"staging" => Some(ChainSpec::StagingTestnet),
+			"kusama" | "ksm" => Some(ChainSpec::Kusama),
+			"westend-local" => Some(ChainSpec::WestendLocal),
 			"" => Some(ChainSpec::default()),
 			_ => None,
 		}
 	}
 }
 
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn kusama_aliases_load_kusama_spec() {
+		for alias in &["kusama", "ksm"] {
+			let spec = ChainSpec::from(alias).expect("kusama aliases are known; qed").load().unwrap();
+			assert_eq!(spec.name(), "Kusama");
+		}
+	}
+}
@@ -22,21 +22,28 @@
 extern crate futures;
 extern crate tokio;
 
+#[macro_use]
 extern crate substrate_cli as cli;
 extern crate polkadot_service as service;
+extern crate polkadot_primitives;
 extern crate exit_future;
+#[macro_use]
+extern crate structopt;
 
 #[macro_use]
 extern crate log;
 
 mod chain_spec;
 
+use std::net::SocketAddr;
 use std::ops::Deref;
+use std::path::PathBuf;
+use std::time::Duration;
 use chain_spec::ChainSpec;
 use futures::Future;
 use tokio::runtime::Runtime;
 use service::Service as BareService;
-use cli::NoCustom;
+use cli::GetLogFilter;
 
 pub use service::{
 	Components as ServiceComponents, PolkadotService, CustomConfiguration, ServiceFactory, Factory,
@@ -46,6 +53,12 @@ pub use service::{
 pub use cli::{VersionInfo, IntoExit};
 pub use cli::error;
 
+// how long in-flight parachain validation work is given to complete on shutdown.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+// the file in the database directory keeping the generated collation encryption key.
+const COLLATION_ENCRYPTION_KEY_FILE: &str = "collation_encryption_key";
+
 fn load_spec(id: &str) -> Result<Option<service::ChainSpec>, String> {
 	Ok(match ChainSpec::from(id) {
 		Some(spec) => Some(spec.load()?),
@@ -53,6 +66,256 @@ fn load_spec(id: &str) -> Result<Option<service::ChainSpec>, String> {
 	})
 }
 
+/// Polkadot-specific subcommands.
+#[derive(Debug, StructOpt, Clone)]
+pub enum PolkadotSubCommands {
+	/// Print canonical encodings and signatures of statements, gossip topics and
+	/// candidate receipts, for checking compatibility of other implementations.
+	#[structopt(name = "test-vectors")]
+	TestVectors,
+
+	/// Simulate the backing of candidates by parachain validator groups, and report
+	/// percentiles of the time until validators see candidates backed.
+	#[structopt(name = "simulate-backing")]
+	SimulateBacking(SimulateBackingParams),
+
+	/// Re-run the execution of a candidate recorded with `--validation-replay-dir`, with
+	/// verbose tracing of validation.
+	#[structopt(name = "replay-validation")]
+	ReplayValidation(ReplayValidationParams),
+
+	/// Hold an authority key and sign attestation statements for validating nodes
+	/// started with `--remote-signer`, refusing to sign conflicting statements.
+	#[structopt(name = "serve-signing-requests")]
+	ServeSigningRequests(ServeSigningParams),
+}
+
+/// Parameters of the `simulate-backing` subcommand.
+#[derive(Debug, StructOpt, Clone)]
+pub struct SimulateBackingParams {
+	/// Number of validators.
+	#[structopt(long = "validators", default_value = "100")]
+	pub validators: usize,
+
+	/// Number of validators in each parachain group.
+	#[structopt(long = "group-size", default_value = "5")]
+	pub group_size: usize,
+
+	/// Probability of each statement sent between two validators being lost.
+	#[structopt(long = "message-loss", default_value = "0")]
+	pub message_loss: f64,
+
+	/// Latency of statements between validators, in milliseconds: `constant:<ms>`,
+	/// `uniform:<min>:<max>` or `exponential:<mean>`.
+	#[structopt(long = "latency", default_value = "exponential:100")]
+	pub latency: service::simulation::LatencyDistribution,
+
+	/// Seed of the simulation, to reproduce a run.
+	#[structopt(long = "seed", default_value = "0")]
+	pub seed: u64,
+}
+
+/// Parameters of the `replay-validation` subcommand.
+#[derive(Debug, StructOpt, Clone)]
+pub struct ReplayValidationParams {
+	/// Path to the replay bundle.
+	#[structopt(parse(from_os_str))]
+	pub bundle: PathBuf,
+}
+
+/// Parameters of the `serve-signing-requests` subcommand.
+#[derive(Debug, StructOpt, Clone)]
+pub struct ServeSigningParams {
+	/// Loopback address to accept signing connections on.
+	#[structopt(long = "listen", default_value = "127.0.0.1:9956")]
+	pub listen: SocketAddr,
+
+	/// Path to a file holding the hex-encoded 32-byte seed of the authority key.
+	#[structopt(long = "seed-file", parse(from_os_str))]
+	pub seed_file: PathBuf,
+
+	/// Number of recent relay chain block numbers to remember signed statements at.
+	#[structopt(long = "max-parents", default_value = "64")]
+	pub max_parents: usize,
+
+	/// Path to the file keeping the signed statements across restarts.
+	#[structopt(long = "history-file", parse(from_os_str))]
+	pub history_file: PathBuf,
+}
+
+impl GetLogFilter for PolkadotSubCommands {
+	fn get_log_filter(&self) -> Option<String> {
+		match *self {
+			PolkadotSubCommands::ReplayValidation(_) => Some("validation=trace".into()),
+			_ => None,
+		}
+	}
+}
+
+/// Polkadot-specific parameters of the `run` command.
+#[derive(Debug, StructOpt, Clone)]
+pub struct PolkadotRunParams {
+	/// Keep the availability store, which holds available block data and the gossip
+	/// seen on each relay parent, in memory rather than on disk, and never write
+	/// buffers to `--buffer-overflow-dir`. The chain database is unaffected. Useful
+	/// with `--dev` for repeatable local testing.
+	#[structopt(long = "ephemeral")]
+	pub ephemeral: bool,
+
+	/// Write the inputs of fetched candidates which fail execution to the given
+	/// directory, to be re-run with the `replay-validation` subcommand. At most
+	/// 64 candidates are recorded per run.
+	#[structopt(long = "validation-replay-dir", parse(from_os_str))]
+	pub validation_replay_dir: Option<PathBuf>,
+
+	/// Write deferred statements and pending ingress to the given directory once
+	/// the buffer of a relay parent outgrows `--buffer-overflow-limit`. Ignored with
+	/// `--ephemeral`.
+	#[structopt(long = "buffer-overflow-dir", parse(from_os_str))]
+	pub buffer_overflow_dir: Option<PathBuf>,
+
+	/// Number of bytes the buffer of a relay parent keeps in memory before
+	/// writing to `--buffer-overflow-dir`.
+	#[structopt(long = "buffer-overflow-limit", default_value = "8388608")]
+	pub buffer_overflow_limit: usize,
+
+	/// Serve the parachain RPC methods over HTTP on the given address, so collators
+	/// can learn the validators assigned to their parachain.
+	#[structopt(long = "parachain-rpc")]
+	pub parachain_rpc: Option<SocketAddr>,
+
+	/// Serve the parachain RPC methods over websockets on the given address, along
+	/// with subscriptions to locally backed candidates.
+	#[structopt(long = "parachain-ws-rpc")]
+	pub parachain_ws_rpc: Option<SocketAddr>,
+
+	/// Also serve the unsafe methods exporting and importing attestation state on the
+	/// parachain RPC servers. Imported state counts towards backing, so only expose
+	/// these to trusted operators.
+	#[structopt(long = "unsafe-attestation-rpc")]
+	pub unsafe_attestation_rpc: bool,
+
+	/// Abandon candidates which aren't backed within the given number of seconds
+	/// of the first statement seen on them.
+	#[structopt(long = "backing-deadline")]
+	pub backing_deadline: Option<u64>,
+
+	/// Delegate signing of attestation statements to the `serve-signing-requests`
+	/// process at the given address. Requires `--remote-signer-key`.
+	#[structopt(long = "remote-signer")]
+	pub remote_signer: Option<SocketAddr>,
+
+	/// Hex-encoded public session key held by the remote signer.
+	#[structopt(long = "remote-signer-key", parse(try_from_str = "parse_session_key"))]
+	pub remote_signer_key: Option<polkadot_primitives::SessionKey>,
+
+	/// File holding the hex-encoded secret of the long-term key collations are
+	/// encrypted to. This key is separate from the session key. Without it a key is
+	/// generated once and kept in the database directory, unless the node is ephemeral.
+	#[structopt(long = "collation-encryption-key-file", parse(from_os_str))]
+	pub collation_encryption_key_file: Option<PathBuf>,
+}
+
+impl_augment_clap!(PolkadotRunParams);
+
+fn parse_hex_32(s: &str) -> Result<[u8; 32], String> {
+	let hex = s.trim();
+	let hex = if hex.starts_with("0x") { &hex[2..] } else { hex };
+	if hex.len() != 64 || !hex.is_ascii() {
+		return Err(format!("Expected 32 hex-encoded bytes, got {:?}", s));
+	}
+
+	let mut bytes = [0u8; 32];
+	for (i, byte) in bytes.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+			.map_err(|e| format!("Invalid hex {:?}: {}", s, e))?;
+	}
+	Ok(bytes)
+}
+
+fn parse_session_key(s: &str) -> Result<polkadot_primitives::SessionKey, String> {
+	parse_hex_32(s).map(Into::into)
+}
+
+// the secret of the collation encryption key kept at the given path, generating and
+// storing one when there is none yet, so that the announced key stays stable.
+fn load_or_generate_encryption_secret(path: &::std::path::Path) -> Result<[u8; 32], String> {
+	use polkadot_primitives::encryption::EncryptionKey;
+
+	if path.exists() {
+		let secret = ::std::fs::read_to_string(path)
+			.map_err(|e| format!("Failed to read collation encryption key file {}: {}", path.display(), e))?;
+		return parse_hex_32(&secret);
+	}
+
+	let secret = EncryptionKey::generate().secret();
+	let hex: String = secret.iter().map(|b| format!("{:02x}", b)).collect();
+	if let Some(dir) = path.parent() {
+		::std::fs::create_dir_all(dir)
+			.map_err(|e| format!("Failed to create directory {}: {}", dir.display(), e))?;
+	}
+	::std::fs::write(path, hex)
+		.map_err(|e| format!("Failed to write collation encryption key file {}: {}", path.display(), e))?;
+
+	info!("Generated collation encryption key, kept in {}", path.display());
+	Ok(secret)
+}
+
+fn serve_signing_requests(params: ServeSigningParams) -> error::Result<()> {
+	let seed = ::std::fs::read_to_string(&params.seed_file)
+		.map_err(|e| format!("Failed to read seed file {}: {}", params.seed_file.display(), e))?;
+	let seed = parse_hex_32(&seed)?;
+
+	service::serve_remote_signer(params.listen, &seed, params.max_parents, params.history_file)
+		.map_err(|e| format!("Failed to serve signing requests on {}: {}", params.listen, e))?;
+
+	Ok(())
+}
+
+fn simulate_backing(params: SimulateBackingParams) -> error::Result<()> {
+	use service::simulation::{simulate, SimulationConfig};
+
+	let report = simulate(&SimulationConfig {
+		validators: params.validators,
+		group_size: params.group_size,
+		message_loss: params.message_loss,
+		latency: params.latency,
+		seed: params.seed,
+	})?;
+
+	println!("Candidates: {}", report.candidates);
+	println!("Statements sent: {}", report.messages);
+	println!("Seen backed: {} of {}", report.backed, report.backed + report.unbacked);
+	for (percentile, time) in report.percentiles {
+		println!("p{}: {}ms", percentile, time.as_secs() * 1000 + u64::from(time.subsec_millis()));
+	}
+
+	Ok(())
+}
+
+fn replay_validation(params: ReplayValidationParams) -> error::Result<()> {
+	let bundle = service::load_replay(&params.bundle)
+		.map_err(|e| format!("Failed to load replay bundle: {}", e))?;
+
+	println!("Parachain: {:?}", bundle.collation.receipt.parachain_index);
+	println!("Candidate: {:?}", bundle.collation.receipt.hash().0);
+	println!("Validation code: {:?}", bundle.code_hash);
+	println!("Recorded outcome: {}", bundle.outcome);
+	match bundle.replay() {
+		Ok(extrinsic) => println!("Replayed outcome: valid, with {} outgoing messages", extrinsic.outgoing_messages.len()),
+		Err(e) => println!("Replayed outcome: {}", e),
+	}
+
+	Ok(())
+}
+
+fn print_test_vectors() {
+	for vector in polkadot_primitives::test_vectors::test_vectors() {
+		let hex: String = vector.data.iter().map(|b| format!("{:02x}", b)).collect();
+		println!("{}: 0x{}", vector.name, hex);
+	}
+}
+
 /// Additional worker making use of the node, to run asynchronously before shutdown.
 ///
 /// This will be invoked with the service and spawn a future that resolves
@@ -84,9 +347,9 @@ pub fn run<I, T, W>(args: I, worker: W, version: cli::VersionInfo) -> error::Res
 	T: Into<std::ffi::OsString> + Clone,
 	W: Worker,
 {
-	cli::parse_and_execute::<service::Factory, NoCustom, NoCustom, _, _, _, _, _>(
+	let subcommand = cli::parse_and_execute::<service::Factory, PolkadotSubCommands, PolkadotRunParams, _, _, _, _, _>(
 		load_spec, &version, "parity-polkadot", args, worker,
-		|worker, _custom_args, mut config| {
+		|worker, run_params, mut config| {
 			info!("{}", version.name);
 			info!("  version {}", config.full_version());
 			info!("  by {}, 2017-2019", version.author);
@@ -94,6 +357,40 @@ pub fn run<I, T, W>(args: I, worker: W, version: cli::VersionInfo) -> error::Res
 			info!("Node name: {}", config.name);
 			info!("Roles: {:?}", config.roles);
 			config.custom = worker.configuration();
+			config.custom.ephemeral |= run_params.ephemeral;
+			if run_params.validation_replay_dir.is_some() {
+				config.custom.validation_replay_dir = run_params.validation_replay_dir;
+			}
+			if run_params.parachain_rpc.is_some() {
+				config.custom.parachain_rpc = run_params.parachain_rpc;
+			}
+			if run_params.parachain_ws_rpc.is_some() {
+				config.custom.parachain_ws_rpc = run_params.parachain_ws_rpc;
+			}
+			config.custom.unsafe_attestation_rpc |= run_params.unsafe_attestation_rpc;
+			if let Some(secs) = run_params.backing_deadline {
+				config.custom.backing_deadline = Some(Duration::from_secs(secs));
+			}
+			if let Some(directory) = run_params.buffer_overflow_dir {
+				config.custom.buffer_overflow = Some(service::OverflowConfig {
+					directory,
+					memory_limit: run_params.buffer_overflow_limit,
+				});
+			}
+			match (run_params.remote_signer, run_params.remote_signer_key) {
+				(Some(addr), Some(key)) => config.custom.remote_signer = Some((addr, key)),
+				(None, None) => {}
+				_ => return Err("`--remote-signer` and `--remote-signer-key` must be given together".into()),
+			}
+			if let Some(path) = run_params.collation_encryption_key_file {
+				let secret = ::std::fs::read_to_string(&path)
+					.map_err(|e| format!("Failed to read collation encryption key file {}: {}", path.display(), e))?;
+				config.custom.collation_encryption_secret = Some(parse_hex_32(&secret)?);
+			} else if !config.custom.ephemeral && config.custom.collation_encryption_secret.is_none() {
+				let path = PathBuf::from(config.database_path.clone()).join(COLLATION_ENCRYPTION_KEY_FILE);
+				config.custom.collation_encryption_secret = Some(load_or_generate_encryption_secret(&path)?);
+			}
+			let shutdown = config.custom.shutdown.clone();
 			let runtime = Runtime::new().map_err(|e| format!("{:?}", e))?;
 			let executor = runtime.executor();
 			match config.roles {
@@ -101,22 +398,35 @@ pub fn run<I, T, W>(args: I, worker: W, version: cli::VersionInfo) -> error::Res
 					run_until_exit(
 						runtime,
 						Factory::new_light(config, executor).map_err(|e| format!("{:?}", e))?,
-						worker
+						worker,
+						shutdown,
 					),
 				_ => run_until_exit(
 						runtime,
 						Factory::new_full(config, executor).map_err(|e| format!("{:?}", e))?,
-						worker
+						worker,
+						shutdown,
 					),
 			}.map_err(|e| format!("{:?}", e))
 		}
-	).map_err(Into::into).map(|_| ())
+	)?;
+
+	match subcommand {
+		Some(PolkadotSubCommands::TestVectors) => print_test_vectors(),
+		Some(PolkadotSubCommands::SimulateBacking(params)) => simulate_backing(params)?,
+		Some(PolkadotSubCommands::ReplayValidation(params)) => replay_validation(params)?,
+		Some(PolkadotSubCommands::ServeSigningRequests(params)) => serve_signing_requests(params)?,
+		None => {}
+	}
+
+	Ok(())
 }
 
 fn run_until_exit<T, C, W>(
 	mut runtime: Runtime,
 	service: T,
 	worker: W,
+	shutdown: service::Shutdown,
 ) -> error::Result<()>
 	where
 	    T: Deref<Target=BareService<C>>,
@@ -130,6 +440,15 @@ fn run_until_exit<T, C, W>(
 	cli::informant::start(&service, exit.clone(), executor.clone());
 
 	let _ = runtime.block_on(worker.work(&*service));
+
+	// let in-flight validation work complete and flush its state while
+	// the network is still up.
+	info!("Shutting down parachain validation");
+	let unfinished = shutdown.drain(SHUTDOWN_GRACE_PERIOD);
+	if unfinished > 0 {
+		warn!("Abandoning {} pieces of parachain validation work still in flight", unfinished);
+	}
+
 	exit_send.fire();
 
 	// we eagerly drop the service so that the internal exit future is fired,
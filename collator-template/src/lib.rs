@@ -0,0 +1,205 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Template for collator binaries.
+//!
+//! This takes care of everything a collator needs apart from the parachain
+//! logic itself: the polkadot service and relay chain connection, the collator
+//! key, shutting down on ctrl-c and the collation loop. A parachain only has to
+//! implement `ParachainContext`.
+//!
+//! ```ignore
+//! fn main() {
+//! 	let res = CollatorTemplate::new(MyContext::new(), 100.into(), "my-collator")
+//! 		.description("collator for my parachain")
+//! 		.genesis_head(HeadData(my_genesis_head()))
+//! 		.run();
+//!
+//! 	if let Err(e) = res {
+//! 		println!("{}", e);
+//! 	}
+//! }
+//! ```
+//!
+//! The collator key is taken from the seed given to `key_seed`, or otherwise from
+//! the hex-encoded 32-byte seed in the `COLLATOR_KEY_SEED` environment variable.
+//! Command line arguments are passed through to the polkadot node.
+
+extern crate polkadot_collator as collator;
+extern crate polkadot_primitives;
+extern crate substrate_primitives;
+extern crate ctrlc;
+extern crate exit_future;
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use polkadot_primitives::parachain::{HeadData, Id as ParaId};
+use substrate_primitives::ed25519::Pair;
+
+pub use collator::{ParachainContext, InvalidHead, VersionInfo};
+pub use polkadot_primitives::parachain::{BlockData, Message};
+
+/// Environment variable holding the hex-encoded seed of the collator key.
+pub const KEY_SEED_VAR: &str = "COLLATOR_KEY_SEED";
+
+/// A collator binary, parametrized by the parachain logic.
+pub struct CollatorTemplate<P> {
+	context: P,
+	para_id: ParaId,
+	key_seed: Option<[u8; 32]>,
+	genesis_head: Option<HeadData>,
+	private_collations: bool,
+	version: VersionInfo,
+}
+
+impl<P: ParachainContext + Send + 'static> CollatorTemplate<P> {
+	/// Create a collator for the given parachain, whose binary is called `executable_name`.
+	pub fn new(context: P, para_id: ParaId, executable_name: &'static str) -> Self {
+		CollatorTemplate {
+			context,
+			para_id,
+			key_seed: None,
+			genesis_head: None,
+			private_collations: false,
+			version: VersionInfo {
+				name: "<unknown>",
+				version: "<unknown>",
+				commit: "<unknown>",
+				executable_name,
+				description: "parachain collator",
+				author: "<unknown>",
+				support_url: "<unknown>",
+			},
+		}
+	}
+
+	/// Set the version information shown by the command line interface.
+	pub fn version(mut self, version: VersionInfo) -> Self {
+		self.version = version;
+		self
+	}
+
+	/// Set the description of the collator shown by the command line interface.
+	pub fn description(mut self, description: &'static str) -> Self {
+		self.version.description = description;
+		self
+	}
+
+	/// Use a fixed seed for the collator key, rather than reading it from the environment.
+	pub fn key_seed(mut self, seed: [u8; 32]) -> Self {
+		self.key_seed = Some(seed);
+		self
+	}
+
+	/// Set the genesis head of the parachain, which is printed at startup so that
+	/// the parachain can be registered.
+	pub fn genesis_head(mut self, head: HeadData) -> Self {
+		self.genesis_head = Some(head);
+		self
+	}
+
+	/// Only send collations encrypted to the validators they are meant for, so that
+	/// their contents can't be observed before they are backed.
+	pub fn private_collations(mut self, private: bool) -> Self {
+		self.private_collations = private;
+		self
+	}
+
+	/// Run the collator with the command line arguments of the process until ctrl-c is pressed.
+	pub fn run(self) -> Result<(), String> {
+		self.run_with_args(::std::env::args())
+	}
+
+	/// Run the collator with given command line arguments until ctrl-c is pressed.
+	pub fn run_with_args<I, T>(self, args: I) -> Result<(), String> where
+		I: IntoIterator<Item=T>,
+		T: Into<::std::ffi::OsString> + Clone,
+	{
+		let seed = match self.key_seed {
+			Some(seed) => seed,
+			None => {
+				let hex = ::std::env::var(KEY_SEED_VAR)
+					.map_err(|_| format!("No collator key given: set {} to a hex-encoded seed", KEY_SEED_VAR))?;
+				parse_seed(&hex)?
+			}
+		};
+		let key = Arc::new(Pair::from_seed(&seed));
+
+		println!("Starting collator for parachain {:?}", self.para_id);
+
+		if let Some(head) = self.genesis_head {
+			println!("Genesis head: 0x{}", to_hex(&head.0));
+		}
+
+		// can't use signal directly here because CtrlC takes only `Fn`.
+		let (exit_send, exit) = exit_future::signal();
+
+		let exit_send_cell = RefCell::new(Some(exit_send));
+		ctrlc::set_handler(move || {
+			if let Some(exit_send) = exit_send_cell.try_borrow_mut().expect("signal handler not reentrant; qed").take() {
+				exit_send.fire();
+			}
+		}).map_err(|e| format!("Error setting up ctrl-c handler: {}", e))?;
+
+		collator::run_collator(
+			self.context,
+			self.para_id,
+			exit,
+			key,
+			self.private_collations,
+			args,
+			self.version,
+		).map_err(|e| format!("{}", e))
+	}
+}
+
+// parse a hex-encoded 32-byte seed, with or without `0x` prefix.
+fn parse_seed(hex: &str) -> Result<[u8; 32], String> {
+	let hex = hex.trim();
+	let hex = if hex.starts_with("0x") { &hex[2..] } else { hex };
+
+	if hex.len() != 64 || !hex.is_ascii() {
+		return Err(format!("Collator key seed must be 32 bytes, got {} hex digits", hex.len()));
+	}
+
+	let mut seed = [0; 32];
+	for (i, byte) in seed.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+			.map_err(|_| format!("Invalid hex in collator key seed: {}", hex))?;
+	}
+
+	Ok(seed)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_seed() {
+		let hex = to_hex(&[0xab; 32]);
+		assert_eq!(parse_seed(&hex), Ok([0xab; 32]));
+		assert_eq!(parse_seed(&format!("0x{}", hex)), Ok([0xab; 32]));
+
+		assert!(parse_seed("0x0102").is_err());
+		assert!(parse_seed(&"zz".repeat(32)).is_err());
+	}
+}
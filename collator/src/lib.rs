@@ -43,14 +43,21 @@
 //!
 //! This crate defines traits which provide context necessary for collation logic
 //! to be performed, as the collation logic itself.
+//!
+//! Parachains without a transaction pool of their own can produce candidates with
+//! the transactions of a simple pool, which accepts them over RPC.
 
 extern crate futures;
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+extern crate parking_lot;
 extern crate substrate_client as client;
 extern crate parity_codec as codec;
 extern crate substrate_primitives as primitives;
 extern crate tokio;
 
 extern crate polkadot_cli;
+extern crate polkadot_rpc;
 extern crate polkadot_runtime;
 extern crate polkadot_primitives;
 
@@ -59,19 +66,30 @@ extern crate log;
 
 use std::collections::{BTreeSet, BTreeMap, HashSet};
 use std::fmt;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use futures::{future, stream, Stream, Future, IntoFuture};
 use client::BlockchainEvents;
 use primitives::ed25519;
-use polkadot_primitives::{AccountId, BlockId, SessionKey};
+use polkadot_primitives::{AccountId, BlockId, BlockNumber, SessionKey};
 use polkadot_primitives::parachain::{self, BlockData, DutyRoster, HeadData, ConsolidatedIngress, Message, Id as ParaId};
 use polkadot_cli::{PolkadotService, CustomConfiguration, CoreApi, ParachainHost};
 use polkadot_cli::{Worker, IntoExit, ProvideRuntimeApi};
 use tokio::timer::Timeout;
 
 pub use polkadot_cli::VersionInfo;
+pub use pool::{TransactionPool, TransactionalContext, Pooled, PooledCandidate, PoolError};
+
+mod pool;
+
+/// The maximum number of transactions pooled by `run_collator_with_transactions`.
+pub const MAX_POOLED_TRANSACTIONS: usize = 4096;
+
+/// The maximum total size in bytes of the pooled transactions a candidate is produced with
+/// by `run_collator_with_transactions`, bounding the size of their proof of validity.
+pub const MAX_CANDIDATE_TRANSACTIONS_SIZE: usize = 1024 * 1024;
 
 const COLLATION_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -125,6 +143,10 @@ pub trait RelayChainContext {
 
 	/// Get un-routed egress queues from a parachain to the local parachain.
 	fn unrouted_egress(&self, id: ParaId) -> Self::FutureEgress;
+
+	/// Get the ingress watermark acknowledged by processing the un-routed egress
+	/// of all routing parachains.
+	fn ingress_watermark(&self) -> BlockNumber;
 }
 
 fn key_to_account_id(key: &ed25519::Pair) -> AccountId {
@@ -184,6 +206,7 @@ pub fn collate<'a, R, P>(
 		R::FutureEgress: 'a,
 		P: ParachainContext + 'a,
 {
+	let ingress_watermark = relay_context.ingress_watermark();
 	collate_ingress(relay_context).map_err(Error::Polkadot).and_then(move |ingress| {
 		let (block_data, head_data) = para_context.produce_candidate(
 			last_head,
@@ -202,6 +225,7 @@ pub fn collate<'a, R, P>(
 			egress_queue_roots: Vec::new(),
 			fees: 0,
 			block_data_hash,
+			ingress_watermark,
 		};
 
 		Ok(parachain::Collation {
@@ -212,7 +236,9 @@ pub fn collate<'a, R, P>(
 }
 
 /// Polkadot-api context.
-struct ApiContext;
+struct ApiContext {
+	ingress_watermark: BlockNumber,
+}
 
 impl RelayChainContext for ApiContext {
 	type Error = client::error::Error;
@@ -225,6 +251,10 @@ impl RelayChainContext for ApiContext {
 	fn unrouted_egress(&self, _id: ParaId) -> Self::FutureEgress {
 		Ok(Vec::new())
 	}
+
+	fn ingress_watermark(&self) -> BlockNumber {
+		self.ingress_watermark
+	}
 }
 
 struct CollationNode<P, E> {
@@ -232,6 +262,7 @@ struct CollationNode<P, E> {
 	exit: E,
 	para_id: ParaId,
 	key: Arc<ed25519::Pair>,
+	private_collations: bool,
 }
 
 impl<P, E> IntoExit for CollationNode<P, E> where
@@ -256,6 +287,7 @@ impl<P, E> Worker for CollationNode<P, E> where
 			key_to_account_id(&*self.key),
 			self.para_id.clone(),
 		));
+		config.private_collations = self.private_collations;
 		config
 	}
 
@@ -263,7 +295,7 @@ impl<P, E> Worker for CollationNode<P, E> where
 		where S: PolkadotService,
 	{
 
-		let CollationNode { parachain_context, exit, para_id, key } = self;
+		let CollationNode { parachain_context, exit, para_id, key, .. } = self;
 		let client = service.client();
 		let network = service.network();
 
@@ -300,10 +332,13 @@ impl<P, E> Worker for CollationNode<P, E> where
 						try_fr!(api.duty_roster(&id)),
 					);
 
+					// nothing is routed yet, so the watermark doesn't move.
+					let ingress_watermark = try_fr!(api.ingress_watermark(&id, para_id));
+
 					let collation_work = collate(
 						para_id,
 						HeadData(last_head),
-						ApiContext,
+						ApiContext { ingress_watermark },
 						parachain_context,
 						key,
 					).map(move |collation| {
@@ -349,12 +384,15 @@ fn compute_targets(para_id: ParaId, session_keys: &[SessionKey], roster: DutyRos
 /// arguments to the underlying polkadot node.
 ///
 /// Provide a future which resolves when the node should exit.
+/// If `private_collations` is set, collations are only sent encrypted to the
+/// validators they are meant for.
 /// This function blocks until done.
 pub fn run_collator<P, E, I, ArgT>(
 	parachain_context: P,
 	para_id: ParaId,
 	exit: E,
 	key: Arc<ed25519::Pair>,
+	private_collations: bool,
 	args: I,
 	version: VersionInfo,
 ) -> polkadot_cli::error::Result<()> where
@@ -364,10 +402,66 @@ pub fn run_collator<P, E, I, ArgT>(
 	I: IntoIterator<Item=ArgT>,
 	ArgT: Into<std::ffi::OsString> + Clone,
 {
-	let node_logic = CollationNode { parachain_context, exit: exit.into_future(), para_id, key };
+	let node_logic = CollationNode {
+		parachain_context,
+		exit: exit.into_future(),
+		para_id,
+		key,
+		private_collations,
+	};
 	polkadot_cli::run(args, node_logic, version)
 }
 
+/// Run a collator node like `run_collator`, producing candidates with the transactions
+/// of a pool. Transactions are submitted to the pool over an HTTP RPC server listening
+/// on `rpc_addr`.
+pub fn run_collator_with_transactions<P, E, I, ArgT>(
+	parachain_context: P,
+	para_id: ParaId,
+	exit: E,
+	key: Arc<ed25519::Pair>,
+	private_collations: bool,
+	rpc_addr: SocketAddr,
+	args: I,
+	version: VersionInfo,
+) -> polkadot_cli::error::Result<()> where
+	P: TransactionalContext + Send + 'static,
+	E: IntoFuture<Item=(),Error=()>,
+	E::Future: Send + Clone + 'static,
+	I: IntoIterator<Item=ArgT>,
+	ArgT: Into<std::ffi::OsString> + Clone,
+{
+	use polkadot_rpc::{ParachainTransactions, ParachainTransactionApi};
+
+	let pool = TransactionPool::new(MAX_POOLED_TRANSACTIONS, MAX_CANDIDATE_TRANSACTIONS_SIZE);
+
+	let mut io = jsonrpc_core::IoHandler::new();
+	let submit_to = pool.clone();
+	io.extend_with(ParachainTransactions::new(move |transaction| {
+		submit_to.submit(transaction).map_err(|e| e.to_string())
+	}).to_delegate());
+
+	let server = jsonrpc_http_server::ServerBuilder::new(io)
+		.threads(1)
+		.start_http(&rpc_addr)
+		.map_err(|e| format!("Unable to start transaction RPC server on {}: {}", rpc_addr, e))?;
+
+	info!("Transaction RPC server listening on {}", rpc_addr);
+
+	let res = run_collator(
+		Pooled::new(parachain_context, pool),
+		para_id,
+		exit,
+		key,
+		private_collations,
+		args,
+		version,
+	);
+
+	server.close();
+	res
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -393,6 +487,10 @@ mod tests {
 		fn unrouted_egress(&self, id: ParaId) -> Result<Vec<Vec<Message>>, ()> {
 			Ok(self.egresses.get(&id).cloned().unwrap_or_default())
 		}
+
+		fn ingress_watermark(&self) -> BlockNumber {
+			0
+		}
 	}
 
 	#[test]
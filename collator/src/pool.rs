@@ -0,0 +1,346 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A simple pool of parachain transactions, for parachains which don't bring their own.
+//!
+//! Transactions are opaque to the pool and kept in order of submission. Each candidate
+//! is produced with the pooled transactions fitting in a candidate, in that order. The
+//! parachain reports which of them it applied, and those are dropped once a candidate
+//! is built on a head descending from the candidate, as the candidate has then been
+//! included in the relay chain.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use polkadot_primitives::Hash;
+use polkadot_primitives::parachain::{BlockData, HeadData, Message, Id as ParaId};
+use primitives::blake2_256;
+
+use {InvalidHead, ParachainContext};
+
+/// The number of recently produced candidates whose transactions are tracked.
+const TRACKED_CANDIDATES: usize = 16;
+
+/// Errors on submitting a transaction to the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolError {
+	/// The pool holds as many transactions as it may.
+	Full,
+	/// The transaction is already in the pool.
+	AlreadyKnown(Hash),
+	/// The transaction is too large to fit in any candidate.
+	TooLarge(usize),
+}
+
+impl fmt::Display for PoolError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			PoolError::Full => write!(f, "Transaction pool is full"),
+			PoolError::AlreadyKnown(ref hash) => write!(f, "Transaction {} is already in the pool", hash),
+			PoolError::TooLarge(size) => write!(f, "Transaction of {} bytes doesn't fit in a candidate", size),
+		}
+	}
+}
+
+// a candidate produced recently.
+struct Produced {
+	parent: HeadData,
+	head: HeadData,
+	applied: Vec<Hash>,
+}
+
+struct Inner {
+	limit: usize,
+	max_candidate_size: usize,
+	pending: Vec<(Hash, Vec<u8>)>,
+	candidates: Vec<Produced>,
+}
+
+/// A pool of parachain transactions. Clones refer to the same pool.
+#[derive(Clone)]
+pub struct TransactionPool {
+	inner: Arc<Mutex<Inner>>,
+}
+
+impl TransactionPool {
+	/// Create a new pool, holding up to `limit` transactions and producing candidates
+	/// with up to `max_candidate_size` bytes of them.
+	pub fn new(limit: usize, max_candidate_size: usize) -> Self {
+		TransactionPool {
+			inner: Arc::new(Mutex::new(Inner {
+				limit,
+				max_candidate_size,
+				pending: Vec::new(),
+				candidates: Vec::new(),
+			})),
+		}
+	}
+
+	/// Submit a transaction, returning its hash.
+	pub fn submit(&self, transaction: Vec<u8>) -> Result<Hash, PoolError> {
+		let hash: Hash = blake2_256(&transaction).into();
+
+		let mut inner = self.inner.lock();
+		if inner.pending.iter().any(|&(ref h, _)| h == &hash) {
+			return Err(PoolError::AlreadyKnown(hash));
+		}
+
+		if transaction.len() > inner.max_candidate_size {
+			return Err(PoolError::TooLarge(transaction.len()));
+		}
+
+		if inner.pending.len() >= inner.limit {
+			return Err(PoolError::Full);
+		}
+
+		inner.pending.push((hash, transaction));
+		Ok(hash)
+	}
+
+	/// The number of transactions in the pool.
+	pub fn len(&self) -> usize {
+		self.inner.lock().pending.len()
+	}
+
+	/// Whether the pool is empty.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The transactions to produce a candidate on the given head with, in order of
+	/// submission and fitting in a candidate. The transactions applied by the
+	/// candidates which produced the head and its ancestors are dropped first.
+	pub fn ready(&self, last_head: &HeadData) -> Vec<Vec<u8>> {
+		let mut inner = self.inner.lock();
+
+		let mut included = HashSet::new();
+		let mut head = last_head.clone();
+		while let Some(idx) = inner.candidates.iter().position(|produced| produced.head == head) {
+			let produced = inner.candidates.remove(idx);
+			included.extend(produced.applied);
+			head = produced.parent;
+		}
+
+		if !included.is_empty() {
+			inner.pending.retain(|&(ref hash, _)| !included.contains(hash));
+		}
+
+		let mut remaining = inner.max_candidate_size;
+		let mut ready = Vec::new();
+		for &(_, ref transaction) in &inner.pending {
+			if transaction.len() <= remaining {
+				remaining -= transaction.len();
+				ready.push(transaction.clone());
+			}
+		}
+
+		ready
+	}
+
+	/// Note a candidate built on `parent`, producing `head` by applying the given transactions.
+	pub fn note_candidate<'a, T>(&self, parent: HeadData, head: HeadData, applied: T)
+		where T: IntoIterator<Item=&'a Vec<u8>>
+	{
+		let applied = applied.into_iter().map(|transaction| blake2_256(transaction).into()).collect();
+
+		let mut inner = self.inner.lock();
+		inner.candidates.retain(|produced| produced.head != head);
+		inner.candidates.push(Produced { parent, head, applied });
+
+		if inner.candidates.len() > TRACKED_CANDIDATES {
+			inner.candidates.remove(0);
+		}
+	}
+
+	/// Drop the given transactions from the pool.
+	pub fn drop_transactions<'a, T>(&self, transactions: T)
+		where T: IntoIterator<Item=&'a Vec<u8>>
+	{
+		let dropped: HashSet<Hash> = transactions.into_iter()
+			.map(|transaction| blake2_256(transaction).into())
+			.collect();
+
+		self.inner.lock().pending.retain(|&(ref hash, _)| !dropped.contains(hash));
+	}
+}
+
+/// A candidate produced with pooled transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PooledCandidate {
+	/// The block data of the candidate.
+	pub block_data: BlockData,
+	/// The head data of the candidate.
+	pub head_data: HeadData,
+	/// Indices of the given transactions applied in the candidate.
+	pub applied: Vec<usize>,
+	/// Indices of the given transactions which can never be applied. These are dropped
+	/// from the pool, while any others not applied are kept for later candidates.
+	pub invalid: Vec<usize>,
+}
+
+/// Parachain context producing candidates with the transactions of a pool.
+pub trait TransactionalContext: Clone {
+	/// Produce a candidate, given the latest ingress queue information, the last parachain
+	/// head and the pooled transactions.
+	fn produce_candidate<I: IntoIterator<Item=(ParaId, Message)>>(
+		&self,
+		last_head: HeadData,
+		ingress: I,
+		transactions: &[Vec<u8>],
+	) -> Result<PooledCandidate, InvalidHead>;
+}
+
+/// A `ParachainContext` passing the transactions of a pool to a `TransactionalContext`.
+#[derive(Clone)]
+pub struct Pooled<P> {
+	context: P,
+	pool: TransactionPool,
+}
+
+impl<P> Pooled<P> {
+	/// Produce candidates of the given context with the transactions of the given pool.
+	pub fn new(context: P, pool: TransactionPool) -> Self {
+		Pooled { context, pool }
+	}
+}
+
+impl<P: TransactionalContext> ParachainContext for Pooled<P> {
+	fn produce_candidate<I: IntoIterator<Item=(ParaId, Message)>>(
+		&self,
+		last_head: HeadData,
+		ingress: I,
+	) -> Result<(BlockData, HeadData), InvalidHead> {
+		let transactions = self.pool.ready(&last_head);
+		let candidate = self.context.produce_candidate(last_head.clone(), ingress, &transactions)?;
+
+		self.pool.drop_transactions(candidate.invalid.iter().filter_map(|&i| transactions.get(i)));
+		self.pool.note_candidate(
+			last_head,
+			candidate.head_data.clone(),
+			candidate.applied.iter().filter_map(|&i| transactions.get(i)),
+		);
+
+		Ok((candidate.block_data, candidate.head_data))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// applies transactions starting with a non-zero byte, and rejects the others.
+	#[derive(Clone)]
+	struct Counter;
+
+	impl TransactionalContext for Counter {
+		fn produce_candidate<I: IntoIterator<Item=(ParaId, Message)>>(
+			&self,
+			last_head: HeadData,
+			_ingress: I,
+			transactions: &[Vec<u8>],
+		) -> Result<PooledCandidate, InvalidHead> {
+			let (applied, invalid): (Vec<usize>, Vec<usize>) = (0..transactions.len())
+				.partition(|&i| transactions[i][0] != 0);
+			let block_data = applied.iter().map(|&i| transactions[i].clone()).collect::<Vec<_>>().concat();
+
+			Ok(PooledCandidate {
+				block_data: BlockData(block_data),
+				head_data: HeadData(vec![last_head.0[0] + applied.len() as u8]),
+				applied,
+				invalid,
+			})
+		}
+	}
+
+	#[test]
+	fn rejects_known_transactions_and_overflow() {
+		let pool = TransactionPool::new(2, 16);
+
+		let hash = pool.submit(vec![1]).unwrap();
+		assert_eq!(pool.submit(vec![1]), Err(PoolError::AlreadyKnown(hash)));
+		pool.submit(vec![2]).unwrap();
+		assert_eq!(pool.submit(vec![3]), Err(PoolError::Full));
+		assert_eq!(pool.submit(vec![4; 17]), Err(PoolError::TooLarge(17)));
+		assert_eq!(pool.len(), 2);
+	}
+
+	#[test]
+	fn drops_transactions_once_built_upon() {
+		let pool = TransactionPool::new(16, 16);
+		let context = Pooled::new(Counter, pool.clone());
+		let genesis = HeadData(vec![0]);
+
+		pool.submit(vec![1]).unwrap();
+		pool.submit(vec![2]).unwrap();
+
+		// the candidate isn't included: both transactions are kept for the next.
+		let (block_data, head) = context.produce_candidate(genesis.clone(), Vec::new()).unwrap();
+		assert_eq!(block_data, BlockData(vec![1, 2]));
+		assert_eq!(head, HeadData(vec![2]));
+		assert_eq!(pool.ready(&genesis).len(), 2);
+
+		pool.submit(vec![3]).unwrap();
+
+		// building on the head produced with the first two drops them.
+		let (block_data, head) = context.produce_candidate(head, Vec::new()).unwrap();
+		assert_eq!(block_data, BlockData(vec![3]));
+		assert_eq!(head, HeadData(vec![3]));
+		assert_eq!(pool.len(), 1);
+	}
+
+	#[test]
+	fn drops_transactions_of_included_ancestors() {
+		let pool = TransactionPool::new(16, 16);
+		let genesis = HeadData(vec![0]);
+		let first = HeadData(vec![1]);
+		let second = HeadData(vec![2]);
+
+		pool.submit(vec![1]).unwrap();
+		pool.submit(vec![2]).unwrap();
+		pool.submit(vec![3]).unwrap();
+
+		pool.note_candidate(genesis.clone(), first.clone(), &[vec![1]]);
+		pool.note_candidate(first, second.clone(), &[vec![2]]);
+
+		// an unknown head keeps all transactions.
+		assert_eq!(pool.ready(&HeadData(vec![9])).len(), 3);
+
+		// both candidates were included, even if the first head was never built upon.
+		assert_eq!(pool.ready(&second), vec![vec![3]]);
+		assert_eq!(pool.len(), 1);
+	}
+
+	#[test]
+	fn drops_invalid_and_keeps_unapplied_transactions() {
+		let pool = TransactionPool::new(16, 4);
+		let context = Pooled::new(Counter, pool.clone());
+		let genesis = HeadData(vec![0]);
+
+		pool.submit(vec![1, 1, 1]).unwrap();
+		pool.submit(vec![0]).unwrap();
+		pool.submit(vec![2, 2]).unwrap();
+
+		// the last doesn't fit after the others, and the invalid one is dropped.
+		let (block_data, head) = context.produce_candidate(genesis, Vec::new()).unwrap();
+		assert_eq!(block_data, BlockData(vec![1, 1, 1]));
+		assert_eq!(pool.len(), 2);
+
+		let (block_data, _) = context.produce_candidate(head, Vec::new()).unwrap();
+		assert_eq!(block_data, BlockData(vec![2, 2]));
+	}
+}
@@ -0,0 +1,110 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Capabilities of the polkadot protocol negotiated with each peer.
+//!
+//! Nodes advertise a bitfield of the protocol features they support in their status.
+//! A feature is only used with a peer when both sides advertise it, so that features
+//! can be rolled out incrementally across a live validator set.
+
+/// A set of polkadot protocol features.
+///
+/// Bits which are unknown to this node are kept when decoding, but never negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Encode, Decode)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+	/// Block data can be fetched directly from the peer by candidate hash.
+	pub const DIRECT_POV_FETCH: Capabilities = Capabilities(1 << 0);
+	// bits 1 to 3 were advertised by earlier versions without ever being used, and
+	// are not to be reused.
+	/// Block data of candidates can be fetched from the collator which produced them,
+	/// over the connection the collator opened. This lets collators without public
+	/// addresses serve the block data of their candidates.
+	pub const COLLATOR_POV_FETCH: Capabilities = Capabilities(1 << 4);
+	/// Statements can be pushed to the peer directly, alongside gossip.
+	pub const STATEMENT_PUSH: Capabilities = Capabilities(1 << 5);
+	/// Payloads can be sent in the versioned wire format. Peers without it are sent the
+	/// legacy unversioned encoding.
+	pub const VERSIONED_WIRE: Capabilities = Capabilities(1 << 6);
+	/// Summaries of the candidates and statements known on a relay parent can be
+	/// requested from the peer, to catch up on attestation state.
+	pub const KNOWLEDGE_REQUESTS: Capabilities = Capabilities(1 << 7);
+	/// Validators announce keys collations may be encrypted to, and collators send
+	/// them collations encrypted to those keys.
+	pub const COLLATION_ENCRYPTION: Capabilities = Capabilities(1 << 8);
+
+	/// The capabilities of peers which predate negotiation.
+	pub const LEGACY: Capabilities = Capabilities::DIRECT_POV_FETCH;
+
+	/// No capabilities.
+	pub fn empty() -> Self {
+		Capabilities(0)
+	}
+
+	/// The capabilities implemented by this node.
+	pub fn supported() -> Self {
+		Capabilities::DIRECT_POV_FETCH
+			.union(Capabilities::COLLATOR_POV_FETCH)
+			.union(Capabilities::STATEMENT_PUSH)
+			.union(Capabilities::VERSIONED_WIRE)
+			.union(Capabilities::KNOWLEDGE_REQUESTS)
+			.union(Capabilities::COLLATION_ENCRYPTION)
+	}
+
+	/// The raw bitfield.
+	pub fn bits(&self) -> u32 {
+		self.0
+	}
+
+	/// Whether all capabilities in `other` are contained in this set.
+	pub fn contains(&self, other: Capabilities) -> bool {
+		self.0 & other.0 == other.0
+	}
+
+	/// The capabilities in both sets.
+	pub fn intersection(&self, other: Capabilities) -> Self {
+		Capabilities(self.0 & other.0)
+	}
+
+	/// The capabilities in either set.
+	pub fn union(&self, other: Capabilities) -> Self {
+		Capabilities(self.0 | other.0)
+	}
+
+	/// This set without the capabilities in `other`.
+	pub fn difference(&self, other: Capabilities) -> Self {
+		Capabilities(self.0 & !other.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn negotiates_common_capabilities() {
+		let local = Capabilities::DIRECT_POV_FETCH.union(Capabilities::STATEMENT_PUSH);
+		let remote = Capabilities::STATEMENT_PUSH.union(Capabilities(1 << 31));
+
+		let negotiated = local.intersection(remote);
+		assert_eq!(negotiated, Capabilities::STATEMENT_PUSH);
+		assert!(!negotiated.contains(Capabilities::DIRECT_POV_FETCH));
+		assert!(local.contains(negotiated));
+		assert_eq!(local.difference(negotiated), Capabilities::DIRECT_POV_FETCH);
+		assert!(Capabilities::empty().intersection(remote).contains(Capabilities::empty()));
+	}
+}
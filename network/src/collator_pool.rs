@@ -17,15 +17,37 @@
 //! Bridge between the network and consensus service for getting collations to it.
 
 use polkadot_primitives::{AccountId, Hash};
-use polkadot_primitives::parachain::{Id as ParaId, Collation};
+use polkadot_primitives::parachain::{Id as ParaId, Collation, CollatorId};
 
 use futures::sync::oneshot;
 
+use std::collections::VecDeque;
 use std::collections::hash_map::{HashMap, Entry};
 use std::time::{Duration, Instant};
 
 const COLLATION_LIFETIME: Duration = Duration::from_secs(60 * 5);
 
+// window over which collations from a single collator are rate-limited.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The maximum number of collations accepted from a single collator within a minute.
+pub const MAX_COLLATIONS_PER_WINDOW: usize = 20;
+
+/// The maximum number of collations buffered for a parachain across relay parents,
+/// waiting to be fetched for validation.
+pub const MAX_PENDING_PER_PARACHAIN: usize = 8;
+
+// misbehavior cost of a collation over the rate limit.
+const RATE_LIMIT_COST: u32 = 10;
+// misbehavior cost of a collation which fails to decode.
+const UNDECODABLE_COST: u32 = 25;
+// misbehavior cost of a collation which fails validation.
+const INVALID_COST: u32 = 50;
+// misbehavior at which a collator is disconnected.
+const MAX_MISBEHAVIOR: u32 = 100;
+// misbehavior forgiven on every peer maintenance.
+const MISBEHAVIOR_DECAY: u32 = 1;
+
 /// The role of the collator. Whether they're the primary or backup for this parachain.
 #[derive(PartialEq, Debug, Clone, Copy, Encode, Decode)]
 pub enum Role {
@@ -72,6 +94,13 @@ enum SlotEntries {
 }
 
 impl SlotEntries {
+	fn pending_len(&self) -> usize {
+		match *self {
+			SlotEntries::Pending(ref cs) => cs.len(),
+			_ => 0,
+		}
+	}
+
 	fn received_collation(&mut self, collation: Collation) {
 		*self = match ::std::mem::replace(self, SlotEntries::Blank) {
 			SlotEntries::Blank => SlotEntries::Pending(vec![collation]),
@@ -89,6 +118,21 @@ impl SlotEntries {
 		};
 	}
 
+	// keep only pending collations from collators matching the predicate.
+	fn retain_collators<F: Fn(&CollatorId) -> bool>(&mut self, pred: F) {
+		let now_blank = match *self {
+			SlotEntries::Pending(ref mut cs) => {
+				cs.retain(|c| pred(&c.receipt.collator));
+				cs.is_empty()
+			}
+			_ => false,
+		};
+
+		if now_blank {
+			*self = SlotEntries::Blank;
+		}
+	}
+
 	fn await_with(&mut self, sender: oneshot::Sender<Collation>) {
 		*self = match ::std::mem::replace(self, SlotEntries::Blank) {
 			SlotEntries::Blank => SlotEntries::Awaiting(vec![sender]),
@@ -110,6 +154,36 @@ impl SlotEntries {
 	}
 }
 
+// recent activity of a collator, for rate-limiting.
+#[derive(Default)]
+struct CollatorActivity {
+	recent: VecDeque<Instant>,
+	misbehavior: u32,
+}
+
+impl CollatorActivity {
+	// note misbehavior of the given cost. returns `true` once it is enough to disconnect.
+	fn misbehaved(&mut self, cost: u32) -> bool {
+		self.misbehavior = self.misbehavior.saturating_add(cost);
+		self.misbehavior >= MAX_MISBEHAVIOR
+	}
+
+	// note a collation received at the given time. returns `false` if over the rate limit.
+	fn note_collation(&mut self, now: Instant) -> bool {
+		while self.recent.front().map_or(false, |t| *t + RATE_LIMIT_WINDOW <= now) {
+			self.recent.pop_front();
+		}
+
+		if self.recent.len() >= MAX_COLLATIONS_PER_WINDOW {
+			self.misbehaved(RATE_LIMIT_COST);
+			false
+		} else {
+			self.recent.push_back(now);
+			true
+		}
+	}
+}
+
 struct ParachainCollators {
 	primary: AccountId,
 	backup: Vec<AccountId>,
@@ -118,8 +192,26 @@ struct ParachainCollators {
 /// Manages connected collators and role assignments from the perspective of a validator.
 pub struct CollatorPool {
 	collators: HashMap<AccountId, ParaId>,
+	activity: HashMap<AccountId, CollatorActivity>,
 	parachain_collators: HashMap<ParaId, ParachainCollators>,
 	collations: HashMap<(Hash, ParaId), CollationSlot>,
+	// number of collations pending in `collations`, for each parachain.
+	pending: HashMap<ParaId, usize>,
+	// the collators permitted for permissioned parachains, as last fetched from the runtime.
+	permitted: HashMap<ParaId, Vec<CollatorId>>,
+}
+
+// account for the change in pending collations of a parachain's slot.
+fn note_pending(pending: &mut HashMap<ParaId, usize>, para_id: ParaId, before: usize, after: usize) {
+	let count = {
+		let count = pending.entry(para_id).or_insert(0);
+		*count = (*count + after).saturating_sub(before);
+		*count
+	};
+
+	if count == 0 {
+		pending.remove(&para_id);
+	}
 }
 
 impl CollatorPool {
@@ -127,14 +219,18 @@ impl CollatorPool {
 	pub fn new() -> Self {
 		CollatorPool {
 			collators: HashMap::new(),
+			activity: HashMap::new(),
 			parachain_collators: HashMap::new(),
 			collations: HashMap::new(),
+			pending: HashMap::new(),
+			permitted: HashMap::new(),
 		}
 	}
 
 	/// Call when a new collator is authenticated. Returns the role.
 	pub fn on_new_collator(&mut self, account_id: AccountId, para_id: ParaId) -> Role {
 		self.collators.insert(account_id.clone(), para_id);
+		self.activity.insert(account_id.clone(), CollatorActivity::default());
 		match self.parachain_collators.entry(para_id) {
 			Entry::Vacant(vacant) => {
 				vacant.insert(ParachainCollators {
@@ -155,6 +251,7 @@ impl CollatorPool {
 	/// Called when a collator disconnects. If it was the primary, returns a new primary for that
 	/// parachain.
 	pub fn on_disconnect(&mut self, account_id: AccountId) -> Option<AccountId> {
+		self.activity.remove(&account_id);
 		self.collators.remove(&account_id).and_then(|para_id| match self.parachain_collators.entry(para_id) {
 			Entry::Vacant(_) => None,
 			Entry::Occupied(mut occ) => {
@@ -181,30 +278,130 @@ impl CollatorPool {
 	/// Called when a collation is received.
 	/// The collator should be registered for the parachain of the collation as a precondition of this function.
 	/// The collation should have been checked for integrity of signature before passing to this function.
-	pub fn on_collation(&mut self, account_id: AccountId, relay_parent: Hash, collation: Collation) {
-		if let Some(para_id) = self.collators.get(&account_id) {
-			debug_assert_eq!(para_id, &collation.receipt.parachain_index);
+	///
+	/// Collations over the collator's rate limit, or for a parachain which already has
+	/// too many collations waiting to be fetched, are dropped. Going over the rate limit
+	/// counts as misbehavior, and this returns an action to disconnect the collator
+	/// once it has misbehaved too much.
+	pub fn on_collation(&mut self, account_id: AccountId, relay_parent: Hash, collation: Collation) -> Option<Action> {
+		let para_id = match self.collators.get(&account_id) {
+			Some(para_id) => *para_id,
+			None => return None,
+		};
+
+		debug_assert_eq!(para_id, collation.receipt.parachain_index);
+
+		if !self.is_permitted(&para_id, &account_id) {
+			debug!(target: "p_net", "Dropping collation from {:?}, not permitted for parachain {:?}",
+				account_id, para_id);
 
-			// TODO: punish if not primary?
+			return self.misbehaved(account_id, INVALID_COST);
+		}
+
+		// TODO: punish if not primary?
 
-			self.collations.entry((relay_parent, para_id.clone()))
-				.or_insert_with(CollationSlot::blank_now)
-				.entries
-				.received_collation(collation);
+		let activity = self.activity.entry(account_id.clone()).or_insert_with(Default::default);
+		if !activity.note_collation(Instant::now()) {
+			debug!(target: "p_net", "Dropping collation from {:?} over the rate limit", account_id);
+
+			return if activity.misbehavior >= MAX_MISBEHAVIOR {
+				Some(Action::Disconnect(account_id))
+			} else {
+				None
+			};
+		}
+
+		let awaited = self.collations.get(&(relay_parent, para_id)).map_or(false, |slot| match slot.entries {
+			SlotEntries::Awaiting(_) => true,
+			_ => false,
+		});
+
+		let pending_for_para = self.pending.get(&para_id).cloned().unwrap_or(0);
+		if !awaited && pending_for_para >= MAX_PENDING_PER_PARACHAIN {
+			debug!(target: "p_net", "Dropping collation for parachain {:?}: too many awaiting fetch", para_id);
+			return None;
+		}
+
+		let slot = self.collations.entry((relay_parent, para_id))
+			.or_insert_with(CollationSlot::blank_now);
+
+		let before = slot.entries.pending_len();
+		slot.entries.received_collation(collation);
+		note_pending(&mut self.pending, para_id, before, slot.entries.pending_len());
+
+		None
+	}
+
+	/// Set the collators permitted to collate for a parachain, or permit anyone if `None`.
+	/// Pending collations from collators no longer permitted are dropped.
+	pub fn set_permitted_collators(&mut self, para_id: ParaId, permitted: Option<Vec<CollatorId>>) {
+		let permitted = match permitted {
+			Some(permitted) => permitted,
+			None => {
+				self.permitted.remove(&para_id);
+				return;
+			}
+		};
+
+		let pending = &mut self.pending;
+		for (&(_, ref id), slot) in self.collations.iter_mut() {
+			if id != &para_id { continue }
+
+			let before = slot.entries.pending_len();
+			slot.entries.retain_collators(|collator| permitted.contains(collator));
+			note_pending(pending, para_id, before, slot.entries.pending_len());
+		}
+
+		self.permitted.insert(para_id, permitted);
+	}
+
+	// whether a collator may collate for a parachain.
+	fn is_permitted(&self, para_id: &ParaId, collator: &CollatorId) -> bool {
+		self.permitted.get(para_id).map_or(true, |permitted| permitted.contains(collator))
+	}
+
+	/// Called when a collation from the collator failed to decode.
+	/// Returns an action to disconnect the collator once it has misbehaved too much.
+	pub fn on_undecodable_collation(&mut self, account_id: AccountId) -> Option<Action> {
+		self.misbehaved(account_id, UNDECODABLE_COST)
+	}
+
+	/// Called when a collation from the collator failed validation.
+	/// Returns an action to disconnect the collator once it has misbehaved too much.
+	pub fn on_invalid_collation(&mut self, account_id: AccountId) -> Option<Action> {
+		self.misbehaved(account_id, INVALID_COST)
+	}
+
+	fn misbehaved(&mut self, account_id: AccountId, cost: u32) -> Option<Action> {
+		let disconnect = match self.activity.get_mut(&account_id) {
+			Some(activity) => activity.misbehaved(cost),
+			None => false,
+		};
+
+		if disconnect {
+			Some(Action::Disconnect(account_id))
+		} else {
+			None
 		}
 	}
 
 	/// Wait for a collation from a parachain.
 	pub fn await_collation(&mut self, relay_parent: Hash, para_id: ParaId, sender: oneshot::Sender<Collation>) {
-		self.collations.entry((relay_parent, para_id))
-			.or_insert_with(CollationSlot::blank_now)
-			.entries
-			.await_with(sender);
+		let slot = self.collations.entry((relay_parent, para_id))
+			.or_insert_with(CollationSlot::blank_now);
+
+		let before = slot.entries.pending_len();
+		slot.entries.await_with(sender);
+		note_pending(&mut self.pending, para_id, before, slot.entries.pending_len());
 	}
 
 	/// Call periodically to perform collator set maintenance.
 	/// Returns a set of actions to perform on the network level.
 	pub fn maintain_peers(&mut self) -> Vec<Action> {
+		for activity in self.activity.values_mut() {
+			activity.misbehavior = activity.misbehavior.saturating_sub(MISBEHAVIOR_DECAY);
+		}
+
 		// TODO: rearrange periodically to new primary, evaluate based on latency etc.
 		Vec::new()
 	}
@@ -212,7 +409,15 @@ impl CollatorPool {
 	/// called when a block with given hash has been imported.
 	pub fn collect_garbage(&mut self, chain_head: Option<&Hash>) {
 		let now = Instant::now();
-		self.collations.retain(|&(ref h, _), slot| chain_head != Some(h) && slot.stay_alive(now));
+		let pending = &mut self.pending;
+		self.collations.retain(|&(ref h, ref para_id), slot| {
+			let keep = chain_head != Some(h) && slot.stay_alive(now);
+			if !keep {
+				note_pending(pending, *para_id, slot.entries.pending_len(), 0);
+			}
+
+			keep
+		});
 	}
 }
 
@@ -271,6 +476,7 @@ mod tests {
 				egress_queue_roots: vec![],
 				fees: 0,
 				block_data_hash: [3; 32].into(),
+				ingress_watermark: 0,
 			},
 			block_data: BlockData(vec![4, 5, 6]),
 		});
@@ -298,6 +504,7 @@ mod tests {
 				egress_queue_roots: vec![],
 				fees: 0,
 				block_data_hash: [3; 32].into(),
+				ingress_watermark: 0,
 			},
 			block_data: BlockData(vec![4, 5, 6]),
 		});
@@ -317,4 +524,138 @@ mod tests {
 		assert!(!slot.stay_alive(now + COLLATION_LIFETIME));
 		assert!(!slot.stay_alive(now + COLLATION_LIFETIME + Duration::from_secs(10)));
 	}
+
+	fn collation(para_id: ParaId, collator: AccountId, n: u8) -> Collation {
+		Collation {
+			receipt: CandidateReceipt {
+				parachain_index: para_id,
+				collator,
+				signature: H512::from([2; 64]).into(),
+				head_data: HeadData(vec![n]),
+				balance_uploads: vec![],
+				egress_queue_roots: vec![],
+				fees: 0,
+				block_data_hash: [3; 32].into(),
+				ingress_watermark: 0,
+			},
+			block_data: BlockData(vec![n]),
+		}
+	}
+
+	#[test]
+	fn rate_limited_collator_eventually_disconnected() {
+		let mut pool = CollatorPool::new();
+		let para_id: ParaId = 5.into();
+		let primary = [0; 32].into();
+
+		pool.on_new_collator(primary, para_id);
+
+		let mut actions = Vec::new();
+		for i in 0..MAX_COLLATIONS_PER_WINDOW + (MAX_MISBEHAVIOR / RATE_LIMIT_COST) as usize {
+			let relay_parent = [i as u8; 32].into();
+			actions.extend(pool.on_collation(primary, relay_parent, collation(para_id, primary, i as u8)));
+		}
+
+		assert_eq!(actions, vec![Action::Disconnect(primary)]);
+	}
+
+	#[test]
+	fn pending_collations_capped_per_parachain() {
+		let mut pool = CollatorPool::new();
+		let para_id: ParaId = 5.into();
+		let primary = [0; 32].into();
+		let relay_parent = [1; 32].into();
+
+		pool.on_new_collator(primary, para_id);
+		for i in 0..MAX_PENDING_PER_PARACHAIN + 2 {
+			assert!(pool.on_collation(primary, relay_parent, collation(para_id, primary, i as u8)).is_none());
+		}
+
+		match pool.collations.get(&(relay_parent, para_id)).unwrap().entries {
+			SlotEntries::Pending(ref cs) => assert_eq!(cs.len(), MAX_PENDING_PER_PARACHAIN),
+			_ => panic!("collations should be pending"),
+		}
+		assert_eq!(pool.pending.get(&para_id), Some(&MAX_PENDING_PER_PARACHAIN));
+
+		// dropped collations don't leave slots behind.
+		let dropped_parent = [3; 32].into();
+		assert!(pool.on_collation(primary, dropped_parent, collation(para_id, primary, 99)).is_none());
+		assert!(!pool.collations.contains_key(&(dropped_parent, para_id)));
+
+		// collations are still delivered to those awaiting them.
+		let other_parent = [2; 32].into();
+		let (tx, rx) = oneshot::channel();
+		pool.await_collation(other_parent, para_id, tx);
+		assert!(pool.on_collation(primary, other_parent, collation(para_id, primary, 100)).is_none());
+		rx.wait().unwrap();
+	}
+
+	#[test]
+	fn pending_count_follows_fetches_and_garbage_collection() {
+		let mut pool = CollatorPool::new();
+		let para_id: ParaId = 5.into();
+		let primary = [0; 32].into();
+		let (parent_a, parent_b) = ([1; 32].into(), [2; 32].into());
+
+		pool.on_new_collator(primary, para_id);
+		pool.on_collation(primary, parent_a, collation(para_id, primary, 1));
+		pool.on_collation(primary, parent_a, collation(para_id, primary, 2));
+		pool.on_collation(primary, parent_b, collation(para_id, primary, 3));
+		assert_eq!(pool.pending.get(&para_id), Some(&3));
+
+		let (tx, rx) = oneshot::channel();
+		pool.await_collation(parent_a, para_id, tx);
+		rx.wait().unwrap();
+		assert_eq!(pool.pending.get(&para_id), Some(&2));
+
+		pool.collect_garbage(Some(&parent_a));
+		assert_eq!(pool.pending.get(&para_id), Some(&1));
+
+		pool.collect_garbage(Some(&parent_b));
+		assert!(pool.pending.is_empty());
+	}
+
+	#[test]
+	fn bad_collations_eventually_disconnect() {
+		let mut pool = CollatorPool::new();
+		let para_id: ParaId = 5.into();
+		let primary = [0; 32].into();
+
+		pool.on_new_collator(primary, para_id);
+		assert_eq!(pool.on_undecodable_collation(primary), None);
+		assert_eq!(pool.on_invalid_collation(primary), None);
+		assert_eq!(pool.on_invalid_collation(primary), Some(Action::Disconnect(primary)));
+
+		let unknown = [1; 32].into();
+		assert_eq!(pool.on_invalid_collation(unknown), None);
+	}
+
+	#[test]
+	fn only_permitted_collators_accepted() {
+		let mut pool = CollatorPool::new();
+		let para_id: ParaId = 5.into();
+		let (permitted, other) = ([0; 32].into(), [1; 32].into());
+		let relay_parent = [1; 32].into();
+
+		pool.on_new_collator(permitted, para_id);
+		pool.on_new_collator(other, para_id);
+		pool.on_collation(permitted, relay_parent, collation(para_id, permitted, 1));
+		pool.on_collation(other, relay_parent, collation(para_id, other, 2));
+		assert_eq!(pool.pending.get(&para_id), Some(&2));
+
+		// pending collations from collators no longer permitted are dropped.
+		pool.set_permitted_collators(para_id, Some(vec![permitted]));
+		assert_eq!(pool.pending.get(&para_id), Some(&1));
+
+		assert_eq!(pool.on_collation(other, relay_parent, collation(para_id, other, 3)), None);
+		assert_eq!(pool.pending.get(&para_id), Some(&1));
+
+		let (tx, rx) = oneshot::channel();
+		pool.await_collation(relay_parent, para_id, tx);
+		assert_eq!(rx.wait().unwrap().receipt.collator, permitted);
+
+		pool.set_permitted_collators(para_id, None);
+		pool.on_collation(other, relay_parent, collation(para_id, other, 4));
+		assert_eq!(pool.pending.get(&para_id), Some(&1));
+	}
 }
@@ -0,0 +1,149 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tuning of the propagation of polkadot gossip.
+//!
+//! In addition to substrate's gossip, statements worth circulating are pushed directly
+//! to a random subset of the connected validators. How many validators, how often and
+//! how many times each statement is pushed is tuned to the size of the validator set,
+//! unless configured explicitly.
+
+use sr_primitives::traits::{BlakeTwo256, Hash as HashT};
+use substrate_network::NodeIndex;
+use polkadot_primitives::Hash;
+
+use std::time::Duration;
+
+// fewest validators statements are pushed to, where that many are connected.
+const MIN_FAN_OUT: usize = 4;
+
+// interval between pushes for the smallest validator sets, in milliseconds.
+const BASE_PROPAGATION_INTERVAL_MILLIS: u64 = 500;
+
+/// Configuration of the propagation of statements on attestation topics.
+///
+/// Parameters left at `None` are derived from the size of the validator set
+/// of each validation session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GossipConfig {
+	/// Number of validators each statement is pushed to in a round.
+	/// Set to `Some(0)` to only rely on substrate's gossip.
+	pub fan_out: Option<usize>,
+	/// Interval between rounds of pushes.
+	pub propagation_interval: Option<Duration>,
+	/// Number of rounds each statement is pushed in, to a different random subset
+	/// of validators each time.
+	pub duplicate_factor: Option<usize>,
+}
+
+/// Gossip parameters in effect for a validation session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GossipParams {
+	/// Number of validators each statement is pushed to in a round.
+	pub fan_out: usize,
+	/// Interval between rounds of pushes.
+	pub propagation_interval: Duration,
+	/// Number of rounds each statement is pushed in.
+	pub duplicate_factor: usize,
+}
+
+impl GossipConfig {
+	/// The parameters for a validator set of the given size.
+	///
+	/// Statements reach all validators in a logarithmic number of hops when pushed to the
+	/// square root of the set. Larger sets push less often, and more times, to keep the
+	/// traffic per node bounded while making up for lost messages.
+	pub fn params(&self, validators: usize) -> GossipParams {
+		let others = validators.saturating_sub(1);
+		let hops = log2_ceil(validators);
+
+		let fan_out = self.fan_out.unwrap_or_else(|| {
+			let sqrt = (validators as f64).sqrt().ceil() as usize;
+			::std::cmp::max(sqrt, MIN_FAN_OUT)
+		});
+
+		let propagation_interval = self.propagation_interval.unwrap_or_else(|| {
+			Duration::from_millis(BASE_PROPAGATION_INTERVAL_MILLIS * ::std::cmp::max(hops, 1) as u64)
+		});
+
+		let duplicate_factor = self.duplicate_factor.unwrap_or_else(|| 1 + hops / 4);
+
+		GossipParams {
+			fan_out: ::std::cmp::min(fan_out, others),
+			propagation_interval,
+			duplicate_factor,
+		}
+	}
+}
+
+fn log2_ceil(n: usize) -> usize {
+	let mut hops = 0;
+	while (1usize << hops) < n {
+		hops += 1;
+	}
+	hops
+}
+
+/// Select up to `fan_out` of the given peers to push to in a round.
+///
+/// The selection is pseudo-random, seeded so that it differs between rounds and nodes.
+pub(crate) fn select_peers(mut peers: Vec<NodeIndex>, fan_out: usize, seed: &Hash, round: u64) -> Vec<NodeIndex> {
+	peers.sort_by_key(|peer| BlakeTwo256::hash_of(&(*seed, round, *peer as u64)));
+	peers.truncate(fan_out);
+	peers
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn params_adapt_to_validator_set_size() {
+		let config = GossipConfig::default();
+
+		let small = config.params(3);
+		assert_eq!(small.fan_out, 2);
+		assert_eq!(small.duplicate_factor, 1);
+
+		let large = config.params(1000);
+		assert_eq!(large.fan_out, 32);
+		assert_eq!(large.duplicate_factor, 3);
+		assert!(large.propagation_interval > small.propagation_interval);
+
+		let fixed = GossipConfig {
+			fan_out: Some(8),
+			propagation_interval: Some(Duration::from_secs(1)),
+			duplicate_factor: Some(1),
+		}.params(1000);
+		assert_eq!(fixed, GossipParams {
+			fan_out: 8,
+			propagation_interval: Duration::from_secs(1),
+			duplicate_factor: 1,
+		});
+	}
+
+	#[test]
+	fn selection_varies_by_round() {
+		let peers: Vec<NodeIndex> = (0..20).collect();
+		let seed = [1; 32].into();
+
+		let first = select_peers(peers.clone(), 5, &seed, 0);
+		assert_eq!(first.len(), 5);
+		assert_eq!(first, select_peers(peers.clone(), 5, &seed, 0));
+		assert!(first != select_peers(peers.clone(), 5, &seed, 1));
+		assert_eq!(select_peers(peers[..3].to_vec(), 5, &seed, 0).len(), 3);
+	}
+}
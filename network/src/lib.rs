@@ -25,6 +25,7 @@ extern crate substrate_primitives;
 extern crate sr_primitives;
 
 extern crate polkadot_validation;
+extern crate polkadot_statement_table;
 extern crate polkadot_availability_store as av_store;
 extern crate polkadot_primitives;
 
@@ -46,22 +47,40 @@ extern crate substrate_client;
 #[cfg(test)]
 extern crate substrate_keyring;
 
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+
+mod capabilities;
 mod collator_pool;
+mod gossip;
 mod local_collations;
+mod overflow;
 mod router;
+pub mod simulation;
 pub mod validation;
+pub mod wire;
 
-use codec::{Decode, Encode};
+use codec::{Decode, Encode, Input, Output};
 use futures::sync::oneshot;
 use polkadot_primitives::{AccountId, Block, SessionKey, Hash, Header};
-use polkadot_primitives::parachain::{Id as ParaId, BlockData, CandidateReceipt, Collation};
+use polkadot_primitives::parachain::{
+	Id as ParaId, BlockData, CandidateHash, CandidateReceipt, CandidateSignature, Collation,
+	CollatorId,
+};
+use polkadot_primitives::encryption::{self, EncryptedPayload, EncryptionKey, EncryptionPublic};
+use substrate_primitives::ed25519;
 use substrate_network::{NodeIndex, RequestId, Context, Severity};
 use substrate_network::{message, generic_message};
 use substrate_network::specialization::NetworkSpecialization as Specialization;
 use substrate_network::StatusMessage as GenericFullStatus;
-use self::validation::{LiveValidationSessions, RecentSessionKeys, InsertedRecentKey};
+use self::validation::{LiveValidationSessions, RecentSessionKeys, InsertedRecentKey, CandidateSummary};
 use self::collator_pool::{CollatorPool, Role, Action};
 use self::local_collations::LocalCollations;
+use self::wire::Versioned;
+
+pub use self::capabilities::Capabilities;
+pub use self::wire::GossipFormat;
 
 use std::collections::{HashMap, HashSet};
 
@@ -72,22 +91,46 @@ mod tests;
 /// Polkadot protocol id.
 pub const DOT_PROTOCOL_ID: ::substrate_network::ProtocolId = *b"dot";
 
+/// The number of validators asked for their knowledge when a validation session starts.
+pub const CATCH_UP_PEERS: usize = 3;
+
 type FullStatus = GenericFullStatus<Block>;
 
 /// Specialization of the network service for the polkadot protocol.
 pub type NetworkService = ::substrate_network::Service<Block, PolkadotProtocol>;
 
 /// Status of a Polkadot node.
-#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Status {
 	collating_for: Option<(AccountId, ParaId)>,
+	capabilities: Capabilities,
+}
+
+impl Encode for Status {
+	fn encode_to<W: Output>(&self, dest: &mut W) {
+		self.collating_for.encode_to(dest);
+		self.capabilities.encode_to(dest);
+	}
+}
+
+impl Decode for Status {
+	fn decode<I: Input>(input: &mut I) -> Option<Self> {
+		let collating_for = Decode::decode(input)?;
+
+		// the status of nodes predating capability negotiation ends here.
+		let capabilities = Capabilities::decode(input).unwrap_or(Capabilities::LEGACY);
+
+		Some(Status { collating_for, capabilities })
+	}
 }
 
 struct BlockDataRequest {
 	attempted_peers: HashSet<SessionKey>,
+	attempted_collator: bool,
 	validation_session_parent: Hash,
-	candidate_hash: Hash,
+	candidate_hash: CandidateHash,
 	block_data_hash: Hash,
+	collator: AccountId,
 	sender: oneshot::Sender<BlockData>,
 }
 
@@ -131,6 +174,8 @@ struct PeerInfo {
 	validator_keys: RecentSessionKeys,
 	claimed_validator: bool,
 	collator_state: CollatorState,
+	// capabilities supported by both the peer and the local node.
+	capabilities: Capabilities,
 }
 
 impl PeerInfo {
@@ -146,13 +191,83 @@ pub enum Message {
 	// TODO: do this with a cryptographic proof of some kind
 	SessionKey(SessionKey),
 	/// Requesting parachain block data by (relay_parent, candidate_hash).
-	RequestBlockData(RequestId, Hash, Hash),
+	RequestBlockData(RequestId, Hash, CandidateHash),
 	/// Provide block data by candidate hash or nothing if unknown.
 	BlockData(RequestId, Option<BlockData>),
 	/// Tell a collator their role.
 	CollatorRole(Role),
 	/// A collation provided by a peer. Relay parent and collation.
-	Collation(Hash, Collation),
+	Collation(Hash, Versioned<Collation>),
+	/// Requesting summaries of known candidates and statements by relay parent.
+	RequestKnowledge(RequestId, Hash),
+	/// Provide summaries of known candidates, or nothing if the relay parent is unknown
+	/// or the requester isn't a validator of its session.
+	Knowledge(RequestId, Option<Vec<CandidateSummary>>),
+	/// As a validator, announce the key collations may be encrypted to, signed
+	/// by the given session key.
+	EncryptionKey(SessionKey, EncryptionPublic, CandidateSignature),
+	/// A collation encrypted to the encryption key of the receiving validator.
+	/// Relay parent and encrypted versioned collation.
+	EncryptedCollation(Hash, EncryptedPayload),
+	/// Encoded statements on the attestation topic of a relay parent, pushed
+	/// directly between validators alongside gossip.
+	Statements(Hash, Vec<Vec<u8>>),
+}
+
+// tracks the encryption keys announced by validators, and whether collations
+// should be encrypted to them.
+struct CollationPrivacy {
+	enabled: bool,
+	keys: HashMap<SessionKey, EncryptionPublic>,
+}
+
+impl CollationPrivacy {
+	// the message to send a collation to the validator with given session key and
+	// negotiated capabilities. when private, this is `None` until the validator has
+	// announced an encryption key, and for validators which can't receive encrypted
+	// collations. it is also `None` when the validator's wire format can't represent
+	// the collation.
+	fn message(
+		&self,
+		key: &SessionKey,
+		capabilities: Capabilities,
+		relay_parent: Hash,
+		collation: Collation,
+	) -> Option<Message> {
+		let collation = Versioned::for_capabilities(collation, capabilities);
+		if !collation.is_decodable_by_peer() {
+			debug!(target: "p_net", "Withholding collation from {:?}, which only understands the legacy wire format", key);
+			return None;
+		}
+
+		if !self.enabled {
+			return Some(Message::Collation(relay_parent, collation));
+		}
+
+		if !capabilities.contains(Capabilities::COLLATION_ENCRYPTION) {
+			debug!(target: "p_net", "Withholding private collation from {:?}, which can't receive encrypted collations", key);
+			return None;
+		}
+
+		match self.keys.get(key) {
+			Some(public) => {
+				let payload = encryption::encrypt(public, &collation.encode());
+				Some(Message::EncryptedCollation(relay_parent, payload))
+			}
+			None => {
+				debug!(target: "p_net", "Withholding collation from {:?} until it announces an encryption key", key);
+				None
+			}
+		}
+	}
+}
+
+// the connected collator with given account ID, if block data can be fetched from it.
+fn collator_pov_peer(peers: &HashMap<NodeIndex, PeerInfo>, collator: &AccountId) -> Option<NodeIndex> {
+	peers.iter()
+		.filter(|&(_, ref info)| info.capabilities.contains(Capabilities::COLLATOR_POV_FETCH))
+		.find(|&(_, ref info)| info.collating_for.as_ref().map_or(false, |&(ref acc_id, _)| acc_id == collator))
+		.map(|(who, _)| *who)
 }
 
 fn send_polkadot_message(ctx: &mut Context<Block>, to: NodeIndex, message: Message) {
@@ -171,7 +286,12 @@ pub struct PolkadotProtocol {
 	live_validation_sessions: LiveValidationSessions,
 	in_flight: HashMap<(RequestId, NodeIndex), BlockDataRequest>,
 	pending: Vec<BlockDataRequest>,
+	knowledge_requests: HashMap<(RequestId, NodeIndex), oneshot::Sender<Option<Vec<CandidateSummary>>>>,
 	extrinsic_store: Option<::av_store::Store>,
+	encryption_key: Option<(SessionKey, EncryptionKey, CandidateSignature)>,
+	collation_privacy: CollationPrivacy,
+	capabilities: Capabilities,
+	gossip_format: GossipFormat,
 	next_req_id: u64,
 }
 
@@ -187,20 +307,82 @@ impl PolkadotProtocol {
 			live_validation_sessions: LiveValidationSessions::new(),
 			in_flight: HashMap::new(),
 			pending: Vec::new(),
+			knowledge_requests: HashMap::new(),
 			extrinsic_store: None,
+			encryption_key: None,
+			collation_privacy: CollationPrivacy { enabled: false, keys: HashMap::new() },
+			capabilities: Capabilities::supported(),
+			gossip_format: GossipFormat::new(),
 			next_req_id: 1,
 		}
 	}
 
+	/// Only send collations encrypted to the validators they are meant for,
+	/// so that their contents are not observed by others before they are backed.
+	pub fn with_private_collations(mut self, private: bool) -> Self {
+		self.collation_privacy.enabled = private;
+		self
+	}
+
+	/// Set the capabilities advertised to peers. Features are only used with a peer
+	/// when both sides advertise them.
+	pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+		self.capabilities = capabilities;
+		self
+	}
+
+	/// Share the view of connected peers' wire formats with the gossip of validation
+	/// sessions, so that gossip stays decodable by peers predating the versioned format.
+	pub fn with_gossip_format(mut self, gossip_format: GossipFormat) -> Self {
+		self.gossip_format = gossip_format;
+		self
+	}
+
+	/// The capabilities negotiated with a connected peer.
+	pub fn peer_capabilities(&self, who: NodeIndex) -> Option<Capabilities> {
+		self.peers.get(&who).map(|info| info.capabilities)
+	}
+
+	/// Set the key collations are encrypted to, along with the local validator key.
+	/// The encryption key is announced to peers signed by the session key.
+	pub fn set_encryption_key(&mut self, session_key: &ed25519::Pair, encryption_key: EncryptionKey) {
+		let signature = encryption_key.announce(session_key);
+		self.encryption_key = Some((session_key.public().into(), encryption_key, signature));
+	}
+
+	// announce the local encryption key to a peer with given negotiated capabilities,
+	// if it belongs to the given session key and the peer knows the announcement.
+	fn announce_encryption_key(
+		&self,
+		ctx: &mut Context<Block>,
+		who: NodeIndex,
+		capabilities: Capabilities,
+		session_key: &SessionKey,
+	) {
+		if !capabilities.contains(Capabilities::COLLATION_ENCRYPTION) { return }
+
+		if let Some((ref key, ref encryption_key, ref signature)) = self.encryption_key {
+			if key == session_key {
+				send_polkadot_message(
+					ctx,
+					who,
+					Message::EncryptionKey(*key, encryption_key.public(), signature.clone()),
+				);
+			}
+		}
+	}
+
 	/// Fetch block data by candidate receipt.
 	fn fetch_block_data(&mut self, ctx: &mut Context<Block>, candidate: &CandidateReceipt, relay_parent: Hash) -> oneshot::Receiver<BlockData> {
 		let (tx, rx) = oneshot::channel();
 
 		self.pending.push(BlockDataRequest {
 			attempted_peers: Default::default(),
+			attempted_collator: false,
 			validation_session_parent: relay_parent,
 			candidate_hash: candidate.hash(),
 			block_data_hash: candidate.block_data_hash,
+			collator: candidate.collator,
 			sender: tx,
 		});
 
@@ -208,6 +390,99 @@ impl PolkadotProtocol {
 		rx
 	}
 
+	/// Ask a validator for summaries of the candidates and statements it knows of
+	/// on the given relay parent. This is used to catch up on attestation state.
+	///
+	/// The receiver is canceled if the validator is not connected, can't be asked for
+	/// its knowledge, or disconnects before answering.
+	pub fn fetch_knowledge(
+		&mut self,
+		ctx: &mut Context<Block>,
+		validator: SessionKey,
+		relay_parent: Hash,
+	) -> oneshot::Receiver<Option<Vec<CandidateSummary>>> {
+		match self.validators.get(&validator).cloned() {
+			Some(who) => self.request_knowledge(ctx, who, relay_parent),
+			None => oneshot::channel().1,
+		}
+	}
+
+	/// Ask up to `CATCH_UP_PEERS` connected validators of the session at the given relay
+	/// parent for their knowledge on it, to catch up on statements issued before the
+	/// session started locally.
+	pub fn catch_up_knowledge(
+		&mut self,
+		ctx: &mut Context<Block>,
+		relay_parent: Hash,
+	) -> Vec<oneshot::Receiver<Option<Vec<CandidateSummary>>>> {
+		let peers: Vec<NodeIndex> = {
+			let sessions = &self.live_validation_sessions;
+			let connected = &self.peers;
+			self.validators.iter()
+				.filter(|&(key, _)| sessions.is_session_validator(&relay_parent, &[*key]) == Some(true))
+				.map(|(_, who)| *who)
+				.filter(|who| connected.get(who)
+					.map_or(false, |info| info.capabilities.contains(Capabilities::KNOWLEDGE_REQUESTS))
+				)
+				.collect()
+		};
+
+		gossip::select_peers(peers, CATCH_UP_PEERS, &relay_parent, 0)
+			.into_iter()
+			.map(|who| self.request_knowledge(ctx, who, relay_parent))
+			.collect()
+	}
+
+	fn request_knowledge(
+		&mut self,
+		ctx: &mut Context<Block>,
+		who: NodeIndex,
+		relay_parent: Hash,
+	) -> oneshot::Receiver<Option<Vec<CandidateSummary>>> {
+		let (tx, rx) = oneshot::channel();
+
+		// peers which don't know the message would take it for a malformed one.
+		let capable = self.peers.get(&who)
+			.map_or(false, |info| info.capabilities.contains(Capabilities::KNOWLEDGE_REQUESTS));
+		if !capable { return rx }
+
+		let req_id = self.next_req_id;
+		self.next_req_id += 1;
+
+		send_polkadot_message(ctx, who, Message::RequestKnowledge(req_id, relay_parent));
+		self.knowledge_requests.insert((req_id, who), tx);
+
+		rx
+	}
+
+	/// Push encoded statements on a relay parent to up to `fan_out` of the connected
+	/// validators capable of receiving them, selected at random for the given seed and round.
+	pub fn propagate_statements(
+		&mut self,
+		ctx: &mut Context<Block>,
+		relay_parent: Hash,
+		statements: Vec<Vec<u8>>,
+		fan_out: usize,
+		seed: &Hash,
+		round: u64,
+	) {
+		if statements.is_empty() { return }
+
+		// peers which don't know the message would take it for a malformed one, and
+		// the statements are encoded in the versioned format.
+		let required = Capabilities::STATEMENT_PUSH.union(Capabilities::VERSIONED_WIRE);
+		let peers = self.validators.values()
+			.cloned()
+			.filter(|who| self.peers.get(who)
+				.map_or(false, |info| info.capabilities.contains(required))
+			)
+			.collect();
+
+		for who in gossip::select_peers(peers, fan_out, seed, round) {
+			send_polkadot_message(ctx, who, Message::Statements(relay_parent, statements.clone()));
+		}
+	}
+
 	/// Note new validation session.
 	fn new_validation_session(
 		&mut self,
@@ -216,6 +491,7 @@ impl PolkadotProtocol {
 		session: validation::ValidationSession,
 	) {
 		if let Some(new_local) = self.live_validation_sessions.new_validation_session(parent_hash, session) {
+			let mut announce_to = Vec::new();
 			for (id, peer_data) in self.peers.iter_mut()
 				.filter(|&(_, ref info)| info.should_send_key())
 			{
@@ -224,6 +500,11 @@ impl PolkadotProtocol {
 					*id,
 					msg
 				));
+				announce_to.push((*id, peer_data.capabilities));
+			}
+
+			for (id, capabilities) in announce_to {
+				self.announce_encryption_key(ctx, id, capabilities, &new_local);
 			}
 		}
 	}
@@ -234,11 +515,15 @@ impl PolkadotProtocol {
 
 	fn dispatch_pending_requests(&mut self, ctx: &mut Context<Block>) {
 		let mut new_pending = Vec::new();
+		let peers = &self.peers;
 		let validator_keys = &mut self.validators;
 		let next_req_id = &mut self.next_req_id;
 		let in_flight = &mut self.in_flight;
 
 		for mut pending in ::std::mem::replace(&mut self.pending, Vec::new()) {
+			// the fetch was abandoned.
+			if pending.sender.is_canceled() { continue }
+
 			let parent = pending.validation_session_parent;
 			let c_hash = pending.candidate_hash;
 
@@ -251,9 +536,24 @@ impl PolkadotProtocol {
 				Err(Some(known_keys)) => {
 					let next_peer = known_keys.iter()
 						.filter_map(|x| validator_keys.get(x).map(|id| (*x, *id)))
+						.filter(|&(_, ref id)| peers.get(id)
+							.map_or(false, |info| info.capabilities.contains(Capabilities::DIRECT_POV_FETCH))
+						)
 						.find(|&(ref key, _)| pending.attempted_peers.insert(*key))
 						.map(|(_, id)| id);
 
+					// fall back to the collator of the candidate, which may be unreachable
+					// other than over the connection it opened.
+					let next_peer = match next_peer {
+						Some(who) => Some(who),
+						None if !pending.attempted_collator => {
+							let collator = collator_pov_peer(peers, &pending.collator);
+							pending.attempted_collator = collator.is_some();
+							collator
+						}
+						None => None,
+					};
+
 					// dispatch to peer
 					if let Some(who) = next_peer {
 						let req_id = *next_req_id;
@@ -296,13 +596,108 @@ impl PolkadotProtocol {
 					)
 					.or_else(|| self.extrinsic_store.as_ref()
 						.and_then(|s| s.block_data(relay_parent, candidate_hash))
-					);
+					)
+					.or_else(|| self.local_block_data(who, &relay_parent, &candidate_hash));
 
 				send_polkadot_message(ctx, who, Message::BlockData(req_id, block_data));
 			}
 			Message::BlockData(req_id, data) => self.on_block_data(ctx, who, req_id, data),
-			Message::Collation(relay_parent, collation) => self.on_collation(ctx, who, relay_parent, collation),
+			Message::Collation(relay_parent, collation) =>
+				self.on_collation(ctx, who, relay_parent, collation.into_inner()),
 			Message::CollatorRole(role) => self.on_new_role(ctx, who, role),
+			Message::RequestKnowledge(req_id, relay_parent) => {
+				// knowledge is only shared with validators of the session.
+				let validator_keys = self.peers.get(&who).map_or(&[][..], |info| info.validator_keys.as_slice());
+				let summaries = match self.live_validation_sessions.is_session_validator(&relay_parent, validator_keys) {
+					Some(true) => self.live_validation_sessions.knowledge_summaries(&relay_parent),
+					_ => None,
+				};
+
+				send_polkadot_message(ctx, who, Message::Knowledge(req_id, summaries));
+			}
+			Message::Knowledge(req_id, summaries) => match self.knowledge_requests.remove(&(req_id, who)) {
+				Some(sender) => { let _ = sender.send(summaries); }
+				None => ctx.report_peer(who, Severity::Bad("Unexpected knowledge response".to_string())),
+			},
+			Message::EncryptionKey(key, public, signature) =>
+				self.on_encryption_key(ctx, who, key, public, signature),
+			Message::EncryptedCollation(relay_parent, payload) => {
+				let collation = self.encryption_key.as_ref()
+					.and_then(|&(_, ref encryption_key, _)| encryption_key.decrypt(&payload))
+					.and_then(|plaintext| Versioned::<Collation>::decode(&mut &plaintext[..]));
+
+				let collator = self.peers.get(&who).and_then(|info| info.collating_for.map(|(acc_id, _)| acc_id));
+				match (collation, collator) {
+					(Some(collation), _) => self.on_collation(ctx, who, relay_parent, collation.into_inner()),
+					(None, Some(collator)) => {
+						debug!(target: "p_net", "Received undecryptable collation from {}", who);
+						if let Some(Action::Disconnect(collator)) = self.collators.on_undecodable_collation(collator) {
+							self.disconnect_bad_collator(ctx, collator);
+						}
+					}
+					(None, None) => ctx.report_peer(who, Severity::Bad("Sent undecryptable collation".to_string())),
+				}
+			}
+			Message::Statements(relay_parent, statements) => {
+				let validator_keys = self.peers.get(&who).map_or(&[][..], |info| info.validator_keys.as_slice());
+				match self.live_validation_sessions.is_session_validator(&relay_parent, validator_keys) {
+					Some(true) => self.live_validation_sessions.deliver_statements(&relay_parent, statements),
+					Some(false) => ctx.report_peer(
+						who,
+						Severity::Bad("Pushed statements without being a validator of the session".to_string()),
+					),
+					// the session may not have started locally yet. the statements are gossiped too.
+					None => trace!(target: "p_net", "Dropping statements pushed on unknown session {}", relay_parent),
+				}
+			}
+		}
+	}
+
+	// the block data of a local collation, for validators fetching it over the
+	// connection we opened. private collations are only served to validators.
+	fn local_block_data(&self, who: NodeIndex, relay_parent: &Hash, candidate_hash: &CandidateHash) -> Option<BlockData> {
+		if self.collation_privacy.enabled {
+			let from_validator = self.peers.get(&who)
+				.map_or(false, |info| !info.validator_keys.as_slice().is_empty());
+
+			if !from_validator { return None }
+		}
+
+		self.local_collations.collation(relay_parent)
+			.filter(|collation| &collation.receipt.hash() == candidate_hash)
+			.map(|collation| collation.block_data.clone())
+	}
+
+	// when a validator announces the key to encrypt collations to.
+	fn on_encryption_key(
+		&mut self,
+		ctx: &mut Context<Block>,
+		who: NodeIndex,
+		key: SessionKey,
+		public: EncryptionPublic,
+		signature: CandidateSignature,
+	) {
+		let (known_key, capabilities) = match self.peers.get(&who) {
+			Some(info) => (info.validator_keys.as_slice().contains(&key), info.capabilities),
+			None => {
+				trace!(target: "p_net", "Network inconsistency: message received from unconnected peer {}", who);
+				return
+			}
+		};
+
+		if !known_key || !encryption::check_announcement(&public, &signature, key) {
+			ctx.report_peer(who, Severity::Bad("Sent invalid encryption key announcement".to_string()));
+			return;
+		}
+
+		let is_new = self.collation_privacy.keys.insert(key, public) != Some(public);
+		if self.collation_privacy.enabled && is_new {
+			for (relay_parent, collation) in self.local_collations.pending_for(&key) {
+				debug!(target: "p_net", "Sending withheld collation on relay parent {:?}", relay_parent);
+				if let Some(message) = self.collation_privacy.message(&key, capabilities, relay_parent, collation) {
+					send_polkadot_message(ctx, who, message);
+				}
+			}
 		}
 	}
 
@@ -326,6 +721,7 @@ impl PolkadotProtocol {
 				InsertedRecentKey::AlreadyKnown => Vec::new(),
 				InsertedRecentKey::New(Some(old_key)) => {
 					self.validators.remove(&old_key);
+					self.collation_privacy.keys.remove(&old_key);
 					local_collations.fresh_key(&old_key, &key)
 				}
 				InsertedRecentKey::New(None) => info.collator_state.role()
@@ -334,11 +730,10 @@ impl PolkadotProtocol {
 			};
 
 			for (relay_parent, collation) in new_collations {
-				send_polkadot_message(
-					ctx,
-					who,
-					Message::Collation(relay_parent, collation),
-				)
+				let message = self.collation_privacy.message(&key, info.capabilities, relay_parent, collation);
+				if let Some(message) = message {
+					send_polkadot_message(ctx, who, message);
+				}
 			}
 
 			self.validators.insert(key, who);
@@ -384,18 +779,17 @@ impl PolkadotProtocol {
 		} else {
 			// update role for all saved session keys for this validator.
 			let local_collations = &mut self.local_collations;
-			for (relay_parent, collation) in info.validator_keys
+			let collation_privacy = &self.collation_privacy;
+			for (key, (relay_parent, collation)) in info.validator_keys
 				.as_slice()
 				.iter()
 				.cloned()
-				.flat_map(|k| local_collations.note_validator_role(k, role))
+				.flat_map(|k| local_collations.note_validator_role(k, role).into_iter().map(move |c| (k, c)))
 			{
 				debug!(target: "p_net", "Broadcasting collation on relay parent {:?}", relay_parent);
-				send_polkadot_message(
-					ctx,
-					who,
-					Message::Collation(relay_parent, collation),
-				)
+				if let Some(message) = collation_privacy.message(&key, info.capabilities, relay_parent, collation) {
+					send_polkadot_message(ctx, who, message);
+				}
 			}
 		}
 	}
@@ -403,14 +797,17 @@ impl PolkadotProtocol {
 
 impl Specialization<Block> for PolkadotProtocol {
 	fn status(&self) -> Vec<u8> {
-		Status { collating_for: self.collating_for.clone() }.encode()
+		Status {
+			collating_for: self.collating_for.clone(),
+			capabilities: self.capabilities,
+		}.encode()
 	}
 
 	fn on_connect(&mut self, ctx: &mut Context<Block>, who: NodeIndex, status: FullStatus) {
 		let local_status = match Status::decode(&mut &status.chain_status[..]) {
 			Some(status) => status,
 			None => {
-				Status { collating_for: None }
+				Status { collating_for: None, capabilities: Capabilities::LEGACY }
 			}
 		};
 
@@ -421,6 +818,7 @@ impl Specialization<Block> for PolkadotProtocol {
 			validator_keys: Default::default(),
 			claimed_validator: validator,
 			collator_state: CollatorState::Fresh,
+			capabilities: self.capabilities.intersection(local_status.capabilities),
 		};
 
 		if let Some((ref acc_id, ref para_id)) = local_status.collating_for {
@@ -446,15 +844,19 @@ impl Specialization<Block> for PolkadotProtocol {
 					who,
 					msg,
 				));
+				self.announce_encryption_key(ctx, who, peer_info.capabilities, local_session_key);
 			}
 		}
 
+		self.gossip_format.note_connected(peer_info.capabilities);
 		self.peers.insert(who, peer_info);
 		self.dispatch_pending_requests(ctx);
 	}
 
 	fn on_disconnect(&mut self, ctx: &mut Context<Block>, who: NodeIndex) {
 		if let Some(info) = self.peers.remove(&who) {
+			self.gossip_format.note_disconnected(info.capabilities);
+
 			if let Some((acc_id, _)) = info.collating_for {
 				let new_primary = self.collators.on_disconnect(acc_id)
 					.and_then(|new_primary| self.collator_peer(new_primary));
@@ -470,9 +872,13 @@ impl Specialization<Block> for PolkadotProtocol {
 
 			for key in info.validator_keys.as_slice().iter() {
 				self.validators.remove(key);
+				self.collation_privacy.keys.remove(key);
 				self.local_collations.on_disconnect(key);
 			}
 
+			// dropping the senders cancels outstanding knowledge requests.
+			self.knowledge_requests.retain(|&(_, ref peer), _| peer != &who);
+
 			{
 				let pending = &mut self.pending;
 				self.in_flight.retain(|&(_, ref peer), val| {
@@ -481,9 +887,11 @@ impl Specialization<Block> for PolkadotProtocol {
 						let (sender, _) = oneshot::channel();
 						pending.push(::std::mem::replace(val, BlockDataRequest {
 							attempted_peers: Default::default(),
+							attempted_collator: false,
 							validation_session_parent: Default::default(),
 							candidate_hash: Default::default(),
 							block_data_hash: Default::default(),
+							collator: Default::default(),
 							sender,
 						}));
 					}
@@ -545,20 +953,31 @@ impl PolkadotProtocol {
 		let collation_para = collation.receipt.parachain_index;
 		let collated_acc = collation.receipt.collator;
 
-		match self.peers.get(&from) {
-			None => ctx.report_peer(from, Severity::Useless("Unknown Polkadot specific reason".to_string())),
+		let action = match self.peers.get(&from) {
+			None => {
+				ctx.report_peer(from, Severity::Useless("Unknown Polkadot specific reason".to_string()));
+				None
+			}
 			Some(peer_info) => match peer_info.collating_for {
-				None => ctx.report_peer(from, Severity::Bad("Sent collation without registering collator intent".to_string())),
+				None => {
+					ctx.report_peer(from, Severity::Bad("Sent collation without registering collator intent".to_string()));
+					None
+				}
 				Some((ref acc_id, ref para_id)) => {
 					let structurally_valid = para_id == &collation_para && acc_id == &collated_acc;
 					if structurally_valid && collation.receipt.check_signature().is_ok() {
 						debug!(target: "p_net", "Received collation for parachain {:?} from peer {}", para_id, from);
 						self.collators.on_collation(acc_id.clone(), relay_parent, collation)
 					} else {
-						ctx.report_peer(from, Severity::Bad("Sent malformed collation".to_string()))
-					};
+						ctx.report_peer(from, Severity::Bad("Sent malformed collation".to_string()));
+						None
+					}
 				}
 			},
+		};
+
+		if let Some(Action::Disconnect(collator)) = action {
+			self.disconnect_bad_collator(ctx, collator);
 		}
 	}
 
@@ -569,6 +988,10 @@ impl PolkadotProtocol {
 		rx
 	}
 
+	fn set_permitted_collators(&mut self, para_id: ParaId, permitted: Option<Vec<CollatorId>>) {
+		self.collators.set_permitted_collators(para_id, permitted);
+	}
+
 	// get connected peer with given account ID for collation.
 	fn collator_peer(&mut self, account_id: AccountId) -> Option<(NodeIndex, &mut PeerInfo)> {
 		let check_info = |info: &PeerInfo| info
@@ -583,6 +1006,14 @@ impl PolkadotProtocol {
 			.next()
 	}
 
+	// note that a collation from a collator failed validation, disconnecting the
+	// collator once it has misbehaved too much.
+	fn note_invalid_collation(&mut self, ctx: &mut Context<Block>, account_id: AccountId) {
+		if let Some(Action::Disconnect(collator)) = self.collators.on_invalid_collation(account_id) {
+			self.disconnect_bad_collator(ctx, collator);
+		}
+	}
+
 	// disconnect a collator by account-id.
 	fn disconnect_bad_collator(&mut self, ctx: &mut Context<Block>, account_id: AccountId) {
 		if let Some((who, _)) = self.collator_peer(account_id) {
@@ -607,11 +1038,11 @@ impl PolkadotProtocol {
 			match self.validators.get(&primary) {
 				Some(who) => {
 					debug!(target: "p_net", "Sending local collation to {:?}", primary);
-					send_polkadot_message(
-						ctx,
-						*who,
-						Message::Collation(relay_parent, cloned_collation),
-					)
+					let capabilities = self.peers.get(who).map_or(Capabilities::LEGACY, |info| info.capabilities);
+					let message = self.collation_privacy.message(&primary, capabilities, relay_parent, cloned_collation);
+					if let Some(message) = message {
+						send_polkadot_message(ctx, *who, message);
+					}
 				},
 				None =>
 					warn!(target: "polkadot_network", "Encountered tracked but disconnected validator {:?}", primary),
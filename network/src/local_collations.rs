@@ -80,6 +80,21 @@ impl<C: Clone> LocalCollations<C> {
 		}
 	}
 
+	/// Collations to send to the validator with the given session key, if we
+	/// are its primary collator.
+	pub fn pending_for(&self, key: &SessionKey) -> Vec<(Hash, C)> {
+		if self.primary_for.contains(key) {
+			self.collations_targeting(key)
+		} else {
+			Vec::new()
+		}
+	}
+
+	/// The local collation on the given relay parent, if any.
+	pub fn collation(&self, relay_parent: &Hash) -> Option<&C> {
+		self.local_collations.get(relay_parent).map(|c| &c.collation)
+	}
+
 	/// Validator disconnected.
 	pub fn on_disconnect(&mut self, key: &SessionKey) {
 		self.primary_for.remove(key);
@@ -0,0 +1,294 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Disk-backed overflow for buffers which grow with network load.
+//!
+//! Deferred statements and pending ingress are buffered per relay parent. Each buffer
+//! holds entries in memory up to a limit, and appends any further entries to a log
+//! file for the relay parent. Entries are read back when they are taken out of the
+//! buffer, and the log is removed along with the buffer.
+//!
+//! The file I/O is done by a dedicated thread, so buffering never blocks on the disk.
+//! Entries whose write fails are lost, with a warning.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use futures::prelude::*;
+use futures::sync::oneshot;
+use parking_lot::Mutex;
+
+/// Configuration of the disk overflow of buffers kept for each relay parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverflowConfig {
+	/// Directory to write overflow logs to.
+	pub directory: PathBuf,
+	/// Number of encoded bytes a single buffer keeps in memory before
+	/// further entries are written to disk.
+	pub memory_limit: usize,
+}
+
+type ReadResult = io::Result<Vec<Vec<u8>>>;
+
+// the file I/O requested of the overflow thread, done in order of the requests.
+enum Request {
+	Append(PathBuf, u64, Vec<u8>),
+	Read(PathBuf, Vec<(u64, usize)>, oneshot::Sender<ReadResult>),
+	Remove(PathBuf),
+}
+
+fn thread_gone() -> io::Error {
+	io::Error::new(io::ErrorKind::BrokenPipe, "Overflow thread has exited")
+}
+
+// open a log file, truncating anything left over by an unclean shutdown.
+fn open<'a>(files: &'a mut HashMap<PathBuf, File>, path: &Path) -> io::Result<&'a mut File> {
+	match files.entry(path.to_path_buf()) {
+		Entry::Occupied(entry) => Ok(entry.into_mut()),
+		Entry::Vacant(entry) => {
+			if let Some(directory) = path.parent() {
+				fs::create_dir_all(directory)?;
+			}
+
+			let file = OpenOptions::new()
+				.read(true)
+				.write(true)
+				.create(true)
+				.truncate(true)
+				.open(path)?;
+
+			Ok(entry.insert(file))
+		}
+	}
+}
+
+fn serve_requests(requests: mpsc::Receiver<Request>) {
+	let mut files = HashMap::new();
+
+	// ends once all logs and stores are dropped.
+	for request in requests {
+		match request {
+			Request::Append(path, offset, entry) => {
+				let res = open(&mut files, &path).and_then(|file| {
+					file.seek(SeekFrom::Start(offset))?;
+					file.write_all(&entry)
+				});
+
+				if let Err(e) = res {
+					warn!(target: "p_net", "Unable to write to overflow log {}: {}", path.display(), e);
+				}
+			}
+			Request::Read(path, positions, result) => {
+				let res = open(&mut files, &path).and_then(|file| {
+					positions.into_iter().map(|(offset, len)| -> io::Result<Vec<u8>> {
+						let mut entry = vec![0; len];
+						file.seek(SeekFrom::Start(offset))?;
+						file.read_exact(&mut entry)?;
+						Ok(entry)
+					}).collect()
+				});
+
+				let _ = result.send(res);
+			}
+			Request::Remove(path) => {
+				if files.remove(&path).is_some() {
+					if let Err(e) = fs::remove_file(&path) {
+						warn!(target: "p_net", "Unable to remove overflow log {}: {}", path.display(), e);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Creates the overflow logs of buffers, in a configured directory. Clones share
+/// the thread doing the file I/O.
+#[derive(Clone)]
+pub(crate) struct OverflowStore {
+	config: OverflowConfig,
+	requests: Arc<Mutex<mpsc::Sender<Request>>>,
+}
+
+impl OverflowStore {
+	/// Create a store, spawning the thread doing the file I/O of its logs.
+	pub(crate) fn new(config: OverflowConfig) -> io::Result<Self> {
+		let (tx, rx) = mpsc::channel();
+		thread::Builder::new()
+			.name("overflow-io".into())
+			.spawn(move || serve_requests(rx))?;
+
+		Ok(OverflowStore {
+			config,
+			requests: Arc::new(Mutex::new(tx)),
+		})
+	}
+
+	/// Create a log with the given file name in the configured directory.
+	/// The file is only created once the first entry is appended.
+	pub(crate) fn log<K: Hash + Eq>(&self, name: String) -> OverflowLog<K> {
+		OverflowLog {
+			path: self.config.directory.join(name),
+			memory_limit: self.config.memory_limit,
+			requests: self.requests.clone(),
+			written: false,
+			end: 0,
+			entries: HashMap::new(),
+		}
+	}
+}
+
+// An append-only log of encoded entries grouped under keys.
+//
+// Only the position of each entry is kept in memory. Space taken by entries which
+// have been read back is reclaimed when the log is dropped.
+pub(crate) struct OverflowLog<K> {
+	path: PathBuf,
+	memory_limit: usize,
+	requests: Arc<Mutex<mpsc::Sender<Request>>>,
+	written: bool,
+	end: u64,
+	entries: HashMap<K, Vec<(u64, usize)>>,
+}
+
+impl<K: Hash + Eq> OverflowLog<K> {
+	/// Whether a buffer with `resident` bytes in memory should write an entry of
+	/// `len` bytes to the log instead.
+	pub(crate) fn should_overflow(&self, resident: usize, len: usize) -> bool {
+		resident.saturating_add(len) > self.memory_limit
+	}
+
+	/// Number of entries in the log which have not been taken.
+	pub(crate) fn len(&self) -> usize {
+		self.entries.values().map(Vec::len).sum()
+	}
+
+	/// Append an encoded entry under a key. The entry is written in the background.
+	pub(crate) fn append(&mut self, key: K, entry: &[u8]) -> io::Result<()> {
+		let offset = self.end;
+		self.requests.lock().send(Request::Append(self.path.clone(), offset, entry.to_vec()))
+			.map_err(|_| thread_gone())?;
+
+		self.written = true;
+		self.end += entry.len() as u64;
+		self.entries.entry(key).or_insert_with(Vec::new).push((offset, entry.len()));
+		Ok(())
+	}
+
+	/// Read back all entries under a key, in the order they were appended, and remove
+	/// them from the log. Returns `None` if there are no entries under the key.
+	pub(crate) fn take(&mut self, key: &K) -> Option<OverflowRead> {
+		let positions = self.entries.remove(key)?;
+
+		let (tx, rx) = oneshot::channel();
+		let sent = self.requests.lock().send(Request::Read(self.path.clone(), positions, tx)).is_ok();
+		Some(OverflowRead { inner: if sent { Some(rx) } else { None } })
+	}
+
+	/// Remove all entries under a key without reading them back.
+	pub(crate) fn discard(&mut self, key: &K) {
+		self.entries.remove(key);
+	}
+}
+
+impl<K> Drop for OverflowLog<K> {
+	fn drop(&mut self) {
+		if self.written {
+			let _ = self.requests.lock().send(Request::Remove(self.path.clone()));
+		}
+	}
+}
+
+/// Entries being read back from an overflow log.
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct OverflowRead {
+	// `None` if the request couldn't be sent.
+	inner: Option<oneshot::Receiver<ReadResult>>,
+}
+
+impl Future for OverflowRead {
+	type Item = Vec<Vec<u8>>;
+	type Error = io::Error;
+
+	fn poll(&mut self) -> Poll<Vec<Vec<u8>>, io::Error> {
+		match self.inner {
+			None => Err(thread_gone()),
+			Some(ref mut inner) => match inner.poll() {
+				Ok(Async::Ready(res)) => res.map(Async::Ready),
+				Ok(Async::NotReady) => Ok(Async::NotReady),
+				Err(_) => Err(thread_gone()),
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(test: &str) -> OverflowConfig {
+		OverflowConfig {
+			directory: ::std::env::temp_dir().join(format!("polkadot-overflow-{}-{}", test, ::std::process::id())),
+			memory_limit: 8,
+		}
+	}
+
+	#[test]
+	fn entries_read_back_in_order() {
+		let store = OverflowStore::new(config("read-back")).unwrap();
+		let mut log = store.log("log".into());
+
+		assert!(!log.should_overflow(4, 4));
+		assert!(log.should_overflow(4, 5));
+
+		log.append(1u32, &[1, 2, 3]).unwrap();
+		log.append(2u32, &[4, 5]).unwrap();
+		log.append(1u32, &[6]).unwrap();
+		assert_eq!(log.len(), 3);
+
+		assert_eq!(log.take(&1).unwrap().wait().unwrap(), vec![vec![1, 2, 3], vec![6]]);
+		assert!(log.take(&1).is_none());
+
+		log.discard(&2);
+		assert_eq!(log.len(), 0);
+		assert!(log.take(&2).is_none());
+	}
+
+	#[test]
+	fn log_removed_on_drop() {
+		let config = config("removed");
+		let path = config.directory.join("log");
+		let store = OverflowStore::new(config).unwrap();
+
+		let mut log = store.log("log".into());
+		log.append(1u32, &[1, 2, 3]).unwrap();
+		assert_eq!(log.take(&1).unwrap().wait().unwrap(), vec![vec![1, 2, 3]]);
+		assert!(path.exists());
+
+		drop(log);
+
+		// requests are served in order, so the removal is done once a later read is.
+		let mut other = store.log("other".into());
+		other.append(1u32, &[4]).unwrap();
+		other.take(&1).unwrap().wait().unwrap();
+		assert!(!path.exists());
+	}
+}
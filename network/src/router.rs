@@ -22,45 +22,42 @@
 //! the `TableRouter` trait from `polkadot-validation`, which is expected to call into a shared statement table
 //! and dispatch evaluation work as necessary when new statements come in.
 
-use sr_primitives::traits::{ProvideRuntimeApi, BlakeTwo256, Hash as HashT};
+use sr_primitives::traits::ProvideRuntimeApi;
 use polkadot_validation::{
 	SharedTable, TableRouter, SignedStatement, GenericStatement, ParachainWork, Incoming,
-	Validated, Outgoing,
+	Validated, Outgoing, Cancellation, ReplayRecorder,
 };
 use polkadot_primitives::{Block, Hash, SessionKey};
 use polkadot_primitives::parachain::{
-	BlockData, Extrinsic, CandidateReceipt, ParachainHost, Id as ParaId, Message
+	BlockData, Extrinsic, CandidateHash, CandidateReceipt, ParachainHost, Id as ParaId, Message,
+	attestation_topic, incoming_message_topic,
 };
+use polkadot_primitives::message_roots::message_queue_root;
 
 use codec::{Encode, Decode};
 use futures::{future, prelude::*};
 use futures::sync::oneshot::{self, Receiver};
 use parking_lot::Mutex;
 
+use overflow::{OverflowLog, OverflowRead, OverflowStore};
+use wire::GossipFormat;
+
 use std::collections::{hash_map::{Entry, HashMap}, HashSet};
 use std::{io, mem};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::timer::Delay;
 
 use validation::{NetworkService, Knowledge, Executor};
 
+// how many newly seen gossip messages to accumulate before persisting the seen-set.
+const PERSIST_SEEN_EVERY: usize = 32;
+
 type IngressPair = (ParaId, Vec<Message>);
 type IngressPairRef<'a> = (ParaId, &'a [Message]);
 
-fn attestation_topic(parent_hash: Hash) -> Hash {
-	let mut v = parent_hash.as_ref().to_vec();
-	v.extend(b"attestations");
-
-	BlakeTwo256::hash(&v[..])
-}
-
-fn incoming_message_topic(parent_hash: Hash, parachain: ParaId) -> Hash {
-	let mut v = parent_hash.as_ref().to_vec();
-	parachain.using_encoded(|s| v.extend(s));
-	v.extend(b"incoming");
-
-	BlakeTwo256::hash(&v[..])
-}
-
 /// Receiver for block data.
 pub struct BlockDataReceiver {
 	outer: Receiver<Receiver<BlockData>>,
@@ -113,20 +110,28 @@ impl Future for IncomingReceiver {
 }
 
 /// Table routing implementation.
-pub struct Router<P, E, N: NetworkService, T> {
+pub struct Router<P, N: NetworkService, T> {
 	table: Arc<SharedTable>,
 	network: Arc<N>,
 	api: Arc<P>,
-	exit: E,
+	// the scope of the validation session. work on each candidate runs in a child scope,
+	// kept until the work completes or the candidate is included or abandoned.
+	cancellation: Cancellation,
+	candidate_scopes: Arc<Mutex<HashMap<CandidateHash, Cancellation>>>,
 	task_executor: T,
 	parent_hash: Hash,
 	attestation_topic: Hash,
 	knowledge: Arc<Mutex<Knowledge>>,
 	fetch_incoming: Arc<Mutex<HashMap<ParaId, IncomingReceiver>>>,
 	deferred_statements: Arc<Mutex<DeferredStatements>>,
+	seen: Arc<Mutex<SeenStatements>>,
+	overflow: Option<OverflowStore>,
+	backing_deadlines: Option<Arc<BackingDeadlines>>,
+	replay: Option<ReplayRecorder>,
+	gossip_format: GossipFormat,
 }
 
-impl<P, E, N: NetworkService, T> Router<P, E, N, T> {
+impl<P, N: NetworkService, T> Router<P, N, T> {
 	pub(crate) fn new(
 		table: Arc<SharedTable>,
 		network: Arc<N>,
@@ -134,8 +139,19 @@ impl<P, E, N: NetworkService, T> Router<P, E, N, T> {
 		task_executor: T,
 		parent_hash: Hash,
 		knowledge: Arc<Mutex<Knowledge>>,
-		exit: E,
+		cancellation: Cancellation,
+		overflow: Option<OverflowStore>,
 	) -> Self {
+		let seen = SeenStatements {
+			hashes: table.restore_seen().into_iter().collect(),
+			unpersisted: Vec::new(),
+		};
+
+		let mut deferred_statements = DeferredStatements::new();
+		if let Some(ref store) = overflow {
+			deferred_statements.overflow = Some(store.log(format!("{:?}.statements", parent_hash)));
+		}
+
 		Router {
 			table,
 			network,
@@ -145,18 +161,74 @@ impl<P, E, N: NetworkService, T> Router<P, E, N, T> {
 			attestation_topic: attestation_topic(parent_hash),
 			knowledge,
 			fetch_incoming: Arc::new(Mutex::new(HashMap::new())),
-			deferred_statements: Arc::new(Mutex::new(DeferredStatements::new())),
-			exit,
+			deferred_statements: Arc::new(Mutex::new(deferred_statements)),
+			seen: Arc::new(Mutex::new(seen)),
+			overflow,
+			backing_deadlines: None,
+			replay: None,
+			gossip_format: GossipFormat::new(),
+			cancellation,
+			candidate_scopes: Arc::new(Mutex::new(HashMap::new())),
 		}
 	}
 
+	/// Abandon candidates not backed within `deadline` of the first statement seen on them,
+	/// counting them in `expired`.
+	pub(crate) fn with_backing_deadline(mut self, deadline: Option<Duration>, expired: Arc<AtomicUsize>) -> Self {
+		self.backing_deadlines = deadline.map(|deadline| Arc::new(BackingDeadlines {
+			deadline,
+			candidates: Mutex::new(HashMap::new()),
+			expired,
+		}));
+		self
+	}
+
+	/// Record failed executions of fetched candidates for replay.
+	pub(crate) fn with_validation_replay(mut self, replay: Option<ReplayRecorder>) -> Self {
+		self.replay = replay;
+		self
+	}
+
+	/// Encode gossip for the wire formats of connected peers.
+	pub(crate) fn with_gossip_format(mut self, gossip_format: GossipFormat) -> Self {
+		self.gossip_format = gossip_format;
+		self
+	}
+
 	/// Get the attestation topic for gossip.
 	pub(crate) fn gossip_topic(&self) -> Hash {
 		self.attestation_topic
 	}
+
+	/// Whether a gossip message with given hash has already been checked,
+	/// possibly before a restart.
+	pub(crate) fn has_seen(&self, message_hash: &Hash) -> bool {
+		self.seen.lock().hashes.contains(message_hash)
+	}
+
+	/// The scope work on a candidate runs in, a child of the session's scope.
+	pub(crate) fn candidate_scope(&self, candidate_hash: CandidateHash) -> Cancellation {
+		let cancellation = &self.cancellation;
+		self.candidate_scopes.lock()
+			.entry(candidate_hash)
+			.or_insert_with(|| cancellation.child())
+			.clone()
+	}
+
+	/// Note that a gossip message with given hash has been checked.
+	/// The seen-set is persisted periodically.
+	pub(crate) fn note_seen(&self, message_hash: Hash) {
+		let mut seen = self.seen.lock();
+		if seen.hashes.insert(message_hash) {
+			seen.unpersisted.push(message_hash);
+			if seen.unpersisted.len() >= PERSIST_SEEN_EVERY {
+				seen.persist(&self.table);
+			}
+		}
+	}
 }
 
-impl<P, E: Clone, N: NetworkService, T: Clone> Clone for Router<P, E, N, T> {
+impl<P, N: NetworkService, T: Clone> Clone for Router<P, N, T> {
 	fn clone(&self) -> Self {
 		Router {
 			table: self.table.clone(),
@@ -168,59 +240,86 @@ impl<P, E: Clone, N: NetworkService, T: Clone> Clone for Router<P, E, N, T> {
 			deferred_statements: self.deferred_statements.clone(),
 			fetch_incoming: self.fetch_incoming.clone(),
 			knowledge: self.knowledge.clone(),
-			exit: self.exit.clone(),
+			seen: self.seen.clone(),
+			overflow: self.overflow.clone(),
+			backing_deadlines: self.backing_deadlines.clone(),
+			replay: self.replay.clone(),
+			gossip_format: self.gossip_format.clone(),
+			cancellation: self.cancellation.clone(),
+			candidate_scopes: self.candidate_scopes.clone(),
 		}
 	}
 }
 
-impl<P: ProvideRuntimeApi + Send + Sync + 'static, E, N, T> Router<P, E, N, T> where
+impl<P: ProvideRuntimeApi + Send + Sync + 'static, N, T> Router<P, N, T> where
 	P::Api: ParachainHost<Block>,
 	N: NetworkService,
 	T: Clone + Executor + Send + 'static,
-	E: Future<Item=(),Error=()> + Clone + Send + 'static,
 {
 	/// Import a statement whose signature has been checked already.
 	pub(crate) fn import_statement(&self, statement: SignedStatement) {
 		trace!(target: "p_net", "importing consensus statement {:?}", statement.statement);
 
-		// defer any statements for which we haven't imported the candidate yet
-		let c_hash = {
-			let candidate_data = match statement.statement {
-				GenericStatement::Candidate(ref c) => Some(c.hash()),
-				GenericStatement::Valid(ref hash)
-					| GenericStatement::Invalid(ref hash)
-					=> self.table.with_candidate(hash, |c| c.map(|_| *hash)),
+		if let Some(ref deadlines) = self.backing_deadlines {
+			let c_hash = match statement.statement {
+				GenericStatement::Candidate(ref c) => c.hash(),
+				GenericStatement::Valid(hash) | GenericStatement::Invalid(hash) => hash,
 			};
-			match candidate_data {
-				Some(x) => x,
-				None => {
-					self.deferred_statements.lock().push(statement);
-					return;
-				}
+
+			if deadlines.is_expired(&c_hash) {
+				trace!(target: "p_net", "Ignoring statement on abandoned candidate {}", c_hash);
+				return;
 			}
-		};
 
-		// import all statements pending on this candidate
-		let (mut statements, _traces) = if let GenericStatement::Candidate(_) = statement.statement {
-			self.deferred_statements.lock().get_deferred(&c_hash)
-		} else {
-			(Vec::new(), Vec::new())
+			if let Some(at) = deadlines.note_seen(c_hash, Instant::now()) {
+				let timer = self.cancellation.until_cancelled(self.backing_deadline(c_hash, at));
+				self.task_executor.spawn(timer);
+			}
+		}
+
+		let table = &self.table;
+		let routed = self.deferred_statements.lock().route(
+			statement,
+			|hash| table.with_candidate(hash, |c| c.is_some()),
+			|hash| table.is_included(hash),
+		);
+
+		let (c_hash, statements, overflowed) = match routed {
+			Some(routed) => routed,
+			None => return,
 		};
 
-		// prepend the candidate statement.
+		// statements deferred to disk are imported once read back.
+		if let Some(overflowed) = overflowed {
+			let router = self.clone();
+			let import = overflowed
+				.map_err(|e| warn!(target: "p_net", "Unable to read deferred statements from disk: {}", e))
+				.map(move |entries| {
+					let statements = entries.iter().filter_map(|entry| SignedStatement::decode(&mut &entry[..]));
+					for statement in statements {
+						router.import_statement(statement);
+					}
+				});
+
+			self.task_executor.spawn(self.cancellation.until_cancelled(import));
+		}
+
 		debug!(target: "consensus", "Importing statements about candidate {:?}", c_hash);
-		statements.insert(0, statement);
 		let producers: Vec<_> = self.table.import_remote_statements(
 			self,
 			statements.iter().cloned(),
 		);
 		// dispatch future work as necessary.
 		for (producer, statement) in producers.into_iter().zip(statements) {
-			self.knowledge.lock().note_statement(statement.sender, &statement.statement);
+			self.knowledge.lock().note_statement(self.parent_hash, &statement);
 
 			if let Some(work) = producer.map(|p| self.create_work(c_hash, p)) {
 				trace!(target: "consensus", "driving statement work to completion");
-				let work = work.select2(self.exit.clone()).then(|_| Ok(()));
+				let candidate_scopes = self.candidate_scopes.clone();
+				let work = self.candidate_scope(c_hash).until_cancelled(work).then(move |_| {
+					candidate_scopes.lock().remove(&c_hash);
+					Ok(())
+				});
 				self.task_executor.spawn(work);
 			}
 		}
@@ -257,7 +356,31 @@ impl<P: ProvideRuntimeApi + Send + Sync + 'static, E, N, T> Router<P, E, N, T> w
 		}
 	}
 
-	fn create_work<D>(&self, candidate_hash: Hash, producer: ParachainWork<D>)
+	// abandons the candidate when its backing deadline passes, unless backed by then.
+	fn backing_deadline(&self, candidate_hash: CandidateHash, at: Instant)
+		-> impl Future<Item=(),Error=()> + Send + 'static
+	{
+		let deadlines = self.backing_deadlines.clone();
+		let table = self.table.clone();
+		let deferred_statements = self.deferred_statements.clone();
+		let candidate_scopes = self.candidate_scopes.clone();
+
+		Delay::new(at).then(move |_| {
+			let expired = deadlines.map_or(false, |deadlines| {
+				deadlines.check(&table, &deferred_statements, &candidate_hash)
+			});
+
+			if expired {
+				if let Some(scope) = candidate_scopes.lock().remove(&candidate_hash) {
+					scope.cancel();
+				}
+			}
+
+			Ok(())
+		})
+	}
+
+	fn create_work<D>(&self, candidate_hash: CandidateHash, producer: ParachainWork<D>)
 		-> impl Future<Item=(),Error=()> + Send + 'static
 		where
 		D: Future<Item=(BlockData, Incoming),Error=io::Error> + Send + 'static,
@@ -265,12 +388,17 @@ impl<P: ProvideRuntimeApi + Send + Sync + 'static, E, N, T> Router<P, E, N, T> w
 		let table = self.table.clone();
 		let network = self.network.clone();
 		let knowledge = self.knowledge.clone();
+		let parent_hash = self.parent_hash;
+		let seen = self.seen.clone();
 		let attestation_topic = self.attestation_topic.clone();
+		let gossip_format = self.gossip_format.clone();
 
-		producer.prime(self.api.clone())
-			.map(move |validated| {
+		producer.prime_recording(self.api.clone(), self.replay.clone())
+			.map_err(|e| debug!(target: "p_net", "Failed to produce statements: {:?}", e))
+			.and_then(move |validated| {
 				// store the data before broadcasting statements, so other peers can fetch.
 				knowledge.lock().note_candidate(
+					parent_hash,
 					candidate_hash,
 					Some(validated.block_data().clone()),
 					validated.extrinsic().cloned(),
@@ -278,19 +406,24 @@ impl<P: ProvideRuntimeApi + Send + Sync + 'static, E, N, T> Router<P, E, N, T> w
 
 				// propagate the statement.
 				// consider something more targeted than gossip in the future.
-				let signed = table.import_validated(validated);
-				network.gossip_message(attestation_topic, signed.encode());
+				table.import_validated(validated)
+					.map_err(|e| debug!(target: "p_net", "Failed to sign validity statement: {}", e))
+					.map(move |signed| {
+						knowledge.lock().note_statement(parent_hash, &signed);
+						network.gossip_message(attestation_topic, gossip_format.encode(signed));
+
+						// persist the new local validation result.
+						seen.lock().persist(&table);
+					})
 			})
-			.map_err(|e| debug!(target: "p_net", "Failed to produce statements: {:?}", e))
 	}
 
 }
 
-impl<P: ProvideRuntimeApi, E, N, T> Router<P, E, N, T> where
+impl<P: ProvideRuntimeApi, N, T> Router<P, N, T> where
 	P::Api: ParachainHost<Block>,
 	N: NetworkService,
 	T: Executor,
-	E: Future<Item=(),Error=()> + Clone + Send + 'static,
 {
 	fn do_fetch_incoming(&self, parachain: ParaId) -> IncomingReceiver {
 		use polkadot_primitives::BlockId;
@@ -320,6 +453,10 @@ impl<P: ProvideRuntimeApi, E, N, T> Router<P, E, N, T> where
 				parachain, parent_hash, e)
 			);
 
+		let overflow = self.overflow.as_ref().map(|store| store.log(
+			format!("{:?}.ingress-{}", parent_hash, u32::from(parachain)),
+		));
+
 		let work = canon_roots.into_future()
 			.and_then(move |ingress_roots| match ingress_roots {
 				None => Err(format!("No parachain {:?} registered at {}", parachain, parent_hash)),
@@ -329,36 +466,58 @@ impl<P: ProvideRuntimeApi, E, N, T> Router<P, E, N, T> where
 				inner: gossip_messages,
 				ingress_roots,
 				incoming: Vec::new(),
+				resident: 0,
+				overflow,
+				reading: None,
 			})
-			.map(move |incoming| if let Some(i) = incoming { let _ = tx.send(i); })
-			.select2(self.exit.clone())
-			.then(|_| Ok(()));
+			.map(move |incoming| if let Some(i) = incoming {
+				// only hand ingress over to validation in canonical order.
+				match ::polkadot_validation::canonical_ingress(i) {
+					Some(i) => { let _ = tx.send(i); }
+					None => debug!(target: "p_net", "Non-canonical ingress for parachain {:?}", parachain),
+				}
+			});
 
-		self.task_executor.spawn(work);
+		self.task_executor.spawn(self.cancellation.until_cancelled(work));
 
 		rx
 	}
 }
 
-impl<P: ProvideRuntimeApi + Send, E, N, T> TableRouter for Router<P, E, N, T> where
+impl<P: ProvideRuntimeApi + Send, N, T> TableRouter for Router<P, N, T> where
 	P::Api: ParachainHost<Block>,
 	N: NetworkService,
 	T: Clone + Executor + Send + 'static,
-	E: Future<Item=(),Error=()> + Clone + Send + 'static,
 {
 	type Error = io::Error;
 	type FetchCandidate = BlockDataReceiver;
 	type FetchIncoming = IncomingReceiver;
 
 	fn local_candidate(&self, receipt: CandidateReceipt, block_data: BlockData, extrinsic: Extrinsic) {
-		// produce a signed statement
 		let hash = receipt.hash();
 		let validated = Validated::collated_local(receipt, block_data.clone(), extrinsic.clone());
-		let statement = self.table.import_validated(validated);
 
 		// give to network to make available.
-		self.knowledge.lock().note_candidate(hash, Some(block_data), Some(extrinsic));
-		self.network.gossip_message(self.attestation_topic, statement.encode());
+		self.knowledge.lock().note_candidate(self.parent_hash, hash, Some(block_data), Some(extrinsic));
+
+		// produce a signed statement, and circulate it once signed.
+		let knowledge = self.knowledge.clone();
+		let network = self.network.clone();
+		let table = self.table.clone();
+		let seen = self.seen.clone();
+		let parent_hash = self.parent_hash;
+		let attestation_topic = self.attestation_topic;
+		let gossip_format = self.gossip_format.clone();
+
+		let statement = self.table.import_validated(validated)
+			.map_err(move |e| warn!(target: "p_net", "Failed to sign local candidate {}: {}", hash, e))
+			.map(move |statement| {
+				knowledge.lock().note_statement(parent_hash, &statement);
+				network.gossip_message(attestation_topic, gossip_format.encode(statement));
+				seen.lock().persist(&table);
+			});
+
+		self.task_executor.spawn(self.cancellation.until_cancelled(statement));
 	}
 
 	fn fetch_block_data(&self, candidate: &CandidateReceipt) -> BlockDataReceiver {
@@ -375,10 +534,53 @@ impl<P: ProvideRuntimeApi + Send, E, N, T> TableRouter for Router<P, E, N, T> wh
 	fn fetch_incoming(&self, parachain: ParaId) -> Self::FetchIncoming {
 		self.do_fetch_incoming(parachain)
 	}
-}
 
-impl<P, E, N: NetworkService, T> Drop for Router<P, E, N, T> {
-	fn drop(&mut self) {
+	fn candidates_included(&self, candidates: &[CandidateHash]) {
+		let parachains: Vec<_> = candidates.iter()
+			.filter_map(|hash| self.table.with_candidate(hash, |c| c.map(|c| c.parachain_index)))
+			.collect();
+
+		{
+			let mut deferred = self.deferred_statements.lock();
+			for hash in candidates {
+				deferred.discard(hash);
+			}
+		}
+
+		// work on included candidates is moot.
+		{
+			let mut candidate_scopes = self.candidate_scopes.lock();
+			for hash in candidates {
+				if let Some(scope) = candidate_scopes.remove(hash) {
+					scope.cancel();
+				}
+			}
+		}
+
+		// the parachains have progressed past this relay parent, so ingress
+		// to them needn't be circulated any more.
+		let mut incoming_fetched = self.fetch_incoming.lock();
+		for para_id in parachains {
+			if incoming_fetched.remove(&para_id).is_some() {
+				self.network.drop_gossip(incoming_message_topic(self.parent_hash, para_id));
+			}
+		}
+	}
+
+	fn flush(&self) {
+		// statements gossiped just before shutdown may not have left the node yet.
+		for statement in self.table.local_statements() {
+			self.network.gossip_message(self.attestation_topic, self.gossip_format.encode(statement));
+		}
+
+		self.seen.lock().persist(&self.table);
+	}
+
+	fn end_session(&self) {
+		// stops all work of the session, including the processing of its gossip.
+		self.cancellation.cancel();
+		self.candidate_scopes.lock().clear();
+
 		let parent_hash = self.parent_hash.clone();
 		self.network.with_spec(move |spec, _| spec.remove_validation_session(&parent_hash));
 		self.network.drop_gossip(self.attestation_topic);
@@ -395,24 +597,46 @@ impl<P, E, N: NetworkService, T> Drop for Router<P, E, N, T> {
 	}
 }
 
+// Hashes of gossip messages which have been checked on a relay parent.
+struct SeenStatements {
+	hashes: HashSet<Hash>,
+	// hashes noted since the set was last persisted.
+	unpersisted: Vec<Hash>,
+}
+
+impl SeenStatements {
+	// persist the hashes noted since the last time, along with newly validated candidates.
+	fn persist(&mut self, table: &SharedTable) {
+		table.persist_seen(self.unpersisted.drain(..));
+	}
+}
+
 // A unique trace for valid statements issued by a validator.
 #[derive(Hash, PartialEq, Eq, Clone, Debug)]
 enum StatementTrace {
-	Valid(SessionKey, Hash),
-	Invalid(SessionKey, Hash),
+	Valid(SessionKey, CandidateHash),
+	Invalid(SessionKey, CandidateHash),
 }
 
 // helper for deferring statements whose associated candidate is unknown.
-struct DeferredStatements {
-	deferred: HashMap<Hash, Vec<SignedStatement>>,
-	known_traces: HashSet<StatementTrace>,
+//
+// when an overflow log is set, statements beyond its memory limit are deferred to disk.
+pub(crate) struct DeferredStatements {
+	deferred: HashMap<CandidateHash, Vec<SignedStatement>>,
+	// traces of all deferred statements, in memory or on disk.
+	known_traces: HashMap<CandidateHash, HashSet<StatementTrace>>,
+	// encoded size of the statements in `deferred`.
+	resident: usize,
+	overflow: Option<OverflowLog<CandidateHash>>,
 }
 
 impl DeferredStatements {
-	fn new() -> Self {
+	pub(crate) fn new() -> Self {
 		DeferredStatements {
 			deferred: HashMap::new(),
-			known_traces: HashSet::new(),
+			known_traces: HashMap::new(),
+			resident: 0,
+			overflow: None,
 		}
 	}
 
@@ -423,52 +647,240 @@ impl DeferredStatements {
 			GenericStatement::Invalid(hash) => (hash, StatementTrace::Invalid(statement.sender, hash)),
 		};
 
-		if self.known_traces.insert(trace) {
-			self.deferred.entry(hash).or_insert_with(Vec::new).push(statement);
+		if !self.known_traces.entry(hash).or_insert_with(HashSet::new).insert(trace) {
+			return;
 		}
+
+		let encoded = statement.encode();
+		if let Some(ref mut overflow) = self.overflow {
+			if overflow.should_overflow(self.resident, encoded.len()) {
+				match overflow.append(hash, &encoded) {
+					Ok(()) => return,
+					Err(e) => warn!(target: "p_net", "Unable to defer statement to disk: {}", e),
+				}
+			}
+		}
+
+		self.resident += encoded.len();
+		self.deferred.entry(hash).or_insert_with(Vec::new).push(statement);
 	}
 
-	fn get_deferred(&mut self, hash: &Hash) -> (Vec<SignedStatement>, Vec<StatementTrace>) {
-		match self.deferred.remove(hash) {
-			None => (Vec::new(), Vec::new()),
-			Some(deferred) => {
-				let mut traces = Vec::new();
-				for statement in deferred.iter() {
-					let trace = match statement.statement {
-						GenericStatement::Candidate(_) => continue,
-						GenericStatement::Valid(hash) => StatementTrace::Valid(statement.sender, hash),
-						GenericStatement::Invalid(hash) => StatementTrace::Invalid(statement.sender, hash),
-					};
+	// route a statement whose signature has been checked.
+	//
+	// statements on candidates not known to the table are deferred, while those on candidates
+	// already included are dropped. when the candidate itself arrives, it is returned along with
+	// all statements that were pending on it in memory, candidate statement first, and the
+	// pending statements being read back from disk.
+	pub(crate) fn route<K, I>(&mut self, statement: SignedStatement, is_known: K, is_included: I)
+		-> Option<(CandidateHash, Vec<SignedStatement>, Option<OverflowRead>)>
+		where
+			K: Fn(&CandidateHash) -> bool,
+			I: Fn(&CandidateHash) -> bool,
+	{
+		let (c_hash, is_candidate) = match statement.statement {
+			GenericStatement::Candidate(ref c) => (c.hash(), true),
+			GenericStatement::Valid(hash) | GenericStatement::Invalid(hash) => (hash, false),
+		};
 
-					self.known_traces.remove(&trace);
-					traces.push(trace);
-				}
+		if !is_candidate {
+			if is_known(&c_hash) {
+				return Some((c_hash, vec![statement], None));
+			}
+
+			// statements on candidates already included on-chain aren't worth holding.
+			if !is_included(&c_hash) {
+				self.push(statement);
+			}
+			return None;
+		}
+
+		// import all statements pending on this candidate
+		let (mut statements, _traces, overflowed) = self.get_deferred(&c_hash);
+		statements.insert(0, statement);
+
+		Some((c_hash, statements, overflowed))
+	}
+
+	// take all statements deferred on a candidate: those in memory, their traces along with
+	// those of statements on disk, and the statements being read back from disk.
+	fn get_deferred(&mut self, hash: &CandidateHash)
+		-> (Vec<SignedStatement>, Vec<StatementTrace>, Option<OverflowRead>)
+	{
+		let deferred = self.deferred.remove(hash).unwrap_or_default();
+		self.resident -= deferred.iter().map(|statement| statement.encode().len()).sum::<usize>();
+
+		let overflowed = self.overflow.as_mut().and_then(|overflow| overflow.take(hash));
+		let traces = self.known_traces.remove(hash).map_or_else(Vec::new, |traces| traces.into_iter().collect());
+
+		(deferred, traces, overflowed)
+	}
+
+	// drop all statements deferred on a candidate, without reading back those on disk.
+	fn discard(&mut self, hash: &CandidateHash) {
+		if let Some(deferred) = self.deferred.remove(hash) {
+			self.resident -= deferred.iter().map(|statement| statement.encode().len()).sum::<usize>();
+		}
+
+		if let Some(ref mut overflow) = self.overflow {
+			overflow.discard(hash);
+		}
+
+		self.known_traces.remove(hash);
+	}
+}
+
+// the state of a candidate's backing deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeadlineState {
+	Pending(Instant),
+	Met,
+	Expired,
+}
+
+// tracks the deadline for candidates to be backed by, from the first statement seen on them.
+struct BackingDeadlines {
+	deadline: Duration,
+	candidates: Mutex<HashMap<CandidateHash, DeadlineState>>,
+	// count of candidates abandoned, shared across relay parents.
+	expired: Arc<AtomicUsize>,
+}
 
-				(deferred, traces)
+impl BackingDeadlines {
+	// note a statement seen on a candidate, returning the deadline if the
+	// candidate was not tracked yet.
+	fn note_seen(&self, hash: CandidateHash, now: Instant) -> Option<Instant> {
+		match self.candidates.lock().entry(hash) {
+			Entry::Occupied(_) => None,
+			Entry::Vacant(entry) => {
+				let at = now + self.deadline;
+				entry.insert(DeadlineState::Pending(at));
+				Some(at)
 			}
 		}
 	}
+
+	fn is_expired(&self, hash: &CandidateHash) -> bool {
+		self.candidates.lock().get(hash) == Some(&DeadlineState::Expired)
+	}
+
+	// note that the deadline of a candidate has passed. returns `true` if
+	// the candidate was pending and not backed in time.
+	fn on_deadline(&self, hash: &CandidateHash, backed: bool) -> bool {
+		let mut candidates = self.candidates.lock();
+		let pending = match candidates.get(hash) {
+			Some(&DeadlineState::Pending(_)) => true,
+			_ => false,
+		};
+
+		if pending {
+			if backed {
+				candidates.insert(*hash, DeadlineState::Met);
+			} else {
+				candidates.insert(*hash, DeadlineState::Expired);
+				self.expired.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+
+		pending && !backed
+	}
+
+	// check a candidate whose deadline has passed, abandoning it and freeing
+	// statements deferred on it if it is not backed. returns whether the candidate
+	// is abandoned.
+	fn check(&self, table: &SharedTable, deferred: &Mutex<DeferredStatements>, hash: &CandidateHash) -> bool {
+		let backed = table.is_backed(hash) || table.is_included(hash);
+		if self.on_deadline(hash, backed) {
+			debug!(target: "p_net", "Abandoning candidate {} not backed within {:?}", hash, self.deadline);
+			deferred.lock().discard(hash);
+		}
+
+		self.is_expired(hash)
+	}
 }
 
 // computes ingress from incoming stream of messages.
 // returns `None` if the stream concludes too early.
+//
+// when an overflow log is set, messages beyond its memory limit are held on disk
+// until all ingress is known.
 #[must_use = "futures do nothing unless polled"]
 struct ComputeIngress<S> {
 	ingress_roots: HashMap<ParaId, Hash>,
 	incoming: Vec<IngressPair>,
+	// size of the messages in `incoming`.
+	resident: usize,
+	overflow: Option<OverflowLog<ParaId>>,
+	// all ingress, once known, while messages held on disk are read back.
+	reading: Option<Box<Future<Item=Incoming, Error=io::Error> + Send>>,
 	inner: S,
 }
 
+impl<S> ComputeIngress<S> {
+	// hold messages from a parachain, on disk if over the memory limit.
+	// returns the messages to keep in memory.
+	fn hold(&mut self, para_id: ParaId, messages: Vec<Message>) -> Vec<Message> {
+		let len: usize = messages.iter().map(|m| m.0.len()).sum();
+		if let Some(ref mut overflow) = self.overflow {
+			if !messages.is_empty() && overflow.should_overflow(self.resident, len) {
+				match overflow.append(para_id, &messages.encode()) {
+					Ok(()) => return Vec::new(),
+					Err(e) => warn!(target: "p_net", "Unable to hold ingress on disk: {}", e),
+				}
+			}
+		}
+
+		self.resident += len;
+		messages
+	}
+
+	// read back all messages held on disk.
+	fn read_back(&mut self) -> Box<Future<Item=Incoming, Error=io::Error> + Send> {
+		let incoming = mem::replace(&mut self.incoming, Vec::new());
+		let mut reads = Vec::new();
+		if let Some(ref mut overflow) = self.overflow {
+			for (i, &(ref para_id, _)) in incoming.iter().enumerate() {
+				if let Some(read) = overflow.take(para_id) {
+					reads.push(read.map(move |entries| (i, entries)));
+				}
+			}
+		}
+
+		Box::new(future::join_all(reads).and_then(move |read| -> io::Result<Incoming> {
+			let mut incoming = incoming;
+			for (i, mut entries) in read {
+				if let Some(entry) = entries.pop() {
+					incoming[i].1 = Decode::decode(&mut &entry[..]).ok_or_else(|| io::Error::new(
+						io::ErrorKind::InvalidData,
+						"Corrupt ingress in overflow log",
+					))?;
+				}
+			}
+
+			Ok(incoming)
+		}))
+	}
+}
+
 impl<S> Future for ComputeIngress<S> where S: Stream<Item=IngressPair> {
 	type Item = Option<Incoming>;
 	type Error = S::Error;
 
 	fn poll(&mut self) -> Poll<Option<Incoming>, Self::Error> {
 		loop {
+			if let Some(ref mut reading) = self.reading {
+				return Ok(Async::Ready(match reading.poll() {
+					Ok(Async::NotReady) => return Ok(Async::NotReady),
+					Ok(Async::Ready(incoming)) => Some(incoming),
+					Err(e) => {
+						warn!(target: "p_net", "Unable to read ingress from disk: {}", e);
+						None
+					}
+				}))
+			}
+
 			if self.ingress_roots.is_empty() {
-				return Ok(Async::Ready(
-					Some(mem::replace(&mut self.incoming, Vec::new()))
-				))
+				self.reading = Some(self.read_back());
+				continue;
 			}
 
 			let (para_id, messages) = match try_ready!(self.inner.poll()) {
@@ -481,7 +893,7 @@ impl<S> Future for ComputeIngress<S> where S: Stream<Item=IngressPair> {
 				Entry::Occupied(occupied) => {
 					let canon_root = occupied.get().clone();
 					let messages = messages.iter().map(|m| &m.0[..]);
-					if ::polkadot_validation::message_queue_root(messages) != canon_root {
+					if message_queue_root(messages) != canon_root {
 						continue;
 					}
 
@@ -497,6 +909,7 @@ impl<S> Future for ComputeIngress<S> where S: Stream<Item=IngressPair> {
 				.expect("incoming starts empty and only inserted when \
 					para_id not inserted before; qed");
 
+			let messages = self.hold(para_id, messages);
 			self.incoming.insert(pos, (para_id, messages));
 		}
 	}
@@ -506,12 +919,15 @@ impl<S> Future for ComputeIngress<S> where S: Stream<Item=IngressPair> {
 mod tests {
 	use super::*;
 	use substrate_primitives::H512;
+	use polkadot_primitives::message_roots::ingress_roots;
 	use futures::stream;
+	use proptest::prelude::*;
+	use tests::candidate_hash;
 
 	#[test]
 	fn deferred_statements_works() {
 		let mut deferred = DeferredStatements::new();
-		let hash = [1; 32].into();
+		let hash = candidate_hash(1);
 		let sig = H512::from([2; 64]).into();
 		let sender = [255; 32].into();
 
@@ -523,9 +939,10 @@ mod tests {
 
 		// pre-push.
 		{
-			let (signed, traces) = deferred.get_deferred(&hash);
+			let (signed, traces, overflowed) = deferred.get_deferred(&hash);
 			assert!(signed.is_empty());
 			assert!(traces.is_empty());
+			assert!(overflowed.is_none());
 		}
 
 		deferred.push(statement.clone());
@@ -533,7 +950,7 @@ mod tests {
 
 		// draining: second push should have been ignored.
 		{
-			let (signed, traces) = deferred.get_deferred(&hash);
+			let (signed, traces, _) = deferred.get_deferred(&hash);
 			assert_eq!(signed.len(), 1);
 
 			assert_eq!(traces.len(), 1);
@@ -543,9 +960,242 @@ mod tests {
 
 		// after draining
 		{
-			let (signed, traces) = deferred.get_deferred(&hash);
+			let (signed, traces, overflowed) = deferred.get_deferred(&hash);
 			assert!(signed.is_empty());
 			assert!(traces.is_empty());
+			assert!(overflowed.is_none());
+		}
+	}
+
+	#[test]
+	fn deferred_statements_overflow_to_disk() {
+		let store = OverflowStore::new(::overflow::OverflowConfig {
+			directory: ::std::env::temp_dir().join(format!("polkadot-deferred-{}", ::std::process::id())),
+			memory_limit: 0,
+		}).unwrap();
+
+		let mut deferred = DeferredStatements::new();
+		deferred.overflow = Some(store.log("statements".into()));
+
+		let hash = candidate_hash(1);
+		let statements: Vec<_> = (0..3u8).map(|i| SignedStatement {
+			statement: GenericStatement::Valid(hash),
+			sender: [i; 32].into(),
+			signature: H512::from([i; 64]).into(),
+		}).collect();
+
+		for statement in &statements {
+			deferred.push(statement.clone());
+		}
+
+		assert!(deferred.deferred.is_empty());
+		assert_eq!(deferred.overflow.as_ref().unwrap().len(), 3);
+
+		let (signed, traces, overflowed) = deferred.get_deferred(&hash);
+		assert!(signed.is_empty());
+		assert_eq!(traces.len(), 3);
+
+		let read: Vec<_> = overflowed.unwrap().wait().unwrap().iter()
+			.filter_map(|entry| SignedStatement::decode(&mut &entry[..]))
+			.collect();
+		assert_eq!(read, statements);
+		assert!(deferred.known_traces.is_empty());
+		assert_eq!(deferred.overflow.as_ref().unwrap().len(), 0);
+	}
+
+	#[test]
+	fn backing_deadline_expires_once() {
+		let deadlines = BackingDeadlines {
+			deadline: Duration::from_secs(5),
+			candidates: Mutex::new(HashMap::new()),
+			expired: Arc::new(AtomicUsize::new(0)),
+		};
+
+		let now = Instant::now();
+		let backed = candidate_hash(1);
+		let withheld = candidate_hash(2);
+
+		assert_eq!(deadlines.note_seen(backed, now), Some(now + Duration::from_secs(5)));
+		assert_eq!(deadlines.note_seen(backed, now + Duration::from_secs(1)), None);
+		assert!(deadlines.note_seen(withheld, now).is_some());
+
+		assert!(!deadlines.on_deadline(&backed, true));
+		assert!(deadlines.on_deadline(&withheld, false));
+		assert!(!deadlines.on_deadline(&withheld, false));
+
+		assert!(!deadlines.is_expired(&backed));
+		assert!(deadlines.is_expired(&withheld));
+		assert_eq!(deadlines.candidates.lock().get(&backed), Some(&DeadlineState::Met));
+		assert_eq!(deadlines.expired.load(Ordering::Relaxed), 1);
+	}
+
+	fn candidate(i: u8) -> CandidateReceipt {
+		CandidateReceipt {
+			parachain_index: (i as u32).into(),
+			collator: [i; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![i]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: Default::default(),
+			ingress_watermark: 0,
+		}
+	}
+
+	fn statement(statement: GenericStatement, sender: u8) -> SignedStatement {
+		SignedStatement {
+			statement,
+			signature: Default::default(),
+			sender: [sender; 32].into(),
+		}
+	}
+
+	fn candidate_hash(statement: &SignedStatement) -> CandidateHash {
+		match statement.statement {
+			GenericStatement::Candidate(ref c) => c.hash(),
+			GenericStatement::Valid(hash) | GenericStatement::Invalid(hash) => hash,
+		}
+	}
+
+	fn is_candidate(statement: &SignedStatement) -> bool {
+		match statement.statement {
+			GenericStatement::Candidate(_) => true,
+			_ => false,
+		}
+	}
+
+	// statements on up to 4 candidates, some of which are never proposed, in random order
+	// with duplicates. yields the statements and the hashes of the candidates never proposed.
+	fn statements() -> impl Strategy<Value=(Vec<SignedStatement>, HashSet<CandidateHash>)> {
+		(
+			prop::collection::vec(any::<bool>(), 1..5),
+			prop::collection::vec((any::<u8>(), 0..8u8, any::<bool>()), 0..24),
+			prop::collection::vec(any::<usize>(), 0..8),
+		).prop_flat_map(|(proposed, votes, duplicates)| {
+			let mut statements = Vec::new();
+			let mut missing = HashSet::new();
+			for (i, &proposed) in proposed.iter().enumerate() {
+				let receipt = candidate(i as u8 + 1);
+				if proposed {
+					statements.push(statement(GenericStatement::Candidate(receipt), 100 + i as u8));
+				} else {
+					missing.insert(receipt.hash());
+				}
+			}
+
+			for (c, sender, valid) in votes {
+				let hash = candidate((c as usize % proposed.len()) as u8 + 1).hash();
+				let vote = if valid { GenericStatement::Valid(hash) } else { GenericStatement::Invalid(hash) };
+				let vote = statement(vote, sender);
+				if !statements.contains(&vote) {
+					statements.push(vote);
+				}
+			}
+
+			if !statements.is_empty() {
+				for i in duplicates {
+					let duplicate = statements[i % statements.len()].clone();
+					statements.push(duplicate);
+				}
+			}
+
+			(Just(statements).prop_shuffle(), Just(missing))
+		})
+	}
+
+	struct Routed {
+		// statements in the order they were handed to the table.
+		imported: Vec<SignedStatement>,
+		// number of times each statement was released from the deferred set.
+		released: HashMap<Vec<u8>, usize>,
+		deferred: DeferredStatements,
+	}
+
+	// route statements as the router does, with a model table that learns
+	// of candidates as their statements are imported.
+	fn route_all<'a, I>(statements: I, included: &HashSet<CandidateHash>) -> Routed
+		where I: IntoIterator<Item=&'a SignedStatement>
+	{
+		let mut deferred = DeferredStatements::new();
+		let mut known = HashSet::new();
+		let mut imported = Vec::new();
+		let mut released = HashMap::new();
+
+		for statement in statements {
+			let routed = deferred.route(
+				statement.clone(),
+				|hash| known.contains(hash),
+				|hash| included.contains(hash),
+			);
+
+			if let Some((hash, statements, _)) = routed {
+				known.insert(hash);
+				for released_statement in statements.iter().skip(1) {
+					*released.entry(released_statement.encode()).or_insert(0) += 1;
+				}
+				imported.extend(statements);
+			}
+		}
+
+		Routed { imported, released, deferred }
+	}
+
+	proptest! {
+		#[test]
+		fn statements_imported_once_after_candidate((statements, missing) in statements()) {
+			// the gossip layer filters out messages it has already seen.
+			let mut seen = HashSet::new();
+			let unique: Vec<_> = statements.iter()
+				.filter(|s| seen.insert(s.encode()))
+				.collect();
+
+			let routed = route_all(unique.iter().cloned(), &HashSet::new());
+
+			let mut imported = HashSet::new();
+			let mut proposed = HashSet::new();
+			for statement in &routed.imported {
+				prop_assert!(imported.insert(statement.encode()), "statement imported twice");
+
+				let hash = candidate_hash(statement);
+				if is_candidate(statement) {
+					proposed.insert(hash);
+				} else {
+					prop_assert!(proposed.contains(&hash), "statement imported before its candidate");
+				}
+			}
+
+			// everything on a proposed candidate is imported, and only that is left deferred.
+			for statement in unique {
+				let hash = candidate_hash(statement);
+				prop_assert_eq!(imported.contains(&statement.encode()), !missing.contains(&hash));
+			}
+
+			let still_deferred: usize = routed.deferred.deferred.values().map(|v| v.len()).sum();
+			prop_assert!(routed.deferred.deferred.keys().all(|hash| missing.contains(hash)));
+			let traces: usize = routed.deferred.known_traces.values().map(|t| t.len()).sum();
+			prop_assert_eq!(traces, still_deferred);
+		}
+
+		#[test]
+		fn duplicates_released_once_and_no_traces_leak((statements, missing) in statements()) {
+			let routed = route_all(&statements, &HashSet::new());
+
+			prop_assert!(routed.released.values().all(|&n| n == 1), "statement released twice");
+
+			if missing.is_empty() {
+				prop_assert!(routed.deferred.deferred.is_empty());
+				prop_assert!(routed.deferred.known_traces.is_empty());
+			}
+		}
+
+		#[test]
+		fn statements_on_included_candidates_never_deferred((statements, missing) in statements()) {
+			let routed = route_all(&statements, &missing);
+
+			prop_assert!(routed.deferred.deferred.is_empty());
+			prop_assert!(routed.deferred.known_traces.is_empty());
+			prop_assert!(routed.imported.iter().all(|s| !missing.contains(&candidate_hash(s))));
 		}
 	}
 
@@ -569,12 +1219,7 @@ mod tests {
 			),
 		];
 
-		let roots: HashMap<_, _> = actual_messages.iter()
-			.map(|&(para_id, ref messages)| (
-				para_id,
-				::polkadot_validation::message_queue_root(messages.iter().map(|m| &m.0)),
-			))
-			.collect();
+		let roots: HashMap<_, _> = ingress_roots(&actual_messages).into_iter().collect();
 
 		let inputs = [
 			(
@@ -609,6 +1254,9 @@ mod tests {
 		let ingress = ComputeIngress {
 			ingress_roots: roots,
 			incoming: Vec::new(),
+			resident: 0,
+			overflow: None,
+			reading: None,
 			inner: stream::iter_ok::<_, ()>(inputs.iter().cloned()),
 		};
 
@@ -0,0 +1,439 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Simulation of the backing of candidates by validator groups.
+//!
+//! Every simulated validator holds a statement table, and defers statements on
+//! candidates it doesn't know yet in the same way the router does. One member of each
+//! group proposes a candidate, and the other members vote on it once they receive it.
+//! Every statement is sent to all other validators, with each copy lost or delayed
+//! independently. The time until each validator sees each candidate backed is recorded.
+
+use polkadot_primitives::SessionKey;
+use polkadot_primitives::parachain::{CandidateReceipt, CandidateHash, HeadData, Id as ParaId};
+use polkadot_statement_table::{Context as TableContext, Table};
+use polkadot_validation::{GenericStatement, SignedStatement};
+use codec::Encode;
+
+use router::DeferredStatements;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The distribution of the latency of a single statement between two validators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatencyDistribution {
+	/// Every statement takes the same time.
+	Constant(Duration),
+	/// Uniformly distributed between a minimum and a maximum.
+	Uniform(Duration, Duration),
+	/// Exponentially distributed with the given mean.
+	Exponential(Duration),
+}
+
+impl FromStr for LatencyDistribution {
+	type Err = String;
+
+	/// Parse `constant:<ms>`, `uniform:<min ms>:<max ms>` or `exponential:<mean ms>`.
+	fn from_str(s: &str) -> Result<Self, String> {
+		let parts: Vec<_> = s.split(':').collect();
+		let millis = |part: &str| part.parse::<u64>()
+			.map(Duration::from_millis)
+			.map_err(|e| format!("Invalid latency {:?}: {}", part, e));
+
+		match parts.as_slice() {
+			["constant", latency] => Ok(LatencyDistribution::Constant(millis(latency)?)),
+			["uniform", min, max] => {
+				let (min, max) = (millis(min)?, millis(max)?);
+				if min > max {
+					return Err(format!("Minimum latency {:?} exceeds the maximum {:?}", min, max));
+				}
+				Ok(LatencyDistribution::Uniform(min, max))
+			}
+			["exponential", mean] => Ok(LatencyDistribution::Exponential(millis(mean)?)),
+			_ => Err(format!(
+				"Unknown latency distribution {:?}. Expected constant:<ms>, uniform:<ms>:<ms> or exponential:<ms>",
+				s,
+			)),
+		}
+	}
+}
+
+impl LatencyDistribution {
+	fn sample(&self, rng: &mut Rng) -> u64 {
+		match *self {
+			LatencyDistribution::Constant(latency) => micros(latency),
+			LatencyDistribution::Uniform(min, max) => {
+				let (min, max) = (micros(min), micros(max));
+				min + (rng.next_f64() * (max - min) as f64) as u64
+			}
+			LatencyDistribution::Exponential(mean) =>
+				(-(micros(mean) as f64) * (1.0 - rng.next_f64()).ln()) as u64,
+		}
+	}
+}
+
+/// Parameters of a backing simulation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulationConfig {
+	/// Number of validators. Validators beyond the last full group only observe.
+	pub validators: usize,
+	/// Number of validators in each parachain group.
+	pub group_size: usize,
+	/// Probability of each copy of a statement being lost.
+	pub message_loss: f64,
+	/// Latency of each copy of a statement.
+	pub latency: LatencyDistribution,
+	/// Seed of the random number generator, so that runs can be reproduced.
+	pub seed: u64,
+}
+
+/// The outcome of a backing simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+	/// Number of candidates proposed, one per group.
+	pub candidates: usize,
+	/// Number of times a validator saw a candidate backed.
+	pub backed: usize,
+	/// Number of times a validator never saw a candidate backed.
+	pub unbacked: usize,
+	/// Number of statements sent between validators, including lost ones.
+	pub messages: usize,
+	/// Time to backing at the 50th, 90th, 99th and 100th percentile, where any was backed.
+	pub percentiles: Vec<(u8, Duration)>,
+}
+
+/// The percentiles of the time to backing which are reported.
+pub const REPORTED_PERCENTILES: [u8; 4] = [50, 90, 99, 100];
+
+// xorshift64*, good enough for a simulation and reproducible across platforms.
+struct Rng(u64);
+
+impl Rng {
+	fn new(seed: u64) -> Self {
+		// the state must never be zero.
+		Rng(seed.wrapping_add(0x9e37_79b9_7f4a_7c15) | 1)
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+	}
+
+	// uniform in `[0, 1)`.
+	fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+}
+
+fn micros(duration: Duration) -> u64 {
+	duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())
+}
+
+struct SimContext {
+	groups: HashMap<SessionKey, ParaId>,
+	requisite_votes: usize,
+}
+
+impl TableContext for SimContext {
+	fn is_member_of(&self, authority: &SessionKey, group: &ParaId) -> bool {
+		self.groups.get(authority) == Some(group)
+	}
+
+	fn requisite_votes(&self, _group: &ParaId) -> usize {
+		self.requisite_votes
+	}
+}
+
+struct Node {
+	key: SessionKey,
+	group: Option<ParaId>,
+	table: Table<SimContext>,
+	deferred: DeferredStatements,
+	// time each candidate was first seen backed, in microseconds.
+	backed: HashMap<CandidateHash, u64>,
+}
+
+// a statement arriving at a validator.
+struct Delivery {
+	at: u64,
+	// orders deliveries at the same time by when they were sent.
+	seq: u64,
+	to: usize,
+	statement: SignedStatement,
+}
+
+impl PartialEq for Delivery {
+	fn eq(&self, other: &Self) -> bool {
+		(self.at, self.seq) == (other.at, other.seq)
+	}
+}
+
+impl Eq for Delivery {}
+
+impl PartialOrd for Delivery {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Delivery {
+	// reversed, for the earliest delivery to come first out of the heap.
+	fn cmp(&self, other: &Self) -> Ordering {
+		(other.at, other.seq).cmp(&(self.at, self.seq))
+	}
+}
+
+struct Simulation<'a> {
+	config: &'a SimulationConfig,
+	context: SimContext,
+	nodes: Vec<Node>,
+	queue: BinaryHeap<Delivery>,
+	rng: Rng,
+	seq: u64,
+	messages: usize,
+}
+
+impl<'a> Simulation<'a> {
+	// send a statement from a validator to all others.
+	fn broadcast(&mut self, from: usize, now: u64, statement: &SignedStatement) {
+		for to in (0..self.nodes.len()).filter(|&to| to != from) {
+			self.messages += 1;
+			if self.rng.next_f64() < self.config.message_loss { continue }
+
+			self.seq += 1;
+			self.queue.push(Delivery {
+				at: now + self.config.latency.sample(&mut self.rng),
+				seq: self.seq,
+				to,
+				statement: statement.clone(),
+			});
+		}
+	}
+
+	// import a statement at a validator, returning the statement it issues in response.
+	fn import(&mut self, at: usize, now: u64, statement: SignedStatement) -> Option<SignedStatement> {
+		let context = &self.context;
+		let node = &mut self.nodes[at];
+
+		let routed = {
+			let table = &node.table;
+			node.deferred.route(statement, |hash| table.get_candidate(hash).is_some(), |_| false)
+		};
+
+		let (candidate_hash, statements) = match routed {
+			Some(routed) => routed,
+			None => return None,
+		};
+
+		let mut response = None;
+		for statement in statements {
+			if let GenericStatement::Candidate(ref candidate) = statement.statement {
+				if node.group == Some(candidate.parachain_index) && statement.sender != node.key {
+					response = Some(SignedStatement {
+						statement: GenericStatement::Valid(candidate_hash),
+						signature: Default::default(),
+						sender: node.key,
+					});
+				}
+			}
+
+			let summary = node.table.import_statement(context, statement);
+			if let Some(summary) = summary {
+				if summary.validity_votes >= context.requisite_votes {
+					node.backed.entry(candidate_hash).or_insert(now);
+				}
+			}
+		}
+
+		response
+	}
+
+	// import a statement at a validator, and send it along with any response on.
+	fn issue(&mut self, at: usize, now: u64, statement: SignedStatement) {
+		self.broadcast(at, now, &statement);
+		if let Some(response) = self.import(at, now, statement) {
+			self.issue(at, now, response);
+		}
+	}
+}
+
+fn session_key(index: usize) -> SessionKey {
+	let mut key = [0; 32];
+	let encoded = (index as u64).encode();
+	key[..encoded.len()].copy_from_slice(&encoded);
+	key.into()
+}
+
+/// Simulate the backing of one candidate in each group.
+pub fn simulate(config: &SimulationConfig) -> Result<SimulationReport, String> {
+	if config.group_size == 0 || config.group_size > config.validators {
+		return Err(format!(
+			"Group size must be between 1 and the number of validators ({}), not {}",
+			config.validators, config.group_size,
+		));
+	}
+
+	if !(config.message_loss >= 0.0 && config.message_loss < 1.0) {
+		return Err(format!("Message loss must be at least 0 and below 1, not {}", config.message_loss));
+	}
+
+	let groups = config.validators / config.group_size;
+	let group_of = |index: usize| if index < groups * config.group_size {
+		Some(ParaId::from((index / config.group_size) as u32))
+	} else {
+		None
+	};
+
+	let context = SimContext {
+		groups: (0..config.validators)
+			.filter_map(|index| group_of(index).map(|group| (session_key(index), group)))
+			.collect(),
+		requisite_votes: config.group_size / 2 + config.group_size % 2,
+	};
+
+	let nodes = (0..config.validators).map(|index| Node {
+		key: session_key(index),
+		group: group_of(index),
+		table: Table::default(),
+		deferred: DeferredStatements::new(),
+		backed: HashMap::new(),
+	}).collect();
+
+	let mut simulation = Simulation {
+		config,
+		context,
+		nodes,
+		queue: BinaryHeap::new(),
+		rng: Rng::new(config.seed),
+		seq: 0,
+		messages: 0,
+	};
+
+	// the first member of each group proposes a candidate.
+	for group in 0..groups {
+		let proposer = group * config.group_size;
+		let candidate = CandidateReceipt {
+			parachain_index: ParaId::from(group as u32),
+			collator: Default::default(),
+			signature: Default::default(),
+			head_data: HeadData((group as u64).encode()),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: Default::default(),
+			ingress_watermark: 0,
+		};
+
+		let statement = SignedStatement {
+			statement: GenericStatement::Candidate(candidate),
+			signature: Default::default(),
+			sender: simulation.nodes[proposer].key,
+		};
+		simulation.issue(proposer, 0, statement);
+	}
+
+	while let Some(delivery) = simulation.queue.pop() {
+		if let Some(response) = simulation.import(delivery.to, delivery.at, delivery.statement) {
+			simulation.issue(delivery.to, delivery.at, response);
+		}
+	}
+
+	let mut times: Vec<u64> = simulation.nodes.iter()
+		.flat_map(|node| node.backed.values().cloned())
+		.collect();
+	times.sort();
+
+	let percentiles = if times.is_empty() {
+		Vec::new()
+	} else {
+		REPORTED_PERCENTILES.iter().map(|&p| {
+			let rank = (times.len() * p as usize + 99) / 100;
+			let time = times[rank.saturating_sub(1)];
+			(p, Duration::from_micros(time))
+		}).collect()
+	};
+
+	let observations = groups * config.validators;
+	Ok(SimulationReport {
+		candidates: groups,
+		backed: times.len(),
+		unbacked: observations - times.len(),
+		messages: simulation.messages,
+		percentiles,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_latency_distributions() {
+		assert_eq!(
+			"constant:100".parse(),
+			Ok(LatencyDistribution::Constant(Duration::from_millis(100))),
+		);
+		assert_eq!(
+			"uniform:50:200".parse(),
+			Ok(LatencyDistribution::Uniform(Duration::from_millis(50), Duration::from_millis(200))),
+		);
+		assert_eq!(
+			"exponential:80".parse(),
+			Ok(LatencyDistribution::Exponential(Duration::from_millis(80))),
+		);
+		assert!("uniform:200:50".parse::<LatencyDistribution>().is_err());
+		assert!("normal:100".parse::<LatencyDistribution>().is_err());
+	}
+
+	#[test]
+	fn lossless_backing_takes_two_hops() {
+		let report = simulate(&SimulationConfig {
+			validators: 10,
+			group_size: 5,
+			message_loss: 0.0,
+			latency: LatencyDistribution::Constant(Duration::from_millis(100)),
+			seed: 0,
+		}).unwrap();
+
+		// the candidate and then the votes on it travel to every validator.
+		assert_eq!(report.candidates, 2);
+		assert_eq!(report.backed, 20);
+		assert_eq!(report.unbacked, 0);
+		assert_eq!(report.percentiles[0], (50, Duration::from_millis(200)));
+		assert_eq!(report.percentiles[3], (100, Duration::from_millis(200)));
+	}
+
+	#[test]
+	fn lost_statements_leave_candidates_unbacked() {
+		let config = SimulationConfig {
+			validators: 20,
+			group_size: 5,
+			message_loss: 0.5,
+			latency: LatencyDistribution::Uniform(Duration::from_millis(10), Duration::from_millis(100)),
+			seed: 42,
+		};
+
+		let report = simulate(&config).unwrap();
+		assert!(report.unbacked > 0);
+		assert_eq!(report.backed + report.unbacked, 4 * 20);
+		assert_eq!(simulate(&config), Ok(report));
+	}
+}
@@ -16,13 +16,13 @@
 
 //! Tests for polkadot and validation network.
 
-use super::{PolkadotProtocol, Status, Message, FullStatus};
-use validation::{ValidationSession, Knowledge};
+use super::{PolkadotProtocol, Status, Message, FullStatus, Capabilities};
+use validation::{ValidationSession, Knowledge, CandidateSummary};
 
 use parking_lot::Mutex;
-use polkadot_validation::GenericStatement;
-use polkadot_primitives::{Block, SessionKey};
-use polkadot_primitives::parachain::{CandidateReceipt, HeadData, BlockData};
+use polkadot_validation::{GenericStatement, SignedStatement, Statement};
+use polkadot_primitives::{Block, Hash, SessionKey};
+use polkadot_primitives::parachain::{CandidateHash, CandidateReceipt, HeadData, BlockData};
 use substrate_primitives::H512;
 use codec::Encode;
 use substrate_network::{
@@ -76,6 +76,11 @@ impl TestContext {
 	}
 }
 
+/// An arbitrary candidate hash, for tests which don't need a real receipt.
+pub(crate) fn candidate_hash(byte: u8) -> CandidateHash {
+	::codec::Decode::decode(&mut &[byte; 32][..]).expect("any 32 bytes are a candidate hash; qed")
+}
+
 fn make_status(status: &Status, roles: Roles) -> FullStatus {
 	FullStatus {
 		version: 1,
@@ -87,13 +92,18 @@ fn make_status(status: &Status, roles: Roles) -> FullStatus {
 	}
 }
 
-fn make_validation_session(local_key: SessionKey) -> (ValidationSession, Arc<Mutex<Knowledge>>) {
+fn make_validation_session(parent_hash: Hash, local_key: SessionKey) -> (ValidationSession, Arc<Mutex<Knowledge>>) {
 	let knowledge = Arc::new(Mutex::new(Knowledge::new()));
+	knowledge.lock().note_session(parent_hash);
 	let c = ValidationSession::new(knowledge.clone(), local_key);
 
 	(c, knowledge)
 }
 
+fn signed(sender: SessionKey, statement: Statement) -> SignedStatement {
+	SignedStatement { statement, signature: Default::default(), sender }
+}
+
 fn on_message(protocol: &mut PolkadotProtocol, ctx: &mut TestContext, from: NodeIndex, message: Message) {
 	let encoded = message.encode();
 	protocol.on_message(ctx, from, &mut Some(GenericMessage::ChainSpecific(encoded)));
@@ -108,8 +118,8 @@ fn sends_session_key() {
 	let parent_hash = [0; 32].into();
 	let local_key = [1; 32].into();
 
-	let validator_status = Status { collating_for: None };
-	let collator_status = Status { collating_for: Some(([2; 32].into(), 5.into())) };
+	let validator_status = Status { collating_for: None, capabilities: Capabilities::supported() };
+	let collator_status = Status { collating_for: Some(([2; 32].into(), 5.into())), capabilities: Capabilities::supported() };
 
 	{
 		let mut ctx = TestContext::default();
@@ -119,7 +129,7 @@ fn sends_session_key() {
 
 	{
 		let mut ctx = TestContext::default();
-		let (session, _knowledge) = make_validation_session(local_key);
+		let (session, _knowledge) = make_validation_session(parent_hash, local_key);
 		protocol.new_validation_session(&mut ctx, parent_hash, session);
 		assert!(ctx.has_message(peer_a, Message::SessionKey(local_key)));
 	}
@@ -151,18 +161,19 @@ fn fetches_from_those_with_knowledge() {
 		egress_queue_roots: Vec::new(),
 		fees: 1_000_000,
 		block_data_hash,
+		ingress_watermark: 0,
 	};
 
 	let candidate_hash = candidate_receipt.hash();
 	let a_key = [3; 32].into();
 	let b_key = [4; 32].into();
 
-	let status = Status { collating_for: None };
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
 
-	let (session, knowledge) = make_validation_session(local_key);
+	let (session, knowledge) = make_validation_session(parent_hash, local_key);
 	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session);
 
-	knowledge.lock().note_statement(a_key, &GenericStatement::Valid(candidate_hash));
+	knowledge.lock().note_statement(parent_hash, &signed(a_key, GenericStatement::Valid(candidate_hash)));
 	let recv = protocol.fetch_block_data(&mut TestContext::default(), &candidate_receipt, parent_hash);
 
 	// connect peer A
@@ -180,7 +191,7 @@ fn fetches_from_those_with_knowledge() {
 		assert!(ctx.has_message(peer_a, Message::RequestBlockData(1, parent_hash, candidate_hash)));
 	}
 
-	knowledge.lock().note_statement(b_key, &GenericStatement::Valid(candidate_hash));
+	knowledge.lock().note_statement(parent_hash, &signed(b_key, GenericStatement::Valid(candidate_hash)));
 
 	// peer B connects and sends session key. request already assigned to A
 	{
@@ -227,12 +238,13 @@ fn fetches_available_block_data() {
 		egress_queue_roots: Vec::new(),
 		fees: 1_000_000,
 		block_data_hash,
+		ingress_watermark: 0,
 	};
 
 	let candidate_hash = candidate_receipt.hash();
 	let av_store = ::av_store::Store::new_in_memory();
 
-	let status = Status { collating_for: None };
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
 
 	protocol.register_availability_store(av_store.clone());
 
@@ -265,7 +277,7 @@ fn remove_bad_collator() {
 	let who = 1;
 	let account_id = [2; 32].into();
 
-	let status = Status { collating_for: Some((account_id, 5.into())) };
+	let status = Status { collating_for: Some((account_id, 5.into())), capabilities: Capabilities::supported() };
 
 	{
 		let mut ctx = TestContext::default();
@@ -289,8 +301,8 @@ fn many_session_keys() {
 	let local_key_a = [3; 32].into();
 	let local_key_b = [4; 32].into();
 
-	let (session_a, _knowledge_a) = make_validation_session(local_key_a);
-	let (session_b, _knowledge_b) = make_validation_session(local_key_b);
+	let (session_a, _knowledge_a) = make_validation_session(parent_a, local_key_a);
+	let (session_b, _knowledge_b) = make_validation_session(parent_b, local_key_b);
 
 	protocol.new_validation_session(&mut TestContext::default(), parent_a, session_a);
 	protocol.new_validation_session(&mut TestContext::default(), parent_b, session_b);
@@ -303,7 +315,7 @@ fn many_session_keys() {
 	{
 		let mut ctx = TestContext::default();
 
-		let status = Status { collating_for: None };
+		let status = Status { collating_for: None, capabilities: Capabilities::supported() };
 		protocol.on_connect(&mut ctx, peer_a, make_status(&status, Roles::AUTHORITY));
 
 		assert!(ctx.has_message(peer_a, Message::SessionKey(local_key_a)));
@@ -317,10 +329,646 @@ fn many_session_keys() {
 	{
 		let mut ctx = TestContext::default();
 
-		let status = Status { collating_for: None };
+		let status = Status { collating_for: None, capabilities: Capabilities::supported() };
 		protocol.on_connect(&mut ctx, peer_b, make_status(&status, Roles::AUTHORITY));
 
 		assert!(!ctx.has_message(peer_b, Message::SessionKey(local_key_a)));
 		assert!(ctx.has_message(peer_b, Message::SessionKey(local_key_b)));
 	}
 }
+
+#[test]
+fn answers_knowledge_requests() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let peer_b = 2;
+	let parent_hash = [0; 32].into();
+	let unknown_parent = [1; 32].into();
+	let local_key = [2; 32].into();
+	let a_key = [3; 32].into();
+	let b_key = [4; 32].into();
+	let candidate_hash = candidate_hash(5);
+
+	let (session, knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session.with_validators(&[a_key]));
+
+	knowledge.lock().note_statement(parent_hash, &signed(a_key, GenericStatement::Valid(candidate_hash)));
+	knowledge.lock().note_statement(parent_hash, &signed(a_key, GenericStatement::Valid(candidate_hash)));
+	knowledge.lock().note_statement(parent_hash, &signed(b_key, GenericStatement::Invalid(candidate_hash)));
+	knowledge.lock().note_candidate(parent_hash, candidate_hash, Some(BlockData(vec![1, 2, 3])), None);
+
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&status, Roles::AUTHORITY));
+		protocol.on_connect(&mut ctx, peer_b, make_status(&status, Roles::FULL));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_a, Message::RequestKnowledge(1, parent_hash));
+		assert!(ctx.has_message(peer_a, Message::Knowledge(1, Some(vec![CandidateSummary {
+			candidate_hash,
+			has_block_data: true,
+			has_extrinsic: false,
+			statements: vec![
+				signed(a_key, GenericStatement::Valid(candidate_hash)),
+				signed(b_key, GenericStatement::Invalid(candidate_hash)),
+			],
+		}]))));
+	}
+
+	// knowledge isn't shared with those outside the session's validator set.
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_b, Message::RequestKnowledge(3, parent_hash));
+		assert!(ctx.has_message(peer_b, Message::Knowledge(3, None)));
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_a, Message::RequestKnowledge(2, unknown_parent));
+		assert!(ctx.has_message(peer_a, Message::Knowledge(2, None)));
+	}
+}
+
+#[test]
+fn fetches_knowledge_from_validators() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let a_key = [2; 32].into();
+	let unknown_key = [3; 32].into();
+
+	let (session, _knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session);
+
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&status, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+	}
+
+	// unconnected validators cannot be asked.
+	let recv = protocol.fetch_knowledge(&mut TestContext::default(), unknown_key, parent_hash);
+	assert!(recv.wait().is_err());
+
+	let recv = {
+		let mut ctx = TestContext::default();
+		let recv = protocol.fetch_knowledge(&mut ctx, a_key, parent_hash);
+		assert!(ctx.has_message(peer_a, Message::RequestKnowledge(1, parent_hash)));
+		recv
+	};
+
+	let summaries = vec![CandidateSummary {
+		candidate_hash: candidate_hash(4),
+		has_block_data: true,
+		has_extrinsic: true,
+		statements: vec![signed(a_key, GenericStatement::Valid(candidate_hash(4)))],
+	}];
+
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_a, Message::Knowledge(1, Some(summaries.clone())));
+		assert!(ctx.disabled.is_empty());
+		assert_eq!(recv.wait().unwrap(), Some(summaries));
+	}
+
+	// unsolicited responses are punished.
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, peer_a, Message::Knowledge(1, None));
+		assert!(ctx.disabled.contains(&peer_a));
+	}
+}
+
+#[test]
+fn catches_up_with_validators_of_session() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let peer_b = 2;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let a_key = [2; 32].into();
+	let b_key = [3; 32].into();
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
+
+	let (session, _knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session.with_validators(&[a_key]));
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&status, Roles::AUTHORITY));
+		protocol.on_connect(&mut ctx, peer_b, make_status(&status, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+		on_message(&mut protocol, &mut ctx, peer_b, Message::SessionKey(b_key));
+	}
+
+	let mut ctx = TestContext::default();
+	let requests = protocol.catch_up_knowledge(&mut ctx, parent_hash);
+	assert_eq!(requests.len(), 1);
+	assert!(ctx.has_message(peer_a, Message::RequestKnowledge(1, parent_hash)));
+	assert!(!ctx.has_message(peer_b, Message::RequestKnowledge(1, parent_hash)));
+}
+
+#[test]
+fn asks_only_capable_validators_for_knowledge() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let peer_b = 2;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let a_key = [2; 32].into();
+	let b_key = [3; 32].into();
+	let capable = Status { collating_for: None, capabilities: Capabilities::supported() };
+	let incapable = Status { collating_for: None, capabilities: Capabilities::LEGACY };
+
+	let (session, _knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session.with_validators(&[a_key, b_key]));
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&capable, Roles::AUTHORITY));
+		protocol.on_connect(&mut ctx, peer_b, make_status(&incapable, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+		on_message(&mut protocol, &mut ctx, peer_b, Message::SessionKey(b_key));
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		let requests = protocol.catch_up_knowledge(&mut ctx, parent_hash);
+		assert_eq!(requests.len(), 1);
+		assert!(ctx.has_message(peer_a, Message::RequestKnowledge(1, parent_hash)));
+		assert!(ctx.messages.iter().all(|&(who, _)| who != peer_b));
+	}
+
+	let mut ctx = TestContext::default();
+	let recv = protocol.fetch_knowledge(&mut ctx, b_key, parent_hash);
+	assert!(ctx.messages.is_empty());
+	assert!(recv.wait().is_err());
+}
+
+#[test]
+fn pushes_statements_between_validators_only() {
+	use futures::{Stream, sync::mpsc};
+
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let validator = 1;
+	let full_node = 2;
+	let legacy_validator = 3;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
+	let legacy = Status { collating_for: None, capabilities: Capabilities::LEGACY };
+
+	let (pushed, pushed_stream) = mpsc::channel(16);
+	let (session, _knowledge) = make_validation_session(parent_hash, local_key);
+	let session = session.with_validators(&[[2; 32].into()]).with_pushed(pushed);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session);
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, validator, make_status(&status, Roles::AUTHORITY));
+		protocol.on_connect(&mut ctx, full_node, make_status(&status, Roles::FULL));
+		protocol.on_connect(&mut ctx, legacy_validator, make_status(&legacy, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, validator, Message::SessionKey([2; 32].into()));
+		on_message(&mut protocol, &mut ctx, legacy_validator, Message::SessionKey([3; 32].into()));
+	}
+
+	let statements = vec![vec![1, 2, 3]];
+	{
+		let mut ctx = TestContext::default();
+		protocol.propagate_statements(&mut ctx, parent_hash, statements.clone(), 4, &[5; 32].into(), 0);
+		assert!(ctx.has_message(validator, Message::Statements(parent_hash, statements.clone())));
+		assert!(!ctx.has_message(full_node, Message::Statements(parent_hash, statements.clone())));
+		assert!(!ctx.has_message(legacy_validator, Message::Statements(parent_hash, statements.clone())));
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, full_node, Message::Statements(parent_hash, vec![vec![4]]));
+		assert!(ctx.disabled.contains(&full_node));
+
+		// validators outside the session's validator set can't push either.
+		on_message(&mut protocol, &mut ctx, legacy_validator, Message::Statements(parent_hash, vec![vec![5]]));
+		assert!(ctx.disabled.contains(&legacy_validator));
+
+		on_message(&mut protocol, &mut ctx, validator, Message::Statements(parent_hash, statements.clone()));
+		assert!(!ctx.disabled.contains(&validator));
+	}
+
+	drop(protocol);
+	assert_eq!(pushed_stream.collect().wait().unwrap(), statements);
+}
+
+#[test]
+fn private_collations_wait_for_encryption_key() {
+	use codec::Decode;
+	use collator_pool::Role;
+	use polkadot_primitives::encryption::EncryptionKey;
+	use polkadot_primitives::parachain::Collation;
+	use substrate_primitives::ed25519;
+	use wire::Versioned;
+
+	let mut protocol = PolkadotProtocol::new(Some(([2; 32].into(), 5.into())))
+		.with_private_collations(true);
+
+	let validator = 1;
+	let validator_pair = ed25519::Pair::from_seed(&[3; 32]);
+	let validator_key: SessionKey = validator_pair.public().into();
+	let encryption_key = EncryptionKey::generate();
+
+	let relay_parent = [4; 32].into();
+	let collation = Collation {
+		block_data: BlockData(vec![1, 2, 3]),
+		receipt: CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [2; 32].into(),
+			signature: H512::from([1; 64]).into(),
+			head_data: HeadData(vec![1, 2, 3]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: [5; 32].into(),
+			ingress_watermark: 0,
+		},
+	};
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, validator, make_status(&Status { collating_for: None, capabilities: Capabilities::supported() }, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, validator, Message::SessionKey(validator_key));
+		on_message(&mut protocol, &mut ctx, validator, Message::CollatorRole(Role::Primary));
+
+		let targets = vec![validator_key].into_iter().collect();
+		protocol.add_local_collation(&mut ctx, relay_parent, targets, collation.clone());
+		assert!(ctx.messages.is_empty());
+	}
+
+	// an announcement not signed by the session key is rejected.
+	{
+		let mut ctx = TestContext::default();
+		let other_pair = ed25519::Pair::from_seed(&[6; 32]);
+		let signature = encryption_key.announce(&other_pair);
+		on_message(&mut protocol, &mut ctx, validator, Message::EncryptionKey(validator_key, encryption_key.public(), signature));
+		assert!(ctx.disabled.contains(&validator));
+		assert!(ctx.messages.is_empty());
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		let signature = encryption_key.announce(&validator_pair);
+		on_message(&mut protocol, &mut ctx, validator, Message::EncryptionKey(validator_key, encryption_key.public(), signature));
+		assert_eq!(ctx.messages.len(), 1);
+
+		let payload = match ctx.messages[0] {
+			(who, GenericMessage::ChainSpecific(ref data)) if who == validator => match Message::decode(&mut &data[..]) {
+				Some(Message::EncryptedCollation(parent, payload)) => {
+					assert_eq!(parent, relay_parent);
+					payload
+				}
+				other => panic!("unexpected message {:?}", other),
+			},
+			_ => panic!("unexpected message"),
+		};
+
+		let decrypted = encryption_key.decrypt(&payload).expect("encrypted to validator key; qed");
+		let decoded = Versioned::<Collation>::decode(&mut &decrypted[..]).unwrap().into_inner();
+		assert_eq!(decoded, collation);
+	}
+}
+
+#[test]
+fn announces_encryption_key_to_capable_peers_only() {
+	use polkadot_primitives::encryption::EncryptionKey;
+	use substrate_primitives::ed25519;
+
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let peer_b = 2;
+	let parent_hash = [0; 32].into();
+	let local_pair = ed25519::Pair::from_seed(&[1; 32]);
+	let local_key: SessionKey = local_pair.public().into();
+	let capable = Status { collating_for: Some(([2; 32].into(), 5.into())), capabilities: Capabilities::supported() };
+	let incapable = Status { collating_for: Some(([3; 32].into(), 6.into())), capabilities: Capabilities::LEGACY };
+
+	let encryption_key = EncryptionKey::generate();
+	let public = encryption_key.public();
+	let signature = encryption_key.announce(&local_pair);
+	protocol.set_encryption_key(&local_pair, encryption_key);
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&capable, Roles::NONE));
+		protocol.on_connect(&mut ctx, peer_b, make_status(&incapable, Roles::NONE));
+	}
+
+	// the key is announced alongside the session key of a new session.
+	let mut ctx = TestContext::default();
+	let (session, _knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut ctx, parent_hash, session);
+
+	assert!(ctx.has_message(peer_a, Message::SessionKey(local_key)));
+	assert!(ctx.has_message(peer_b, Message::SessionKey(local_key)));
+	assert!(ctx.has_message(peer_a, Message::EncryptionKey(local_key, public, signature.clone())));
+	assert!(!ctx.has_message(peer_b, Message::EncryptionKey(local_key, public, signature)));
+}
+
+#[test]
+fn private_collations_withheld_from_validators_without_encryption() {
+	use collator_pool::Role;
+	use polkadot_primitives::encryption::EncryptionKey;
+	use polkadot_primitives::parachain::Collation;
+	use substrate_primitives::ed25519;
+
+	let mut protocol = PolkadotProtocol::new(Some(([2; 32].into(), 5.into())))
+		.with_private_collations(true);
+
+	let validator = 1;
+	let validator_pair = ed25519::Pair::from_seed(&[3; 32]);
+	let validator_key: SessionKey = validator_pair.public().into();
+	let encryption_key = EncryptionKey::generate();
+	let incapable = Status {
+		collating_for: None,
+		capabilities: Capabilities::supported().difference(Capabilities::COLLATION_ENCRYPTION),
+	};
+
+	let collation = Collation {
+		block_data: BlockData(vec![1, 2, 3]),
+		receipt: CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [2; 32].into(),
+			signature: H512::from([1; 64]).into(),
+			head_data: HeadData(vec![1, 2, 3]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: [5; 32].into(),
+			ingress_watermark: 0,
+		},
+	};
+
+	let mut ctx = TestContext::default();
+	protocol.on_connect(&mut ctx, validator, make_status(&incapable, Roles::AUTHORITY));
+	on_message(&mut protocol, &mut ctx, validator, Message::SessionKey(validator_key));
+	on_message(&mut protocol, &mut ctx, validator, Message::CollatorRole(Role::Primary));
+
+	let signature = encryption_key.announce(&validator_pair);
+	on_message(&mut protocol, &mut ctx, validator, Message::EncryptionKey(validator_key, encryption_key.public(), signature));
+
+	let targets = vec![validator_key].into_iter().collect();
+	protocol.add_local_collation(&mut ctx, [4; 32].into(), targets, collation);
+	assert!(ctx.messages.is_empty());
+}
+
+#[test]
+fn legacy_validators_receive_legacy_collations() {
+	use collator_pool::Role;
+	use polkadot_primitives::parachain::Collation;
+
+	let mut protocol = PolkadotProtocol::new(Some(([2; 32].into(), 5.into())));
+
+	let legacy_validator = 1;
+	let validator = 2;
+	let legacy_key: SessionKey = [3; 32].into();
+	let key: SessionKey = [4; 32].into();
+	let legacy = Status { collating_for: None, capabilities: Capabilities::LEGACY };
+	let current = Status { collating_for: None, capabilities: Capabilities::supported() };
+
+	let mut collation = Collation {
+		block_data: BlockData(vec![1, 2, 3]),
+		receipt: CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [2; 32].into(),
+			signature: H512::from([1; 64]).into(),
+			head_data: HeadData(vec![1, 2, 3]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: [5; 32].into(),
+			ingress_watermark: 0,
+		},
+	};
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, legacy_validator, make_status(&legacy, Roles::AUTHORITY));
+		protocol.on_connect(&mut ctx, validator, make_status(&current, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, legacy_validator, Message::SessionKey(legacy_key));
+		on_message(&mut protocol, &mut ctx, validator, Message::SessionKey(key));
+		on_message(&mut protocol, &mut ctx, legacy_validator, Message::CollatorRole(Role::Primary));
+		on_message(&mut protocol, &mut ctx, validator, Message::CollatorRole(Role::Primary));
+	}
+
+	// the collation as encoded before the envelope and the ingress watermark were added.
+	let mut legacy_collation = collation.encode();
+	let len = legacy_collation.len() - collation.receipt.ingress_watermark.encode().len();
+	legacy_collation.truncate(len);
+
+	let collation_bytes = |ctx: &TestContext, to: NodeIndex| ctx.messages.iter()
+		.filter_map(|&(who, ref message)| match *message {
+			// skip the message index and relay parent.
+			GenericMessage::ChainSpecific(ref data) if who == to => Some(data[33..].to_vec()),
+			_ => None,
+		})
+		.next();
+
+	{
+		let mut ctx = TestContext::default();
+		let targets = vec![legacy_key, key].into_iter().collect();
+		protocol.add_local_collation(&mut ctx, [6; 32].into(), targets, collation.clone());
+
+		assert_eq!(collation_bytes(&ctx, legacy_validator), Some(legacy_collation));
+		assert_eq!(&collation_bytes(&ctx, validator).unwrap()[..2], &[0xff, 2]);
+	}
+
+	// collations acknowledging ingress can't be represented in the legacy format.
+	collation.receipt.ingress_watermark = 10;
+	{
+		let mut ctx = TestContext::default();
+		let targets = vec![legacy_key, key].into_iter().collect();
+		protocol.add_local_collation(&mut ctx, [7; 32].into(), targets, collation);
+
+		assert_eq!(collation_bytes(&ctx, legacy_validator), None);
+		assert!(collation_bytes(&ctx, validator).is_some());
+	}
+}
+
+#[test]
+fn status_without_capabilities_is_legacy() {
+	use codec::Decode;
+	use polkadot_primitives::AccountId;
+	use polkadot_primitives::parachain::Id as ParaId;
+
+	let collating_for: Option<(AccountId, ParaId)> = Some(([2; 32].into(), 5.into()));
+	let legacy = collating_for.encode();
+
+	let status = Status::decode(&mut &legacy[..]).unwrap();
+	assert_eq!(status.collating_for, collating_for);
+	assert_eq!(status.capabilities, Capabilities::LEGACY);
+
+	let current = Status { collating_for, capabilities: Capabilities::STATEMENT_PUSH };
+	assert_eq!(Status::decode(&mut &current.encode()[..]).unwrap(), current);
+}
+
+#[test]
+fn fetches_only_from_peers_capable_of_direct_fetch() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let peer_a = 1;
+	let peer_b = 2;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+
+	let candidate_receipt = CandidateReceipt {
+		parachain_index: 5.into(),
+		collator: [255; 32].into(),
+		head_data: HeadData(vec![9, 9, 9]),
+		signature: H512::from([1; 64]).into(),
+		balance_uploads: Vec::new(),
+		egress_queue_roots: Vec::new(),
+		fees: 1_000_000,
+		block_data_hash: BlockData(vec![1, 2, 3, 4]).hash(),
+		ingress_watermark: 0,
+	};
+
+	let candidate_hash = candidate_receipt.hash();
+	let a_key = [3; 32].into();
+	let b_key = [4; 32].into();
+
+	let incapable = Status { collating_for: None, capabilities: Capabilities::empty() };
+	let capable = Status { collating_for: None, capabilities: Capabilities::supported() };
+
+	let (session, knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session);
+
+	knowledge.lock().note_statement(parent_hash, &signed(a_key, GenericStatement::Valid(candidate_hash)));
+	knowledge.lock().note_statement(parent_hash, &signed(b_key, GenericStatement::Valid(candidate_hash)));
+	let _recv = protocol.fetch_block_data(&mut TestContext::default(), &candidate_receipt, parent_hash);
+
+	// peer A doesn't support direct fetching and isn't asked.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_a, make_status(&incapable, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_a, Message::SessionKey(a_key));
+		assert_eq!(protocol.peer_capabilities(peer_a), Some(Capabilities::empty()));
+		assert!(!ctx.has_message(peer_a, Message::RequestBlockData(1, parent_hash, candidate_hash)));
+	}
+
+	// peer B does.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, peer_b, make_status(&capable, Roles::AUTHORITY));
+		on_message(&mut protocol, &mut ctx, peer_b, Message::SessionKey(b_key));
+		assert!(ctx.has_message(peer_b, Message::RequestBlockData(1, parent_hash, candidate_hash)));
+	}
+}
+
+#[test]
+fn fetches_block_data_from_collator() {
+	let mut protocol = PolkadotProtocol::new(None);
+
+	let collator = 1;
+	let parent_hash = [0; 32].into();
+	let local_key = [1; 32].into();
+	let collator_id = [2; 32].into();
+
+	let block_data = BlockData(vec![1, 2, 3, 4]);
+	let candidate_receipt = CandidateReceipt {
+		parachain_index: 5.into(),
+		collator: collator_id,
+		head_data: HeadData(vec![9, 9, 9]),
+		signature: H512::from([1; 64]).into(),
+		balance_uploads: Vec::new(),
+		egress_queue_roots: Vec::new(),
+		fees: 1_000_000,
+		block_data_hash: block_data.hash(),
+		ingress_watermark: 0,
+	};
+
+	let candidate_hash = candidate_receipt.hash();
+	let status = Status { collating_for: Some((collator_id, 5.into())), capabilities: Capabilities::supported() };
+
+	let (session, _knowledge) = make_validation_session(parent_hash, local_key);
+	protocol.new_validation_session(&mut TestContext::default(), parent_hash, session);
+	let recv = protocol.fetch_block_data(&mut TestContext::default(), &candidate_receipt, parent_hash);
+
+	// no validator has the data, so the collator is asked over its connection.
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, collator, make_status(&status, Roles::NONE));
+		assert!(ctx.has_message(collator, Message::RequestBlockData(1, parent_hash, candidate_hash)));
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, collator, Message::BlockData(1, Some(block_data.clone())));
+		drop(protocol);
+		assert_eq!(recv.wait().unwrap(), block_data);
+	}
+}
+
+#[test]
+fn collator_serves_block_data_of_local_collations() {
+	use polkadot_primitives::parachain::Collation;
+
+	let mut protocol = PolkadotProtocol::new(Some(([2; 32].into(), 5.into())))
+		.with_private_collations(true);
+
+	let validator = 1;
+	let full_node = 2;
+	let validator_key = [3; 32].into();
+	let relay_parent = [4; 32].into();
+
+	let block_data = BlockData(vec![1, 2, 3]);
+	let collation = Collation {
+		block_data: block_data.clone(),
+		receipt: CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [2; 32].into(),
+			signature: H512::from([1; 64]).into(),
+			head_data: HeadData(vec![1, 2, 3]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 0,
+			block_data_hash: block_data.hash(),
+			ingress_watermark: 0,
+		},
+	};
+	let candidate_hash = collation.receipt.hash();
+	let status = Status { collating_for: None, capabilities: Capabilities::supported() };
+
+	{
+		let mut ctx = TestContext::default();
+		protocol.on_connect(&mut ctx, validator, make_status(&status, Roles::AUTHORITY));
+		protocol.on_connect(&mut ctx, full_node, make_status(&status, Roles::FULL));
+		on_message(&mut protocol, &mut ctx, validator, Message::SessionKey(validator_key));
+
+		let targets = vec![validator_key].into_iter().collect();
+		protocol.add_local_collation(&mut ctx, relay_parent, targets, collation);
+	}
+
+	// private collations are only served to validators.
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, full_node, Message::RequestBlockData(1, relay_parent, candidate_hash));
+		assert!(ctx.has_message(full_node, Message::BlockData(1, None)));
+	}
+
+	{
+		let mut ctx = TestContext::default();
+		on_message(&mut protocol, &mut ctx, validator, Message::RequestBlockData(2, relay_parent, candidate_hash));
+		assert!(ctx.has_message(validator, Message::BlockData(2, Some(block_data))));
+	}
+}
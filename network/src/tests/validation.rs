@@ -22,9 +22,14 @@ use substrate_primitives::{Ed25519AuthorityId, NativeOrEncoded};
 use substrate_keyring::Keyring;
 use {PolkadotProtocol};
 
-use polkadot_validation::{SharedTable, MessagesFrom, Network, TableRouter};
-use polkadot_primitives::{AccountId, Block, Hash, Header, BlockId};
-use polkadot_primitives::parachain::{Id as ParaId, Chain, DutyRoster, ParachainHost, OutgoingMessage};
+use polkadot_validation::{SharedTable, MessagesFrom, Network, TableRouter, Cancellation};
+use polkadot_primitives::{AccountId, Block, BlockNumber, Hash, Header, BlockId};
+use polkadot_primitives::acceptance::InclusionError;
+use polkadot_primitives::message_roots::message_queue_root;
+use polkadot_primitives::parachain::{
+	Id as ParaId, CandidateHash, Chain, DutyRoster, ParachainHost, OutgoingMessage, CoreAssignment, CoreIndex,
+	AssignmentKind, ValidatorIndex, InclusionStats, InclusionWeights, AttestedCandidate, CollatorId,
+};
 use parking_lot::Mutex;
 use substrate_client::error::Result as ClientResult;
 use substrate_client::runtime_api::{Core, RuntimeVersion, ApiExt};
@@ -36,19 +41,7 @@ use std::sync::Arc;
 use futures::{prelude::*, sync::mpsc};
 use tokio::runtime::{Runtime, TaskExecutor};
 
-use super::TestContext;
-
-#[derive(Clone, Copy)]
-struct NeverExit;
-
-impl Future for NeverExit {
-	type Item = ();
-	type Error = ();
-
-	fn poll(&mut self) -> Poll<(), ()> {
-		Ok(Async::NotReady)
-	}
-}
+use super::{TestContext, candidate_hash};
 
 struct GossipRouter {
 	incoming_messages: mpsc::UnboundedReceiver<(Hash, ConsensusMessage)>,
@@ -256,6 +249,39 @@ impl ParachainHost<Block> for RuntimeApi {
 		}))
 	}
 
+	fn scheduled_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<()>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<Vec<CoreAssignment>>> {
+		let data = self.data.lock();
+		let scheduled = data.active_parachains.iter().enumerate().map(|(core, para_id)| CoreAssignment {
+			core: core as CoreIndex,
+			para_id: *para_id,
+			kind: AssignmentKind::Parachain,
+			group: data.duties.iter()
+				.enumerate()
+				.filter(|&(_, duty)| duty == &Chain::Parachain(*para_id))
+				.map(|(idx, _)| idx as ValidatorIndex)
+				.collect(),
+		}).collect();
+
+		Ok(NativeOrEncoded::Native(scheduled))
+	}
+
+	fn parachain_validators_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<()>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<Vec<Ed25519AuthorityId>>> {
+		let validators = self.data.lock().validators.iter().map(|v| v.to_fixed_bytes().into()).collect();
+		Ok(NativeOrEncoded::Native(validators))
+	}
+
 	fn active_parachains_runtime_api_impl(
 		&self,
 		_at: &BlockId,
@@ -296,11 +322,73 @@ impl ParachainHost<Block> for RuntimeApi {
 		let id = id.unwrap();
 		Ok(NativeOrEncoded::Native(self.data.lock().ingress.get(&id).cloned()))
 	}
+
+	fn ingress_posted_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		id: Option<ParaId>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<Option<Vec<(ParaId, BlockNumber)>>>> {
+		let id = id.unwrap();
+		let posted = self.data.lock().ingress.get(&id)
+			.map(|roots| roots.iter().map(|&(from, _)| (from, 0)).collect());
+		Ok(NativeOrEncoded::Native(posted))
+	}
+
+	fn ingress_watermark_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<ParaId>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<BlockNumber>> {
+		Ok(NativeOrEncoded::Native(0))
+	}
+
+	fn permitted_collators_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<ParaId>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<Option<Vec<CollatorId>>>> {
+		Ok(NativeOrEncoded::Native(None))
+	}
+
+	fn inclusion_stats_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<ParaId>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<Option<InclusionStats>>> {
+		Ok(NativeOrEncoded::Native(None))
+	}
+
+	fn inclusion_weights_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<()>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<InclusionWeights>> {
+		Ok(NativeOrEncoded::Native(Default::default()))
+	}
+
+	fn check_candidates_runtime_api_impl(
+		&self,
+		_at: &BlockId,
+		_: ExecutionContext,
+		_: Option<Vec<AttestedCandidate>>,
+		_: Vec<u8>,
+	) -> ClientResult<NativeOrEncoded<Vec<(u32, InclusionError)>>> {
+		Ok(NativeOrEncoded::Native(Vec::new()))
+	}
 }
 
 type TestValidationNetwork = ::validation::ValidationNetwork<
 	TestApi,
-	NeverExit,
 	TestNetwork,
 	TaskExecutor,
 >;
@@ -324,7 +412,7 @@ fn build_network(n: usize, executor: TaskExecutor) -> Built {
 
 		TestValidationNetwork::new(
 			net,
-			NeverExit,
+			Cancellation::new(),
 			runtime_api.clone(),
 			executor.clone(),
 		)
@@ -356,7 +444,7 @@ impl IngressBuilder {
 		let mut map = HashMap::new();
 		for ((source, target), messages) in self.egress {
 			map.entry(target).or_insert_with(Vec::new)
-				.push((source, polkadot_validation::message_queue_root(&messages)));
+				.push((source, message_queue_root(&messages)));
 		}
 
 		for roots in map.values_mut() {
@@ -380,8 +468,10 @@ fn make_table(data: &ApiData, local_key: &Keyring, parent_hash: Hash) -> Arc<Sha
 
 	Arc::new(SharedTable::new(
 		group_info,
+		authorities,
 		Arc::new(local_key.pair()),
 		parent_hash,
+		0,
 		store,
 	))
 }
@@ -464,3 +554,213 @@ fn ingress_fetch_works() {
 	runtime.spawn(built.gossip.then(|_| Ok(()))); // in background.
 	runtime.block_on(work).unwrap();
 }
+
+#[test]
+fn ending_session_cancels_its_work() {
+	let mut runtime = Runtime::new().unwrap();
+	let built = build_network(1, runtime.executor());
+
+	let id_a: ParaId = 1.into();
+	let id_b: ParaId = 2.into();
+	let key_a = Keyring::Alice;
+	let parent_hash = [1; 32].into();
+
+	let router = {
+		let mut api_handle = built.api_handle.lock();
+		let mut ingress = HashMap::new();
+		ingress.insert(id_a, vec![(id_b, [9; 32].into())]);
+		*api_handle = ApiData {
+			active_parachains: vec![id_a, id_b],
+			duties: vec![Chain::Parachain(id_a)],
+			validators: vec![key_a.to_raw_public().into()],
+			ingress,
+		};
+
+		built.networks[0].communication_for(make_table(&*api_handle, &key_a, parent_hash), Vec::new())
+	};
+
+	// the ingress from `id_b` never arrives, so fetching only ends with the session.
+	let fetch = router.fetch_incoming(id_a);
+	router.end_session();
+	assert!(runtime.block_on(fetch).is_err());
+}
+
+#[test]
+fn checked_statements_apply_backpressure_and_drop_duplicates() {
+	use codec::{Decode, Encode};
+	use polkadot_validation::{SignedStatement, GenericStatement};
+	use validation::checked_statements;
+
+	let statement = |i: u8| SignedStatement {
+		statement: GenericStatement::Valid(candidate_hash(i)),
+		signature: Default::default(),
+		sender: Keyring::Alice.to_raw_public().into(),
+	};
+
+	let (gossip_tx, gossip_rx) = mpsc::unbounded();
+	for &i in &[1, 2, 1, 3] {
+		gossip_tx.unbounded_send(statement(i).encode()).unwrap();
+	}
+	drop(gossip_tx);
+
+	// a queue with room for a single statement.
+	let (mut task, mut checked) = checked_statements(
+		gossip_rx,
+		0,
+		16,
+		|_, msg| SignedStatement::decode(&mut &msg[..]),
+	);
+
+	::futures::future::lazy(move || {
+		// gossip is not processed further while the queue is full.
+		assert_eq!(task.poll(), Ok(Async::NotReady));
+		assert_eq!(checked.poll(), Ok(Async::Ready(Some(statement(1)))));
+		assert_eq!(checked.poll(), Ok(Async::NotReady));
+
+		assert_eq!(task.poll(), Ok(Async::NotReady));
+		assert_eq!(checked.poll(), Ok(Async::Ready(Some(statement(2)))));
+
+		// the duplicate of the first statement is dropped without waiting for space.
+		assert_eq!(task.poll(), Ok(Async::Ready(())));
+		drop(task);
+
+		assert_eq!(checked.poll(), Ok(Async::Ready(Some(statement(3)))));
+		assert_eq!(checked.poll(), Ok(Async::Ready(None)));
+		Ok::<_, ()>(())
+	}).wait().unwrap();
+}
+
+#[test]
+fn checked_statements_forget_old_duplicates() {
+	use codec::{Decode, Encode};
+	use polkadot_validation::{SignedStatement, GenericStatement};
+	use validation::checked_statements;
+
+	let statement = |i: u8| SignedStatement {
+		statement: GenericStatement::Valid(candidate_hash(i)),
+		signature: Default::default(),
+		sender: Keyring::Alice.to_raw_public().into(),
+	};
+
+	let (gossip_tx, gossip_rx) = mpsc::unbounded();
+	for &i in &[1, 2, 2, 3, 1] {
+		gossip_tx.unbounded_send(statement(i).encode()).unwrap();
+	}
+	drop(gossip_tx);
+
+	// only the last two processed messages are remembered.
+	let (task, checked) = checked_statements(
+		gossip_rx,
+		16,
+		2,
+		|_, msg| SignedStatement::decode(&mut &msg[..]),
+	);
+
+	let (_, checked) = task.join(checked.collect()).wait().unwrap();
+	assert_eq!(checked, vec![statement(1), statement(2), statement(3), statement(1)]);
+}
+
+#[test]
+fn rebroadcast_local_statements_until_included_or_capped() {
+	use std::time::Duration;
+	use polkadot_validation::{GroupInfo, Validated};
+	use polkadot_primitives::parachain::{BlockData, CandidateReceipt, Extrinsic, HeadData};
+	use validation::{statements_to_rebroadcast, RebroadcastConfig, StatementRebroadcast};
+
+	let (mut gossip_router, gossip_handle) = make_gossip();
+	let network = Arc::new(TestNetwork {
+		proto: Arc::new(Mutex::new(PolkadotProtocol::new(None))),
+		gossip: gossip_handle,
+	});
+
+	let para_id = ParaId::from(1);
+	let local_id = Keyring::Alice.to_raw_public().into();
+	let mut groups = HashMap::new();
+	groups.insert(para_id, GroupInfo {
+		validity_guarantors: [local_id].iter().cloned().collect(),
+		needed_validity: 1,
+	});
+
+	let table = Arc::new(SharedTable::new(
+		groups,
+		vec![local_id],
+		Arc::new(Keyring::Alice.pair()),
+		[1; 32].into(),
+		0,
+		::av_store::Store::new_in_memory(),
+	));
+
+	let candidate = CandidateReceipt {
+		parachain_index: para_id,
+		collator: [2; 32].into(),
+		signature: Default::default(),
+		head_data: HeadData(vec![1, 2, 3]),
+		balance_uploads: Vec::new(),
+		egress_queue_roots: Vec::new(),
+		fees: 0,
+		block_data_hash: [3; 32].into(),
+		ingress_watermark: 0,
+	};
+	let candidate_hash = candidate.hash();
+
+	let local_statement = table.import_validated(Validated::collated_local(
+		candidate,
+		BlockData(vec![4, 5, 6]),
+		Extrinsic { outgoing_messages: Vec::new() },
+	)).wait().unwrap();
+	assert!(statements_to_rebroadcast(&table).contains(&local_statement));
+
+	let config = RebroadcastConfig {
+		interval: Duration::from_secs(1),
+		jitter: Duration::from_secs(1),
+		max_rebroadcasts: 2,
+	};
+	let mut task = StatementRebroadcast::new(&table, network, [4; 32].into(), config);
+
+	::futures::future::lazy(move || {
+		for _ in 0..3 {
+			task.rebroadcast(&table);
+		}
+
+		// sent once per round, up to the cap.
+		let mut sent = 0;
+		while let Ok(Async::Ready(Some(_))) = gossip_router.incoming_messages.poll() {
+			sent += 1;
+		}
+		assert_eq!(sent, 2);
+
+		table.note_included(&[candidate_hash]);
+		assert!(statements_to_rebroadcast(&table).is_empty());
+		Ok::<_, ()>(())
+	}).wait().unwrap();
+}
+
+#[test]
+fn rebroadcast_delay_jittered_within_bounds() {
+	use std::collections::HashSet;
+	use std::time::Duration;
+	use validation::{rebroadcast_delay, RebroadcastConfig};
+
+	let config = RebroadcastConfig {
+		interval: Duration::from_secs(6),
+		jitter: Duration::from_secs(2),
+		max_rebroadcasts: 5,
+	};
+
+	let delays = |seed: Hash| (0..32)
+		.map(|round| rebroadcast_delay(&config, &seed, round))
+		.collect::<Vec<_>>();
+
+	let first = delays([1; 32].into());
+	for delay in &first {
+		assert!(*delay >= config.interval);
+		assert!(*delay <= config.interval + config.jitter);
+	}
+
+	// rounds, and validators seeding the jitter differently, don't re-broadcast in lockstep.
+	assert!(first.iter().collect::<HashSet<_>>().len() > 1);
+	assert!(first != delays([2; 32].into()));
+
+	let no_jitter = RebroadcastConfig { jitter: Duration::from_secs(0), ..config };
+	assert_eq!(rebroadcast_delay(&no_jitter, &[1; 32].into(), 3), no_jitter.interval);
+}
@@ -19,27 +19,44 @@
 //! This fulfills the `polkadot_validation::Network` trait, providing a hook to be called
 //! each time a validation session begins on a new chain head.
 
-use sr_primitives::traits::ProvideRuntimeApi;
+use sr_primitives::traits::{ProvideRuntimeApi, BlakeTwo256, Hash as HashT};
 use substrate_network::{consensus_gossip::ConsensusMessage, Context as NetContext};
-use polkadot_validation::{Network as ParachainNetwork, SharedTable, Collators, Statement, GenericStatement};
-use polkadot_primitives::{AccountId, Block, Hash, SessionKey};
-use polkadot_primitives::parachain::{Id as ParaId, Collation, Extrinsic, ParachainHost, BlockData};
-use codec::Decode;
+use polkadot_validation::{
+	Network as ParachainNetwork, SharedTable, Collators, GenericStatement, SignedStatement,
+	Cancellation, ReplayRecorder,
+};
+use polkadot_primitives::{AccountId, Block, BlockId, Hash, SessionKey};
+use polkadot_primitives::parachain::{
+	Id as ParaId, CandidateHash, Collation, Extrinsic, ParachainHost, BlockData, ValidityAttestation,
+};
+use codec::{Decode, Encode};
 
 use futures::prelude::*;
+use futures::AsyncSink;
 use futures::future::Executor as FutureExecutor;
-use futures::sync::mpsc;
+use futures::stream;
+use futures::sync::{mpsc, oneshot};
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use arrayvec::ArrayVec;
 use tokio::runtime::TaskExecutor;
+use tokio::timer::Delay;
 use parking_lot::Mutex;
 
+use gossip;
+use overflow::OverflowStore;
 use router::Router;
+use wire::{GossipFormat, Versioned};
 use super::PolkadotProtocol;
 
+pub use gossip::{GossipConfig, GossipParams};
+pub use overflow::OverflowConfig;
+
 /// An executor suitable for dispatching async consensus tasks.
 pub trait Executor {
 	fn spawn<F: Future<Item=(),Error=()> + Send + 'static>(&self, f: F);
@@ -112,44 +129,167 @@ impl NetworkService for super::NetworkService {
 	}
 }
 
-// task that processes all gossipped consensus messages,
-// checking signatures
-struct MessageProcessTask<P, E, N: NetworkService, T> {
-	inner_stream: mpsc::UnboundedReceiver<ConsensusMessage>,
-	parent_hash: Hash,
-	table_router: Router<P, E, N, T>,
+/// Capacity of the queue of checked statements awaiting import into the table.
+pub(crate) const CHECKED_STATEMENTS_BUFFER: usize = 256;
+
+/// Capacity of the queue of statements pushed by other validators awaiting checks.
+/// Pushes beyond it are dropped, as the statements are gossiped as well.
+pub(crate) const PUSHED_STATEMENTS_BUFFER: usize = 256;
+
+/// Number of processed gossip messages remembered per session to drop duplicates of.
+pub(crate) const PROCESSED_STATEMENTS_REMEMBERED: usize = 16 * 1024;
+
+// task that processes all gossipped consensus messages, checking signatures
+// and forwarding checked statements to the import task through a bounded queue.
+//
+// when the queue is full, gossip is not polled until the import task catches up.
+// note that this only moves the backlog: substrate hands gossip messages over through
+// an unbounded receiver, which keeps buffering them meanwhile.
+//
+// duplicates of recently processed messages are dropped without taking up space in the
+// queue, so statements both gossiped and pushed directly are only checked once. older
+// duplicates are checked again, which is cheap for those noted as seen by the router.
+pub(crate) struct StatementCheckTask<S, F> {
+	inner_stream: S,
+	check: F,
+	checked: mpsc::Sender<SignedStatement>,
+	pending: Option<SignedStatement>,
+	processed: HashSet<Hash>,
+	// processed hashes in order of processing, oldest first.
+	processed_order: VecDeque<Hash>,
+	remember: usize,
+}
+
+/// Check statements from a stream of gossip messages, yielding checked statements
+/// through a queue of given capacity.
+///
+/// The check function is given the hash and contents of each distinct message among
+/// the last `remember` processed.
+pub(crate) fn checked_statements<S, F>(
+	inner_stream: S,
+	capacity: usize,
+	remember: usize,
+	check: F,
+) -> (StatementCheckTask<S, F>, mpsc::Receiver<SignedStatement>) where
+	S: Stream<Item=ConsensusMessage, Error=()>,
+	F: FnMut(Hash, &ConsensusMessage) -> Option<SignedStatement>,
+{
+	let (tx, rx) = mpsc::channel(capacity);
+	let task = StatementCheckTask {
+		inner_stream,
+		check,
+		checked: tx,
+		pending: None,
+		processed: HashSet::new(),
+		processed_order: VecDeque::new(),
+		remember,
+	};
+
+	(task, rx)
 }
 
-impl<P, E, N, T> MessageProcessTask<P, E, N, T> where
+impl<S, F> Future for StatementCheckTask<S, F> where
+	S: Stream<Item=ConsensusMessage, Error=()>,
+	F: FnMut(Hash, &ConsensusMessage) -> Option<SignedStatement>,
+{
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		loop {
+			// a checked statement waits for capacity before more gossip is processed.
+			if let Some(statement) = self.pending.take() {
+				match self.checked.start_send(statement) {
+					Ok(AsyncSink::Ready) => {}
+					Ok(AsyncSink::NotReady(statement)) => {
+						self.pending = Some(statement);
+						return Ok(Async::NotReady);
+					}
+					// the import task has finished.
+					Err(_) => return Ok(Async::Ready(())),
+				}
+			}
+
+			match self.inner_stream.poll() {
+				Ok(Async::Ready(Some(msg))) => {
+					let message_hash = BlakeTwo256::hash(&msg[..]);
+					if !self.processed.insert(message_hash) {
+						trace!(target: "validation", "Dropping duplicate statement {}", message_hash);
+						continue;
+					}
+
+					self.processed_order.push_back(message_hash);
+					while self.processed_order.len() > self.remember {
+						if let Some(oldest) = self.processed_order.pop_front() {
+							self.processed.remove(&oldest);
+						}
+					}
+
+					self.pending = (self.check)(message_hash, &msg);
+				}
+				Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Err(e) => debug!(target: "p_net", "Error getting consensus message: {:?}", e),
+			}
+		}
+	}
+}
+
+// check a gossiped statement against the relay parent, noting it as seen if valid.
+fn check_gossip_statement<P, N, T>(
+	router: &Router<P, N, T>,
+	parent_hash: &Hash,
+	message_hash: Hash,
+	msg: &ConsensusMessage,
+) -> Option<SignedStatement> where
 	P: ProvideRuntimeApi + Send + Sync + 'static,
 	P::Api: ParachainHost<Block>,
-	E: Future<Item=(),Error=()> + Clone + Send + 'static,
 	N: NetworkService,
 	T: Clone + Executor + Send + 'static,
 {
-	fn process_message(&self, msg: ConsensusMessage) -> Option<Async<()>> {
-		use polkadot_validation::SignedStatement;
-
-		debug!(target: "validation", "Processing validation statement for live session");
-		if let Some(statement) = SignedStatement::decode(&mut msg.as_slice()) {
-			if ::polkadot_validation::check_statement(
-				&statement.statement,
-				&statement.signature,
-				statement.sender,
-				&self.parent_hash
-			) {
-				self.table_router.import_statement(statement);
-			}
-		}
-
+	debug!(target: "validation", "Processing validation statement for live session");
+	// messages checked before a restart need not be checked again.
+	let already_checked = router.has_seen(&message_hash);
+
+	let statement = Versioned::<SignedStatement>::decode(&mut msg.as_slice()).map(Versioned::into_inner)?;
+	if already_checked || ::polkadot_validation::check_statement(
+		&statement.statement,
+		&statement.signature,
+		statement.sender,
+		parent_hash,
+	) {
+		router.note_seen(message_hash);
+		Some(statement)
+	} else {
 		None
 	}
 }
 
-impl<P, E, N, T> Future for MessageProcessTask<P, E, N, T> where
+// feed the statements in responses to knowledge requests to the checks of a session.
+fn catch_up_statements(
+	responses: Vec<oneshot::Receiver<Option<Vec<CandidateSummary>>>>,
+	pushed: mpsc::Sender<ConsensusMessage>,
+) -> impl Future<Item=(),Error=()> + Send {
+	stream::futures_unordered(responses)
+		.then(|res| Ok::<_, ()>(res.ok().and_then(|summaries| summaries).unwrap_or_else(Vec::new)))
+		.map(|summaries| stream::iter_ok(summaries.into_iter()
+			.flat_map(|summary| summary.statements)
+			.map(|statement| Versioned::new(statement).encode())
+		))
+		.flatten()
+		.forward(pushed.sink_map_err(|_| ()))
+		.map(|_| ())
+}
+
+// task importing checked statements into the table at its own pace.
+struct StatementImportTask<P, N: NetworkService, T> {
+	checked: mpsc::Receiver<SignedStatement>,
+	table_router: Router<P, N, T>,
+}
+
+impl<P, N, T> Future for StatementImportTask<P, N, T> where
 	P: ProvideRuntimeApi + Send + Sync + 'static,
 	P::Api: ParachainHost<Block>,
-	E: Future<Item=(),Error=()> + Clone + Send + 'static,
 	N: NetworkService,
 	T: Clone + Executor + Send + 'static,
 {
@@ -158,53 +298,363 @@ impl<P, E, N, T> Future for MessageProcessTask<P, E, N, T> where
 
 	fn poll(&mut self) -> Poll<(), ()> {
 		loop {
-			match self.inner_stream.poll() {
-				Ok(Async::Ready(Some(val))) => if let Some(async) = self.process_message(val) {
-					return Ok(async);
-				},
-				Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+			match self.checked.poll() {
+				Ok(Async::Ready(Some(statement))) => self.table_router.import_statement(statement),
+				Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(())),
 				Ok(Async::NotReady) => return Ok(Async::NotReady),
-				Err(e) => debug!(target: "p_net", "Error getting consensus message: {:?}", e),
 			}
 		}
 	}
 }
 
+/// Configuration of the periodic re-broadcast of statements on the attestation topic.
+///
+/// Gossip is best-effort, and a single lost statement can leave a candidate one
+/// vote short of being backed. The local node's own statements and the statements
+/// backing candidates are circulated again, until included or the cap is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebroadcastConfig {
+	/// Interval between re-broadcasts.
+	pub interval: Duration,
+	/// Upper bound on a delay added to each interval, so that validators don't
+	/// all re-broadcast at the same time.
+	pub jitter: Duration,
+	/// Maximum number of times the same statement is re-broadcast.
+	pub max_rebroadcasts: usize,
+}
+
+impl Default for RebroadcastConfig {
+	fn default() -> Self {
+		RebroadcastConfig {
+			interval: Duration::from_secs(6),
+			jitter: Duration::from_secs(2),
+			max_rebroadcasts: 5,
+		}
+	}
+}
+
+// statements worth re-broadcasting: local statements and those backing candidates,
+// on candidates not yet included.
+pub(crate) fn statements_to_rebroadcast(table: &SharedTable) -> Vec<SignedStatement> {
+	let mut statements: Vec<_> = table.local_statements().into_iter()
+		.filter(|statement| {
+			let candidate_hash = match statement.statement {
+				GenericStatement::Candidate(ref c) => c.hash(),
+				GenericStatement::Valid(h) | GenericStatement::Invalid(h) => h,
+			};
+			!table.is_included(&candidate_hash)
+		})
+		.collect();
+
+	for attested in table.proposed_set() {
+		let candidate_hash = attested.candidate.hash();
+		if table.is_included(&candidate_hash) { continue }
+
+		for (sender, attestation) in attested.validity_votes {
+			let (statement, signature) = match attestation {
+				ValidityAttestation::Implicit(s) => (GenericStatement::Candidate(attested.candidate.clone()), s),
+				ValidityAttestation::Explicit(s) => (GenericStatement::Valid(candidate_hash), s),
+			};
+
+			statements.push(SignedStatement { statement, signature, sender });
+		}
+	}
+
+	statements
+}
+
+// the interval before the given round of re-broadcasts, with pseudo-random jitter.
+pub(crate) fn rebroadcast_delay(config: &RebroadcastConfig, seed: &Hash, round: u64) -> Duration {
+	let jitter_millis = config.jitter.as_secs() * 1000 + u64::from(config.jitter.subsec_millis());
+
+	let digest = BlakeTwo256::hash_of(&(*seed, round));
+	let random = u64::decode(&mut digest.as_ref()).unwrap_or(0);
+
+	config.interval + Duration::from_millis(random % (jitter_millis + 1))
+}
+
+// task re-broadcasting statements on a live attestation topic. ends once the
+// table is dropped along with the validation session.
+pub(crate) struct StatementRebroadcast<N> {
+	table: Weak<SharedTable>,
+	network: Arc<N>,
+	topic: Hash,
+	config: RebroadcastConfig,
+	// seeds the jitter, so that it differs between validators and sessions.
+	seed: Hash,
+	round: u64,
+	delay: Delay,
+	broadcasts: HashMap<Hash, usize>,
+	gossip_format: GossipFormat,
+}
+
+impl<N: NetworkService> StatementRebroadcast<N> {
+	pub(crate) fn new(
+		table: &Arc<SharedTable>,
+		network: Arc<N>,
+		topic: Hash,
+		config: RebroadcastConfig,
+	) -> Self {
+		let seed = BlakeTwo256::hash_of(&(table.session_key(), topic));
+
+		StatementRebroadcast {
+			table: Arc::downgrade(table),
+			network,
+			topic,
+			config,
+			seed,
+			round: 0,
+			delay: Delay::new(Instant::now() + rebroadcast_delay(&config, &seed, 0)),
+			broadcasts: HashMap::new(),
+			gossip_format: GossipFormat::new(),
+		}
+	}
+
+	// encode re-broadcast statements for the wire formats of connected peers.
+	pub(crate) fn with_gossip_format(mut self, gossip_format: GossipFormat) -> Self {
+		self.gossip_format = gossip_format;
+		self
+	}
+
+	pub(crate) fn rebroadcast(&mut self, table: &SharedTable) {
+		let mut sent = HashSet::new();
+		for statement in statements_to_rebroadcast(table) {
+			let message = self.gossip_format.encode(statement);
+			let message_hash = BlakeTwo256::hash(&message[..]);
+			if !sent.insert(message_hash) { continue }
+
+			let broadcasts = self.broadcasts.entry(message_hash).or_insert(0);
+			if *broadcasts >= self.config.max_rebroadcasts { continue }
+			*broadcasts += 1;
+
+			self.network.gossip_message(self.topic, message);
+		}
+	}
+}
+
+impl<N: NetworkService> Future for StatementRebroadcast<N> {
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		loop {
+			try_ready!(self.delay.poll().map_err(|e| warn!(target: "p_net", "Rebroadcast timer error: {:?}", e)));
+
+			let table = match self.table.upgrade() {
+				Some(table) => table,
+				None => return Ok(Async::Ready(())),
+			};
+
+			trace!(target: "p_net", "Re-broadcasting statements on {}", self.topic);
+			self.rebroadcast(&table);
+			self.round += 1;
+
+			let next = Instant::now() + rebroadcast_delay(&self.config, &self.seed, self.round);
+			self.delay.reset(next);
+		}
+	}
+}
+
+// task pushing statements worth circulating directly to random subsets of validators,
+// alongside gossip. ends once the table is dropped along with the validation session.
+pub(crate) struct StatementPropagation<N> {
+	table: Weak<SharedTable>,
+	network: Arc<N>,
+	relay_parent: Hash,
+	params: GossipParams,
+	// seeds the selection of validators, so that it differs between validators and sessions.
+	seed: Hash,
+	round: u64,
+	delay: Delay,
+	pushes: HashMap<Hash, usize>,
+}
+
+impl<N: NetworkService> StatementPropagation<N> {
+	pub(crate) fn new(
+		table: &Arc<SharedTable>,
+		network: Arc<N>,
+		relay_parent: Hash,
+		params: GossipParams,
+	) -> Self {
+		StatementPropagation {
+			table: Arc::downgrade(table),
+			network,
+			relay_parent,
+			params,
+			seed: BlakeTwo256::hash_of(&(table.session_key(), relay_parent)),
+			round: 0,
+			delay: Delay::new(Instant::now() + params.propagation_interval),
+			pushes: HashMap::new(),
+		}
+	}
+
+	pub(crate) fn propagate(&mut self, table: &SharedTable) {
+		let mut statements = Vec::new();
+		for statement in statements_to_rebroadcast(table) {
+			let message = Versioned::new(statement).encode();
+			let message_hash = BlakeTwo256::hash(&message[..]);
+
+			let pushes = self.pushes.entry(message_hash).or_insert(0);
+			if *pushes >= self.params.duplicate_factor { continue }
+			*pushes += 1;
+
+			statements.push(message);
+		}
+
+		if statements.is_empty() { return }
+
+		let (relay_parent, fan_out, seed, round) = (self.relay_parent, self.params.fan_out, self.seed, self.round);
+		self.network.with_spec(move |spec, ctx| {
+			spec.propagate_statements(ctx, relay_parent, statements, fan_out, &seed, round);
+		});
+	}
+}
+
+impl<N: NetworkService> Future for StatementPropagation<N> {
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		loop {
+			try_ready!(self.delay.poll().map_err(|e| warn!(target: "p_net", "Propagation timer error: {:?}", e)));
+
+			let table = match self.table.upgrade() {
+				Some(table) => table,
+				None => return Ok(Async::Ready(())),
+			};
+
+			trace!(target: "p_net", "Pushing statements on {} to validators", self.relay_parent);
+			self.propagate(&table);
+			self.round += 1;
+
+			let next = Instant::now() + self.params.propagation_interval;
+			self.delay.reset(next);
+		}
+	}
+}
+
 /// Wrapper around the network service
-pub struct ValidationNetwork<P, E, N, T> {
+pub struct ValidationNetwork<P, N, T> {
 	network: Arc<N>,
 	api: Arc<P>,
 	executor: T,
-	exit: E,
+	cancellation: Cancellation,
+	rebroadcast: Option<RebroadcastConfig>,
+	gossip: GossipConfig,
+	overflow: Option<OverflowStore>,
+	backing_deadline: Option<Duration>,
+	expired_backing: Arc<AtomicUsize>,
+	replay: Option<ReplayRecorder>,
+	knowledge: Arc<Mutex<Knowledge>>,
+	gossip_format: GossipFormat,
 }
 
-impl<P, E, N, T> ValidationNetwork<P, E, N, T> {
-	/// Create a new validation session networking object.
-	pub fn new(network: Arc<N>, exit: E, api: Arc<P>, executor: T) -> Self {
-		ValidationNetwork { network, exit, api, executor }
+impl<P, N, T> ValidationNetwork<P, N, T> {
+	/// Create a new validation session networking object. Each validation session
+	/// runs in a child scope of the given cancellation.
+	pub fn new(network: Arc<N>, cancellation: Cancellation, api: Arc<P>, executor: T) -> Self {
+		ValidationNetwork {
+			network,
+			cancellation,
+			api,
+			executor,
+			rebroadcast: None,
+			gossip: GossipConfig::default(),
+			overflow: None,
+			backing_deadline: None,
+			expired_backing: Arc::new(AtomicUsize::new(0)),
+			replay: None,
+			knowledge: Arc::new(Mutex::new(Knowledge::new())),
+			gossip_format: GossipFormat::new(),
+		}
+	}
+
+	/// Periodically re-broadcast statements in validation sessions, or not if `None`.
+	pub fn with_rebroadcast(mut self, rebroadcast: Option<RebroadcastConfig>) -> Self {
+		self.rebroadcast = rebroadcast;
+		self
+	}
+
+	/// Tune how statements are pushed directly to validators. Parameters left unset
+	/// adapt to the size of the validator set of each session.
+	pub fn with_gossip(mut self, gossip: GossipConfig) -> Self {
+		self.gossip = gossip;
+		self
+	}
+
+	/// Write deferred statements and pending ingress beyond a memory limit to disk,
+	/// or keep them all in memory if `None`.
+	pub fn with_overflow(mut self, overflow: Option<OverflowConfig>) -> Self {
+		self.overflow = overflow.and_then(|config| match OverflowStore::new(config) {
+			Ok(store) => Some(store),
+			Err(e) => {
+				warn!(target: "p_net", "Unable to write buffers to disk, keeping them in memory: {}", e);
+				None
+			}
+		});
+		self
+	}
+
+	/// Abandon fetching and validating candidates which aren't backed within the given
+	/// time of the first statement seen on them, or never if `None`.
+	pub fn with_backing_deadline(mut self, deadline: Option<Duration>) -> Self {
+		self.backing_deadline = deadline;
+		self
+	}
+
+	/// Record the inputs of fetched candidates failing execution to the given directory,
+	/// for replay, or not if `None`.
+	pub fn with_validation_replay(mut self, dir: Option<PathBuf>) -> Self {
+		self.replay = dir.and_then(|dir| match ReplayRecorder::new(dir) {
+			Ok(recorder) => Some(recorder),
+			Err(e) => {
+				warn!(target: "p_net", "Unable to record validation replays: {}", e);
+				None
+			}
+		});
+		self
+	}
+
+	/// Encode gossip for the wire formats of connected peers, as tracked by the
+	/// protocol handler sharing the given view.
+	pub fn with_gossip_format(mut self, gossip_format: GossipFormat) -> Self {
+		self.gossip_format = gossip_format;
+		self
+	}
+
+	/// The number of candidates abandoned for missing the backing deadline.
+	pub fn expired_backing(&self) -> usize {
+		self.expired_backing.load(Ordering::Relaxed)
 	}
 }
 
-impl<P, E: Clone, N, T: Clone> Clone for ValidationNetwork<P, E, N, T> {
+impl<P, N, T: Clone> Clone for ValidationNetwork<P, N, T> {
 	fn clone(&self) -> Self {
 		ValidationNetwork {
 			network: self.network.clone(),
-			exit: self.exit.clone(),
+			cancellation: self.cancellation.clone(),
 			api: self.api.clone(),
 			executor: self.executor.clone(),
+			rebroadcast: self.rebroadcast,
+			gossip: self.gossip,
+			overflow: self.overflow.clone(),
+			backing_deadline: self.backing_deadline,
+			expired_backing: self.expired_backing.clone(),
+			replay: self.replay.clone(),
+			knowledge: self.knowledge.clone(),
+			gossip_format: self.gossip_format.clone(),
 		}
 	}
 }
 
 /// A long-lived network which can create parachain statement  routing processes on demand.
-impl<P, E, N, T> ParachainNetwork for ValidationNetwork<P, E, N, T> where
+impl<P, N, T> ParachainNetwork for ValidationNetwork<P, N, T> where
 	P: ProvideRuntimeApi + Send + Sync + 'static,
 	P::Api: ParachainHost<Block>,
-	E: Clone + Future<Item=(),Error=()> + Send + 'static,
 	N: NetworkService,
 	T: Clone + Executor + Send + 'static,
 {
-	type TableRouter = Router<P, E, N, T>;
+	type TableRouter = Router<P, N, T>;
 
 	fn communication_for(
 		&self,
@@ -212,43 +662,81 @@ impl<P, E, N, T> ParachainNetwork for ValidationNetwork<P, E, N, T> where
 		outgoing: polkadot_validation::Outgoing,
 	) -> Self::TableRouter {
 		let parent_hash = table.consensus_parent_hash().clone();
+		let session = self.cancellation.child();
 
-		let knowledge = Arc::new(Mutex::new(Knowledge::new()));
+		// knowledge is shared by all sessions, so candidates backed on sibling forks
+		// are stored once.
+		self.knowledge.lock().note_session(parent_hash);
+		let knowledge = self.knowledge.clone();
 
 		let local_session_key = table.session_key();
+		let validators = table.parachain_validators().iter().cloned().collect();
 		let table_router = Router::new(
-			table,
+			table.clone(),
 			self.network.clone(),
 			self.api.clone(),
 			self.executor.clone(),
 			parent_hash,
 			knowledge.clone(),
-			self.exit.clone(),
-		);
+			session.clone(),
+			self.overflow.clone(),
+		)
+			.with_backing_deadline(self.backing_deadline, self.expired_backing.clone())
+			.with_validation_replay(self.replay.clone())
+			.with_gossip_format(self.gossip_format.clone());
 
 		table_router.broadcast_egress(outgoing);
 
 		let attestation_topic = table_router.gossip_topic();
 
+		if let Some(config) = self.rebroadcast {
+			let rebroadcast = StatementRebroadcast::new(&table, self.network.clone(), attestation_topic, config)
+				.with_gossip_format(self.gossip_format.clone());
+			self.executor.spawn(session.until_cancelled(rebroadcast));
+		}
+
+		let gossip = self.gossip.params(table.parachain_validators().len());
+		if gossip.fan_out > 0 {
+			let propagation = StatementPropagation::new(&table, self.network.clone(), parent_hash, gossip);
+			self.executor.spawn(session.until_cancelled(propagation));
+		}
+
 		let table_router_clone = table_router.clone();
 		let executor = self.executor.clone();
 
-		// spin up a task in the background that processes all incoming statements
-		// TODO: propagate statements on a timer?
-		let inner_stream = self.network.gossip_messages_for(attestation_topic);
+		// spin up a task in the background that processes all incoming statements,
+		// gossiped or pushed directly by other validators.
+		let (pushed, pushed_stream) = mpsc::channel(PUSHED_STATEMENTS_BUFFER);
+		let inner_stream = self.network.gossip_messages_for(attestation_topic).select(pushed_stream);
 		self.network
 			.with_spec(move |spec, ctx| {
+				let catch_up_sender = pushed.clone();
 				spec.new_validation_session(ctx, parent_hash, ValidationSession {
 					knowledge,
 					local_session_key,
+					validators,
+					pushed,
 				});
-				let process_task = MessageProcessTask {
+
+				// statements issued before the session started locally are fetched from
+				// other validators, and checked along with the pushed ones.
+				let catch_up = catch_up_statements(spec.catch_up_knowledge(ctx, parent_hash), catch_up_sender);
+				executor.spawn(session.until_cancelled(catch_up));
+
+				let check_router = table_router_clone.clone();
+				let (check_task, checked) = checked_statements(
 					inner_stream,
-					parent_hash,
+					CHECKED_STATEMENTS_BUFFER,
+					PROCESSED_STATEMENTS_REMEMBERED,
+					move |message_hash, msg| check_gossip_statement(&check_router, &parent_hash, message_hash, msg),
+				);
+				let import_task = StatementImportTask {
+					checked,
 					table_router: table_router_clone,
 				};
 
-				executor.spawn(process_task);
+				executor.spawn(session.until_cancelled(check_task));
+				executor.spawn(session.until_cancelled(import_task));
 		});
 
 		table_router
@@ -284,7 +772,7 @@ impl Future for AwaitingCollation {
 	}
 }
 
-impl<P, E: Clone, N, T: Clone> Collators for ValidationNetwork<P, E, N, T> where
+impl<P, N, T: Clone> Collators for ValidationNetwork<P, N, T> where
 	P: ProvideRuntimeApi + Send + Sync + 'static,
 	P::Api: ParachainHost<Block>,
 	N: NetworkService,
@@ -293,8 +781,19 @@ impl<P, E: Clone, N, T: Clone> Collators for ValidationNetwork<P, E, N, T> where
 	type Collation = AwaitingCollation;
 
 	fn collate(&self, parachain: ParaId, relay_parent: Hash) -> Self::Collation {
+		// collations from collators not permitted at the relay parent aren't worth fetching.
+		let permitted = self.api.runtime_api()
+			.permitted_collators(&BlockId::hash(relay_parent), parachain)
+			.map_err(|e| warn!(target: "p_net", "Unable to fetch permitted collators of parachain {:?}: {:?}",
+				parachain, e))
+			.ok();
+
 		let (tx, rx) = ::futures::sync::oneshot::channel();
 		self.network.with_spec(move |spec, _| {
+			if let Some(permitted) = permitted {
+				spec.set_permitted_collators(parachain, permitted);
+			}
+
 			let collation = spec.await_collation(relay_parent, parachain);
 			let _ = tx.send(collation);
 		});
@@ -303,21 +802,54 @@ impl<P, E: Clone, N, T: Clone> Collators for ValidationNetwork<P, E, N, T> where
 
 
 	fn note_bad_collator(&self, collator: AccountId) {
-		self.network.with_spec(move |spec, ctx| spec.disconnect_bad_collator(ctx, collator));
+		self.network.with_spec(move |spec, ctx| spec.note_invalid_collation(ctx, collator));
 	}
 }
 
+/// A summary of what is known locally about a candidate.
+///
+/// These are exchanged between validators to catch up on attestation state.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct CandidateSummary {
+	/// The hash of the candidate.
+	pub candidate_hash: CandidateHash,
+	/// Whether the block data for the candidate is held locally.
+	pub has_block_data: bool,
+	/// Whether the extrinsic data for the candidate is held locally.
+	pub has_extrinsic: bool,
+	/// The signed statements on the candidate seen locally, from which the
+	/// attestation state of the candidate can be rebuilt.
+	pub statements: Vec<SignedStatement>,
+}
+
+// candidate data held locally, shared by all sessions referencing the candidate.
 #[derive(Default)]
-struct KnowledgeEntry {
-	knows_block_data: Vec<SessionKey>,
-	knows_extrinsic: Vec<SessionKey>,
+struct StoredCandidate {
 	block_data: Option<BlockData>,
 	extrinsic: Option<Extrinsic>,
+	// relay parents of the live sessions referencing the candidate.
+	referenced_at: HashSet<Hash>,
+}
+
+// knowledge of peers about a candidate in a single session. statements are signed
+// on a relay parent, so this isn't shared between sessions.
+#[derive(Default)]
+struct PeerKnowledge {
+	knows_block_data: Vec<SessionKey>,
+	knows_extrinsic: Vec<SessionKey>,
+	statements: Vec<SignedStatement>,
 }
 
-/// Tracks knowledge of peers.
+/// Tracks knowledge of peers, and candidate data held locally.
+///
+/// This is shared by all validation sessions. Candidate data is keyed by candidate hash,
+/// so a candidate seen in sessions at several relay parents, e.g. when backed again on a
+/// sibling fork, is stored once and released when all sessions referencing it end.
+/// Knowledge of peers is kept per session.
 pub(crate) struct Knowledge {
-	candidates: HashMap<Hash, KnowledgeEntry>,
+	candidates: HashMap<CandidateHash, StoredCandidate>,
+	// knowledge of peers in live sessions, on relay parent.
+	sessions: HashMap<Hash, HashMap<CandidateHash, PeerKnowledge>>,
 }
 
 impl Knowledge {
@@ -325,37 +857,125 @@ impl Knowledge {
 	pub(crate) fn new() -> Self {
 		Knowledge {
 			candidates: HashMap::new(),
+			sessions: HashMap::new(),
 		}
 	}
 
-	/// Note a statement seen from another validator.
-	pub(crate) fn note_statement(&mut self, from: SessionKey, statement: &Statement) {
+	/// Note a session starting at the given relay parent. Knowledge noted for
+	/// relay parents without a live session is ignored.
+	pub(crate) fn note_session(&mut self, relay_parent: Hash) {
+		self.sessions.entry(relay_parent).or_insert_with(HashMap::new);
+	}
+
+	/// Note the session at the given relay parent ending, releasing candidates
+	/// no longer referenced by any session.
+	pub(crate) fn release_session(&mut self, relay_parent: &Hash) {
+		let session = match self.sessions.remove(relay_parent) {
+			Some(session) => session,
+			None => return,
+		};
+
+		for hash in session.keys() {
+			let unreferenced = match self.candidates.get_mut(hash) {
+				Some(stored) => {
+					stored.referenced_at.remove(relay_parent);
+					stored.referenced_at.is_empty()
+				}
+				None => false,
+			};
+
+			if unreferenced {
+				self.candidates.remove(hash);
+			}
+		}
+	}
+
+	// the knowledge of peers about a candidate in the session at the relay parent,
+	// if live. this references the candidate from the session.
+	fn peer_knowledge(&mut self, relay_parent: Hash, hash: CandidateHash) -> Option<&mut PeerKnowledge> {
+		let session = self.sessions.get_mut(&relay_parent)?;
+
+		self.candidates.entry(hash)
+			.or_insert_with(Default::default)
+			.referenced_at
+			.insert(relay_parent);
+
+		Some(session.entry(hash).or_insert_with(Default::default))
+	}
+
+	/// Note a signed statement seen in the session at the given relay parent.
+	pub(crate) fn note_statement(&mut self, relay_parent: Hash, statement: &SignedStatement) {
+		let (candidate_hash, knows_extrinsic) = match statement.statement {
+			GenericStatement::Candidate(ref c) => (c.hash(), true),
+			GenericStatement::Valid(hash) => (hash, true),
+			GenericStatement::Invalid(hash) => (hash, false),
+		};
+
+		let entry = match self.peer_knowledge(relay_parent, candidate_hash) {
+			Some(entry) => entry,
+			None => return,
+		};
+
+		if entry.statements.contains(statement) { return }
+		entry.statements.push(statement.clone());
+
 		// those proposing the candidate or declaring it valid know everything.
 		// those claiming it invalid do not have the extrinsic data as it is
 		// generated by valid execution.
-		match *statement {
-			GenericStatement::Candidate(ref c) => {
-				let mut entry = self.candidates.entry(c.hash()).or_insert_with(Default::default);
-				entry.knows_block_data.push(from);
-				entry.knows_extrinsic.push(from);
-			}
-			GenericStatement::Valid(ref hash) => {
-				let mut entry = self.candidates.entry(*hash).or_insert_with(Default::default);
-				entry.knows_block_data.push(from);
-				entry.knows_extrinsic.push(from);
-			}
-			GenericStatement::Invalid(ref hash) => self.candidates.entry(*hash)
-				.or_insert_with(Default::default)
-				.knows_block_data
-				.push(from),
+		entry.knows_block_data.push(statement.sender);
+		if knows_extrinsic {
+			entry.knows_extrinsic.push(statement.sender);
+		}
+	}
+
+	/// Note a candidate collated or seen locally in the session at the given relay parent.
+	pub(crate) fn note_candidate(
+		&mut self,
+		relay_parent: Hash,
+		hash: CandidateHash,
+		block_data: Option<BlockData>,
+		extrinsic: Option<Extrinsic>,
+	) {
+		if self.peer_knowledge(relay_parent, hash).is_none() { return }
+
+		if let Some(stored) = self.candidates.get_mut(&hash) {
+			stored.block_data = stored.block_data.take().or(block_data);
+			stored.extrinsic = stored.extrinsic.take().or(extrinsic);
+		}
+	}
+
+	/// The block data of a candidate if held locally, or otherwise the session keys of
+	/// validators believed to have it in the session at the given relay parent.
+	pub(crate) fn block_data(&self, relay_parent: &Hash, hash: &CandidateHash) -> Result<&BlockData, &[SessionKey]> {
+		if let Some(block_data) = self.candidates.get(hash).and_then(|stored| stored.block_data.as_ref()) {
+			return Ok(block_data);
 		}
+
+		Err(self.sessions.get(relay_parent)
+			.and_then(|session| session.get(hash))
+			.map_or(&[][..], |entry| &entry.knows_block_data[..]))
 	}
 
-	/// Note a candidate collated or seen locally.
-	pub(crate) fn note_candidate(&mut self, hash: Hash, block_data: Option<BlockData>, extrinsic: Option<Extrinsic>) {
-		let entry = self.candidates.entry(hash).or_insert_with(Default::default);
-		entry.block_data = entry.block_data.take().or(block_data);
-		entry.extrinsic = entry.extrinsic.take().or(extrinsic);
+	/// Produce summaries of the candidates known in the session at the given relay parent,
+	/// ordered by candidate hash.
+	pub(crate) fn summaries(&self, relay_parent: &Hash) -> Vec<CandidateSummary> {
+		let session = match self.sessions.get(relay_parent) {
+			Some(session) => session,
+			None => return Vec::new(),
+		};
+
+		let mut summaries: Vec<_> = session.iter().map(|(hash, entry)| {
+			let stored = self.candidates.get(hash);
+			CandidateSummary {
+				candidate_hash: *hash,
+				has_block_data: stored.map_or(false, |stored| stored.block_data.is_some()),
+				has_extrinsic: stored.map_or(false, |stored| stored.extrinsic.is_some()),
+				statements: entry.statements.clone(),
+			}
+		}).collect();
+
+		summaries.sort_by(|a, b| a.candidate_hash.cmp(&b.candidate_hash));
+		summaries
 	}
 }
 
@@ -363,6 +983,10 @@ impl Knowledge {
 pub(crate) struct ValidationSession {
 	knowledge: Arc<Mutex<Knowledge>>,
 	local_session_key: SessionKey,
+	// the validator set of the session.
+	validators: HashSet<SessionKey>,
+	// statements pushed directly by other validators.
+	pushed: mpsc::Sender<ConsensusMessage>,
 }
 
 impl ValidationSession {
@@ -370,21 +994,36 @@ impl ValidationSession {
 	pub(crate) fn new(knowledge: Arc<Mutex<Knowledge>>, local_session_key: SessionKey) -> Self {
 		ValidationSession {
 			knowledge,
-			local_session_key
+			local_session_key,
+			validators: ::std::iter::once(local_session_key).collect(),
+			pushed: mpsc::channel(PUSHED_STATEMENTS_BUFFER).0,
 		}
 	}
 
+	#[cfg(test)]
+	pub(crate) fn with_validators(mut self, validators: &[SessionKey]) -> Self {
+		self.validators.extend(validators.iter().cloned());
+		self
+	}
+
+	#[cfg(test)]
+	pub(crate) fn with_pushed(mut self, pushed: mpsc::Sender<ConsensusMessage>) -> Self {
+		self.pushed = pushed;
+		self
+	}
+
 	// execute a closure with locally stored block data for a candidate, or a slice of session identities
 	// we believe should have the data.
-	fn with_block_data<F, U>(&self, hash: &Hash, f: F) -> U
+	fn with_block_data<F, U>(&self, parent_hash: &Hash, hash: &CandidateHash, f: F) -> U
 		where F: FnOnce(Result<&BlockData, &[SessionKey]>) -> U
 	{
 		let knowledge = self.knowledge.lock();
-		let res = knowledge.candidates.get(hash)
-			.ok_or(&[] as &_)
-			.and_then(|entry| entry.block_data.as_ref().ok_or(&entry.knows_block_data[..]));
+		f(knowledge.block_data(parent_hash, hash))
+	}
 
-		f(res)
+	// summaries of all candidates known in the session at the relay parent.
+	fn knowledge_summaries(&self, parent_hash: &Hash) -> Vec<CandidateSummary> {
+		self.knowledge.lock().summaries(parent_hash)
 	}
 }
 
@@ -473,6 +1112,8 @@ impl LiveValidationSessions {
 	/// Remove validation session.
 	pub(crate) fn remove(&mut self, parent_hash: &Hash) {
 		if let Some(validation_session) = self.live_instances.remove(parent_hash) {
+			validation_session.knowledge.lock().release_session(parent_hash);
+
 			let key_still_used = self.live_instances.values()
 				.any(|c| c.local_session_key == validation_session.local_session_key);
 
@@ -492,19 +1133,47 @@ impl LiveValidationSessions {
 	/// This calls the closure with `Some(data)` where the session and data are live,
 	/// `Err(Some(keys))` when the session is live but the data unknown, with a list of keys
 	/// who have the data, and `Err(None)` where the session is unknown.
-	pub(crate) fn with_block_data<F, U>(&self, parent_hash: &Hash, c_hash: &Hash, f: F) -> U
+	pub(crate) fn with_block_data<F, U>(&self, parent_hash: &Hash, c_hash: &CandidateHash, f: F) -> U
 		where F: FnOnce(Result<&BlockData, Option<&[SessionKey]>>) -> U
 	{
 		match self.live_instances.get(parent_hash) {
-			Some(c) => c.with_block_data(c_hash, |res| f(res.map_err(Some))),
+			Some(c) => c.with_block_data(parent_hash, c_hash, |res| f(res.map_err(Some))),
 			None => f(Err(None))
 		}
 	}
+
+	/// Summaries of the candidates known in the validation session at parent hash,
+	/// or `None` if the session is unknown.
+	pub(crate) fn knowledge_summaries(&self, parent_hash: &Hash) -> Option<Vec<CandidateSummary>> {
+		self.live_instances.get(parent_hash).map(|c| c.knowledge_summaries(parent_hash))
+	}
+
+	/// Whether any of the given session keys is in the validator set of the
+	/// validation session at parent hash, or `None` if the session is unknown.
+	pub(crate) fn is_session_validator(&self, parent_hash: &Hash, keys: &[SessionKey]) -> Option<bool> {
+		self.live_instances.get(parent_hash)
+			.map(|session| keys.iter().any(|key| session.validators.contains(key)))
+	}
+
+	/// Hand statements pushed by another validator to the validation session at
+	/// parent hash. Statements on unknown sessions, or beyond the capacity of the
+	/// session's queue, are dropped.
+	pub(crate) fn deliver_statements(&mut self, parent_hash: &Hash, statements: Vec<ConsensusMessage>) {
+		if let Some(session) = self.live_instances.get_mut(parent_hash) {
+			for statement in statements {
+				if session.pushed.try_send(statement).is_err() {
+					trace!(target: "p_net", "Dropping pushed statement on {}: queue full", parent_hash);
+					break;
+				}
+			}
+		}
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use tests::candidate_hash;
 
 	#[test]
 	fn last_keys_works() {
@@ -555,4 +1224,49 @@ mod tests {
 			_ => panic!("not new"),
 		}
 	}
+
+	#[test]
+	fn candidates_shared_across_sessions() {
+		let parent_a = [1; 32].into();
+		let parent_b = [2; 32].into();
+		let candidate_hash = candidate_hash(3);
+		let a_key: SessionKey = [4; 32].into();
+		let b_key: SessionKey = [5; 32].into();
+
+		let mut knowledge = Knowledge::new();
+		knowledge.note_session(parent_a);
+		knowledge.note_session(parent_b);
+
+		// the candidate is backed on both forks, but its data is stored once.
+		knowledge.note_candidate(parent_a, candidate_hash, Some(BlockData(vec![1, 2, 3])), None);
+		let signed = |sender| SignedStatement {
+			statement: GenericStatement::Valid(candidate_hash),
+			signature: Default::default(),
+			sender,
+		};
+
+		knowledge.note_statement(parent_b, &signed(a_key));
+		knowledge.note_statement(parent_a, &signed(b_key));
+		assert_eq!(knowledge.candidates.len(), 1);
+
+		// block data is shared, but statements are only reported in their own session.
+		let summaries = knowledge.summaries(&parent_b);
+		assert_eq!(summaries.len(), 1);
+		assert!(summaries[0].has_block_data);
+		assert_eq!(summaries[0].statements, vec![signed(a_key)]);
+		assert_eq!(knowledge.summaries(&parent_a)[0].statements, vec![signed(b_key)]);
+		assert_eq!(knowledge.block_data(&parent_b, &candidate_hash), Ok(&BlockData(vec![1, 2, 3])));
+
+		// released only once both sessions end.
+		knowledge.release_session(&parent_a);
+		assert!(knowledge.candidates.get(&candidate_hash).unwrap().block_data.is_some());
+		assert!(knowledge.summaries(&parent_a).is_empty());
+
+		// knowledge noted for ended sessions is ignored.
+		knowledge.note_candidate(parent_a, candidate_hash(6), Some(BlockData(vec![4])), None);
+		assert_eq!(knowledge.candidates.len(), 1);
+
+		knowledge.release_session(&parent_b);
+		assert!(knowledge.candidates.is_empty());
+	}
 }
@@ -0,0 +1,452 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Versioned wire encodings for gossiped statements and collations.
+//!
+//! Payloads are prefixed with an envelope marker and a format version, so that the
+//! encoding of statements and candidate receipts can evolve without every validator
+//! upgrading at the same time.
+//!
+//! The marker byte was chosen so that it cannot begin a legacy encoding of either a
+//! signed statement (whose first byte is a statement index between 1 and 3) or a collation
+//! (whose first byte is a compact length prefix, for which `0xff` is invalid).
+//! This lets the decoder fall back to the unversioned format.
+//!
+//! Peers which don't advertise `Capabilities::VERSIONED_WIRE` predate the envelope, and
+//! are sent the legacy encoding. Gossip reaches all peers alike, so it is encoded in the
+//! legacy format for as long as any connected peer needs it. Payloads which can't be
+//! represented in the legacy format, such as receipts acknowledging ingress, are always
+//! sent in the current version.
+//!
+//! Version history:
+//!   - unversioned and `1`: candidate receipts without an ingress watermark.
+//!   - `2`: candidate receipts end with their ingress watermark.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use codec::{Decode, Encode, Input, Output};
+use polkadot_primitives::parachain::{BlockData, CandidateReceipt, Collation};
+use polkadot_validation::{GenericStatement, SignedStatement};
+
+use capabilities::Capabilities;
+
+/// Marker byte beginning every versioned envelope.
+pub const ENVELOPE_MARKER: u8 = 0xff;
+
+/// The version of the wire format produced by this node.
+pub const CURRENT_VERSION: u8 = 2;
+
+/// The version passed to `WirePayload::decode_version` for the legacy unversioned encoding.
+pub const LEGACY_VERSION: u8 = 0;
+
+/// A payload whose encoding may differ between versions of the wire format.
+pub trait WirePayload: Encode + Decode {
+	/// Decode the payload as encoded by an older version of the wire format.
+	fn decode_version<I: Input>(version: u8, input: &mut I) -> Option<Self>;
+
+	/// Encode the payload in the legacy unversioned format, if it can be represented
+	/// in it.
+	fn encode_legacy(&self) -> Option<Vec<u8>>;
+}
+
+/// A payload wrapped in a versioned envelope.
+///
+/// Encoding produces the current version, unless created for a peer which only
+/// understands the legacy format. Decoding accepts the current version, older versions,
+/// as well as the legacy unversioned encoding.
+// TODO: stop accepting the legacy format and version 1 after the next release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned<T> {
+	payload: T,
+	legacy: bool,
+}
+
+impl<T> Versioned<T> {
+	/// Wrap a payload, to be encoded in the current version.
+	pub fn new(payload: T) -> Self {
+		Versioned { payload, legacy: false }
+	}
+
+	/// Wrap a payload for a peer with the given negotiated capabilities, to be encoded
+	/// in the legacy format if the peer doesn't understand the versioned one.
+	pub fn for_capabilities(payload: T, capabilities: Capabilities) -> Self {
+		Versioned { payload, legacy: !capabilities.contains(Capabilities::VERSIONED_WIRE) }
+	}
+
+	/// Unwrap the inner payload.
+	pub fn into_inner(self) -> T {
+		self.payload
+	}
+}
+
+impl<T: WirePayload> Versioned<T> {
+	/// Whether a peer the payload was wrapped for can decode it. Payloads wrapped for
+	/// legacy peers which can't be represented in the legacy format can't be.
+	pub fn is_decodable_by_peer(&self) -> bool {
+		!self.legacy || self.payload.encode_legacy().is_some()
+	}
+}
+
+impl<T: WirePayload> Encode for Versioned<T> {
+	fn encode_to<W: Output>(&self, dest: &mut W) {
+		if self.legacy {
+			if let Some(legacy) = self.payload.encode_legacy() {
+				dest.write(&legacy);
+				return;
+			}
+		}
+
+		dest.push_byte(ENVELOPE_MARKER);
+		dest.push_byte(CURRENT_VERSION);
+		self.payload.encode_to(dest);
+	}
+}
+
+impl<T: WirePayload> Decode for Versioned<T> {
+	fn decode<I: Input>(input: &mut I) -> Option<Self> {
+		let first = input.read_byte()?;
+		if first != ENVELOPE_MARKER {
+			// legacy encoding: hand the consumed byte back to the payload decoder.
+			let mut rewound = Rewind { byte: Some(first), inner: input };
+			return T::decode_version(LEGACY_VERSION, &mut rewound)
+				.map(|payload| Versioned { payload, legacy: true });
+		}
+
+		let payload = match input.read_byte()? {
+			CURRENT_VERSION => T::decode(input)?,
+			version if version < CURRENT_VERSION => T::decode_version(version, input)?,
+			_ => return None,
+		};
+
+		Some(Versioned::new(payload))
+	}
+}
+
+/// Shared view of whether gossip must remain decodable by connected peers which
+/// predate the versioned format. Clones refer to the same view.
+#[derive(Debug, Clone, Default)]
+pub struct GossipFormat {
+	legacy_peers: Arc<AtomicUsize>,
+}
+
+impl GossipFormat {
+	/// Create a new view, with no peers connected.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Encode a payload for gossip.
+	pub fn encode<T: WirePayload>(&self, payload: T) -> Vec<u8> {
+		let legacy = self.legacy_peers.load(Ordering::Relaxed) > 0;
+		Versioned { payload, legacy }.encode()
+	}
+
+	// note a peer with the given negotiated capabilities connecting.
+	pub(crate) fn note_connected(&self, capabilities: Capabilities) {
+		if !capabilities.contains(Capabilities::VERSIONED_WIRE) {
+			self.legacy_peers.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	// note a peer with the given negotiated capabilities disconnecting.
+	pub(crate) fn note_disconnected(&self, capabilities: Capabilities) {
+		if !capabilities.contains(Capabilities::VERSIONED_WIRE) {
+			self.legacy_peers.fetch_sub(1, Ordering::Relaxed);
+		}
+	}
+}
+
+impl WirePayload for SignedStatement {
+	fn decode_version<I: Input>(version: u8, input: &mut I) -> Option<Self> {
+		let statement = match input.read_byte()? {
+			1 => GenericStatement::Candidate(decode_receipt(version, input)?),
+			2 => GenericStatement::Valid(Decode::decode(input)?),
+			3 => GenericStatement::Invalid(Decode::decode(input)?),
+			_ => return None,
+		};
+
+		Some(SignedStatement {
+			statement,
+			signature: Decode::decode(input)?,
+			sender: Decode::decode(input)?,
+		})
+	}
+
+	fn encode_legacy(&self) -> Option<Vec<u8>> {
+		let mut encoded = Vec::new();
+		match self.statement {
+			GenericStatement::Candidate(ref receipt) => {
+				encoded.push(1);
+				encode_legacy_receipt(receipt, &mut encoded)?;
+			}
+			GenericStatement::Valid(ref hash) => {
+				encoded.push(2);
+				hash.encode_to(&mut encoded);
+			}
+			GenericStatement::Invalid(ref hash) => {
+				encoded.push(3);
+				hash.encode_to(&mut encoded);
+			}
+		}
+
+		self.signature.encode_to(&mut encoded);
+		self.sender.encode_to(&mut encoded);
+		Some(encoded)
+	}
+}
+
+impl WirePayload for Collation {
+	fn decode_version<I: Input>(version: u8, input: &mut I) -> Option<Self> {
+		Some(Collation {
+			block_data: BlockData::decode(input)?,
+			receipt: decode_receipt(version, input)?,
+		})
+	}
+
+	fn encode_legacy(&self) -> Option<Vec<u8>> {
+		let mut encoded = self.block_data.encode();
+		encode_legacy_receipt(&self.receipt, &mut encoded)?;
+		Some(encoded)
+	}
+}
+
+// encode a candidate receipt without its ingress watermark. legacy decoders take
+// receipts to acknowledge no ingress, so others can't be represented.
+fn encode_legacy_receipt(receipt: &CandidateReceipt, dest: &mut Vec<u8>) -> Option<()> {
+	if receipt.ingress_watermark != 0 {
+		return None;
+	}
+
+	receipt.parachain_index.encode_to(dest);
+	receipt.collator.encode_to(dest);
+	receipt.signature.encode_to(dest);
+	receipt.head_data.encode_to(dest);
+	receipt.balance_uploads.encode_to(dest);
+	receipt.egress_queue_roots.encode_to(dest);
+	receipt.fees.encode_to(dest);
+	receipt.block_data_hash.encode_to(dest);
+	Some(())
+}
+
+// decode a candidate receipt as encoded by an older version.
+fn decode_receipt<I: Input>(version: u8, input: &mut I) -> Option<CandidateReceipt> {
+	if version >= 2 {
+		return CandidateReceipt::decode(input);
+	}
+
+	// receipts without a watermark acknowledge no ingress.
+	Some(CandidateReceipt {
+		parachain_index: Decode::decode(input)?,
+		collator: Decode::decode(input)?,
+		signature: Decode::decode(input)?,
+		head_data: Decode::decode(input)?,
+		balance_uploads: Decode::decode(input)?,
+		egress_queue_roots: Decode::decode(input)?,
+		fees: Decode::decode(input)?,
+		block_data_hash: Decode::decode(input)?,
+		ingress_watermark: 0,
+	})
+}
+
+// An input which yields a single already-consumed byte before the rest of the inner input.
+struct Rewind<'a, I: 'a> {
+	byte: Option<u8>,
+	inner: &'a mut I,
+}
+
+impl<'a, I: Input> Input for Rewind<'a, I> {
+	fn read(&mut self, into: &mut [u8]) -> usize {
+		if into.is_empty() {
+			return 0;
+		}
+
+		match self.byte.take() {
+			Some(byte) => {
+				into[0] = byte;
+				1 + self.inner.read(&mut into[1..])
+			}
+			None => self.inner.read(into),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::HeadData;
+	use substrate_primitives::H512;
+	use tests::candidate_hash;
+
+	fn signed_statement() -> SignedStatement {
+		SignedStatement {
+			statement: GenericStatement::Valid(candidate_hash(1)),
+			signature: H512::from([2; 64]).into(),
+			sender: [255; 32].into(),
+		}
+	}
+
+	// the encoding of a receipt before the ingress watermark was added.
+	fn encode_without_watermark(receipt: &CandidateReceipt) -> Vec<u8> {
+		let mut encoded = receipt.encode();
+		let len = encoded.len() - receipt.ingress_watermark.encode().len();
+		encoded.truncate(len);
+		encoded
+	}
+
+	fn candidate_statement(receipt: &CandidateReceipt) -> (SignedStatement, Vec<u8>) {
+		let statement = SignedStatement {
+			statement: GenericStatement::Candidate(receipt.clone()),
+			..signed_statement()
+		};
+
+		let mut encoded = vec![1];
+		encoded.extend(encode_without_watermark(receipt));
+		encoded.extend(statement.signature.encode());
+		encoded.extend(statement.sender.encode());
+		(statement, encoded)
+	}
+
+	fn collation() -> Collation {
+		Collation {
+			block_data: BlockData(vec![1, 2, 3]),
+			receipt: CandidateReceipt {
+				parachain_index: 5.into(),
+				collator: [255; 32].into(),
+				signature: H512::from([2; 64]).into(),
+				head_data: HeadData(vec![9, 9, 9]),
+				balance_uploads: Vec::new(),
+				egress_queue_roots: Vec::new(),
+				fees: 100,
+				block_data_hash: [3; 32].into(),
+				ingress_watermark: 0,
+			},
+		}
+	}
+
+	#[test]
+	fn versioned_roundtrip() {
+		let statement = signed_statement();
+		let encoded = Versioned::new(statement.clone()).encode();
+		assert_eq!(&encoded[..2], &[ENVELOPE_MARKER, CURRENT_VERSION]);
+
+		let decoded = Versioned::<SignedStatement>::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded.into_inner(), statement);
+	}
+
+	#[test]
+	fn roundtrip_keeps_watermark() {
+		let mut collation = collation();
+		collation.receipt.ingress_watermark = 7;
+
+		let encoded = Versioned::new(collation.clone()).encode();
+		let decoded = Versioned::<Collation>::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded.into_inner(), collation);
+
+		let statement = SignedStatement {
+			statement: GenericStatement::Candidate(collation.receipt),
+			..signed_statement()
+		};
+		let encoded = Versioned::new(statement.clone()).encode();
+		let decoded = Versioned::<SignedStatement>::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded.into_inner(), statement);
+	}
+
+	#[test]
+	fn accepts_legacy_encoding() {
+		let statement = signed_statement();
+		let legacy = statement.encode();
+		let decoded = Versioned::<SignedStatement>::decode(&mut &legacy[..]).unwrap();
+		assert_eq!(decoded.into_inner(), statement);
+
+		let collation = collation();
+		let (statement, legacy) = candidate_statement(&collation.receipt);
+		let decoded = Versioned::<SignedStatement>::decode(&mut &legacy[..]).unwrap();
+		assert_eq!(decoded.into_inner(), statement);
+
+		let mut legacy = collation.block_data.encode();
+		legacy.extend(encode_without_watermark(&collation.receipt));
+		let decoded = Versioned::<Collation>::decode(&mut &legacy[..]).unwrap();
+		assert_eq!(decoded.into_inner(), collation);
+	}
+
+	#[test]
+	fn accepts_version_without_watermark() {
+		let collation = collation();
+		let (statement, payload) = candidate_statement(&collation.receipt);
+		let mut encoded = vec![ENVELOPE_MARKER, 1];
+		encoded.extend(payload);
+		let decoded = Versioned::<SignedStatement>::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded.into_inner(), statement);
+
+		let mut encoded = vec![ENVELOPE_MARKER, 1];
+		encoded.extend(collation.block_data.encode());
+		encoded.extend(encode_without_watermark(&collation.receipt));
+		let decoded = Versioned::<Collation>::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded.into_inner(), collation);
+	}
+
+	#[test]
+	fn legacy_peers_receive_legacy_encoding() {
+		let collation = collation();
+		let (statement, legacy_statement) = candidate_statement(&collation.receipt);
+
+		let encoded = Versioned::for_capabilities(statement.clone(), Capabilities::LEGACY).encode();
+		assert_eq!(encoded, legacy_statement);
+
+		let encoded = Versioned::for_capabilities(collation.clone(), Capabilities::LEGACY).encode();
+		let mut legacy = collation.block_data.encode();
+		legacy.extend(encode_without_watermark(&collation.receipt));
+		assert_eq!(encoded, legacy);
+
+		let encoded = Versioned::for_capabilities(collation, Capabilities::supported()).encode();
+		assert_eq!(&encoded[..2], &[ENVELOPE_MARKER, CURRENT_VERSION]);
+	}
+
+	#[test]
+	fn watermarked_receipts_not_decodable_by_legacy_peers() {
+		let mut collation = collation();
+		collation.receipt.ingress_watermark = 7;
+
+		let versioned = Versioned::for_capabilities(collation.clone(), Capabilities::LEGACY);
+		assert!(!versioned.is_decodable_by_peer());
+		assert_eq!(&versioned.encode()[..2], &[ENVELOPE_MARKER, CURRENT_VERSION]);
+
+		let versioned = Versioned::for_capabilities(collation, Capabilities::supported());
+		assert!(versioned.is_decodable_by_peer());
+	}
+
+	#[test]
+	fn gossip_legacy_while_legacy_peers_connected() {
+		let format = GossipFormat::new();
+		let statement = signed_statement();
+		assert_eq!(&format.encode(statement.clone())[..2], &[ENVELOPE_MARKER, CURRENT_VERSION]);
+
+		format.note_connected(Capabilities::supported());
+		format.note_connected(Capabilities::LEGACY);
+		assert_eq!(format.encode(statement.clone()), statement.encode());
+
+		format.clone().note_disconnected(Capabilities::LEGACY);
+		assert_eq!(&format.encode(statement)[..2], &[ENVELOPE_MARKER, CURRENT_VERSION]);
+	}
+
+	#[test]
+	fn rejects_unknown_version() {
+		let mut encoded = Versioned::new(signed_statement()).encode();
+		encoded[1] = CURRENT_VERSION + 1;
+		assert!(Versioned::<SignedStatement>::decode(&mut &encoded[..]).is_none());
+	}
+}
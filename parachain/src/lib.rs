@@ -62,6 +62,10 @@ extern crate wasmi;
 #[macro_use]
 extern crate error_chain;
 
+#[cfg(feature = "std")]
+#[macro_use]
+extern crate log;
+
 #[cfg(feature = "std")]
 extern crate serde;
 
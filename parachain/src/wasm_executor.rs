@@ -161,6 +161,7 @@ impl<'a, E: 'a + Externalities> ValidationExternals<'a, E> {
 			if mem.len() < (data_ptr + data_len) {
 				Err(Trap::new(wasmi::TrapKind::MemoryAccessOutOfBounds))
 			} else {
+				trace!(target: "validation", "Posting message of {} bytes to parachain {}", data_len, target);
 				let res = self.externalities.post_message(MessageRef {
 					target: target.into(),
 					data: &mem[data_ptr..][..data_len],
@@ -229,6 +230,13 @@ pub fn validate_candidate<E: Externalities>(
 		module.run_start(&mut externals).map_err(WasmError::Trap)?
 	};
 
+	trace!(
+		target: "validation",
+		"Instantiated validation code of {} bytes with {} initial memory pages",
+		validation_code.len(),
+		memory.current_size().0,
+	);
+
 	// allocate call data in memory.
 	// we guarantee that:
 	// - `offset` has alignment at least of 8,
@@ -251,6 +259,13 @@ pub fn validate_candidate<E: Externalities>(
 				copying never fails if memory is large enough; qed"
 			);
 
+		trace!(
+			target: "validation",
+			"Passing {} bytes of validation params at offset {}",
+			encoded_call_data.len(),
+			allocated_mem_start.0,
+		);
+
 		(allocated_mem_start.0, encoded_call_data.len())
 	};
 
@@ -260,6 +275,7 @@ pub fn validate_candidate<E: Externalities>(
 		&mut externals,
 	)
 		.map_err(|e| -> Error {
+			trace!(target: "validation", "Validation failed: {:?}", e);
 			e.as_host_error()
 				.and_then(|he| he.downcast_ref::<ExternalitiesError>())
 				.map(|ee| ErrorKind::Externalities(ee.clone()).into())
@@ -283,6 +299,7 @@ pub fn validate_candidate<E: Externalities>(
 				len_offset - len
 			};
 
+			trace!(target: "validation", "Validation returned {} bytes at offset {}", len, return_offset);
 			memory.with_direct_access(|mem| {
 				if mem.len() < return_offset + len {
 					return Err(ErrorKind::BadReturn.into());
@@ -0,0 +1,269 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rules a parachain candidate must follow to be included in the relay chain.
+//!
+//! The runtime checks these on inclusion, and validators check them before
+//! seconding or voting for a candidate, so that no backing work is spent on
+//! candidates the runtime would reject.
+
+use message_roots::EMPTY_TRIE_ROOT;
+use parachain::{CandidateReceipt, Id as ParaId, OutgoingMessage};
+use BlockNumber;
+
+/// Maximum size of the head data of a candidate, in bytes.
+pub const MAX_HEAD_DATA_SIZE: usize = 32 * 1024;
+
+/// Maximum number of messages a candidate may send.
+pub const MAX_OUTGOING_MESSAGES: usize = 1024;
+
+/// Maximum size of a single message sent by a candidate, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// The reason a candidate is rejected for inclusion in the relay chain.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum InclusionError {
+	/// More candidates than active parachains.
+	TooManyCandidates,
+	/// Candidates out of order by parachain ID, or more than one for a parachain.
+	OutOfOrder,
+	/// The candidate is for a parachain which isn't active.
+	UnregisteredParachain,
+	/// The collator isn't permitted to collate for the parachain.
+	CollatorNotPermitted,
+	/// The head data is larger than `MAX_HEAD_DATA_SIZE`.
+	HeadDataTooLarge,
+	/// Egress queue roots out of order by parachain ID, or duplicated.
+	EgressOutOfOrder,
+	/// An egress queue root routing to the parachain itself.
+	RoutingToSelf,
+	/// An egress queue root of an empty queue.
+	EmptyEgressRoot,
+	/// An egress queue root routing to a parachain which isn't active.
+	RoutingToUnknownParachain,
+	/// The ingress watermark is behind the current watermark of the parachain.
+	IngressWatermarkMovedBack,
+	/// The ingress watermark is past the block the candidate is included in.
+	IngressWatermarkAhead,
+	/// No validators are assigned to the parachain.
+	NoValidatorGroup,
+	/// Fewer validity attestations than a majority of the validators assigned to the parachain.
+	NotEnoughAttestations,
+	/// A validity attestation by a validator not assigned to the parachain.
+	WrongValidatorGroup,
+	/// More than one validity attestation by the same validator.
+	DoubleAttestation,
+	/// A validity attestation with a bad signature.
+	BadAttestationSignature,
+	/// The candidates weigh more than the maximum total weight of a block.
+	Overweight,
+}
+
+impl InclusionError {
+	/// A description of the error.
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			InclusionError::TooManyCandidates => "Too many parachain candidates",
+			InclusionError::OutOfOrder => "Parachain candidates out of order by ID",
+			InclusionError::UnregisteredParachain => "Submitted candidate for unregistered or out-of-order parachain",
+			InclusionError::CollatorNotPermitted => "Candidate produced by collator not permitted for parachain",
+			InclusionError::HeadDataTooLarge => "Candidate head data too large",
+			InclusionError::EgressOutOfOrder => "Egress routes out of order by ID",
+			InclusionError::RoutingToSelf => "Parachain routing to self",
+			InclusionError::EmptyEgressRoot => "Empty trie root included",
+			InclusionError::RoutingToUnknownParachain => "Routing to non-existent parachain",
+			InclusionError::IngressWatermarkMovedBack => "Ingress watermark moved back",
+			InclusionError::IngressWatermarkAhead => "Ingress watermark acknowledges ingress not yet routed",
+			InclusionError::NoValidatorGroup => "no validator group for parachain",
+			InclusionError::NotEnoughAttestations => "Not enough validity attestations",
+			InclusionError::WrongValidatorGroup => "Attesting validator not on this chain's validation duty.",
+			InclusionError::DoubleAttestation => "Voter already attested validity once",
+			InclusionError::BadAttestationSignature => "Candidate validity attestation signature is bad.",
+			InclusionError::Overweight => "Parachain candidates exceed the maximum block weight",
+		}
+	}
+}
+
+impl From<InclusionError> for &'static str {
+	fn from(e: InclusionError) -> &'static str {
+		e.as_str()
+	}
+}
+
+/// Check a candidate receipt, given the active parachains in ascending order.
+pub fn check_candidate(candidate: &CandidateReceipt, active_parachains: &[ParaId]) -> Result<(), InclusionError> {
+	if candidate.head_data.0.len() > MAX_HEAD_DATA_SIZE {
+		return Err(InclusionError::HeadDataTooLarge);
+	}
+
+	check_egress_queue_roots(candidate, active_parachains)
+}
+
+fn check_egress_queue_roots(candidate: &CandidateReceipt, active_parachains: &[ParaId]) -> Result<(), InclusionError> {
+	let mut last_egress_id = None;
+	let mut iter = active_parachains.iter();
+	for (egress_para_id, root) in &candidate.egress_queue_roots {
+		// egress routes should be ascending order by parachain ID without duplicate.
+		if !last_egress_id.as_ref().map_or(true, |x| x < &egress_para_id) {
+			return Err(InclusionError::EgressOutOfOrder);
+		}
+
+		// a parachain can't route to self
+		if *egress_para_id == candidate.parachain_index {
+			return Err(InclusionError::RoutingToSelf);
+		}
+
+		// no empty trie roots
+		if *root == EMPTY_TRIE_ROOT.into() {
+			return Err(InclusionError::EmptyEgressRoot);
+		}
+
+		// can't route to a parachain which doesn't exist
+		if iter.find(|x| x == &egress_para_id).is_none() {
+			return Err(InclusionError::RoutingToUnknownParachain);
+		}
+
+		last_egress_id = Some(egress_para_id)
+	}
+
+	Ok(())
+}
+
+/// Check the messages sent by a candidate.
+///
+/// Only validators can check these, as the runtime sees no more than the
+/// egress queue roots.
+pub fn check_outgoing_messages(messages: &[OutgoingMessage]) -> Result<(), &'static str> {
+	if messages.len() > MAX_OUTGOING_MESSAGES {
+		return Err("Too many outgoing messages");
+	}
+
+	if messages.iter().any(|message| message.data.len() > MAX_MESSAGE_SIZE) {
+		return Err("Outgoing message too large");
+	}
+
+	Ok(())
+}
+
+/// Whether ingress routed to a parachain at block `posted` has yet to be processed
+/// by it, given the parachain's ingress watermark.
+///
+/// Candidates acknowledge a new watermark in their receipt, which takes effect once
+/// they are included. Ingress routed before it is pruned.
+pub fn is_unprocessed_ingress<N: PartialOrd>(posted: N, watermark: N) -> bool {
+	posted >= watermark
+}
+
+/// The ingress watermark acknowledged by a candidate which processed the ingress routed
+/// at the given blocks, given the parachain's current watermark.
+///
+/// The watermark moves past the latest block any processed ingress was routed at,
+/// and never moves back.
+pub fn ingress_watermark<I: IntoIterator<Item=BlockNumber>>(current: BlockNumber, processed: I) -> BlockNumber {
+	processed.into_iter().fold(current, |watermark, posted| ::rstd::cmp::max(watermark, posted + 1))
+}
+
+/// Check the ingress watermark acknowledged by a candidate, given the parachain's current
+/// watermark and the number of the block the candidate is included in.
+pub fn check_ingress_watermark(
+	candidate: &CandidateReceipt,
+	current: BlockNumber,
+	now: BlockNumber,
+) -> Result<(), InclusionError> {
+	if candidate.ingress_watermark < current {
+		return Err(InclusionError::IngressWatermarkMovedBack);
+	}
+
+	if candidate.ingress_watermark > now {
+		return Err(InclusionError::IngressWatermarkAhead);
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parachain::HeadData;
+
+	fn candidate(head_data: Vec<u8>, egress_queue_roots: Vec<(ParaId, ::Hash)>) -> CandidateReceipt {
+		CandidateReceipt {
+			parachain_index: 1.into(),
+			collator: Default::default(),
+			signature: Default::default(),
+			head_data: HeadData(head_data),
+			balance_uploads: Vec::new(),
+			egress_queue_roots,
+			fees: 0,
+			block_data_hash: Default::default(),
+			ingress_watermark: 0,
+		}
+	}
+
+	#[test]
+	fn candidate_checks() {
+		let active = [0.into(), 1.into(), 2.into()];
+
+		assert!(check_candidate(&candidate(vec![1; MAX_HEAD_DATA_SIZE], Vec::new()), &active).is_ok());
+		assert_eq!(
+			check_candidate(&candidate(vec![1; MAX_HEAD_DATA_SIZE + 1], Vec::new()), &active),
+			Err(InclusionError::HeadDataTooLarge),
+		);
+
+		let routes = vec![(0.into(), [1; 32].into()), (2.into(), [2; 32].into())];
+		assert!(check_candidate(&candidate(Vec::new(), routes), &active).is_ok());
+
+		let to_self = vec![(1.into(), [1; 32].into())];
+		assert_eq!(check_candidate(&candidate(Vec::new(), to_self), &active), Err(InclusionError::RoutingToSelf));
+
+		let unknown = vec![(3.into(), [1; 32].into())];
+		assert_eq!(
+			check_candidate(&candidate(Vec::new(), unknown), &active),
+			Err(InclusionError::RoutingToUnknownParachain),
+		);
+	}
+
+	#[test]
+	fn message_limits() {
+		let message = |size| OutgoingMessage { target: 2.into(), data: vec![0; size] };
+
+		assert!(check_outgoing_messages(&[message(MAX_MESSAGE_SIZE)]).is_ok());
+		assert_eq!(check_outgoing_messages(&[message(MAX_MESSAGE_SIZE + 1)]), Err("Outgoing message too large"));
+
+		let too_many: Vec<_> = (0..MAX_OUTGOING_MESSAGES + 1).map(|_| message(1)).collect();
+		assert_eq!(check_outgoing_messages(&too_many), Err("Too many outgoing messages"));
+
+		assert!(is_unprocessed_ingress(5, 5));
+		assert!(!is_unprocessed_ingress(4, 5));
+	}
+
+	#[test]
+	fn ingress_watermarks() {
+		assert_eq!(ingress_watermark(3, Vec::new()), 3);
+		assert_eq!(ingress_watermark(3, vec![4, 7, 5]), 8);
+		assert_eq!(ingress_watermark(9, vec![4]), 9);
+
+		let mut acknowledging = candidate(Vec::new(), Vec::new());
+		acknowledging.ingress_watermark = 5;
+		assert!(check_ingress_watermark(&acknowledging, 5, 5).is_ok());
+		assert_eq!(check_ingress_watermark(&acknowledging, 6, 10), Err(InclusionError::IngressWatermarkMovedBack));
+		assert_eq!(
+			check_ingress_watermark(&acknowledging, 0, 4),
+			Err(InclusionError::IngressWatermarkAhead),
+		);
+	}
+}
@@ -0,0 +1,202 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Encryption of payloads to validators, used to keep collations private until
+//! they are backed.
+//!
+//! Each validator holds a long-term X25519 encryption key, separate from its session
+//! key, and announces the public half signed by the session key. Payloads are sealed
+//! to the announced key with an ephemeral X25519 key exchange and XChaCha20-Poly1305.
+
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, NewAead, Payload, generic_array::GenericArray};
+use primitives::{blake2_256, ed25519};
+use rand::{Rng, RngCore};
+use runtime_primitives::traits::Verify;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use parachain::CandidateSignature;
+use SessionKey;
+
+// prefixed to an encryption public key when signing its announcement.
+const ANNOUNCEMENT_CONTEXT: &[u8] = b"polkadot collation encryption key";
+// prefixed to the key exchange output when deriving the payload key.
+const PAYLOAD_KEY_CONTEXT: &[u8] = b"polkadot collation payload key";
+
+/// An encryption public key.
+pub type EncryptionPublic = [u8; 32];
+
+/// A payload encrypted to a single recipient.
+#[derive(Clone, PartialEq, Eq, Debug, Encode, Decode)]
+pub struct EncryptedPayload {
+	/// The ephemeral public key of the sender.
+	pub ephemeral: EncryptionPublic,
+	/// The XChaCha20-Poly1305 nonce.
+	pub nonce: [u8; 24],
+	/// The encrypted payload, followed by its authentication tag.
+	pub ciphertext: Vec<u8>,
+}
+
+/// A validator's long-term encryption key pair.
+pub struct EncryptionKey {
+	secret: [u8; 32],
+	public: EncryptionPublic,
+}
+
+impl EncryptionKey {
+	/// Generate a new random encryption key.
+	pub fn generate() -> Self {
+		Self::from_secret(::rand::thread_rng().gen())
+	}
+
+	/// Create the encryption key with the given secret, as returned by `secret`.
+	pub fn from_secret(secret: [u8; 32]) -> Self {
+		let public = *PublicKey::from(&StaticSecret::from(secret)).as_bytes();
+		EncryptionKey { secret, public }
+	}
+
+	/// The secret half of the key, to be stored.
+	pub fn secret(&self) -> [u8; 32] {
+		self.secret
+	}
+
+	/// The public half of the key.
+	pub fn public(&self) -> EncryptionPublic {
+		self.public
+	}
+
+	/// Sign an announcement of the public key with the session key it is used with.
+	pub fn announce(&self, session_key: &ed25519::Pair) -> CandidateSignature {
+		session_key.sign(&announcement_payload(&self.public)).into()
+	}
+
+	/// Decrypt a payload encrypted to this key. Returns `None` if the payload
+	/// was not encrypted to this key or was tampered with.
+	pub fn decrypt(&self, payload: &EncryptedPayload) -> Option<Vec<u8>> {
+		let cipher = payload_cipher(&StaticSecret::from(self.secret), &payload.ephemeral, &self.public);
+		let aad = associated_data(&payload.ephemeral, &self.public);
+
+		cipher.decrypt(
+			GenericArray::from_slice(&payload.nonce),
+			Payload { msg: &payload.ciphertext, aad: &aad },
+		).ok()
+	}
+}
+
+/// Check that an encryption public key was announced by the given session key.
+pub fn check_announcement(
+	public: &EncryptionPublic,
+	signature: &CandidateSignature,
+	session_key: SessionKey,
+) -> bool {
+	signature.verify(&announcement_payload(public)[..], &session_key.into())
+}
+
+/// Encrypt a payload to the holder of the given encryption key.
+pub fn encrypt(recipient: &EncryptionPublic, plaintext: &[u8]) -> EncryptedPayload {
+	let mut rng = ::rand::thread_rng();
+	let ephemeral_secret = StaticSecret::from(rng.gen::<[u8; 32]>());
+	let ephemeral = *PublicKey::from(&ephemeral_secret).as_bytes();
+
+	let mut nonce = [0u8; 24];
+	rng.fill_bytes(&mut nonce);
+
+	let cipher = payload_cipher(&ephemeral_secret, recipient, recipient);
+	let aad = associated_data(&ephemeral, recipient);
+	let ciphertext = cipher.encrypt(
+		GenericArray::from_slice(&nonce),
+		Payload { msg: plaintext, aad: &aad },
+	).expect("payloads are far below the maximum XChaCha20-Poly1305 message length; qed");
+
+	EncryptedPayload { ephemeral, nonce, ciphertext }
+}
+
+fn announcement_payload(public: &EncryptionPublic) -> Vec<u8> {
+	let mut payload = ANNOUNCEMENT_CONTEXT.to_vec();
+	payload.extend(public.iter());
+	payload
+}
+
+// both public keys are authenticated along with the payload.
+fn associated_data(ephemeral: &EncryptionPublic, recipient: &EncryptionPublic) -> Vec<u8> {
+	let mut aad = ephemeral.to_vec();
+	aad.extend(recipient.iter());
+	aad
+}
+
+// the cipher keyed by the hash of the key exchange between `secret` and `other`.
+// a fresh ephemeral key is used for every payload, so every payload has its own key.
+fn payload_cipher(secret: &StaticSecret, other: &EncryptionPublic, recipient: &EncryptionPublic) -> XChaCha20Poly1305 {
+	let shared = secret.diffie_hellman(&PublicKey::from(*other));
+
+	let mut material = PAYLOAD_KEY_CONTEXT.to_vec();
+	material.extend(shared.as_bytes().iter());
+	material.extend(recipient.iter());
+
+	XChaCha20Poly1305::new(GenericArray::clone_from_slice(&blake2_256(&material)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pair(seed: u8) -> ed25519::Pair {
+		ed25519::Pair::from_seed(&[seed; 32])
+	}
+
+	#[test]
+	fn encrypted_payload_roundtrip() {
+		let key = EncryptionKey::generate();
+		assert_eq!(key.public(), EncryptionKey::from_secret(key.secret()).public());
+
+		let plaintext = vec![42u8; 100];
+		let payload = encrypt(&key.public(), &plaintext);
+		assert!(payload.ciphertext != plaintext);
+		assert_eq!(key.decrypt(&payload), Some(plaintext));
+	}
+
+	#[test]
+	fn wrong_key_or_tampering_fails_decryption() {
+		let key = EncryptionKey::generate();
+		let other = EncryptionKey::generate();
+
+		let payload = encrypt(&key.public(), b"collation");
+		assert_eq!(other.decrypt(&payload), None);
+
+		let mut tampered = payload.clone();
+		tampered.ciphertext[0] ^= 1;
+		assert_eq!(key.decrypt(&tampered), None);
+
+		let mut tampered = payload.clone();
+		tampered.nonce[0] ^= 1;
+		assert_eq!(key.decrypt(&tampered), None);
+
+		let mut tampered = payload;
+		tampered.ephemeral = EncryptionKey::generate().public();
+		assert_eq!(key.decrypt(&tampered), None);
+	}
+
+	#[test]
+	fn announcement_checked_against_session_key() {
+		let session_key = pair(1);
+		let key = EncryptionKey::generate();
+		let signature = key.announce(&session_key);
+
+		assert!(check_announcement(&key.public(), &signature, session_key.public().into()));
+		assert!(!check_announcement(&key.public(), &signature, pair(2).public().into()));
+		assert!(!check_announcement(&[0; 32], &signature, session_key.public().into()));
+	}
+}
@@ -41,6 +41,18 @@ extern crate serde_derive;
 #[cfg(feature = "std")]
 extern crate serde;
 
+#[cfg(feature = "std")]
+extern crate x25519_dalek;
+
+#[cfg(feature = "std")]
+extern crate chacha20poly1305;
+
+#[cfg(feature = "std")]
+extern crate rand;
+
+#[cfg(feature = "std")]
+extern crate substrate_trie as trie;
+
 #[macro_use]
 extern crate substrate_client;
 
@@ -50,6 +62,14 @@ use runtime_primitives::{generic, traits::Extrinsic};
 pub use runtime_primitives::traits::{BlakeTwo256, Hash as HashT};
 
 pub mod parachain;
+pub mod message_roots;
+pub mod acceptance;
+
+#[cfg(feature = "std")]
+pub mod encryption;
+
+#[cfg(feature = "std")]
+pub mod test_vectors;
 
 pub use codec::Compact;
 
@@ -0,0 +1,121 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trie roots of parachain message queues.
+//!
+//! A candidate commits to the messages it sends by the root of an ordered trie
+//! of the messages to each target parachain. The same roots are used to check
+//! the messages a parachain receives, so everything which computes or checks
+//! them should use these functions.
+
+#[cfg(feature = "std")]
+use primitives::Blake2Hasher;
+
+#[cfg(feature = "std")]
+use parachain::{Id as ParaId, Message, OutgoingMessage};
+#[cfg(feature = "std")]
+use Hash;
+
+/// The root of an empty message queue, which is never included in a candidate.
+///
+/// This is the BLAKE2 hash of the null trie node.
+pub const EMPTY_TRIE_ROOT: [u8; 32] = [
+	3, 23, 10, 46, 117, 151, 183, 183, 227, 216, 76, 5, 57, 29, 19, 154,
+	98, 177, 87, 231, 135, 134, 216, 192, 130, 242, 157, 207, 76, 17, 19, 20
+];
+
+/// Compute a trie root for a set of messages.
+#[cfg(feature = "std")]
+pub fn message_queue_root<A, I: IntoIterator<Item=A>>(messages: I) -> Hash
+	where A: AsRef<[u8]>
+{
+	::trie::ordered_trie_root::<Blake2Hasher, _, _>(messages)
+}
+
+/// Compute the set of egress roots for all given outgoing messages.
+///
+/// Roots are ascending by target parachain ID, and messages to the same target
+/// are kept in the order they were sent.
+#[cfg(feature = "std")]
+pub fn egress_roots(mut outgoing: Vec<OutgoingMessage>) -> Vec<(ParaId, Hash)> {
+	// stable sort messages by parachain ID.
+	outgoing.sort_by_key(|msg| ParaId::from(msg.target));
+
+	let mut egress_roots = Vec::new();
+	{
+		let mut messages_iter = outgoing.iter().peekable();
+		while let Some(batch_target) = messages_iter.peek().map(|o| o.target) {
+			// we borrow the iterator mutably to ensure it advances so the
+			// next iteration of the loop starts with `messages_iter` pointing to
+			// the next batch.
+			let messages_to = messages_iter
+				.clone()
+				.take_while(|o| o.target == batch_target)
+				.map(|o| { let _ = messages_iter.next(); &o.data[..] });
+
+			let computed_root = message_queue_root(messages_to);
+			egress_roots.push((batch_target, computed_root));
+		}
+	}
+
+	egress_roots
+}
+
+/// Compute the root of the incoming message queue from each source parachain.
+///
+/// These are the egress roots the sources committed to for the receiving parachain.
+#[cfg(feature = "std")]
+pub fn ingress_roots(incoming: &[(ParaId, Vec<Message>)]) -> Vec<(ParaId, Hash)> {
+	incoming.iter()
+		.map(|&(source, ref messages)| (source, message_queue_root(messages.iter().map(|m| &m.0))))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn empty_trie_root_is_root_of_no_messages() {
+		assert_eq!(message_queue_root(Vec::<Vec<u8>>::new()), EMPTY_TRIE_ROOT.into());
+	}
+
+	#[test]
+	fn egress_roots_grouped_by_target() {
+		let messages = vec![
+			OutgoingMessage { target: 3.into(), data: vec![1, 1, 1] },
+			OutgoingMessage { target: 1.into(), data: vec![1, 2, 3] },
+			OutgoingMessage { target: 2.into(), data: vec![4, 5, 6] },
+			OutgoingMessage { target: 1.into(), data: vec![7, 8, 9] },
+		];
+
+		assert_eq!(egress_roots(messages), vec![
+			(1.into(), message_queue_root(&[vec![1, 2, 3], vec![7, 8, 9]])),
+			(2.into(), message_queue_root(&[vec![4, 5, 6]])),
+			(3.into(), message_queue_root(&[vec![1, 1, 1]])),
+		]);
+
+		let incoming = vec![
+			(1.into(), vec![Message(vec![1, 2, 3]), Message(vec![7, 8, 9])]),
+			(2.into(), vec![Message(vec![4, 5, 6])]),
+		];
+
+		assert_eq!(ingress_roots(&incoming), vec![
+			(1.into(), message_queue_root(&[vec![1, 2, 3], vec![7, 8, 9]])),
+			(2.into(), message_queue_root(&[vec![4, 5, 6]])),
+		]);
+	}
+}
@@ -18,6 +18,7 @@
 
 use rstd::prelude::*;
 use rstd::cmp::Ordering;
+use codec::Encode;
 use super::{Hash, SessionKey};
 
 use {AccountId};
@@ -30,6 +31,10 @@ pub use polkadot_parachain::Id;
 /// Signature on candidate's block data by a collator.
 pub type CandidateSignature = ::runtime_primitives::Ed25519Signature;
 
+/// Identity of a collator: the relay chain account whose Ed25519 key signs its candidates.
+/// This is always the primitive `AccountId`, whatever account type the runtime uses.
+pub type CollatorId = AccountId;
+
 /// Identifier for a chain, either one of a number of parachains or the relay chain.
 #[derive(Copy, Clone, PartialEq, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Debug))]
@@ -48,6 +53,39 @@ pub struct DutyRoster {
 	pub validator_duty: Vec<Chain>,
 }
 
+/// Index of a validator in the validator set of a block.
+pub type ValidatorIndex = u32;
+
+/// Index of an execution core. Each core may be occupied by one parachain or
+/// parathread claim per relay chain block.
+pub type CoreIndex = u32;
+
+/// The kind of claim a core is assigned to.
+#[derive(Copy, Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+pub enum AssignmentKind {
+	/// The core is assigned to a parachain, which is scheduled in every block.
+	Parachain,
+	/// The core is assigned to a parathread claim, scheduled for a single block.
+	Parathread,
+}
+
+/// The assignment of a validator group to a core at a relay chain block.
+#[derive(Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "std", serde(deny_unknown_fields))]
+pub struct CoreAssignment {
+	/// The core being assigned.
+	pub core: CoreIndex,
+	/// The parachain or parathread occupying the core.
+	pub para_id: Id,
+	/// The kind of claim occupying the core.
+	pub kind: AssignmentKind,
+	/// The validators assigned to the core, as indices into the validator set.
+	pub group: Vec<ValidatorIndex>,
+}
+
 /// An outgoing message
 #[derive(Clone, PartialEq, Eq, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
@@ -87,6 +125,28 @@ pub struct Extrinsic {
 	pub outgoing_messages: Vec<OutgoingMessage>
 }
 
+/// The hash of a candidate receipt, which identifies the candidate.
+///
+/// This is a distinct type so that candidate hashes can't be mixed up with block
+/// hashes or other digests. Compute one from a receipt with `CandidateReceipt::hash`,
+/// or decode one received from elsewhere.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+pub struct CandidateHash(Hash);
+
+#[cfg(feature = "std")]
+impl ::std::fmt::Display for CandidateHash {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		::std::fmt::Display::fmt(&self.0, f)
+	}
+}
+
+impl AsRef<[u8]> for CandidateHash {
+	fn as_ref(&self) -> &[u8] {
+		self.0.as_ref()
+	}
+}
+
 /// Candidate receipt type.
 #[derive(PartialEq, Eq, Clone, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
@@ -96,7 +156,7 @@ pub struct CandidateReceipt {
 	/// The ID of the parachain this is a candidate for.
 	pub parachain_index: Id,
 	/// The collator's relay-chain account ID
-	pub collator: super::AccountId,
+	pub collator: CollatorId,
 	/// Signature on blake2-256 of the block data by collator.
 	pub signature: CandidateSignature,
 	/// The head-data
@@ -110,13 +170,16 @@ pub struct CandidateReceipt {
 	pub fees: u64,
 	/// blake2-256 Hash of block data.
 	pub block_data_hash: Hash,
+	/// The ingress watermark acknowledged by the candidate: all ingress routed to the
+	/// parachain before this relay chain block number has been processed.
+	pub ingress_watermark: super::BlockNumber,
 }
 
 impl CandidateReceipt {
-	/// Get the blake2_256 hash
-	pub fn hash(&self) -> Hash {
+	/// Get the blake2_256 hash of the encoded receipt.
+	pub fn hash(&self) -> CandidateHash {
 		use runtime_primitives::traits::{BlakeTwo256, Hash};
-		BlakeTwo256::hash_of(self)
+		CandidateHash(BlakeTwo256::hash_of(self))
 	}
 
 	/// Check integrity vs. provided block data.
@@ -158,8 +221,8 @@ pub struct Collation {
 }
 
 /// Parachain ingress queue message.
-#[derive(PartialEq, Eq, Clone)]
-#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Encode, Decode, Debug))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 pub struct Message(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u8>);
 
 /// Consolidated ingress queue data.
@@ -179,7 +242,6 @@ pub struct BlockData(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u
 
 impl BlockData {
 	/// Compute hash of block data.
-	#[cfg(feature = "std")]
 	pub fn hash(&self) -> Hash {
 		use runtime_primitives::traits::{BlakeTwo256, Hash};
 		BlakeTwo256::hash(&self.0[..])
@@ -206,7 +268,7 @@ pub struct ValidationCode(#[cfg_attr(feature = "std", serde(with="bytes"))] pub
 pub struct Activity(#[cfg_attr(feature = "std", serde(with="bytes"))] pub Vec<u8>);
 
 /// Statements which can be made about parachain candidates.
-#[derive(Clone, PartialEq, Eq, Encode)]
+#[derive(Clone, PartialEq, Eq, Encode, Decode)]
 #[cfg_attr(feature = "std", derive(Debug))]
 pub enum Statement {
 	/// Proposal of a parachain candidate.
@@ -214,16 +276,46 @@ pub enum Statement {
 	Candidate(CandidateReceipt),
 	/// State that a parachain candidate is valid.
 	#[codec(index = "2")]
-	Valid(Hash),
+	Valid(CandidateHash),
 	/// State a candidate is invalid.
 	#[codec(index = "3")]
-	Invalid(Hash),
+	Invalid(CandidateHash),
+}
+
+impl Statement {
+	/// The payload signed by validators issuing this statement on the given relay parent.
+	pub fn localized_payload(&self, parent_hash: &Hash) -> Vec<u8> {
+		let mut encoded = self.encode();
+		encoded.extend(parent_hash.as_ref());
+		encoded
+	}
+}
+
+/// Compute the gossip topic for attestation statements on a relay parent.
+pub fn attestation_topic(parent_hash: Hash) -> Hash {
+	use runtime_primitives::traits::{BlakeTwo256, Hash as HashT};
+
+	let mut v = parent_hash.as_ref().to_vec();
+	v.extend(b"attestations");
+
+	BlakeTwo256::hash(&v[..])
+}
+
+/// Compute the gossip topic for messages incoming to a parachain on a relay parent.
+pub fn incoming_message_topic(parent_hash: Hash, parachain: Id) -> Hash {
+	use runtime_primitives::traits::{BlakeTwo256, Hash as HashT};
+
+	let mut v = parent_hash.as_ref().to_vec();
+	parachain.using_encoded(|s| v.extend(s));
+	v.extend(b"incoming");
+
+	BlakeTwo256::hash(&v[..])
 }
 
 /// An either implicit or explicit attestation to the validity of a parachain
 /// candidate.
 #[derive(Clone, PartialEq, Decode, Encode)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
 pub enum ValidityAttestation {
 	/// implicit validity attestation by issuing.
 	/// This corresponds to issuance of a `Candidate` statement.
@@ -237,7 +329,8 @@ pub enum ValidityAttestation {
 
 /// An attested candidate.
 #[derive(Clone, PartialEq, Decode, Encode)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
 pub struct AttestedCandidate {
 	/// The candidate data.
 	pub candidate: CandidateReceipt,
@@ -257,13 +350,91 @@ impl AttestedCandidate {
 	}
 }
 
+/// A candidate which has gathered enough validity votes to be included, seen by
+/// a validator before it is included on-chain.
+#[derive(Clone, PartialEq, Decode, Encode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct BackedCandidate {
+	/// The relay chain parent the candidate was backed on.
+	pub relay_parent: Hash,
+	/// The candidate along with the statements backing it.
+	pub candidate: AttestedCandidate,
+}
+
+/// Statistics on the inclusion of a parachain's candidates over recent sessions.
+#[derive(Clone, PartialEq, Eq, Default, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct InclusionStats {
+	/// The number of candidates included in each recent session, oldest first.
+	/// The last entry is for the current session.
+	pub included: Vec<u32>,
+	/// The total inclusion interval of the candidates included in each recent session.
+	/// The inclusion interval of a candidate is the number of relay chain blocks since
+	/// the previous candidate of the parachain was included. The first candidate has
+	/// no previous one to measure it from.
+	pub interval: Vec<u64>,
+	/// The number of candidates whose inclusion interval was measured in each recent session.
+	pub measured: Vec<u32>,
+	/// The relay chain block at which a candidate was last included.
+	pub last_included: Option<super::BlockNumber>,
+}
+
+/// The weights the runtime assigns to checking and importing candidates, which
+/// block authors use to estimate how many candidates fit in a block.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize, Debug))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct InclusionWeights {
+	/// The weight of importing a candidate, not counting its validity votes.
+	pub candidate_base: u64,
+	/// The weight of checking a single validity vote.
+	pub validity_vote: u64,
+	/// The weight of routing a single egress queue root.
+	pub egress_root: u64,
+	/// The maximum total weight of the candidates in a single block.
+	pub max_block: u64,
+}
+
+impl InclusionWeights {
+	/// The weight of checking and importing the given candidate.
+	pub fn candidate(&self, candidate: &AttestedCandidate) -> u64 {
+		self.candidate_base
+			.saturating_add(self.validity_vote.saturating_mul(candidate.validity_votes.len() as u64))
+			.saturating_add(self.egress_root.saturating_mul(candidate.candidate.egress_queue_roots.len() as u64))
+	}
+}
+
+impl InclusionStats {
+	/// The number of candidates included over the recent sessions.
+	pub fn total_included(&self) -> u32 {
+		self.included.iter().sum()
+	}
+
+	/// The average inclusion interval over the recent sessions, in relay chain blocks.
+	pub fn average_interval(&self) -> Option<u64> {
+		let measured = self.measured.iter().map(|&m| u64::from(m)).sum::<u64>();
+		if measured == 0 {
+			None
+		} else {
+			Some(self.interval.iter().sum::<u64>() / measured)
+		}
+	}
+}
+
 decl_runtime_apis! {
 	/// The API for querying the state of parachains on-chain.
+	#[api_version(2)]
 	pub trait ParachainHost {
 		/// Get the current validators.
 		fn validators() -> Vec<AccountId>;
 		/// Get the current duty roster.
 		fn duty_roster() -> DutyRoster;
+		/// Get the assignments of validator groups to cores for the current block.
+		fn scheduled() -> Vec<CoreAssignment>;
+		/// Get the session keys of the validators eligible for parachain duty.
+		fn parachain_validators() -> Vec<SessionKey>;
 		/// Get the currently active parachains.
 		fn active_parachains() -> Vec<Id>;
 		/// Get the given parachain's head data blob.
@@ -273,6 +444,22 @@ decl_runtime_apis! {
 		/// Get the ingress roots to a specific parachain at a
 		/// block.
 		fn ingress(to: Id) -> Option<Vec<(Id, Hash)>>;
+		/// Get the blocks at which the ingress routes to a specific parachain, which it
+		/// has yet to process, were last updated.
+		fn ingress_posted(to: Id) -> Option<Vec<(Id, super::BlockNumber)>>;
+		/// Get the ingress watermark of a parachain.
+		fn ingress_watermark(id: Id) -> super::BlockNumber;
+		/// Get the collators permitted to collate for a parachain, or `None` if
+		/// anyone may collate for it.
+		fn permitted_collators(id: Id) -> Option<Vec<CollatorId>>;
+		/// Get the inclusion statistics of a parachain over recent sessions.
+		fn inclusion_stats(id: Id) -> Option<InclusionStats>;
+		/// Get the weights of checking and importing candidates.
+		fn inclusion_weights() -> InclusionWeights;
+		/// Check candidates for inclusion in the block being built, getting the positions
+		/// of those which would be rejected along with the reason. Call after initialising
+		/// the block.
+		fn check_candidates(candidates: Vec<AttestedCandidate>) -> Vec<(u32, super::acceptance::InclusionError)>;
 	}
 }
 
@@ -0,0 +1,166 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deterministic test vectors for the encodings, signatures and gossip topics
+//! used between validators and collators.
+//!
+//! Alternative implementations can check their output against these byte-for-byte.
+//! Everything is derived from fixed keys and data, and Ed25519 signatures are
+//! deterministic, so the vectors never change unless the formats do. The expected
+//! vectors are kept in `res/test-vectors.txt`, in the output format of the
+//! `test-vectors` subcommand.
+
+use codec::Encode;
+use primitives::{blake2_256, ed25519};
+
+use parachain::{
+	self, BlockData, CandidateReceipt, CandidateSignature, HeadData, Id as ParaId, Statement,
+};
+use Hash;
+
+/// Seed of the collator key used in the test vectors.
+pub const COLLATOR_SEED: [u8; 32] = [1; 32];
+
+/// Seed of the validator key used in the test vectors.
+pub const VALIDATOR_SEED: [u8; 32] = [2; 32];
+
+/// The relay parent used in the test vectors.
+pub const RELAY_PARENT: [u8; 32] = [3; 32];
+
+/// The parachain used in the test vectors.
+pub const PARA_ID: u32 = 100;
+
+/// A named byte string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+	/// Name of the vector.
+	pub name: &'static str,
+	/// The canonical bytes.
+	pub data: Vec<u8>,
+}
+
+impl TestVector {
+	fn new(name: &'static str, data: Vec<u8>) -> Self {
+		TestVector { name, data }
+	}
+}
+
+/// The candidate receipt used in the test vectors, signed by the collator key.
+pub fn candidate_receipt() -> CandidateReceipt {
+	let collator = ed25519::Pair::from_seed(&COLLATOR_SEED);
+	let block_data = BlockData(vec![1, 2, 3, 4]);
+	let block_data_hash: Hash = blake2_256(&block_data.0).into();
+
+	CandidateReceipt {
+		parachain_index: PARA_ID.into(),
+		collator: collator.public().0.into(),
+		signature: collator.sign(block_data_hash.as_ref()).into(),
+		head_data: HeadData(vec![5, 6, 7]),
+		balance_uploads: vec![(collator.public().0.into(), 10)],
+		egress_queue_roots: vec![((PARA_ID + 1).into(), [4; 32].into())],
+		fees: 7,
+		block_data_hash,
+		ingress_watermark: 3,
+	}
+}
+
+/// Generate all test vectors.
+pub fn test_vectors() -> Vec<TestVector> {
+	let validator = ed25519::Pair::from_seed(&VALIDATOR_SEED);
+	let relay_parent: Hash = RELAY_PARENT.into();
+	let para_id: ParaId = PARA_ID.into();
+
+	let receipt = candidate_receipt();
+	let receipt_hash = receipt.hash();
+
+	let mut vectors = vec![
+		TestVector::new("collator_public", receipt.collator.as_ref().to_vec()),
+		TestVector::new("validator_public", validator.public().0.to_vec()),
+		TestVector::new("candidate_receipt", receipt.encode()),
+		TestVector::new("candidate_receipt_hash", receipt_hash.as_ref().to_vec()),
+		TestVector::new("attestation_topic", parachain::attestation_topic(relay_parent).as_ref().to_vec()),
+		TestVector::new(
+			"incoming_message_topic",
+			parachain::incoming_message_topic(relay_parent, para_id).as_ref().to_vec(),
+		),
+	];
+
+	let statements = vec![
+		(["statement_candidate", "statement_candidate_payload", "statement_candidate_signature"],
+			Statement::Candidate(receipt)),
+		(["statement_valid", "statement_valid_payload", "statement_valid_signature"],
+			Statement::Valid(receipt_hash)),
+		(["statement_invalid", "statement_invalid_payload", "statement_invalid_signature"],
+			Statement::Invalid(receipt_hash)),
+	];
+
+	for ([encoded_name, payload_name, signature_name], statement) in statements {
+		let payload = statement.localized_payload(&relay_parent);
+		let signature: CandidateSignature = validator.sign(&payload).into();
+
+		vectors.push(TestVector::new(encoded_name, statement.encode()));
+		vectors.push(TestVector::new(payload_name, payload));
+		vectors.push(TestVector::new(signature_name, signature.encode()));
+	}
+
+	vectors
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use runtime_primitives::traits::Verify;
+
+	const GOLDEN: &str = include_str!("../res/test-vectors.txt");
+
+	fn find(vectors: &[TestVector], name: &str) -> Vec<u8> {
+		vectors.iter().find(|v| v.name == name).expect("vector exists").data.clone()
+	}
+
+	#[test]
+	fn test_vectors_are_deterministic_and_valid() {
+		let vectors = test_vectors();
+		assert_eq!(vectors, test_vectors());
+
+		let mut names: Vec<_> = vectors.iter().map(|v| v.name).collect();
+		names.sort();
+		names.dedup();
+		assert_eq!(names.len(), vectors.len());
+
+		assert!(candidate_receipt().check_signature().is_ok());
+
+		let validator: ::AccountId = ed25519::Pair::from_seed(&VALIDATOR_SEED).public().0.into();
+		for name in &["candidate", "valid", "invalid"] {
+			let payload = find(&vectors, &format!("statement_{}_payload", name));
+			let signature = find(&vectors, &format!("statement_{}_signature", name));
+			let mut sig_bytes = [0; 64];
+			sig_bytes.copy_from_slice(&signature);
+
+			let signature: CandidateSignature = primitives::H512::from(sig_bytes).into();
+			assert!(signature.verify(&payload[..], &validator));
+		}
+	}
+
+	#[test]
+	fn test_vectors_match_golden() {
+		let vectors: Vec<_> = test_vectors().into_iter().map(|vector| {
+			let hex: String = vector.data.iter().map(|b| format!("{:02x}", b)).collect();
+			format!("{}: 0x{}", vector.name, hex)
+		}).collect();
+
+		assert_eq!(vectors, GOLDEN.lines().collect::<Vec<_>>());
+	}
+}
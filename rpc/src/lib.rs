@@ -0,0 +1,367 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Polkadot-specific RPC methods.
+//!
+//! These expose the parachain validator assignments at a relay chain block, so that
+//! collators can learn which validators are assigned to their parachain and connect
+//! to exactly those peers, as well as statistics on the inclusion of parachain candidates.
+//!
+//! Over a transport supporting subscriptions, candidates backed by the local validator
+//! can be watched before they are included on-chain.
+//!
+//! Unsafe methods export and import the attestation state of live validation sessions,
+//! to debug stuck sessions or move a validator to another node mid-session.
+//!
+//! Collators pooling parachain transactions accept them over RPC as well.
+
+extern crate futures;
+extern crate jsonrpc_core;
+#[macro_use]
+extern crate jsonrpc_derive;
+extern crate jsonrpc_pubsub;
+#[macro_use]
+extern crate log;
+extern crate parking_lot;
+extern crate polkadot_primitives;
+extern crate sr_primitives;
+extern crate substrate_client as client;
+extern crate substrate_primitives;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use client::Client;
+use futures::prelude::*;
+use futures::future::Executor;
+use futures::sync::{mpsc, oneshot};
+use jsonrpc_core::{Error, ErrorCode, Result};
+use jsonrpc_pubsub::{typed::Subscriber, Session, SubscriptionId};
+use parking_lot::Mutex;
+use polkadot_primitives::{Block, BlockId, Hash, SessionKey};
+use polkadot_primitives::parachain::{
+	Id as ParaId, BackedCandidate, CoreAssignment, InclusionStats, ParachainHost,
+};
+use sr_primitives::traits::ProvideRuntimeApi;
+use substrate_primitives::{Blake2Hasher, Bytes};
+
+/// Parachain RPC methods.
+#[rpc]
+pub trait ParachainApi {
+	/// Get the assignments of validator groups to cores at the given block,
+	/// or the best block if none is given.
+	#[rpc(name = "parachain_scheduled")]
+	fn scheduled(&self, at: Option<Hash>) -> Result<Vec<CoreAssignment>>;
+
+	/// Get the session keys of the validators assigned to a parachain at the given block,
+	/// or the best block if none is given.
+	#[rpc(name = "parachain_assignedValidators")]
+	fn assigned_validators(&self, para_id: ParaId, at: Option<Hash>) -> Result<Vec<SessionKey>>;
+
+	/// Get the inclusion statistics of a parachain over recent sessions at the given block,
+	/// or the best block if none is given.
+	#[rpc(name = "parachain_inclusionStats")]
+	fn inclusion_stats(&self, para_id: ParaId, at: Option<Hash>) -> Result<Option<InclusionStats>>;
+}
+
+/// Implementation of the parachain RPC methods on top of a client.
+pub struct Parachain<B, E, RA> {
+	client: Arc<Client<B, E, Block, RA>>,
+}
+
+impl<B, E, RA> Parachain<B, E, RA> {
+	/// Create new parachain RPC handler.
+	pub fn new(client: Arc<Client<B, E, Block, RA>>) -> Self {
+		Parachain { client }
+	}
+}
+
+impl<B, E, RA> Parachain<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+{
+	fn block_id(&self, at: Option<Hash>) -> Result<BlockId> {
+		let hash = match at {
+			Some(hash) => hash,
+			None => self.client.info().map_err(client_error)?.chain.best_hash,
+		};
+
+		Ok(BlockId::hash(hash))
+	}
+}
+
+impl<B, E, RA> ParachainApi for Parachain<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: ParachainHost<Block>,
+{
+	fn scheduled(&self, at: Option<Hash>) -> Result<Vec<CoreAssignment>> {
+		let id = self.block_id(at)?;
+		self.client.runtime_api().scheduled(&id).map_err(client_error)
+	}
+
+	fn assigned_validators(&self, para_id: ParaId, at: Option<Hash>) -> Result<Vec<SessionKey>> {
+		let id = self.block_id(at)?;
+		let api = self.client.runtime_api();
+
+		let scheduled = api.scheduled(&id).map_err(client_error)?;
+		let validators = api.parachain_validators(&id).map_err(client_error)?;
+
+		Ok(assigned_validators(&scheduled, &validators, para_id))
+	}
+
+	fn inclusion_stats(&self, para_id: ParaId, at: Option<Hash>) -> Result<Option<InclusionStats>> {
+		let id = self.block_id(at)?;
+		self.client.runtime_api().inclusion_stats(&id, para_id).map_err(client_error)
+	}
+}
+
+/// Unsafe parachain RPC methods, manipulating the attestation state of the local validator.
+#[rpc]
+pub trait ParachainAttestationApi {
+	/// Export the SCALE-encoded attestation state of the live validation session at the
+	/// given relay parent, if any.
+	#[rpc(name = "parachain_exportAttestationState")]
+	fn export_attestation_state(&self, relay_parent: Hash) -> Result<Option<Bytes>>;
+
+	/// Import SCALE-encoded attestation state exported by another node. Returns `true` if
+	/// it was imported into a live session, and `false` if it is held until the session
+	/// at its relay parent starts.
+	#[rpc(name = "parachain_importAttestationState")]
+	fn import_attestation_state(&self, snapshot: Bytes) -> Result<bool>;
+}
+
+type ExportAttestation = Box<Fn(Hash) -> Option<Vec<u8>> + Send + Sync>;
+type ImportAttestation = Box<Fn(&[u8]) -> ::std::result::Result<bool, String> + Send + Sync>;
+
+/// Implementation of the unsafe parachain RPC methods.
+pub struct ParachainAttestation {
+	export: ExportAttestation,
+	import: ImportAttestation,
+}
+
+impl ParachainAttestation {
+	/// Create new attestation state RPC handler, exporting encoded state with `export`
+	/// and importing it with `import`, which fails on malformed or mismatched state.
+	pub fn new<X, I>(export: X, import: I) -> Self where
+		X: Fn(Hash) -> Option<Vec<u8>> + Send + Sync + 'static,
+		I: Fn(&[u8]) -> ::std::result::Result<bool, String> + Send + Sync + 'static,
+	{
+		ParachainAttestation {
+			export: Box::new(export),
+			import: Box::new(import),
+		}
+	}
+}
+
+impl ParachainAttestationApi for ParachainAttestation {
+	fn export_attestation_state(&self, relay_parent: Hash) -> Result<Option<Bytes>> {
+		Ok((self.export)(relay_parent).map(Bytes))
+	}
+
+	fn import_attestation_state(&self, snapshot: Bytes) -> Result<bool> {
+		(self.import)(&snapshot.0[..]).map_err(Error::invalid_params)
+	}
+}
+
+/// Parachain transaction RPC methods, served by collators pooling transactions
+/// for the candidates they produce.
+#[rpc]
+pub trait ParachainTransactionApi {
+	/// Submit an opaque parachain transaction to the pool of the collator, to be included
+	/// in one of its next candidates. Returns the hash of the transaction.
+	#[rpc(name = "parachain_submitTransaction")]
+	fn submit_transaction(&self, transaction: Bytes) -> Result<Hash>;
+}
+
+type SubmitTransaction = Box<Fn(Vec<u8>) -> ::std::result::Result<Hash, String> + Send + Sync>;
+
+/// Implementation of the parachain transaction RPC methods.
+pub struct ParachainTransactions {
+	submit: SubmitTransaction,
+}
+
+impl ParachainTransactions {
+	/// Create new transaction RPC handler, submitting transactions with `submit`,
+	/// which fails on transactions the pool rejects.
+	pub fn new<S>(submit: S) -> Self where
+		S: Fn(Vec<u8>) -> ::std::result::Result<Hash, String> + Send + Sync + 'static,
+	{
+		ParachainTransactions {
+			submit: Box::new(submit),
+		}
+	}
+}
+
+impl ParachainTransactionApi for ParachainTransactions {
+	fn submit_transaction(&self, transaction: Bytes) -> Result<Hash> {
+		(self.submit)(transaction.0).map_err(Error::invalid_params)
+	}
+}
+
+/// Parachain RPC subscriptions.
+#[rpc]
+pub trait ParachainPubSubApi {
+	/// RPC metadata.
+	type Metadata;
+
+	/// Subscribe to candidates gathering enough validity votes on the local validator
+	/// to be included.
+	#[pubsub(
+		subscription = "parachain_backedCandidate",
+		subscribe,
+		name = "parachain_subscribeBackedCandidates"
+	)]
+	fn subscribe_backed_candidates(&self, metadata: Self::Metadata, subscriber: Subscriber<BackedCandidate>);
+
+	/// Unsubscribe from backed candidates.
+	#[pubsub(
+		subscription = "parachain_backedCandidate",
+		unsubscribe,
+		name = "parachain_unsubscribeBackedCandidates"
+	)]
+	fn unsubscribe_backed_candidates(&self, metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
+}
+
+type SubscriptionTask = Box<Future<Item=(), Error=()> + Send>;
+
+/// Implementation of the parachain RPC subscriptions.
+///
+/// Each subscription is fed by a fresh stream of backed candidates, and runs
+/// on the given executor.
+pub struct ParachainPubSub<E> {
+	executor: E,
+	backed_candidates: Box<Fn() -> mpsc::Receiver<BackedCandidate> + Send + Sync>,
+	next_id: AtomicUsize,
+	active: Arc<Mutex<HashMap<u64, oneshot::Sender<()>>>>,
+}
+
+impl<E> ParachainPubSub<E> {
+	/// Create new parachain subscription handler, producing a stream of backed
+	/// candidates for each subscription from `backed_candidates`.
+	pub fn new<F>(executor: E, backed_candidates: F) -> Self
+		where F: Fn() -> mpsc::Receiver<BackedCandidate> + Send + Sync + 'static
+	{
+		ParachainPubSub {
+			executor,
+			backed_candidates: Box::new(backed_candidates),
+			next_id: AtomicUsize::new(0),
+			active: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+}
+
+impl<E> ParachainPubSubApi for ParachainPubSub<E> where
+	E: Executor<SubscriptionTask> + Send + Sync + 'static,
+{
+	type Metadata = Option<Arc<Session>>;
+
+	fn subscribe_backed_candidates(&self, _metadata: Self::Metadata, subscriber: Subscriber<BackedCandidate>) {
+		let id = self.next_id.fetch_add(1, Ordering::SeqCst) as u64;
+		let sink = match subscriber.assign_id(SubscriptionId::Number(id)) {
+			Ok(sink) => sink,
+			Err(()) => return,
+		};
+
+		let (cancel, cancelled) = oneshot::channel();
+		let active = self.active.clone();
+		let task = sink
+			.sink_map_err(|e| debug!(target: "rpc", "Error sending backed candidate: {:?}", e))
+			.send_all((self.backed_candidates)().map(Ok))
+			.map(|_| ())
+			.select(cancelled.map_err(|_| ()))
+			.then(move |_| {
+				active.lock().remove(&id);
+				Ok(())
+			});
+
+		self.active.lock().insert(id, cancel);
+		if self.executor.execute(Box::new(task)).is_err() {
+			warn!(target: "rpc", "Unable to spawn backed candidate subscription");
+			self.active.lock().remove(&id);
+		}
+	}
+
+	fn unsubscribe_backed_candidates(&self, _metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+		let cancel = match id {
+			SubscriptionId::Number(id) => self.active.lock().remove(&id),
+			SubscriptionId::String(_) => None,
+		};
+
+		Ok(cancel.map(|cancel| { let _ = cancel.send(()); }).is_some())
+	}
+}
+
+/// Resolve the session keys of the validators in the groups assigned to a parachain.
+///
+/// Group members are indices into the given validator set. Out-of-range indices are skipped.
+pub fn assigned_validators(
+	scheduled: &[CoreAssignment],
+	validators: &[SessionKey],
+	para_id: ParaId,
+) -> Vec<SessionKey> {
+	scheduled.iter()
+		.filter(|assignment| assignment.para_id == para_id)
+		.flat_map(|assignment| assignment.group.iter())
+		.filter_map(|&idx| validators.get(idx as usize).cloned())
+		.collect()
+}
+
+fn client_error<E: ::std::fmt::Debug>(e: E) -> Error {
+	Error {
+		code: ErrorCode::ServerError(1),
+		message: format!("Client error: {:?}", e),
+		data: None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::AssignmentKind;
+
+	#[test]
+	fn resolves_assigned_validators() {
+		let validators: Vec<SessionKey> = (0..5u8).map(|i| [i; 32].into()).collect();
+		let scheduled = vec![
+			CoreAssignment {
+				core: 0,
+				para_id: 1.into(),
+				kind: AssignmentKind::Parachain,
+				group: vec![3, 0],
+			},
+			CoreAssignment {
+				core: 1,
+				para_id: 2.into(),
+				kind: AssignmentKind::Parachain,
+				group: vec![1, 4, 9],
+			},
+		];
+
+		assert_eq!(
+			assigned_validators(&scheduled, &validators, 1.into()),
+			vec![validators[3], validators[0]],
+		);
+		assert_eq!(
+			assigned_validators(&scheduled, &validators, 2.into()),
+			vec![validators[1], validators[4]],
+		);
+		assert!(assigned_validators(&scheduled, &validators, 3.into()).is_empty());
+	}
+}
@@ -0,0 +1,82 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reporting of the ingress watermarks acknowledged by included candidates.
+//!
+//! Each candidate acknowledges an ingress watermark in its receipt. The block author
+//! reports the watermarks of the candidates included in the block through an inherent,
+//! after which the routed message queues processed by their parachains are pruned.
+
+use rstd::prelude::*;
+use primitives::BlockNumber;
+use primitives::parachain::Id as ParaId;
+use sr_primitives::traits::As;
+use srml_support::StorageValue;
+use srml_support::dispatch::Result;
+use inherents::{ProvideInherent, InherentData, RuntimeString, MakeFatalError, InherentIdentifier};
+use system::ensure_inherent;
+use parachains;
+
+pub trait Trait: parachains::Trait {}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Ingress {
+		// Were the ingress watermarks reported in this block?
+		DidUpdate: bool;
+	}
+}
+
+decl_module! {
+	/// Ingress watermarks module.
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		/// Report the ingress watermarks acknowledged by the candidates included in this
+		/// block, in ascending order by parachain ID.
+		fn set_watermarks(origin, watermarks: Vec<(ParaId, T::BlockNumber)>) -> Result {
+			ensure_inherent(origin)?;
+			ensure!(!<DidUpdate<T>>::exists(), "Ingress watermarks must be reported only once in the block");
+
+			<parachains::Module<T>>::acknowledge_ingress(watermarks)?;
+
+			<DidUpdate<T>>::put(true);
+
+			Ok(())
+		}
+
+		fn on_finalise(_n: T::BlockNumber) {
+			assert!(<Self as Store>::DidUpdate::take(), "Ingress watermarks must be reported once in the block");
+		}
+	}
+}
+
+pub const INHERENT_IDENTIFIER: InherentIdentifier = *b"ingrwmrk";
+
+pub type InherentType = Vec<(ParaId, BlockNumber)>;
+
+impl<T: Trait> ProvideInherent for Module<T> {
+	type Call = Call<T>;
+	type Error = MakeFatalError<RuntimeString>;
+	const INHERENT_IDENTIFIER: InherentIdentifier = INHERENT_IDENTIFIER;
+
+	fn create_inherent(data: &InherentData) -> Option<Self::Call> {
+		let data = data.get_data::<InherentType>(&INHERENT_IDENTIFIER)
+			.expect("Ingress watermarks could not be decoded.")
+			.expect("No ingress watermarks found in inherent data.");
+
+		Some(Call::set_watermarks(
+			data.into_iter().map(|(id, watermark)| (id, T::BlockNumber::sa(watermark))).collect()
+		))
+	}
+}
@@ -74,6 +74,7 @@ extern crate substrate_keyring as keyring;
 extern crate substrate_trie;
 
 mod parachains;
+mod ingress;
 mod claims;
 
 use rstd::prelude::*;
@@ -105,6 +106,7 @@ pub use consensus::Call as ConsensusCall;
 pub use timestamp::Call as TimestampCall;
 pub use balances::Call as BalancesCall;
 pub use parachains::{Call as ParachainsCall, INHERENT_IDENTIFIER as PARACHAIN_INHERENT_IDENTIFIER};
+pub use ingress::INHERENT_IDENTIFIER as INGRESS_INHERENT_IDENTIFIER;
 pub use sr_primitives::{Permill, Perbill};
 pub use timestamp::BlockPeriod;
 pub use srml_support::StorageValue;
@@ -114,7 +116,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("polkadot"),
 	impl_name: create_runtime_str!("parity-polkadot"),
 	authoring_version: 1,
-	spec_version: 107,
+	spec_version: 125,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
 };
@@ -185,7 +187,7 @@ impl Convert<AccountId, SessionKey> for SessionKeyConversion {
 
 impl session::Trait for Runtime {
 	type ConvertAccountIdToSessionKey = SessionKeyConversion;
-	type OnSessionChange = (Staking, grandpa::SyncedAuthorities<Runtime>);
+	type OnSessionChange = (Staking, grandpa::SyncedAuthorities<Runtime>, Parachains);
 	type Event = Event;
 }
 
@@ -228,7 +230,21 @@ impl grandpa::Trait for Runtime {
 	type Event = Event;
 }
 
-impl parachains::Trait for Runtime {}
+/// Pays funds collected by the parachains module into the treasury pot.
+pub struct ParachainsToTreasury;
+impl parachains::OnFundsCollected<Balance> for ParachainsToTreasury {
+	fn on_funds_collected(amount: Balance) {
+		<treasury::Pot<Runtime>>::mutate(|pot| *pot = pot.saturating_add(amount));
+	}
+}
+
+impl parachains::Trait for Runtime {
+	type Currency = Balances;
+	type OnFundsCollected = ParachainsToTreasury;
+	type Event = Event;
+}
+
+impl ingress::Trait for Runtime {}
 
 impl upgrade_key::Trait for Runtime {
 	type Event = Event;
@@ -270,7 +286,8 @@ construct_runtime!(
 		CouncilMotions: council_motions::{Module, Call, Storage, Event<T>, Origin},
 		CouncilSeats: council_seats::{Config<T>},
 		Treasury: treasury,
-		Parachains: parachains::{Module, Call, Storage, Config<T>, Inherent},
+		Parachains: parachains::{Module, Call, Storage, Config<T>, Inherent, Event<T>},
+		Ingress: ingress::{Module, Call, Storage, Inherent},
 		Sudo: sudo,
 		UpgradeKey: upgrade_key,
 		Claims: claims,
@@ -355,6 +372,12 @@ impl_runtime_apis! {
 		fn duty_roster() -> parachain::DutyRoster {
 			Parachains::calculate_duty_roster()
 		}
+		fn scheduled() -> Vec<parachain::CoreAssignment> {
+			Parachains::scheduled()
+		}
+		fn parachain_validators() -> Vec<SessionKey> {
+			Parachains::parachain_validators()
+		}
 		fn active_parachains() -> Vec<parachain::Id> {
 			Parachains::active_parachains()
 		}
@@ -367,6 +390,26 @@ impl_runtime_apis! {
 		fn ingress(to: parachain::Id) -> Option<Vec<(parachain::Id, Hash)>> {
 			Parachains::ingress(to)
 		}
+		fn ingress_posted(to: parachain::Id) -> Option<Vec<(parachain::Id, BlockNumber)>> {
+			Parachains::ingress_posted(to)
+		}
+		fn ingress_watermark(id: parachain::Id) -> BlockNumber {
+			Parachains::ingress_watermark(&id)
+		}
+		fn permitted_collators(id: parachain::Id) -> Option<Vec<parachain::CollatorId>> {
+			Parachains::permitted_collators(&id)
+		}
+		fn inclusion_stats(id: parachain::Id) -> Option<parachain::InclusionStats> {
+			Parachains::inclusion_stats(&id)
+		}
+		fn inclusion_weights() -> parachain::InclusionWeights {
+			Parachains::inclusion_weights()
+		}
+		fn check_candidates(candidates: Vec<parachain::AttestedCandidate>)
+			-> Vec<(u32, primitives::acceptance::InclusionError)>
+		{
+			Parachains::check_candidates(&candidates)
+		}
 	}
 
 	impl fg_primitives::GrandpaApi<Block> for Runtime {
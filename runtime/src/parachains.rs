@@ -20,13 +20,19 @@ use rstd::prelude::*;
 use codec::Decode;
 
 use bitvec::BigEndian;
-use sr_primitives::traits::{Hash as HashT, BlakeTwo256};
+use sr_primitives::Permill;
+use sr_primitives::traits::{As, Hash as HashT, BlakeTwo256, Zero};
 use primitives::Hash;
-use primitives::parachain::{Id as ParaId, Chain, DutyRoster, AttestedCandidate, Statement};
-use {system, session};
+use primitives::parachain::{
+	Id as ParaId, Chain, DutyRoster, AttestedCandidate, Statement, CoreAssignment, CoreIndex,
+	AssignmentKind, ValidatorIndex, InclusionStats, InclusionWeights, CollatorId,
+};
+use primitives::acceptance::{self, InclusionError};
+use {system, session, consensus};
 
 use srml_support::{StorageValue, StorageMap};
 use srml_support::dispatch::Result;
+use srml_support::traits::{Currency, ArithmeticType};
 
 use inherents::{ProvideInherent, InherentData, RuntimeString, MakeFatalError, InherentIdentifier};
 
@@ -36,15 +42,61 @@ use sr_primitives::{StorageOverlay, ChildrenStorageOverlay};
 #[cfg(any(feature = "std", test))]
 use rstd::marker::PhantomData;
 
-use system::ensure_inherent;
+use system::{ensure_inherent, ensure_signed};
 
-pub trait Trait: session::Trait {}
+type BalanceOf<T> = <<T as Trait>::Currency as ArithmeticType>::Type;
 
-// result of <NodeCodec<Blake2Hasher> as trie_db::NodeCodec<Blake2Hasher>>::hashed_null_node()
-const EMPTY_TRIE_ROOT: [u8; 32] = [
-	3, 23, 10, 46, 117, 151, 183, 183, 227, 216, 76, 5, 57, 29, 19, 154,
-	98, 177, 87, 231, 135, 134, 216, 192, 130, 242, 157, 207, 76, 17, 19, 20
-];
+/// Handler for funds collected by the parachains module, so that they are not burned.
+pub trait OnFundsCollected<Balance> {
+	/// Called with the amount collected.
+	fn on_funds_collected(amount: Balance);
+}
+
+impl<Balance> OnFundsCollected<Balance> for () {
+	fn on_funds_collected(_amount: Balance) {}
+}
+
+pub trait Trait: session::Trait {
+	/// The currency in which parathread fees and deposits are paid.
+	type Currency: ArithmeticType + Currency<Self::AccountId, Balance=BalanceOf<Self>>;
+
+	/// Receives claim fees, forfeited deposits and the treasury's share of slashes.
+	type OnFundsCollected: OnFundsCollected<BalanceOf<Self>>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_event!(
+	pub enum Event<T> where <T as system::Trait>::BlockNumber, <T as system::Trait>::AccountId {
+		/// A reset of the parachain's head was scheduled, taking effect at the given block.
+		HeadResetScheduled(ParaId, BlockNumber),
+		/// The parachain's head was reset and its pending messages wiped.
+		HeadReset(ParaId),
+		/// A parathread claim was not scheduled in time. The claim fee was refunded to
+		/// the claimant, except for the share which is burned.
+		ParathreadClaimExpired(ParaId, AccountId),
+	}
+);
+
+/// The number of sessions inclusion statistics are kept for.
+pub const INCLUSION_STATS_SESSIONS: usize = 8;
+
+/// The maximum number of stale parathread claims expired in a single block.
+/// Any further stale claims are expired in the following blocks.
+pub const MAX_CLAIM_EXPIRIES_PER_BLOCK: usize = 16;
+
+/// The weight of importing a candidate, not counting its validity votes.
+pub const CANDIDATE_BASE_WEIGHT: u64 = 1_000;
+
+/// The weight of checking a single validity vote.
+pub const VALIDITY_VOTE_WEIGHT: u64 = 250;
+
+/// The weight of routing a single egress queue root.
+pub const EGRESS_ROOT_WEIGHT: u64 = 10;
+
+/// The maximum total weight of the candidates included in a single block.
+pub const MAX_BLOCK_CANDIDATES_WEIGHT: u64 = 100 * (CANDIDATE_BASE_WEIGHT + 10 * VALIDITY_VOTE_WEIGHT);
 
 decl_storage! {
 	trait Store for Module<T: Trait> as Parachains {
@@ -56,6 +108,58 @@ decl_storage! {
 		pub Heads get(parachain_head): map ParaId => Option<Vec<u8>>;
 		// message routing roots (from, to).
 		pub Routing: map (ParaId, ParaId) => Option<Hash>;
+		// The block number at which each message routing root was last updated (from, to).
+		RoutingPosted: map (ParaId, ParaId) => T::BlockNumber;
+		// The ingress watermark of each parachain: all ingress routed before this block
+		// number has been processed by the parachain.
+		pub Watermarks get(ingress_watermark): map ParaId => T::BlockNumber;
+		// The ingress watermarks acknowledged by the candidates included in this block,
+		// until they are reported by the block author.
+		IncludedWatermarks: Vec<(ParaId, T::BlockNumber)>;
+
+		// The registered parathreads, in ascending order by ID.
+		pub Parathreads get(parathreads): Vec<ParaId>;
+		// The depositor and amount reserved for each registered parathread.
+		pub Deposits get(parathread_deposit): map ParaId => Option<(T::AccountId, BalanceOf<T>)>;
+		// Parathreads which have claimed a core, along with their claimants, in the order claimed.
+		pub ParathreadClaims get(parathread_claims): Vec<(ParaId, T::AccountId)>;
+		// The block each pending parathread claim was made at, and the fee reserved for it.
+		pub ClaimedAt get(parathread_claimed_at): map ParaId => Option<(T::BlockNumber, BalanceOf<T>)>;
+		// The fee paid to the treasury for each parathread claim. It is reserved from the
+		// claimant until the claim is scheduled.
+		pub ParathreadClaimFee get(parathread_claim_fee) config(): BalanceOf<T>;
+		// The number of blocks after which unscheduled parathread claims expire. Zero means never.
+		pub ParathreadClaimExpiry get(parathread_claim_expiry) config(): T::BlockNumber;
+		// The share of the claim fee burned when a claim expires. The rest is refunded.
+		pub ClaimExpiryBurn get(claim_expiry_burn) config(): Permill;
+		// The number of cores available to parathread claims in each block.
+		pub ParathreadCores get(parathread_cores) config(): u32;
+		// The parathreads scheduled onto a core for the candidates of the next block,
+		// in ascending order by ID.
+		pub ScheduledParathreads get(scheduled_parathreads): Vec<ParaId>;
+		// The deposit reserved when registering a parathread. Forfeited on misbehavior.
+		pub RegistrationDeposit get(registration_deposit) config(): BalanceOf<T>;
+		// The share of parachain misbehavior slashes paid to the treasury. The rest is burned.
+		pub SlashTreasuryShare get(slash_treasury_share) config(): Permill;
+
+		// The number of top elected validators eligible for parachain duty. Zero means all.
+		pub ParachainValidatorCount get(parachain_validator_count) config(): u32;
+		// The parachain validator count to apply from the next session, if changed.
+		NextParachainValidatorCount: Option<u32>;
+
+		// The account managing each parachain. Parathreads are managed by their depositor.
+		pub Managers: map ParaId => Option<T::AccountId>;
+		// The collators permitted to collate for each permissioned parachain, by the key
+		// signing their candidates. Anyone may collate for parachains without an entry.
+		pub PermittedCollators get(permitted_collators): map ParaId => Option<Vec<CollatorId>>;
+
+		// The number of blocks between scheduling a head reset and it taking effect.
+		pub HeadResetDelay get(head_reset_delay) config(): T::BlockNumber;
+		// Scheduled head resets: the parachain, the block they take effect at and the new head.
+		pub PendingHeadResets get(pending_head_resets): Vec<(ParaId, T::BlockNumber, Vec<u8>)>;
+
+		// Inclusion statistics of each parachain over the last `INCLUSION_STATS_SESSIONS` sessions.
+		pub Stats get(inclusion_stats): map ParaId => Option<InclusionStats>;
 
 		// Did the parachain heads get updated in this block?
 		DidUpdate: bool;
@@ -89,41 +193,41 @@ decl_storage! {
 decl_module! {
 	/// Parachains module.
 	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
 		/// Provide candidate receipts for parachains, in ascending order by id.
 		fn set_heads(origin, heads: Vec<AttestedCandidate>) -> Result {
 			ensure_inherent(origin)?;
 			ensure!(!<DidUpdate<T>>::exists(), "Parachain heads must be updated only once in the block");
 
 			let active_parachains = Self::active_parachains();
+			let scheduled_parathreads = Self::scheduled_parathreads();
+			let now = <system::Module<T>>::block_number();
 
 			// perform integrity checks before writing to storage.
 			{
-				let n_parachains = active_parachains.len();
-				ensure!(heads.len() <= n_parachains, "Too many parachain candidates");
+				if heads.len() > active_parachains.len() + scheduled_parathreads.len() {
+					return Err(InclusionError::TooManyCandidates.into());
+				}
+
+				let weights = Self::inclusion_weights();
+				let total = heads.iter().fold(0u64, |total, head| total.saturating_add(weights.candidate(head)));
+				if total > weights.max_block {
+					return Err(InclusionError::Overweight.into());
+				}
 
 				let mut last_id = None;
-				let mut iter = active_parachains.iter();
 				for head in &heads {
-					// proposed heads must be ascending order by parachain ID without duplicate.
-					ensure!(
-						last_id.as_ref().map_or(true, |x| x < &head.parachain_index()),
-						"Parachain candidates out of order by ID"
-					);
-
-					// must be unknown since active parachains are always sorted.
-					ensure!(
-						iter.find(|x| x == &&head.parachain_index()).is_some(),
-						"Submitted candidate for unregistered or out-of-order parachain {}"
-					);
-
-					Self::check_egress_queue_roots(&head, &active_parachains)?;
-
+					Self::check_head(head, last_id, &active_parachains, &scheduled_parathreads, now)?;
 					last_id = Some(head.parachain_index());
 				}
 			}
 
-			Self::check_attestations(&heads)?;
+			if let Some((_, e)) = Self::check_attestations(&heads).into_iter().next() {
+				return Err(e.into());
+			}
 
+			let mut included_watermarks = Vec::with_capacity(heads.len());
 			for head in heads {
 				let id = head.parachain_index();
 				<Heads<T>>::insert(id, head.candidate.head_data.0);
@@ -131,9 +235,16 @@ decl_module! {
 				// update egress.
 				for &(to, root) in &head.candidate.egress_queue_roots {
 					<Routing<T>>::insert((id, to), root);
+					<RoutingPosted<T>>::insert((id, to), now);
 				}
+
+				// the watermark takes effect once reported by the block author.
+				included_watermarks.push((id, T::BlockNumber::sa(head.candidate.ingress_watermark)));
+
+				Self::note_inclusion(id, now.as_());
 			}
 
+			<IncludedWatermarks<T>>::put(included_watermarks);
 			<DidUpdate<T>>::put(true);
 
 			Ok(())
@@ -142,6 +253,8 @@ decl_module! {
 		/// Register a parachain with given code.
 		/// Fails if given ID is already used.
 		pub fn register_parachain(id: ParaId, code: Vec<u8>, initial_head_data: Vec<u8>) -> Result {
+			ensure!(Self::parathreads().binary_search(&id).is_err(), "Parathread already exists");
+
 			let mut parachains = Self::active_parachains();
 			match parachains.binary_search(&id) {
 				Ok(_) => fail!("Parachain already exists"),
@@ -165,11 +278,18 @@ decl_module! {
 
 			<Code<T>>::remove(id);
 			<Heads<T>>::remove(id);
+			<Watermarks<T>>::remove(id);
+			<Managers<T>>::remove(id);
+			<PermittedCollators<T>>::remove(id);
+			<Stats<T>>::remove(id);
+			Self::cancel_head_reset(id);
 
 			// clear all routing entries to and from other parachains.
 			for other in parachains.iter().cloned() {
 				<Routing<T>>::remove((id, other));
 				<Routing<T>>::remove((other, id));
+				<RoutingPosted<T>>::remove((id, other));
+				<RoutingPosted<T>>::remove((other, id));
 			}
 
 			<Parachains<T>>::put(parachains);
@@ -177,9 +297,137 @@ decl_module! {
 			Ok(())
 		}
 
-		fn on_finalise(_n: T::BlockNumber) {
+		/// Set the number of top elected validators eligible for parachain duty,
+		/// taking effect from the next session. Zero makes all validators eligible.
+		pub fn set_parachain_validator_count(count: u32) -> Result {
+			<NextParachainValidatorCount<T>>::put(count);
+			Ok(())
+		}
+
+		/// Set the account managing a parachain.
+		pub fn set_parachain_manager(id: ParaId, manager: T::AccountId) -> Result {
+			ensure!(Self::active_parachains().binary_search(&id).is_ok(), "Unknown parachain");
+			<Managers<T>>::insert(id, manager);
+			Ok(())
+		}
+
+		/// Restrict the collators of a parachain to the given ones, or lift the
+		/// restriction if `None`. Must be called by the manager of the parachain.
+		fn set_permitted_collators(origin, id: ParaId, collators: Option<Vec<CollatorId>>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::manager_of(id) == Some(who), "Only the manager may set permitted collators");
+
+			match collators {
+				Some(collators) => <PermittedCollators<T>>::insert(id, collators),
+				None => <PermittedCollators<T>>::remove(id),
+			}
+
+			Ok(())
+		}
+
+		/// Schedule a reset of the head of a parachain or parathread to the given head data,
+		/// wiping its pending messages. This takes effect after the head reset delay, and
+		/// replaces any reset already scheduled. Must be called by the manager of the parachain.
+		fn schedule_head_reset(origin, id: ParaId, head_data: Vec<u8>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::manager_of(id) == Some(who), "Only the manager may reset the parachain head");
+
+			Self::do_schedule_head_reset(id, head_data)
+		}
+
+		/// Schedule a reset of the head of a parachain or parathread, as `schedule_head_reset`.
+		pub fn force_schedule_head_reset(id: ParaId, head_data: Vec<u8>) -> Result {
+			Self::do_schedule_head_reset(id, head_data)
+		}
+
+		/// Register a parathread with given code, reserving the registration deposit
+		/// from the sender. Fails if given ID is already used.
+		fn register_parathread(origin, id: ParaId, code: Vec<u8>, initial_head_data: Vec<u8>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::active_parachains().binary_search(&id).is_err(), "Parachain already exists");
+
+			let mut parathreads = Self::parathreads();
+			let idx = match parathreads.binary_search(&id) {
+				Ok(_) => fail!("Parathread already exists"),
+				Err(idx) => idx,
+			};
+
+			let deposit = Self::registration_deposit();
+			T::Currency::reserve(&who, deposit)?;
+
+			parathreads.insert(idx, id);
+			<Code<T>>::insert(id, code);
+			<Heads<T>>::insert(id, initial_head_data);
+			<Deposits<T>>::insert(id, (who, deposit));
+			<Parathreads<T>>::put(parathreads);
+
+			Ok(())
+		}
+
+		/// Deregister a parathread, returning the registration deposit to the depositor.
+		fn deregister_parathread(origin, id: ParaId) -> Result {
+			let who = ensure_signed(origin)?;
+			let (depositor, deposit) = <Deposits<T>>::get(&id).ok_or("Unknown parathread")?;
+			ensure!(who == depositor, "Only the depositor may deregister a parathread");
+
+			T::Currency::unreserve(&depositor, deposit);
+			Self::remove_parathread(id);
+
+			Ok(())
+		}
+
+		/// Claim a core for a registered parathread, reserving the claim fee until
+		/// the claim is scheduled or expires.
+		fn claim_parathread(origin, id: ParaId) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::parathreads().binary_search(&id).is_ok(), "Unknown parathread");
+
+			let mut claims = Self::parathread_claims();
+			ensure!(claims.iter().all(|&(ref claimed, _)| claimed != &id), "Parathread already claimed");
+
+			let fee = Self::parathread_claim_fee();
+			T::Currency::reserve(&who, fee).map_err(|_| "Insufficient balance for parathread claim fee")?;
+
+			claims.push((id, who));
+			<ParathreadClaims<T>>::put(claims);
+			<ClaimedAt<T>>::insert(id, (<system::Module<T>>::block_number(), fee));
+
+			Ok(())
+		}
+
+		/// Deregister a misbehaving parathread, forfeiting its registration deposit
+		/// to the treasury.
+		pub fn forfeit_parathread_deposit(id: ParaId) -> Result {
+			let (depositor, deposit) = <Deposits<T>>::get(&id).ok_or("Unknown parathread")?;
+
+			let unslashed = T::Currency::slash_reserved(&depositor, deposit).unwrap_or_else(Zero::zero);
+			T::OnFundsCollected::on_funds_collected(deposit - unslashed);
+			Self::remove_parathread(id);
+
+			Ok(())
+		}
+
+		fn on_initialise(n: T::BlockNumber) {
+			Self::expire_parathread_claims(n);
+		}
+
+		fn on_finalise(n: T::BlockNumber) {
 			assert!(<Self as Store>::DidUpdate::take(), "Parachain heads must be updated once in the block");
+			<IncludedWatermarks<T>>::kill();
+
+			Self::apply_head_resets(n);
+			Self::schedule_parathreads();
+		}
+	}
+}
+
+impl<T: Trait> session::OnSessionChange<T::Moment> for Module<T> {
+	fn on_session_change(_elapsed: T::Moment, _should_reward: bool) {
+		if let Some(count) = <NextParachainValidatorCount<T>>::take() {
+			<ParachainValidatorCount<T>>::put(count);
 		}
+
+		<Module<T>>::rotate_inclusion_stats();
 	}
 }
 
@@ -188,22 +436,28 @@ fn majority_of(list_len: usize) -> usize {
 }
 
 fn localized_payload(statement: Statement, parent_hash: ::primitives::Hash) -> Vec<u8> {
-	use codec::Encode;
-
-	let mut encoded = statement.encode();
-	encoded.extend(parent_hash.as_ref());
-	encoded
+	statement.localized_payload(&parent_hash)
 }
 
 impl<T: Trait> Module<T> {
 	/// Calculate the current block's duty roster using system's random seed.
+	///
+	/// Only the top elected validators, as limited by the parachain validator count,
+	/// are given parachain duty. All others validate the relay chain.
+	///
+	/// The parathreads scheduled for the block are given groups just like parachains.
 	pub fn calculate_duty_roster() -> DutyRoster {
-		let parachains = Self::active_parachains();
+		let parachains = Self::scheduled_chains();
 		let parachain_count = parachains.len();
 		let validator_count = <session::Module<T>>::validator_count() as usize;
-		let validators_per_parachain = if parachain_count != 0 { (validator_count - 1) / parachain_count } else { 0 };
+		let eligible_count = Self::eligible_validator_count(validator_count);
+		let validators_per_parachain = if parachain_count != 0 {
+			eligible_count.saturating_sub(1) / parachain_count
+		} else {
+			0
+		};
 
-		let mut roles_val = (0..validator_count).map(|i| match i {
+		let mut roles_val = (0..eligible_count).map(|i| match i {
 			i if i < parachain_count * validators_per_parachain => {
 				let idx = i / validators_per_parachain;
 				Chain::Parachain(parachains[idx].clone())
@@ -216,12 +470,12 @@ impl<T: Trait> Module<T> {
 		let mut seed = BlakeTwo256::hash(&random_seed);
 
 		// shuffle
-		for i in 0..(validator_count - 1) {
+		for i in 0..eligible_count.saturating_sub(1) {
 			// 4 bytes of entropy used per cycle, 32 bytes entropy per hash
 			let offset = (i * 4 % 32) as usize;
 
 			// number of roles remaining to select from.
-			let remaining = (validator_count - i) as usize;
+			let remaining = (eligible_count - i) as usize;
 
 			// 8 32-bit ints per 256-bit seed.
 			let val_index = u32::decode(&mut &seed[offset..offset + 4]).expect("using 4 bytes for a 32-bit quantity") as usize % remaining;
@@ -235,62 +489,424 @@ impl<T: Trait> Module<T> {
 			roles_val.swap(remaining - 1, val_index);
 		}
 
+		roles_val.extend((eligible_count..validator_count).map(|_| Chain::Relay));
+
 		DutyRoster {
 			validator_duty: roles_val,
 		}
 	}
 
+	/// Get the session keys of the validators eligible for parachain duty, in authority order.
+	pub fn parachain_validators() -> Vec<T::SessionKey> {
+		let mut authorities = <consensus::Module<T>>::authorities();
+		let eligible_count = Self::eligible_validator_count(authorities.len());
+		authorities.truncate(eligible_count);
+		authorities
+	}
+
+	// The number of validators eligible for parachain duty out of the given total.
+	//
+	// The staking election orders validators by stake, so the least-staked validators
+	// are the first to be excluded.
+	fn eligible_validator_count(validator_count: usize) -> usize {
+		match Self::parachain_validator_count() as usize {
+			0 => validator_count,
+			n => ::rstd::cmp::min(n, validator_count),
+		}
+	}
+
+	/// Calculate the assignment of validator groups to cores for the current block.
+	///
+	/// Every active parachain occupies a core, in ascending order by parachain ID, followed
+	/// by the parathreads scheduled for the block. Each is validated by the group assigned
+	/// to it in the duty roster.
+	pub fn scheduled() -> Vec<CoreAssignment> {
+		let duty_roster = Self::calculate_duty_roster();
+		let parachain_count = Self::active_parachains().len();
+
+		Self::scheduled_chains().into_iter().enumerate().map(|(core, para_id)| {
+			let group = duty_roster.validator_duty.iter()
+				.enumerate()
+				.filter(|&(_, duty)| duty == &Chain::Parachain(para_id))
+				.map(|(idx, _)| idx as ValidatorIndex)
+				.collect();
+
+			let kind = if core < parachain_count {
+				AssignmentKind::Parachain
+			} else {
+				AssignmentKind::Parathread
+			};
+
+			CoreAssignment {
+				core: core as CoreIndex,
+				para_id,
+				kind,
+				group,
+			}
+		}).collect()
+	}
+
+	// the chains occupying a core in the current block: the active parachains
+	// followed by the scheduled parathreads.
+	fn scheduled_chains() -> Vec<ParaId> {
+		let mut chains = Self::active_parachains();
+		chains.extend(Self::scheduled_parathreads());
+		chains
+	}
+
+	// schedule the oldest parathread claims onto the parathread cores for the
+	// candidates of the next block, collecting their claim fees.
+	fn schedule_parathreads() {
+		let cores = Self::parathread_cores() as usize;
+		let mut scheduled: Vec<_> = Self::parathread_claims().into_iter()
+			.take(cores)
+			.filter_map(|(id, _)| Self::take_parathread_claim(id).map(|_| id))
+			.collect();
+
+		scheduled.sort_unstable();
+		<ScheduledParathreads<T>>::put(scheduled);
+	}
+
+	/// Slash an account for parachain-related misbehavior, paying the configured
+	/// share of the slashed amount to the treasury and burning the rest.
+	///
+	/// Returns the amount which could not be slashed.
+	pub fn slash_misbehavior(who: &T::AccountId, amount: BalanceOf<T>) -> BalanceOf<T> {
+		let unslashed = T::Currency::slash(who, amount).unwrap_or_else(Zero::zero);
+		let slashed = amount - unslashed;
+
+		T::OnFundsCollected::on_funds_collected(Self::slash_treasury_share() * slashed);
+		unslashed
+	}
+
+	/// The account managing a parachain or parathread, if any.
+	pub fn manager_of(id: ParaId) -> Option<T::AccountId> {
+		<Managers<T>>::get(&id).or_else(|| <Deposits<T>>::get(&id).map(|(depositor, _)| depositor))
+	}
+
+	/// Whether the given collator may collate for a parachain.
+	pub fn is_permitted_collator(id: ParaId, collator: &CollatorId) -> bool {
+		Self::permitted_collators(&id).map_or(true, |permitted| permitted.contains(collator))
+	}
+
+	// note the inclusion of a candidate at the given block in the statistics.
+	fn note_inclusion(id: ParaId, now: u64) {
+		let mut stats = <Stats<T>>::get(&id).unwrap_or_else(|| InclusionStats {
+			included: vec![0],
+			interval: vec![0],
+			measured: vec![0],
+			last_included: None,
+		});
+
+		// the first inclusion has no previous one to measure the interval from.
+		if let Some(last) = stats.last_included {
+			if let Some(interval) = stats.interval.last_mut() {
+				*interval += now.saturating_sub(last);
+			}
+			if let Some(measured) = stats.measured.last_mut() {
+				*measured += 1;
+			}
+		}
+		if let Some(included) = stats.included.last_mut() {
+			*included += 1;
+		}
+
+		stats.last_included = Some(now);
+		<Stats<T>>::insert(id, stats);
+	}
+
+	// start a new session in the inclusion statistics, dropping the oldest.
+	fn rotate_inclusion_stats() {
+		for id in Self::active_parachains().into_iter().chain(Self::parathreads()) {
+			if let Some(mut stats) = <Stats<T>>::get(&id) {
+				stats.included.push(0);
+				stats.interval.push(0);
+				stats.measured.push(0);
+
+				let excess = stats.included.len().saturating_sub(INCLUSION_STATS_SESSIONS);
+				stats.included.drain(..excess);
+				stats.interval.drain(..excess);
+				stats.measured.drain(..excess);
+
+				<Stats<T>>::insert(id, stats);
+			}
+		}
+	}
+
+	fn do_schedule_head_reset(id: ParaId, head_data: Vec<u8>) -> Result {
+		ensure!(<Heads<T>>::exists(&id), "Unknown parachain");
+
+		let at = <system::Module<T>>::block_number() + Self::head_reset_delay();
+		<PendingHeadResets<T>>::mutate(|resets| {
+			resets.retain(|&(ref pending, _, _)| pending != &id);
+			resets.push((id, at, head_data));
+		});
+
+		Self::deposit_event(RawEvent::HeadResetScheduled(id, at));
+		Ok(())
+	}
+
+	fn cancel_head_reset(id: ParaId) {
+		if Self::pending_head_resets().iter().any(|&(ref pending, _, _)| pending == &id) {
+			<PendingHeadResets<T>>::mutate(|resets| resets.retain(|&(ref pending, _, _)| pending != &id));
+		}
+	}
+
+	// apply the head resets which take effect at the given block.
+	fn apply_head_resets(now: T::BlockNumber) {
+		let resets = Self::pending_head_resets();
+		if resets.iter().all(|&(_, at, _)| at > now) { return }
+
+		let (due, pending): (Vec<_>, Vec<_>) = resets.into_iter().partition(|&(_, at, _)| at <= now);
+		<PendingHeadResets<T>>::put(pending);
+
+		let chains: Vec<_> = Self::active_parachains().into_iter().chain(Self::parathreads()).collect();
+		for (id, _, head_data) in due {
+			<Heads<T>>::insert(id, head_data);
+
+			// wipe all messages routed to and from the chain.
+			for other in chains.iter().cloned().filter(|other| other != &id) {
+				<Routing<T>>::remove((id, other));
+				<Routing<T>>::remove((other, id));
+				<RoutingPosted<T>>::remove((id, other));
+				<RoutingPosted<T>>::remove((other, id));
+			}
+			<Watermarks<T>>::insert(id, now);
+
+			Self::deposit_event(RawEvent::HeadReset(id));
+		}
+	}
+
+	// take the pending claim of a parathread once it is scheduled, paying the
+	// claim fee to the treasury. Returns the claimant, if there was a claim.
+	fn take_parathread_claim(id: ParaId) -> Option<T::AccountId> {
+		let mut claims = Self::parathread_claims();
+		let idx = claims.iter().position(|&(ref claimed, _)| claimed == &id)?;
+		let (_, claimant) = claims.remove(idx);
+		<ParathreadClaims<T>>::put(claims);
+
+		if let Some((_, fee)) = <ClaimedAt<T>>::take(&id) {
+			let unslashed = T::Currency::slash_reserved(&claimant, fee).unwrap_or_else(Zero::zero);
+			T::OnFundsCollected::on_funds_collected(fee - unslashed);
+		}
+
+		Some(claimant)
+	}
+
+	// expire claims made more than the claim expiry ago, refunding all but the
+	// burned share of the claim fee. At most `MAX_CLAIM_EXPIRIES_PER_BLOCK` are expired.
+	fn expire_parathread_claims(now: T::BlockNumber) {
+		let expiry = Self::parathread_claim_expiry();
+		if expiry.is_zero() { return }
+
+		// claims are in the order they were made, so the stale ones come first.
+		let claims = Self::parathread_claims();
+		let stale = claims.iter()
+			.take(MAX_CLAIM_EXPIRIES_PER_BLOCK)
+			.take_while(|&&(ref id, _)| Self::parathread_claimed_at(id)
+				.map_or(true, |(claimed_at, _)| claimed_at + expiry <= now)
+			)
+			.count();
+
+		if stale == 0 { return }
+
+		let mut claims = claims;
+		let burn = Self::claim_expiry_burn();
+		for (id, claimant) in claims.drain(..stale) {
+			if let Some((_, fee)) = <ClaimedAt<T>>::take(&id) {
+				let _ = T::Currency::slash_reserved(&claimant, burn * fee);
+				T::Currency::unreserve(&claimant, fee - burn * fee);
+			}
+
+			Self::deposit_event(RawEvent::ParathreadClaimExpired(id, claimant));
+		}
+
+		<ParathreadClaims<T>>::put(claims);
+	}
+
+	// cancel the pending claim of a parathread, refunding the claim fee in full.
+	fn cancel_parathread_claim(id: ParaId) {
+		let claims = Self::parathread_claims();
+		let claimant = match claims.iter().find(|&&(ref claimed, _)| claimed == &id) {
+			Some(&(_, ref claimant)) => claimant.clone(),
+			None => return,
+		};
+
+		if let Some((_, fee)) = <ClaimedAt<T>>::take(&id) {
+			T::Currency::unreserve(&claimant, fee);
+		}
+		<ParathreadClaims<T>>::mutate(|claims| claims.retain(|&(ref claimed, _)| claimed != &id));
+	}
+
+	fn remove_parathread(id: ParaId) {
+		let mut parathreads = Self::parathreads();
+		if let Ok(idx) = parathreads.binary_search(&id) {
+			parathreads.remove(idx);
+		}
+
+		<Code<T>>::remove(id);
+		<Heads<T>>::remove(id);
+		<Deposits<T>>::remove(id);
+		<PermittedCollators<T>>::remove(id);
+		<Stats<T>>::remove(id);
+		Self::cancel_head_reset(id);
+		Self::cancel_parathread_claim(id);
+		<ScheduledParathreads<T>>::mutate(|scheduled| scheduled.retain(|scheduled| scheduled != &id));
+		<Parathreads<T>>::put(parathreads);
+	}
+
 	/// Calculate the ingress to a specific parachain.
 	///
 	/// Yields a list of parachains being routed from, and the egress
-	/// queue roots to consider.
+	/// queue roots to consider. Routes which were last updated before the
+	/// ingress watermark of the parachain have already been processed and are omitted.
 	pub fn ingress(to: ParaId) -> Option<Vec<(ParaId, Hash)>> {
 		let active_parachains = Self::active_parachains();
 		if !active_parachains.contains(&to) { return None }
 
+		let watermark = Self::ingress_watermark(&to);
 		Some(active_parachains.into_iter().filter(|i| i != &to)
+			.filter(move |from| acceptance::is_unprocessed_ingress(<RoutingPosted<T>>::get((*from, to)), watermark))
 			.filter_map(move |from| {
 				<Routing<T>>::get((from, to.clone())).map(move |h| (from, h))
 			})
 			.collect())
 	}
 
-	fn check_egress_queue_roots(head: &AttestedCandidate, active_parachains: &[ParaId]) -> Result {
-		let mut last_egress_id = None;
-		let mut iter = active_parachains.iter();
-		for (egress_para_id, root) in &head.candidate.egress_queue_roots {
-			// egress routes should be ascending order by parachain ID without duplicate.
-			ensure!(
-				last_egress_id.as_ref().map_or(true, |x| x < &egress_para_id),
-				"Egress routes out of order by ID"
-			);
-
-			// a parachain can't route to self
-			ensure!(
-				*egress_para_id != head.candidate.parachain_index,
-				"Parachain routing to self"
-			);
+	/// Calculate the blocks at which the ingress routes to a specific parachain were
+	/// last updated, for the routes yielded by `ingress`.
+	pub fn ingress_posted(to: ParaId) -> Option<Vec<(ParaId, ::primitives::BlockNumber)>> {
+		let active_parachains = Self::active_parachains();
+		if !active_parachains.contains(&to) { return None }
 
-			// no empty trie roots
-			ensure!(
-				*root != EMPTY_TRIE_ROOT.into(),
-				"Empty trie root included"
-			);
+		let watermark = Self::ingress_watermark(&to);
+		Some(active_parachains.into_iter().filter(|i| i != &to)
+			.filter(|from| <Routing<T>>::exists((*from, to)))
+			.map(|from| (from, <RoutingPosted<T>>::get((from, to))))
+			.filter(|&(_, posted)| acceptance::is_unprocessed_ingress(posted, watermark))
+			.map(|(from, posted)| (from, posted.as_()))
+			.collect())
+	}
 
-			// can't route to a parachain which doesn't exist
-			ensure!(
-				iter.find(|x| x == &egress_para_id).is_some(),
-				"Routing to non-existent parachain"
-			);
+	/// Apply the ingress watermarks acknowledged by the candidates included in this block,
+	/// as reported by the block author in ascending order by parachain ID.
+	///
+	/// Every included candidate must be reported, with the watermark in its receipt.
+	/// The routed message queues processed by each parachain are pruned.
+	pub fn acknowledge_ingress(watermarks: Vec<(ParaId, T::BlockNumber)>) -> Result {
+		ensure!(<DidUpdate<T>>::exists(), "Ingress watermarks must be reported after parachain heads");
+		ensure!(
+			watermarks == <IncludedWatermarks<T>>::get(),
+			"Reported ingress watermarks differ from those of the included candidates"
+		);
+		<IncludedWatermarks<T>>::kill();
+
+		let chains: Vec<_> = Self::active_parachains().into_iter().chain(Self::parathreads()).collect();
+		for (to, watermark) in watermarks {
+			for from in chains.iter().cloned().filter(|from| from != &to) {
+				if !acceptance::is_unprocessed_ingress(<RoutingPosted<T>>::get((from, to)), watermark) {
+					<Routing<T>>::remove((from, to));
+					<RoutingPosted<T>>::remove((from, to));
+				}
+			}
 
-			last_egress_id = Some(egress_para_id)
+			<Watermarks<T>>::insert(to, watermark);
 		}
+
 		Ok(())
 	}
 
-	// check the attestations on these candidates. The candidates should have been checked
-	// that each candidates' chain ID is valid.
-	fn check_attestations(attested_candidates: &[AttestedCandidate]) -> Result {
+	/// The weights of checking and importing candidates.
+	pub fn inclusion_weights() -> InclusionWeights {
+		InclusionWeights {
+			candidate_base: CANDIDATE_BASE_WEIGHT,
+			validity_vote: VALIDITY_VOTE_WEIGHT,
+			egress_root: EGRESS_ROOT_WEIGHT,
+			max_block: MAX_BLOCK_CANDIDATES_WEIGHT,
+		}
+	}
+
+	/// Check candidates for inclusion in the block being built, returning the positions
+	/// of those which would be rejected along with the reason.
+	///
+	/// Unlike `set_heads`, which rejects the whole block, every candidate is checked, so
+	/// that block authors can drop the offending candidates before authoring. Candidates
+	/// out of order with those before them are rejected without affecting the others.
+	pub fn check_candidates(heads: &[AttestedCandidate]) -> Vec<(u32, InclusionError)> {
+		let active_parachains = Self::active_parachains();
+		let scheduled_parathreads = Self::scheduled_parathreads();
+		let now = <system::Module<T>>::block_number();
+
+		let mut rejected = Vec::new();
+		let mut accepted = Vec::with_capacity(heads.len());
+		let mut accepted_positions = Vec::with_capacity(heads.len());
+		let mut last_id = None;
+		for (i, head) in heads.iter().enumerate() {
+			match Self::check_head(head, last_id, &active_parachains, &scheduled_parathreads, now) {
+				Ok(()) => {
+					last_id = Some(head.parachain_index());
+					accepted.push(head.clone());
+					accepted_positions.push(i as u32);
+				}
+				Err(e) => rejected.push((i as u32, e)),
+			}
+		}
+
+		rejected.extend(Self::check_attestations(&accepted).into_iter()
+			.map(|(i, e)| (accepted_positions[i], e)));
+
+		// candidates which would take the block over its weight limit are rejected,
+		// keeping those before them.
+		let weights = Self::inclusion_weights();
+		let mut total = 0u64;
+		for (i, head) in heads.iter().enumerate() {
+			let position = i as u32;
+			if rejected.iter().any(|&(p, _)| p == position) { continue }
+
+			let with_head = total.saturating_add(weights.candidate(head));
+			if with_head > weights.max_block {
+				rejected.push((position, InclusionError::Overweight));
+			} else {
+				total = with_head;
+			}
+		}
+
+		rejected
+	}
+
+	// check a candidate for inclusion in a block at `now`, following a candidate for
+	// the parachain `last_id`. The attestations are checked separately.
+	fn check_head(
+		head: &AttestedCandidate,
+		last_id: Option<ParaId>,
+		active_parachains: &[ParaId],
+		scheduled_parathreads: &[ParaId],
+		now: T::BlockNumber,
+	) -> ::rstd::result::Result<(), InclusionError> {
+		let id = head.parachain_index();
+
+		// proposed heads must be ascending order by parachain ID without duplicate.
+		if !last_id.map_or(true, |last| last < id) {
+			return Err(InclusionError::OutOfOrder);
+		}
+
+		// active parachains and scheduled parathreads are always sorted.
+		if active_parachains.binary_search(&id).is_err() && scheduled_parathreads.binary_search(&id).is_err() {
+			return Err(InclusionError::UnregisteredParachain);
+		}
+
+		if !Self::is_permitted_collator(id, &head.candidate.collator) {
+			return Err(InclusionError::CollatorNotPermitted);
+		}
+
+		acceptance::check_candidate(&head.candidate, active_parachains)?;
+		acceptance::check_ingress_watermark(&head.candidate, Self::ingress_watermark(&id).as_(), now.as_())
+	}
+
+	// check the attestations on these candidates, returning the positions of those with bad
+	// attestations along with the reason. The candidates should have been checked that each
+	// candidates' chain ID is valid, and that they are in ascending order by it.
+	fn check_attestations(attested_candidates: &[AttestedCandidate]) -> Vec<(usize, InclusionError)> {
 		use primitives::parachain::ValidityAttestation;
 		use sr_primitives::traits::Verify;
 
@@ -366,14 +982,15 @@ impl<T: Trait> Module<T> {
 
 		let mut validator_groups = GroupedDutyIter::new(&sorted_validators[..]);
 
-		for candidate in attested_candidates {
-			let validator_group = validator_groups.group_for(candidate.parachain_index())
-				.ok_or("no validator group for parachain")?;
+		let check_candidate = |
+			candidate: &AttestedCandidate,
+			validator_group: Option<&[(usize, ParaId)]>,
+		| -> ::rstd::result::Result<(), InclusionError> {
+			let validator_group = validator_group.ok_or(InclusionError::NoValidatorGroup)?;
 
-			ensure!(
-				candidate.validity_votes.len() >= majority_of(validator_group.len()),
-				"Not enough validity attestations"
-			);
+			if candidate.validity_votes.len() < majority_of(validator_group.len()) {
+				return Err(InclusionError::NotEnoughAttestations);
+			}
 
 			let mut candidate_hash = None;
 			let mut encoded_implicit = None;
@@ -384,10 +1001,10 @@ impl<T: Trait> Module<T> {
 			for (auth_id, validity_attestation) in &candidate.validity_votes {
 				// protect against double-votes.
 				match validator_group.iter().find(|&(idx, _)| &authorities[*idx] == auth_id) {
-					None => return Err("Attesting validator not on this chain's validation duty."),
+					None => return Err(InclusionError::WrongValidatorGroup),
 					Some(&(idx, _)) => {
 						if track_voters.get(idx) {
-							return Err("Voter already attested validity once")
+							return Err(InclusionError::DoubleAttestation)
 						}
 						track_voters.set(idx, true)
 					}
@@ -414,14 +1031,21 @@ impl<T: Trait> Module<T> {
 					}
 				};
 
-				ensure!(
-					sig.verify(&payload[..], &auth_id.0.into()),
-					"Candidate validity attestation signature is bad."
-				);
+				if !sig.verify(&payload[..], &auth_id.0.into()) {
+					return Err(InclusionError::BadAttestationSignature);
+				}
 			}
-		}
 
-		Ok(())
+			Ok(())
+		};
+
+		attested_candidates.iter()
+			.enumerate()
+			.filter_map(|(i, candidate)| {
+				let validator_group = validator_groups.group_for(candidate.parachain_index());
+				check_candidate(candidate, validator_group).err().map(|e| (i, e))
+			})
+			.collect()
 	}
 
 /*
@@ -468,8 +1092,10 @@ mod tests {
 	use sr_primitives::{generic, BuildStorage};
 	use sr_primitives::traits::{BlakeTwo256, IdentityLookup};
 	use primitives::{parachain::{CandidateReceipt, HeadData, ValidityAttestation}, SessionKey};
+	use primitives::message_roots::EMPTY_TRIE_ROOT;
 	use keyring::Keyring;
-	use {consensus, timestamp};
+	use std::cell::RefCell;
+	use {balances, consensus, timestamp};
 
 	impl_outer_origin! {
 		pub enum Origin for Test {}
@@ -504,9 +1130,47 @@ mod tests {
 		type Moment = u64;
 		type OnTimestampSet = ();
 	}
-	impl Trait for Test {}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type EnsureAccountLiquid = ();
+		type Event = ();
+	}
+
+	thread_local! {
+		static TREASURY: RefCell<u64> = RefCell::new(0);
+	}
+
+	pub struct TestTreasury;
+	impl OnFundsCollected<u64> for TestTreasury {
+		fn on_funds_collected(amount: u64) {
+			TREASURY.with(|t| *t.borrow_mut() += amount);
+		}
+	}
+
+	fn treasury() -> u64 {
+		TREASURY.with(|t| *t.borrow())
+	}
+
+	impl Trait for Test {
+		type Currency = balances::Module<Test>;
+		type OnFundsCollected = TestTreasury;
+		type Event = ();
+	}
 
 	type Parachains = Module<Test>;
+	type Balances = balances::Module<Test>;
+
+	const CLAIM_FEE: u64 = 10;
+	const REGISTRATION_DEPOSIT: u64 = 100;
+	const HEAD_RESET_DELAY: u64 = 10;
+	const CLAIM_EXPIRY: u64 = 20;
+	const PARATHREAD_CORES: u32 = 1;
+
+	fn account(n: u8) -> ::AccountId {
+		[n; 32].into()
+	}
 
 	fn new_test_ext(parachains: Vec<(ParaId, Vec<u8>, Vec<u8>)>) -> TestExternalities<Blake2Hasher> {
 		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
@@ -529,8 +1193,23 @@ mod tests {
 			session_length: 1000,
 			validators: authority_keys.iter().map(|k| k.to_raw_public().into()).collect(),
 		}.build_storage().unwrap().0);
+		t.extend(balances::GenesisConfig::<Test>{
+			balances: vec![(account(1), 1000), (account(2), 5)],
+			transfer_fee: 0,
+			creation_fee: 0,
+			existential_deposit: 0,
+			vesting: vec![],
+		}.build_storage().unwrap().0);
 		t.extend(GenesisConfig::<Test>{
 			parachains: parachains,
+			parathread_claim_fee: CLAIM_FEE,
+			parathread_claim_expiry: CLAIM_EXPIRY,
+			claim_expiry_burn: Permill::from_percent(20),
+			parathread_cores: PARATHREAD_CORES,
+			registration_deposit: REGISTRATION_DEPOSIT,
+			slash_treasury_share: Permill::from_percent(40),
+			parachain_validator_count: 0,
+			head_reset_delay: HEAD_RESET_DELAY,
 			_phdata: Default::default(),
 		}.build_storage().unwrap().0);
 		t.into()
@@ -586,6 +1265,7 @@ mod tests {
 				egress_queue_roots,
 				fees: 0,
 				block_data_hash: Default::default(),
+				ingress_watermark: 0,
 			}
 		}
 	}
@@ -629,6 +1309,285 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn parathread_claim_fee_goes_to_treasury() {
+		with_externalities(&mut new_test_ext(vec![(5u32.into(), vec![1], vec![1])]), || {
+			let id = ParaId::from(7u32);
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), id, vec![2], vec![2]));
+			assert!(Parachains::register_parathread(Origin::signed(account(1)), 5u32.into(), vec![], vec![]).is_err());
+			assert!(Parachains::register_parachain(id, vec![], vec![]).is_err());
+
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), id));
+			assert_eq!(Parachains::parathread_claims(), vec![(id, account(1))]);
+			assert_eq!(Balances::free_balance(&account(1)), 1000 - REGISTRATION_DEPOSIT - CLAIM_FEE);
+			assert_eq!(Balances::reserved_balance(&account(1)), REGISTRATION_DEPOSIT + CLAIM_FEE);
+			assert_eq!(treasury(), 0);
+
+			// only one pending claim per parathread.
+			assert!(Parachains::claim_parathread(Origin::signed(account(1)), id).is_err());
+
+			// the fee is collected once the claim is scheduled.
+			Parachains::schedule_parathreads();
+			assert_eq!(Parachains::scheduled_parathreads(), vec![id]);
+			assert_eq!(Balances::reserved_balance(&account(1)), REGISTRATION_DEPOSIT);
+			assert_eq!(treasury(), CLAIM_FEE);
+			assert!(Parachains::parathread_claims().is_empty());
+			assert_eq!(Parachains::take_parathread_claim(id), None);
+
+			// claims which cannot be paid for are rejected without charging anything.
+			assert_ok!(Parachains::deregister_parathread(Origin::signed(account(1)), id));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), id, vec![2], vec![2]));
+			assert!(Parachains::claim_parathread(Origin::signed(account(2)), id).is_err());
+			assert_eq!(Balances::free_balance(&account(2)), 5);
+			assert_eq!(treasury(), CLAIM_FEE);
+		});
+	}
+
+	#[test]
+	fn stale_parathread_claims_expire() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			let (a, b) = (ParaId::from(7u32), ParaId::from(8u32));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), a, vec![2], vec![2]));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), b, vec![3], vec![3]));
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), a));
+			system::Module::<Test>::set_block_number(5);
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), b));
+
+			Parachains::expire_parathread_claims(CLAIM_EXPIRY);
+			assert_eq!(Parachains::parathread_claims().len(), 2);
+
+			// only the first claim is stale. 20% of its fee is burned.
+			Parachains::expire_parathread_claims(1 + CLAIM_EXPIRY);
+			assert_eq!(Parachains::parathread_claims(), vec![(b, account(1))]);
+			assert_eq!(Parachains::parathread_claimed_at(&a), None);
+			assert_eq!(Balances::reserved_balance(&account(1)), 2 * REGISTRATION_DEPOSIT + CLAIM_FEE);
+			assert_eq!(Balances::free_balance(&account(1)), 1000 - 2 * REGISTRATION_DEPOSIT - CLAIM_FEE - 2);
+			assert_eq!(treasury(), 0);
+
+			// deregistering refunds a pending claim in full.
+			assert_ok!(Parachains::deregister_parathread(Origin::signed(account(1)), b));
+			assert!(Parachains::parathread_claims().is_empty());
+			assert_eq!(Balances::reserved_balance(&account(1)), REGISTRATION_DEPOSIT);
+			assert_eq!(Balances::free_balance(&account(1)), 1000 - REGISTRATION_DEPOSIT - 2);
+		});
+	}
+
+	#[test]
+	fn claims_left_waiting_for_a_core_expire() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			let (a, b) = (ParaId::from(7u32), ParaId::from(8u32));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), a, vec![2], vec![2]));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), b, vec![3], vec![3]));
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), a));
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), b));
+
+			// the only parathread core goes to the first claim.
+			Parachains::schedule_parathreads();
+			assert_eq!(Parachains::scheduled_parathreads(), vec![a]);
+			assert_eq!(treasury(), CLAIM_FEE);
+
+			// the scheduled claim is paid for; the waiting one expires.
+			Parachains::expire_parathread_claims(1 + CLAIM_EXPIRY);
+			assert!(Parachains::parathread_claims().is_empty());
+			assert_eq!(Parachains::parathread_claimed_at(&b), None);
+			assert_eq!(Balances::reserved_balance(&account(1)), 2 * REGISTRATION_DEPOSIT);
+			assert_eq!(Balances::free_balance(&account(1)), 1000 - 2 * REGISTRATION_DEPOSIT - CLAIM_FEE - 2);
+			assert_eq!(treasury(), CLAIM_FEE);
+		});
+	}
+
+	#[test]
+	fn forfeited_deposit_goes_to_treasury() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			let id = ParaId::from(7u32);
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), id, vec![2], vec![2]));
+			assert_eq!(Balances::reserved_balance(&account(1)), REGISTRATION_DEPOSIT);
+			assert_eq!(Parachains::parathread_deposit(&id), Some((account(1), REGISTRATION_DEPOSIT)));
+
+			// honest deregistration returns the deposit.
+			assert!(Parachains::deregister_parathread(Origin::signed(account(2)), id).is_err());
+			assert_ok!(Parachains::deregister_parathread(Origin::signed(account(1)), id));
+			assert_eq!(Balances::free_balance(&account(1)), 1000);
+			assert_eq!(Balances::reserved_balance(&account(1)), 0);
+			assert_eq!(treasury(), 0);
+
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), id, vec![2], vec![2]));
+			assert_ok!(Parachains::forfeit_parathread_deposit(id));
+			assert_eq!(Balances::free_balance(&account(1)), 1000 - REGISTRATION_DEPOSIT);
+			assert_eq!(Balances::reserved_balance(&account(1)), 0);
+			assert_eq!(treasury(), REGISTRATION_DEPOSIT);
+
+			assert!(Parachains::parathreads().is_empty());
+			assert_eq!(Parachains::parachain_code(&id), None);
+			assert_eq!(Parachains::parathread_deposit(&id), None);
+		});
+	}
+
+	#[test]
+	fn treasury_receives_share_of_misbehavior_slash() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_eq!(Parachains::slash_misbehavior(&account(1), 500), 0);
+			assert_eq!(Balances::free_balance(&account(1)), 500);
+			assert_eq!(treasury(), 200);
+
+			// only the amount actually slashed is shared with the treasury.
+			assert_eq!(Parachains::slash_misbehavior(&account(2), 10), 5);
+			assert_eq!(Balances::free_balance(&account(2)), 0);
+			assert_eq!(treasury(), 202);
+		});
+	}
+
+	#[test]
+	fn permitted_collators_set_by_manager_and_enforced() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let id = ParaId::from(0u32);
+			let collator = account(9);
+
+			// only the manager may restrict collators.
+			assert!(Parachains::set_permitted_collators(Origin::signed(account(1)), id, Some(vec![collator])).is_err());
+			assert!(Parachains::set_parachain_manager(5u32.into(), account(1)).is_err());
+			assert_ok!(Parachains::set_parachain_manager(id, account(1)));
+			assert!(Parachains::set_permitted_collators(Origin::signed(account(2)), id, Some(vec![collator])).is_err());
+			assert_ok!(Parachains::set_permitted_collators(Origin::signed(account(1)), id, Some(vec![collator])));
+			assert_eq!(Parachains::permitted_collators(&id), Some(vec![collator]));
+
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let candidate = |collator| {
+				let mut candidate = AttestedCandidate {
+					validity_votes: vec![],
+					candidate: CandidateReceipt {
+						parachain_index: id,
+						collator,
+						signature: Default::default(),
+						head_data: HeadData(vec![1, 2, 3]),
+						balance_uploads: vec![],
+						egress_queue_roots: vec![],
+						fees: 0,
+						block_data_hash: Default::default(),
+						ingress_watermark: 0,
+					}
+				};
+				make_attestations(&mut candidate);
+				candidate
+			};
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate(account(8))]),
+				Origin::INHERENT,
+			).is_err());
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate(collator)]),
+				Origin::INHERENT,
+			).is_ok());
+
+			// lifting the restriction allows anyone again.
+			assert_ok!(Parachains::set_permitted_collators(Origin::signed(account(1)), id, None));
+			assert!(Parachains::is_permitted_collator(id, &account(8)));
+		});
+	}
+
+	#[test]
+	fn parathread_managed_by_depositor() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			let id = ParaId::from(7u32);
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), id, vec![2], vec![2]));
+			assert_eq!(Parachains::manager_of(id), Some(account(1)));
+
+			assert_ok!(Parachains::set_permitted_collators(Origin::signed(account(1)), id, Some(vec![account(9)])));
+			assert!(!Parachains::is_permitted_collator(id, &account(8)));
+
+			assert_ok!(Parachains::deregister_parathread(Origin::signed(account(1)), id));
+			assert_eq!(Parachains::manager_of(id), None);
+			assert_eq!(Parachains::permitted_collators(&id), None);
+		});
+	}
+
+	#[test]
+	fn head_reset_after_delay_wipes_messages() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![1]),
+			(1u32.into(), vec![], vec![1]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let id = ParaId::from(0u32);
+			let other = ParaId::from(1u32);
+			<Routing<Test>>::insert((id, other), H256::from([1; 32]));
+			<Routing<Test>>::insert((other, id), H256::from([2; 32]));
+
+			assert_ok!(Parachains::set_parachain_manager(id, account(1)));
+			assert!(Parachains::schedule_head_reset(Origin::signed(account(2)), id, vec![9]).is_err());
+			assert!(Parachains::schedule_head_reset(Origin::signed(account(1)), 5u32.into(), vec![9]).is_err());
+
+			system::Module::<Test>::set_block_number(1);
+			assert_ok!(Parachains::schedule_head_reset(Origin::signed(account(1)), id, vec![9]));
+			assert_eq!(Parachains::pending_head_resets(), vec![(id, 1 + HEAD_RESET_DELAY, vec![9])]);
+
+			Parachains::apply_head_resets(HEAD_RESET_DELAY);
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![1]));
+			assert_eq!(Parachains::ingress(other), Some(vec![(id, [1; 32].into())]));
+
+			system::Module::<Test>::set_block_number(1 + HEAD_RESET_DELAY);
+			Parachains::apply_head_resets(1 + HEAD_RESET_DELAY);
+			assert_eq!(Parachains::parachain_head(&id), Some(vec![9]));
+			assert_eq!(Parachains::ingress(id), Some(Vec::new()));
+			assert_eq!(Parachains::ingress(other), Some(Vec::new()));
+			assert!(Parachains::pending_head_resets().is_empty());
+
+			// the reset of the other chain is canceled when it is deregistered.
+			assert_ok!(Parachains::force_schedule_head_reset(other, vec![8]));
+			assert_ok!(Parachains::deregister_parachain(other));
+			assert!(Parachains::pending_head_resets().is_empty());
+		});
+	}
+
+	#[test]
+	fn inclusion_stats_track_recent_sessions() {
+		use session::OnSessionChange;
+
+		with_externalities(&mut new_test_ext(vec![(0u32.into(), vec![], vec![])]), || {
+			let id = ParaId::from(0u32);
+			assert_eq!(Parachains::inclusion_stats(&id), None);
+
+			Parachains::note_inclusion(id, 5);
+			Parachains::note_inclusion(id, 7);
+			Parachains::note_inclusion(id, 11);
+
+			let stats = Parachains::inclusion_stats(&id).unwrap();
+			assert_eq!(stats.total_included(), 3);
+			// two intervals measured, of 2 and 4 blocks.
+			assert_eq!(stats.average_interval(), Some(3));
+			assert_eq!(stats.last_included, Some(11));
+
+			for _ in 0..INCLUSION_STATS_SESSIONS - 1 {
+				Parachains::on_session_change(0, false);
+			}
+			Parachains::note_inclusion(id, 12);
+			let stats = Parachains::inclusion_stats(&id).unwrap();
+			assert_eq!(stats.included.len(), INCLUSION_STATS_SESSIONS);
+			assert_eq!(stats.total_included(), 4);
+
+			// the session with the first inclusions is dropped.
+			Parachains::on_session_change(0, false);
+			let stats = Parachains::inclusion_stats(&id).unwrap();
+			assert_eq!(stats.included.len(), INCLUSION_STATS_SESSIONS);
+			assert_eq!(stats.total_included(), 1);
+			assert_eq!(stats.average_interval(), Some(1));
+
+			assert_ok!(Parachains::deregister_parachain(id));
+			assert_eq!(Parachains::inclusion_stats(&id), None);
+		});
+	}
+
 	#[test]
 	fn duty_roster_works() {
 		let parachains = vec![
@@ -663,6 +1622,134 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn duty_roster_limited_to_parachain_validators() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let authorities = ::Consensus::authorities();
+			assert_eq!(Parachains::parachain_validators(), authorities);
+
+			// the new count is only applied from the next session.
+			assert_ok!(Parachains::set_parachain_validator_count(5));
+			assert_eq!(Parachains::parachain_validator_count(), 0);
+			<Parachains as session::OnSessionChange<u64>>::on_session_change(0, false);
+			assert_eq!(Parachains::parachain_validator_count(), 5);
+			assert_eq!(Parachains::parachain_validators(), authorities[..5].to_vec());
+
+			for seed in 0..3u8 {
+				system::Module::<Test>::set_random_seed([seed; 32].into());
+				let duty_roster = Parachains::calculate_duty_roster();
+				assert_eq!(duty_roster.validator_duty.len(), 8);
+
+				for i in (0..2).map(ParaId::from) {
+					assert_eq!(duty_roster.validator_duty.iter().filter(|&&j| j == Chain::Parachain(i)).count(), 2);
+				}
+
+				// validators outside the top five never get parachain duty.
+				assert!(duty_roster.validator_duty[5..].iter().all(|&j| j == Chain::Relay));
+			}
+		});
+	}
+
+	#[test]
+	fn scheduled_works() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let duty_roster = Parachains::calculate_duty_roster();
+			let scheduled = Parachains::scheduled();
+
+			assert_eq!(scheduled.len(), 2);
+			for (core, assignment) in scheduled.iter().enumerate() {
+				assert_eq!(assignment.core, core as CoreIndex);
+				assert_eq!(assignment.para_id, ParaId::from(core as u32));
+				assert_eq!(assignment.kind, AssignmentKind::Parachain);
+				assert_eq!(assignment.group.len(), 3);
+
+				for &idx in &assignment.group {
+					assert_eq!(
+						duty_roster.validator_duty[idx as usize],
+						Chain::Parachain(assignment.para_id),
+					);
+				}
+			}
+		});
+	}
+
+	#[test]
+	fn parathread_claims_scheduled_onto_cores() {
+		with_externalities(&mut new_test_ext(vec![(0u32.into(), vec![], vec![])]), || {
+			let (a, b) = (ParaId::from(7u32), ParaId::from(8u32));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), a, vec![2], vec![2]));
+			assert_ok!(Parachains::register_parathread(Origin::signed(account(1)), b, vec![3], vec![3]));
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), b));
+			assert_ok!(Parachains::claim_parathread(Origin::signed(account(1)), a));
+
+			// claims are scheduled in the order they were made, one per parathread core.
+			Parachains::schedule_parathreads();
+			assert_eq!(Parachains::scheduled_parathreads(), vec![b]);
+			assert_eq!(Parachains::parathread_claims(), vec![(a, account(1))]);
+			assert_eq!(treasury(), CLAIM_FEE);
+
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let scheduled = Parachains::scheduled();
+			assert_eq!(scheduled.len(), 2);
+			assert_eq!(scheduled[0].kind, AssignmentKind::Parachain);
+			assert_eq!(scheduled[1].para_id, b);
+			assert_eq!(scheduled[1].kind, AssignmentKind::Parathread);
+			assert_eq!(scheduled[1].group.len(), 3);
+
+			let candidate = |id| {
+				let mut candidate = AttestedCandidate {
+					validity_votes: vec![],
+					candidate: CandidateReceipt {
+						parachain_index: id,
+						collator: Default::default(),
+						signature: Default::default(),
+						head_data: HeadData(vec![1, 2, 3]),
+						balance_uploads: vec![],
+						egress_queue_roots: vec![],
+						fees: 0,
+						block_data_hash: Default::default(),
+						ingress_watermark: 0,
+					}
+				};
+				make_attestations(&mut candidate);
+				candidate
+			};
+
+			// only scheduled parathreads may have candidates included.
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate(a)]),
+				Origin::INHERENT,
+			).is_err());
+
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate(b)]),
+				Origin::INHERENT,
+			).is_ok());
+			assert_eq!(Parachains::parachain_head(&b), Some(vec![1, 2, 3]));
+
+			// the next claim takes the core for the following block.
+			Parachains::schedule_parathreads();
+			assert_eq!(Parachains::scheduled_parathreads(), vec![a]);
+			assert!(Parachains::parathread_claims().is_empty());
+			assert_eq!(treasury(), 2 * CLAIM_FEE);
+
+			Parachains::schedule_parathreads();
+			assert!(Parachains::scheduled_parathreads().is_empty());
+			assert_eq!(Parachains::scheduled().len(), 1);
+		});
+	}
+
 	#[test]
 	fn unattested_candidate_is_rejected() {
 		let parachains = vec![
@@ -683,6 +1770,7 @@ mod tests {
 					egress_queue_roots: vec![],
 					fees: 0,
 					block_data_hash: Default::default(),
+					ingress_watermark: 0,
 				}
 			};
 
@@ -710,6 +1798,7 @@ mod tests {
 					egress_queue_roots: vec![],
 					fees: 0,
 					block_data_hash: Default::default(),
+					ingress_watermark: 0,
 				}
 			};
 
@@ -724,6 +1813,7 @@ mod tests {
 					egress_queue_roots: vec![],
 					fees: 0,
 					block_data_hash: Default::default(),
+					ingress_watermark: 0,
 				}
 			};
 
@@ -762,6 +1852,7 @@ mod tests {
 					egress_queue_roots: vec![],
 					fees: 0,
 					block_data_hash: Default::default(),
+					ingress_watermark: 0,
 				}
 			};
 
@@ -777,6 +1868,86 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn check_candidates_identifies_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let make_candidate = |para_id: u32| AttestedCandidate {
+				validity_votes: vec![],
+				candidate: CandidateReceipt {
+					parachain_index: para_id.into(),
+					collator: Default::default(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: vec![],
+					egress_queue_roots: vec![],
+					fees: 0,
+					block_data_hash: Default::default(),
+					ingress_watermark: 0,
+				}
+			};
+
+			let mut candidate_a = make_candidate(0);
+			make_attestations(&mut candidate_a);
+
+			let mut double_validity = candidate_a.clone();
+			double_validity.validity_votes.push(candidate_a.validity_votes[0].clone());
+			assert_eq!(
+				Parachains::check_candidates(&[double_validity]),
+				vec![(0, InclusionError::DoubleAttestation)],
+			);
+
+			let candidates = vec![candidate_a.clone(), make_candidate(1), make_candidate(1), make_candidate(5)];
+			assert_eq!(
+				Parachains::check_candidates(&candidates),
+				vec![
+					(2, InclusionError::OutOfOrder),
+					(3, InclusionError::UnregisteredParachain),
+					(1, InclusionError::NotEnoughAttestations),
+				],
+			);
+
+			// the remaining candidate is accepted.
+			assert!(Parachains::check_candidates(&[candidate_a.clone()]).is_empty());
+			assert!(Parachains::dispatch(Call::set_heads(vec![candidate_a]), Origin::INHERENT).is_ok());
+		});
+	}
+
+	#[test]
+	fn overweight_candidates_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let mut candidate = new_candidate_with_egress_roots(vec![]);
+			make_attestations(&mut candidate);
+			assert!(Parachains::check_candidates(&[candidate.clone()]).is_empty());
+
+			// the weight is checked before the votes, so repeating them is enough to
+			// take the candidate over the limit.
+			let weights = Parachains::inclusion_weights();
+			let vote = candidate.validity_votes[0].clone();
+			while weights.candidate(&candidate) <= weights.max_block {
+				candidate.validity_votes.push(vote.clone());
+			}
+
+			let result = Parachains::dispatch(
+				Call::set_heads(vec![candidate]),
+				Origin::INHERENT,
+			);
+
+			assert_eq!(Err("Parachain candidates exceed the maximum block weight"), result);
+		});
+	}
+
 	#[test]
 	fn ingress_works() {
 		let parachains = vec![
@@ -799,6 +1970,7 @@ mod tests {
 					egress_queue_roots: from_a.clone(),
 					fees: 0,
 					block_data_hash: Default::default(),
+					ingress_watermark: 0,
 				}
 			};
 
@@ -814,6 +1986,7 @@ mod tests {
 					egress_queue_roots: from_b.clone(),
 					fees: 0,
 					block_data_hash: Default::default(),
+					ingress_watermark: 0,
 				}
 			};
 
@@ -847,6 +2020,85 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn acknowledged_watermark_prunes_processed_ingress() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+			(99u32.into(), vec![1, 2, 3], vec![4, 5, 6]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			let make_candidate = |para_id: u32, egress_queue_roots: Vec<(ParaId, Hash)>, ingress_watermark| {
+				let mut candidate = AttestedCandidate {
+					validity_votes: vec![],
+					candidate: CandidateReceipt {
+						parachain_index: para_id.into(),
+						collator: Default::default(),
+						signature: Default::default(),
+						head_data: HeadData(vec![1, 2, 3]),
+						balance_uploads: vec![],
+						egress_queue_roots,
+						fees: 0,
+						block_data_hash: Default::default(),
+						ingress_watermark,
+					}
+				};
+
+				make_attestations(&mut candidate);
+				candidate
+			};
+
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			system::Module::<Test>::set_block_number(1);
+
+			let candidate_a = make_candidate(0, vec![(1.into(), [1; 32].into())], 0);
+			let candidate_b = make_candidate(1, vec![(99.into(), [2; 32].into())], 0);
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_a, candidate_b]),
+				Origin::INHERENT,
+			).is_ok());
+
+			// every included candidate must be reported.
+			assert!(Parachains::acknowledge_ingress(vec![(0.into(), 0)]).is_err());
+			assert_ok!(Parachains::acknowledge_ingress(vec![(0.into(), 0), (1.into(), 0)]));
+
+			// routed in the same block: not yet processed by 1.
+			assert_eq!(
+				Parachains::ingress(ParaId::from(1)),
+				Some(vec![(0.into(), [1; 32].into())]),
+			);
+			assert_eq!(Parachains::ingress_posted(ParaId::from(1)), Some(vec![(0.into(), 1)]));
+
+			<DidUpdate<Test>>::kill();
+			system::Module::<Test>::set_block_number(2);
+
+			// ingress can't be acknowledged before it is routed.
+			let candidate_b = make_candidate(1, vec![], 3);
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_b]),
+				Origin::INHERENT,
+			).is_err());
+
+			let watermark = acceptance::ingress_watermark(0, vec![1]);
+			let candidate_b = make_candidate(1, vec![], watermark);
+			assert!(Parachains::dispatch(
+				Call::set_heads(vec![candidate_b]),
+				Origin::INHERENT,
+			).is_ok());
+			assert_ok!(Parachains::acknowledge_ingress(vec![(1.into(), watermark)]));
+
+			// 1 has processed the ingress from 0, which is pruned, but 99 has not advanced.
+			assert_eq!(Parachains::ingress_watermark(&ParaId::from(1)), 2);
+			assert_eq!(Parachains::ingress(ParaId::from(1)), Some(Vec::new()));
+			assert!(<Routing<Test>>::get((ParaId::from(0), ParaId::from(1))).is_none());
+			assert_eq!(
+				Parachains::ingress(ParaId::from(99)),
+				Some(vec![(1.into(), [2; 32].into())]),
+			);
+		});
+	}
+
 	#[test]
 	fn egress_routed_to_non_existent_parachain_is_rejected() {
 		// That no parachain is routed to which doesn't exist
@@ -947,6 +2199,29 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn oversized_head_data_rejected() {
+		let parachains = vec![
+			(0u32.into(), vec![], vec![]),
+			(1u32.into(), vec![], vec![]),
+		];
+
+		with_externalities(&mut new_test_ext(parachains), || {
+			system::Module::<Test>::set_random_seed([0u8; 32].into());
+			let mut candidate = new_candidate_with_egress_roots(vec![]);
+			candidate.candidate.head_data = HeadData(vec![1; acceptance::MAX_HEAD_DATA_SIZE + 1]);
+
+			make_attestations(&mut candidate);
+
+			let result = Parachains::dispatch(
+				Call::set_heads(vec![candidate.clone()]),
+				Origin::INHERENT,
+			);
+
+			assert_eq!(Err("Candidate head data too large"), result);
+		});
+	}
+
 	#[test]
 	fn empty_trie_root_const_is_blake2_hashed_null_node() {
 		let hashed_null_node =  <NodeCodec<Blake2Hasher> as trie_db::NodeCodec<Blake2Hasher>>::hashed_null_node();
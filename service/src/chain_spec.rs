@@ -17,6 +17,8 @@
 //! Polkadot chain configurations.
 
 use primitives::{H256, Ed25519AuthorityId as AuthorityId, ed25519};
+use polkadot_primitives::parachain::Id as ParaId;
+use genesis::GenesisBuilder;
 use polkadot_runtime::{
 	GenesisConfig, ConsensusConfig, CouncilSeatsConfig, DemocracyConfig, TreasuryConfig,
 	SessionConfig, StakingConfig, TimestampConfig, BalancesConfig, Perbill,
@@ -26,6 +28,21 @@ use polkadot_runtime::{
 
 const STAGING_TELEMETRY_URL: &str = "wss://telemetry.polkadot.io/submit/";
 const DEFAULT_PROTOCOL_ID: &str = "dot";
+const KUSAMA_PROTOCOL_ID: &str = "ksm";
+const WESTEND_PROTOCOL_ID: &str = "wnd";
+
+/// The ID of the adder test parachain registered on parachain-enabled testnets.
+const ADDER_PARACHAIN_ID: u32 = 100;
+
+// encoded genesis head of the adder test parachain: block number 0, zero parent hash
+// and the hash of the zero state.
+const ADDER_GENESIS_HEAD: [u8; 72] = [
+	0, 0, 0, 0, 0, 0, 0, 0,
+	0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+	0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+	1, 27, 77, 3, 221, 140, 1, 241, 4, 145, 67, 207, 156, 76, 129, 126,
+	75, 22, 127, 29, 27, 131, 229, 198, 240, 241, 13, 137, 186, 30, 123, 206,
+];
 
 /// Specialised `ChainSpec`.
 pub type ChainSpec = ::service::ChainSpec<GenesisConfig>;
@@ -155,7 +172,26 @@ pub fn staging_testnet_config() -> ChainSpec {
 	)
 }
 
-fn testnet_genesis(initial_authorities: Vec<AuthorityId>, upgrade_key: H256) -> GenesisConfig {
+/// Kusama config. Uses the staging genesis with its own protocol ID so the
+/// network does not mix with other chains.
+pub fn kusama_config() -> ChainSpec {
+	ChainSpec::from_genesis(
+		"Kusama",
+		"kusama",
+		staging_testnet_config_genesis,
+		vec![],
+		Some(STAGING_TELEMETRY_URL.into()),
+		Some(KUSAMA_PROTOCOL_ID),
+		None,
+		None,
+	)
+}
+
+fn testnet_genesis(
+	initial_authorities: Vec<AuthorityId>,
+	upgrade_key: H256,
+	parachains: Vec<(ParaId, Vec<u8>, Vec<u8>)>,
+) -> GenesisConfig {
 	let endowed_accounts = vec![
 		ed25519::Pair::from_seed(b"Alice                           ").public().0.into(),
 		ed25519::Pair::from_seed(b"Bob                             ").public().0.into(),
@@ -164,86 +200,14 @@ fn testnet_genesis(initial_authorities: Vec<AuthorityId>, upgrade_key: H256) ->
 		ed25519::Pair::from_seed(b"Eve                             ").public().0.into(),
 		ed25519::Pair::from_seed(b"Ferdie                          ").public().0.into(),
 	];
-	GenesisConfig {
-		consensus: Some(ConsensusConfig {
-			code: include_bytes!("../../runtime/wasm/target/wasm32-unknown-unknown/release/polkadot_runtime.compact.wasm").to_vec(),
-			authorities: initial_authorities.clone(),
-		}),
-		system: None,
-		indices: Some(IndicesConfig {
-			ids: endowed_accounts.clone(),
-		}),
-		balances: Some(BalancesConfig {
-			existential_deposit: 500,
-			transfer_fee: 0,
-			creation_fee: 0,
-			balances: endowed_accounts.iter().map(|&k|(k, (1u128 << 60))).collect(),
-			vesting: vec![],
-		}),
-		session: Some(SessionConfig {
-			validators: initial_authorities.iter().cloned().map(Into::into).collect(),
-			session_length: 10,
-		}),
-		staking: Some(StakingConfig {
-			current_era: 0,
-			intentions: initial_authorities.iter().cloned().map(Into::into).collect(),
-			minimum_validator_count: 1,
-			validator_count: 2,
-			sessions_per_era: 5,
-			bonding_duration: 2 * 60 * 12,
-			offline_slash: Perbill::zero(),
-			session_reward: Perbill::zero(),
-			current_offline_slash: 0,
-			current_session_reward: 0,
-			offline_slash_grace: 0,
-			invulnerables: initial_authorities.iter().cloned().map(Into::into).collect(),
-		}),
-		democracy: Some(DemocracyConfig {
-			launch_period: 9,
-			voting_period: 18,
-			minimum_deposit: 10,
-			public_delay: 10 * 60,
-			max_lock_periods: 6,
-		}),
-		grandpa: Some(GrandpaConfig {
-			authorities: initial_authorities.clone().into_iter().map(|k| (k, 1)).collect(),
-		}),
-		council_seats: Some(CouncilSeatsConfig {
-			active_council: endowed_accounts.iter().filter(|a| initial_authorities.iter().find(|&b| a[..] == b.0).is_none()).map(|a| (a.clone(), 1000000)).collect(),
-			candidacy_bond: 10,
-			voter_bond: 2,
-			present_slash_per_voter: 1,
-			carry_count: 4,
-			presentation_duration: 10,
-			approval_voting_period: 20,
-			term_duration: 1000000,
-			desired_seats: (endowed_accounts.len() - initial_authorities.len()) as u32,
-			inactive_grace_period: 1,
-		}),
-		council_voting: Some(CouncilVotingConfig {
-			cooloff_period: 75,
-			voting_period: 20,
-			enact_delay_period: 0,
-		}),
-		parachains: Some(Default::default()),
-		timestamp: Some(TimestampConfig {
-			period: 2,					// 2*2=4 second block time.
-		}),
-		treasury: Some(Default::default()),
-		upgrade_key: Some(UpgradeKeyConfig {
-			key: upgrade_key,
-		}),
-		sudo: Some(SudoConfig {
-			key: upgrade_key,
-		}),
-		claims: Some(ClaimsConfig {
-			claims: vec![],
-		}),
-		fees: Some(FeesConfig {
-			transaction_base_fee: 1,
-			transaction_byte_fee: 0,
-		})
-	}
+
+	parachains.into_iter().fold(
+		GenesisBuilder::new()
+			.validators(initial_authorities)
+			.endowed_accounts(endowed_accounts)
+			.upgrade_key(upgrade_key),
+		|builder, (id, code, head)| builder.parachain(id, code, head),
+	).build()
 }
 
 fn development_config_genesis() -> GenesisConfig {
@@ -251,7 +215,8 @@ fn development_config_genesis() -> GenesisConfig {
 		vec![
 			ed25519::Pair::from_seed(b"Alice                           ").public().into(),
 		],
-		ed25519::Pair::from_seed(b"Alice                           ").public().0.into()
+		ed25519::Pair::from_seed(b"Alice                           ").public().0.into(),
+		vec![],
 	)
 }
 
@@ -275,7 +240,8 @@ fn local_testnet_genesis() -> GenesisConfig {
 			ed25519::Pair::from_seed(b"Alice                           ").public().into(),
 			ed25519::Pair::from_seed(b"Bob                             ").public().into(),
 		],
-		ed25519::Pair::from_seed(b"Alice                           ").public().0.into()
+		ed25519::Pair::from_seed(b"Alice                           ").public().0.into(),
+		vec![],
 	)
 }
 
@@ -292,3 +258,49 @@ pub fn local_testnet_config() -> ChainSpec {
 		None,
 	)
 }
+
+fn westend_local_genesis() -> GenesisConfig {
+	testnet_genesis(
+		vec![
+			ed25519::Pair::from_seed(b"Alice                           ").public().into(),
+			ed25519::Pair::from_seed(b"Bob                             ").public().into(),
+		],
+		ed25519::Pair::from_seed(b"Alice                           ").public().0.into(),
+		vec![(
+			ADDER_PARACHAIN_ID.into(),
+			include_bytes!("../../parachain/tests/res/adder.wasm").to_vec(),
+			ADDER_GENESIS_HEAD.to_vec(),
+		)],
+	)
+}
+
+/// Parachain-enabled local testnet config (multivalidator Alice + Bob, with the adder
+/// test parachain registered at genesis).
+///
+/// Use `build-spec --chain westend-local` to produce a spec which can be extended with
+/// further parachains and loaded with `--chain <path>`.
+pub fn westend_local_config() -> ChainSpec {
+	ChainSpec::from_genesis(
+		"Westend Local Testnet",
+		"westend_local_testnet",
+		westend_local_genesis,
+		vec![],
+		None,
+		Some(WESTEND_PROTOCOL_ID),
+		None,
+		None,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sr_primitives::BuildStorage;
+
+	#[test]
+	fn kusama_spec_loads() {
+		let spec = kusama_config();
+		assert_eq!(spec.name(), "Kusama");
+		assert!(spec.build_storage().is_ok());
+	}
+}
@@ -0,0 +1,280 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Programmatic construction of testnet genesis configurations.
+//!
+//! ```ignore
+//! let genesis = GenesisBuilder::new()
+//! 	.validators(vec![alice, bob])
+//! 	.endowed_accounts(vec![alice_account])
+//! 	.parachain(100.into(), code, genesis_head)
+//! 	.build();
+//! ```
+
+use primitives::Ed25519AuthorityId as AuthorityId;
+use polkadot_primitives::{AccountId, Balance};
+use polkadot_primitives::parachain::Id as ParaId;
+use polkadot_runtime::{
+	GenesisConfig, ConsensusConfig, CouncilSeatsConfig, DemocracyConfig, SessionConfig, StakingConfig,
+	TimestampConfig, BalancesConfig, Perbill, CouncilVotingConfig, GrandpaConfig, UpgradeKeyConfig,
+	SudoConfig, IndicesConfig, ClaimsConfig, FeesConfig, ParachainsConfig, Permill,
+};
+
+/// Builder for a testnet genesis configuration.
+///
+/// Everything not configurable here is given testnet defaults: zero fees and
+/// rewards, short sessions and a 4 second block time.
+#[derive(Clone)]
+pub struct GenesisBuilder {
+	validators: Vec<AuthorityId>,
+	endowed_accounts: Vec<AccountId>,
+	endowment: Balance,
+	upgrade_key: Option<AccountId>,
+	parachains: Vec<(ParaId, Vec<u8>, Vec<u8>)>,
+	session_length: u64,
+}
+
+impl Default for GenesisBuilder {
+	fn default() -> Self {
+		GenesisBuilder {
+			validators: Vec::new(),
+			endowed_accounts: Vec::new(),
+			endowment: 1 << 60,
+			upgrade_key: None,
+			parachains: Vec::new(),
+			session_length: 10,
+		}
+	}
+}
+
+impl GenesisBuilder {
+	/// Create a new builder with no validators, accounts or parachains.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Set the initial validators.
+	pub fn validators(mut self, validators: Vec<AuthorityId>) -> Self {
+		self.validators = validators;
+		self
+	}
+
+	/// Set the accounts endowed at genesis.
+	pub fn endowed_accounts(mut self, accounts: Vec<AccountId>) -> Self {
+		self.endowed_accounts = accounts;
+		self
+	}
+
+	/// Set the balance each endowed account starts with.
+	pub fn endowment(mut self, endowment: Balance) -> Self {
+		self.endowment = endowment;
+		self
+	}
+
+	/// Set the upgrade and sudo key. Defaults to the first endowed account.
+	pub fn upgrade_key(mut self, key: AccountId) -> Self {
+		self.upgrade_key = Some(key);
+		self
+	}
+
+	/// Register a parachain at genesis with given validation code and head data.
+	pub fn parachain(mut self, id: ParaId, code: Vec<u8>, genesis_head: Vec<u8>) -> Self {
+		self.parachains.push((id, code, genesis_head));
+		self
+	}
+
+	/// Set the session length in blocks.
+	pub fn session_length(mut self, session_length: u64) -> Self {
+		self.session_length = session_length;
+		self
+	}
+
+	/// Build the genesis configuration.
+	pub fn build(self) -> GenesisConfig {
+		let GenesisBuilder {
+			validators: initial_authorities,
+			endowed_accounts,
+			endowment,
+			upgrade_key,
+			parachains,
+			session_length,
+		} = self;
+
+		let upgrade_key = upgrade_key
+			.or_else(|| endowed_accounts.first().cloned())
+			.unwrap_or_default();
+
+		let council = endowed_accounts.iter()
+			.filter(|a| initial_authorities.iter().find(|&b| a[..] == b.0).is_none())
+			.map(|a| (a.clone(), 1000000))
+			.collect::<Vec<_>>();
+		let desired_seats = council.len() as u32;
+
+		GenesisConfig {
+			consensus: Some(ConsensusConfig {
+				code: include_bytes!("../../runtime/wasm/target/wasm32-unknown-unknown/release/polkadot_runtime.compact.wasm").to_vec(),
+				authorities: initial_authorities.clone(),
+			}),
+			system: None,
+			indices: Some(IndicesConfig {
+				ids: endowed_accounts.clone(),
+			}),
+			balances: Some(BalancesConfig {
+				existential_deposit: 500,
+				transfer_fee: 0,
+				creation_fee: 0,
+				balances: endowed_accounts.iter().map(|&k| (k, endowment)).collect(),
+				vesting: vec![],
+			}),
+			session: Some(SessionConfig {
+				validators: initial_authorities.iter().cloned().map(Into::into).collect(),
+				session_length,
+			}),
+			staking: Some(StakingConfig {
+				current_era: 0,
+				intentions: initial_authorities.iter().cloned().map(Into::into).collect(),
+				minimum_validator_count: 1,
+				validator_count: 2,
+				sessions_per_era: 5,
+				bonding_duration: 2 * 60 * 12,
+				offline_slash: Perbill::zero(),
+				session_reward: Perbill::zero(),
+				current_offline_slash: 0,
+				current_session_reward: 0,
+				offline_slash_grace: 0,
+				invulnerables: initial_authorities.iter().cloned().map(Into::into).collect(),
+			}),
+			democracy: Some(DemocracyConfig {
+				launch_period: 9,
+				voting_period: 18,
+				minimum_deposit: 10,
+				public_delay: 10 * 60,
+				max_lock_periods: 6,
+			}),
+			grandpa: Some(GrandpaConfig {
+				authorities: initial_authorities.clone().into_iter().map(|k| (k, 1)).collect(),
+			}),
+			council_seats: Some(CouncilSeatsConfig {
+				active_council: council,
+				candidacy_bond: 10,
+				voter_bond: 2,
+				present_slash_per_voter: 1,
+				carry_count: 4,
+				presentation_duration: 10,
+				approval_voting_period: 20,
+				term_duration: 1000000,
+				desired_seats,
+				inactive_grace_period: 1,
+			}),
+			council_voting: Some(CouncilVotingConfig {
+				cooloff_period: 75,
+				voting_period: 20,
+				enact_delay_period: 0,
+			}),
+			parachains: Some(ParachainsConfig {
+				parachains,
+				parathread_claim_fee: 10,
+				parathread_claim_expiry: 600,
+				claim_expiry_burn: Permill::from_percent(10),
+				parathread_cores: 1,
+				registration_deposit: 100,
+				slash_treasury_share: Permill::from_percent(50),
+				parachain_validator_count: 0,
+				head_reset_delay: 100,
+				_phdata: Default::default(),
+			}),
+			timestamp: Some(TimestampConfig {
+				period: 2,					// 2*2=4 second block time.
+			}),
+			treasury: Some(Default::default()),
+			upgrade_key: Some(UpgradeKeyConfig {
+				key: upgrade_key,
+			}),
+			sudo: Some(SudoConfig {
+				key: upgrade_key,
+			}),
+			claims: Some(ClaimsConfig {
+				claims: vec![],
+			}),
+			fees: Some(FeesConfig {
+				transaction_base_fee: 1,
+				transaction_byte_fee: 0,
+			})
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use primitives::{ed25519, Blake2Hasher};
+	use runtime_io::{with_externalities, TestExternalities};
+	use sr_primitives::BuildStorage;
+	use polkadot_runtime::{Balances, Parachains, Session, Sudo};
+
+	fn authority(seed: &[u8; 32]) -> AuthorityId {
+		ed25519::Pair::from_seed(seed).public().into()
+	}
+
+	fn externalities(genesis: GenesisConfig) -> TestExternalities<Blake2Hasher> {
+		genesis.build_storage().unwrap().0.into()
+	}
+
+	#[test]
+	fn builds_configured_genesis() {
+		let alice = authority(b"Alice                           ");
+		let bob = authority(b"Bob                             ");
+		let account: AccountId = alice.0.into();
+
+		let genesis = GenesisBuilder::new()
+			.validators(vec![alice.clone(), bob.clone()])
+			.endowed_accounts(vec![account])
+			.endowment(1_000_000)
+			.parachain(ParaId::from(100u32), vec![1, 2, 3], vec![4, 5])
+			.session_length(20)
+			.build();
+
+		with_externalities(&mut externalities(genesis), || {
+			assert_eq!(Session::validators(), vec![alice.0.into(), bob.0.into()]);
+			assert_eq!(Session::length(), 20);
+			assert_eq!(Balances::free_balance(&account), 1_000_000);
+
+			assert_eq!(Parachains::active_parachains(), vec![ParaId::from(100u32)]);
+			assert_eq!(Parachains::parachain_code(&ParaId::from(100u32)), Some(vec![1, 2, 3]));
+			assert_eq!(Parachains::parachain_head(&ParaId::from(100u32)), Some(vec![4, 5]));
+
+			// the first endowed account is the sudo key by default.
+			assert_eq!(Sudo::key(), account);
+		});
+	}
+
+	#[test]
+	fn explicit_upgrade_key_overrides_default() {
+		let alice = authority(b"Alice                           ");
+		let key: AccountId = [9; 32].into();
+
+		let genesis = GenesisBuilder::new()
+			.validators(vec![alice.clone()])
+			.endowed_accounts(vec![alice.0.into()])
+			.upgrade_key(key)
+			.build();
+
+		with_externalities(&mut externalities(genesis), || {
+			assert_eq!(Sudo::key(), key);
+			assert!(Parachains::active_parachains().is_empty());
+		});
+	}
+}
@@ -24,6 +24,7 @@ extern crate polkadot_primitives;
 extern crate polkadot_runtime;
 extern crate polkadot_executor;
 extern crate polkadot_network;
+extern crate polkadot_rpc;
 extern crate sr_primitives;
 extern crate substrate_primitives as primitives;
 extern crate substrate_client as client;
@@ -34,6 +35,14 @@ extern crate substrate_finality_grandpa as grandpa;
 extern crate substrate_transaction_pool as transaction_pool;
 extern crate tokio;
 extern crate substrate_inherents as inherents;
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+extern crate jsonrpc_pubsub;
+extern crate jsonrpc_ws_server;
+extern crate futures;
+extern crate parity_codec as codec;
+#[cfg(test)]
+extern crate sr_io as runtime_io;
 
 #[macro_use]
 extern crate log;
@@ -41,13 +50,18 @@ extern crate log;
 extern crate hex_literal;
 
 pub mod chain_spec;
+pub mod genesis;
 
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
-use polkadot_primitives::{parachain, AccountId, Block};
+use std::time::{Duration, Instant};
+use futures::{Future, Stream};
+use polkadot_primitives::{parachain, AccountId, Block, SessionKey};
+use polkadot_primitives::encryption::EncryptionKey;
 use polkadot_runtime::{GenesisConfig, RuntimeApi};
 use primitives::ed25519;
 use tokio::runtime::TaskExecutor;
+use tokio::timer::Interval;
 use service::{FactoryFullConfiguration, FullBackend, LightBackend, FullExecutor, LightExecutor};
 use transaction_pool::txpool::{Pool as TransactionPool};
 use aura::{import_queue, start_aura, AuraImportQueue, SlotDuration, NothingExtra};
@@ -60,11 +74,18 @@ pub use service::{
 };
 pub use service::config::full_version_from_strs;
 pub use client::{backend::Backend, runtime_api::Core as CoreApi, ExecutionStrategy};
-pub use polkadot_network::{PolkadotProtocol, NetworkService};
+pub use polkadot_network::{PolkadotProtocol, NetworkService, GossipFormat};
+pub use polkadot_network::validation::{GossipConfig, OverflowConfig, RebroadcastConfig};
+pub use polkadot_network::simulation;
 pub use polkadot_primitives::parachain::ParachainHost;
+pub use consensus::{AttestationSessions, BackedCandidates, Shutdown, ReplayBundle, load_replay};
 pub use primitives::{Blake2Hasher};
 pub use sr_primitives::traits::ProvideRuntimeApi;
 pub use chain_spec::ChainSpec;
+pub use genesis::GenesisBuilder;
+
+// how often to report candidates abandoned for missing the backing deadline.
+const EXPIRED_BACKING_REPORT_EVERY: Duration = Duration::from_secs(60);
 
 /// All configuration for the polkadot node.
 pub type Configuration = FactoryFullConfiguration<Factory>;
@@ -83,6 +104,70 @@ pub struct CustomConfiguration {
 		grandpa::LinkHalfForService<Factory>
 	)>,
 
+	/// Set to `Some` with an address to serve the parachain RPC methods on,
+	/// so collators can learn the validators assigned to their parachain.
+	pub parachain_rpc: Option<SocketAddr>,
+
+	/// Set to `Some` with an address to serve the parachain RPC methods on over
+	/// websockets, along with subscriptions to locally backed candidates.
+	pub parachain_ws_rpc: Option<SocketAddr>,
+
+	/// Set to `Some` with the address of a remote signing process and the session key
+	/// it holds, to delegate signing of attestation statements to it. The local node
+	/// then validates parachains without needing the secret key.
+	pub remote_signer: Option<(SocketAddr, SessionKey)>,
+
+	/// Whether collations should only be sent encrypted to the validators they are meant for.
+	pub private_collations: bool,
+
+	/// Set to `Some` with the secret of the long-term key collations are encrypted
+	/// to. A fresh key is generated if `None`.
+	pub collation_encryption_secret: Option<[u8; 32]>,
+
+	/// Keep the availability store in memory rather than on disk, and don't write
+	/// buffers to disk when they outgrow their memory limit. The chain database is
+	/// unaffected.
+	pub ephemeral: bool,
+
+	/// Handle to drain parachain validation before the service is dropped.
+	pub shutdown: Shutdown,
+
+	/// Broadcast of candidates backed by the local validator. Subscribe to it
+	/// to learn of backed candidates before they are included on-chain.
+	pub backed_candidates: BackedCandidates,
+
+	/// Attestation state of the live validation sessions of the local validator.
+	pub attestation_sessions: AttestationSessions,
+
+	/// Whether to serve the unsafe RPC methods exporting and importing attestation state
+	/// on the parachain RPC servers. Imported state is counted towards backing, so these
+	/// must only be exposed to trusted operators.
+	pub unsafe_attestation_rpc: bool,
+
+	/// How attestation statements are periodically re-broadcast, to keep backing live
+	/// when gossip messages are lost. Set to `None` to disable.
+	pub statement_rebroadcast: Option<RebroadcastConfig>,
+
+	/// How statements are pushed directly to other validators alongside gossip.
+	/// Unset parameters adapt to the size of the validator set.
+	pub gossip: GossipConfig,
+
+	/// The wire format gossip is encoded in, shared between the network protocol,
+	/// which tracks the formats connected peers understand, and validation.
+	pub gossip_format: GossipFormat,
+
+	/// Set to `Some` to write deferred statements and pending ingress to disk once
+	/// they outgrow a memory limit. Ignored for ephemeral nodes.
+	pub buffer_overflow: Option<OverflowConfig>,
+
+	/// Set to `Some` to abandon candidates which aren't backed within the given time
+	/// of the first statement seen on them, freeing the resources used on them.
+	pub backing_deadline: Option<Duration>,
+
+	/// Set to `Some` to write the inputs of fetched candidates which fail execution to
+	/// the given directory, so the failures can be replayed.
+	pub validation_replay_dir: Option<::std::path::PathBuf>,
+
 	inherent_data_providers: InherentDataProviders,
 }
 
@@ -91,11 +176,63 @@ impl Default for CustomConfiguration {
 		Self {
 			collating_for: None,
 			grandpa_import_setup: None,
+			parachain_rpc: None,
+			parachain_ws_rpc: None,
+			remote_signer: None,
+			private_collations: false,
+			collation_encryption_secret: None,
+			ephemeral: false,
+			shutdown: Shutdown::new(),
+			backed_candidates: BackedCandidates::new(),
+			attestation_sessions: AttestationSessions::new(),
+			unsafe_attestation_rpc: false,
+			statement_rebroadcast: Some(RebroadcastConfig::default()),
+			gossip: GossipConfig::default(),
+			gossip_format: GossipFormat::new(),
+			buffer_overflow: None,
+			backing_deadline: None,
+			validation_replay_dir: None,
 			inherent_data_providers: InherentDataProviders::new(),
 		}
 	}
 }
 
+// the unsafe RPC methods on attestation state, exchanged SCALE-encoded.
+fn attestation_rpc(sessions: &AttestationSessions) -> polkadot_rpc::ParachainAttestation {
+	use codec::{Decode, Encode};
+	use consensus::AttestationSnapshot;
+
+	let export_sessions = sessions.clone();
+	let import_sessions = sessions.clone();
+	polkadot_rpc::ParachainAttestation::new(
+		move |relay_parent| export_sessions.export(&relay_parent).map(|snapshot| snapshot.encode()),
+		move |encoded: &[u8]| {
+			let snapshot = AttestationSnapshot::decode(&mut &encoded[..])
+				.ok_or_else(|| "Malformed attestation snapshot".to_string())?;
+			import_sessions.import(snapshot).map_err(|e| e.to_string())
+		},
+	)
+}
+
+/// Run a remote signing process for the authority key generated from `seed`, answering
+/// signing requests of validating nodes connecting to `addr`, which must be a loopback
+/// address. It refuses to sign conflicting statements at any of the last `max_parents`
+/// relay chain block numbers, keeping the statements signed in the file at `history`.
+/// Never returns unless accepting connections fails.
+pub fn serve_remote_signer(
+	addr: SocketAddr,
+	seed: &[u8; 32],
+	max_parents: usize,
+	history: ::std::path::PathBuf,
+) -> ::std::io::Result<()> {
+	let mut policy = consensus::SigningPolicy::open(history, max_parents)?;
+	let listener = ::std::net::TcpListener::bind(&addr)?;
+	let key = ed25519::Pair::from_seed(seed);
+
+	info!("Serving signing requests for {} on {}", key.public(), addr);
+	consensus::serve_signing_requests(listener, &key, &mut policy)
+}
+
 /// Chain API type for the transaction pool.
 pub type TxChainApi<Backend, Executor> = transaction_pool::ChainApi<
 	client::Client<Backend, Executor, Block, RuntimeApi>,
@@ -156,7 +293,11 @@ construct_service_factory! {
 	struct Factory {
 		Block = Block,
 		RuntimeApi = RuntimeApi,
-		NetworkProtocol = PolkadotProtocol { |config: &Configuration| Ok(PolkadotProtocol::new(config.custom.collating_for)) },
+		NetworkProtocol = PolkadotProtocol { |config: &Configuration| {
+			Ok(PolkadotProtocol::new(config.custom.collating_for)
+				.with_private_collations(config.custom.private_collations)
+				.with_gossip_format(config.custom.gossip_format.clone()))
+		} },
 		RuntimeDispatch = polkadot_executor::Executor,
 		FullTransactionPoolApi = TxChainApi<FullBackend<Self>, FullExecutor<Self>>
 			{ |config, client| Ok(TransactionPool::new(config, TxChainApi::new(client))) },
@@ -193,7 +334,63 @@ construct_service_factory! {
 					executor.spawn(voter);
 				}
 
-				let extrinsic_store = {
+				if let Some(addr) = service.config.custom.parachain_rpc {
+					use polkadot_rpc::{Parachain, ParachainApi};
+
+					let mut io = jsonrpc_core::IoHandler::new();
+					io.extend_with(Parachain::new(service.client()).to_delegate());
+					if service.config.custom.unsafe_attestation_rpc {
+						use polkadot_rpc::ParachainAttestationApi;
+						io.extend_with(attestation_rpc(&service.config.custom.attestation_sessions).to_delegate());
+					}
+
+					let server = jsonrpc_http_server::ServerBuilder::new(io)
+						.threads(1)
+						.start_http(&addr)
+						.map_err(|e| format!("Unable to start parachain RPC server on {}: {}", addr, e))?;
+
+					info!("Parachain RPC server listening on {}", addr);
+					executor.spawn(service.on_exit().then(move |_| {
+						server.close();
+						Ok(())
+					}));
+				}
+
+				if let Some(addr) = service.config.custom.parachain_ws_rpc {
+					use polkadot_rpc::{Parachain, ParachainApi, ParachainPubSub, ParachainPubSubApi};
+
+					let backed_candidates = service.config.custom.backed_candidates.clone();
+					let mut io = jsonrpc_pubsub::PubSubHandler::default();
+					io.extend_with(Parachain::new(service.client()).to_delegate());
+					io.extend_with(ParachainPubSub::new(
+						executor.clone(),
+						move || backed_candidates.subscribe(),
+					).to_delegate());
+					if service.config.custom.unsafe_attestation_rpc {
+						use polkadot_rpc::ParachainAttestationApi;
+						io.extend_with(attestation_rpc(&service.config.custom.attestation_sessions).to_delegate());
+					}
+
+					let server = jsonrpc_ws_server::ServerBuilder::with_meta_extractor(
+						io,
+						|context: &jsonrpc_ws_server::RequestContext| {
+							Some(Arc::new(jsonrpc_pubsub::Session::new(context.sender())))
+						},
+					)
+						.start(&addr)
+						.map_err(|e| format!("Unable to start parachain websocket RPC server on {}: {}", addr, e))?;
+
+					info!("Parachain websocket RPC server listening on {}", addr);
+					executor.spawn(service.on_exit().then(move |_| {
+						server.close();
+						Ok(())
+					}));
+				}
+
+				let extrinsic_store = if service.config.custom.ephemeral {
+					info!("Keeping the availability store in memory");
+					::av_store::Store::new_in_memory()
+				} else {
 					use std::path::PathBuf;
 
 					let mut path = PathBuf::from(service.config.database_path.clone());
@@ -205,32 +402,108 @@ construct_service_factory! {
 					})?
 				};
 
-				// run authorship only if authority.
-				let key = match key {
-					Some(key) => key,
-					None => return Ok(service),
+				// validate parachains only if authority, or signing through a remote signer.
+				let statement_signer: Arc<::consensus::StatementSigner> = match (service.config.custom.remote_signer, key.clone()) {
+					(Some((addr, session_key)), _) => {
+						info!("Delegating signing of attestation statements for {} to {}", session_key, addr);
+						Arc::new(::consensus::RemoteSigner::new(addr, session_key)?)
+					}
+					(None, Some(key)) => key,
+					(None, None) => return Ok(service),
 				};
 
 				let client = service.client();
 
+				// the encryption key is announced signed by the session key, so is only
+				// used when the session key is held locally.
+				if let Some(ref key) = key {
+					let key = key.clone();
+					let encryption_key = match service.config.custom.collation_encryption_secret {
+						Some(secret) => EncryptionKey::from_secret(secret),
+						None => EncryptionKey::generate(),
+					};
+					service.network().with_spec(move |spec, _| spec.set_encryption_key(&key, encryption_key));
+				}
+
+				// validation sessions and the work within them are cancelled on exit.
+				let cancellation = ::consensus::Cancellation::new();
+				{
+					let cancellation = cancellation.clone();
+					executor.spawn(service.on_exit().then(move |_| {
+						cancellation.cancel();
+						Ok(())
+					}));
+				}
+
 				// collator connections and validation network both fulfilled by this
 				let validation_network = ValidationNetwork::new(
 					service.network(),
-					service.on_exit(),
+					cancellation,
 					service.client(),
 					executor.clone(),
-				);
+				)
+					.with_rebroadcast(service.config.custom.statement_rebroadcast)
+					.with_gossip(service.config.custom.gossip)
+					.with_gossip_format(service.config.custom.gossip_format.clone())
+					.with_overflow(if service.config.custom.ephemeral {
+						None
+					} else {
+						service.config.custom.buffer_overflow.clone()
+					})
+					.with_backing_deadline(service.config.custom.backing_deadline)
+					.with_validation_replay(service.config.custom.validation_replay_dir.clone());
+
+				if let Some(deadline) = service.config.custom.backing_deadline {
+					let validation_network = validation_network.clone();
+					let mut reported = 0;
+					let report = Interval::new(Instant::now() + EXPIRED_BACKING_REPORT_EVERY, EXPIRED_BACKING_REPORT_EVERY)
+						.map_err(|e| warn!("Timer error reporting expired backing: {:?}", e))
+						.for_each(move |_| {
+							let expired = validation_network.expired_backing();
+							if expired > reported {
+								info!(
+									"Abandoned {} candidates not backed within {:?}, {} since start",
+									expired - reported,
+									deadline,
+									expired
+								);
+								reported = expired;
+							}
+							Ok(())
+						})
+						.select(service.on_exit())
+						.then(|_| Ok(()));
+
+					executor.spawn(report);
+				}
+
 				let proposer_factory = ::consensus::ProposerFactory::new(
 					client.clone(),
 					validation_network.clone(),
 					validation_network,
 					service.transaction_pool(),
 					executor.clone(),
-					key.clone(),
+					statement_signer,
 					extrinsic_store,
 					SlotDuration::get_or_compute(&*client)?,
+					service.config.custom.shutdown.clone(),
+					service.config.custom.backed_candidates.clone(),
+					service.config.custom.attestation_sessions.clone(),
 				);
 
+				// run authorship only if authority.
+				let key = match key {
+					Some(key) => key,
+					None => {
+						info!("No local authority key: validating parachains without authoring blocks");
+						executor.spawn(service.on_exit().then(move |_| {
+							drop(proposer_factory);
+							Ok(())
+						}));
+						return Ok(service);
+					}
+				};
+
 				info!("Using authority key {}", key.public());
 				let task = start_aura(
 					SlotDuration::get_or_compute(&*client)?,
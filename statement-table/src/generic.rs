@@ -23,11 +23,20 @@
 //! Each parachain is associated with a committee of authorities, who issue statements
 //! indicating whether the candidate is valid or invalid. Once a threshold of the committee
 //! has signed validity statements, the candidate may be marked includable.
+//!
+//! Only the statement and attestation types are available without `std`. The table
+//! itself is only needed off-chain.
+
+use rstd::prelude::*;
 
+#[cfg(feature = "std")]
 use std::collections::hash_map::{HashMap, Entry};
+#[cfg(feature = "std")]
 use std::hash::Hash;
+#[cfg(feature = "std")]
 use std::fmt::Debug;
 
+#[cfg(feature = "std")]
 /// Context for the statement table.
 pub trait Context {
 	/// A authority ID
@@ -140,9 +149,11 @@ pub enum Misbehavior<C, D, V, S> {
 	DoubleSign(DoubleSign<C, D, S>),
 }
 
+#[cfg(feature = "std")]
 /// Type alias for misbehavior corresponding to context type.
 pub type MisbehaviorFor<C> = Misbehavior<<C as Context>::Candidate, <C as Context>::Digest, <C as Context>::AuthorityId, <C as Context>::Signature>;
 
+#[cfg(feature = "std")]
 // kinds of votes for validity
 #[derive(Clone, PartialEq, Eq)]
 enum ValidityVote<S: Eq + Clone> {
@@ -189,6 +200,7 @@ pub struct AttestedCandidate<Group, Candidate, AuthorityId, Signature> {
 	pub validity_votes: Vec<(AuthorityId, ValidityAttestation<Signature>)>,
 }
 
+#[cfg(feature = "std")]
 /// Stores votes and data about a candidate.
 pub struct CandidateData<C: Context> {
 	group_id: C::GroupId,
@@ -197,6 +209,7 @@ pub struct CandidateData<C: Context> {
 	indicated_bad_by: Vec<C::AuthorityId>,
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> CandidateData<C> {
 	/// whether this has been indicated bad by anyone.
 	pub fn indicated_bad(&self) -> bool {
@@ -257,11 +270,13 @@ impl<C: Context> CandidateData<C> {
 	}
 }
 
+#[cfg(feature = "std")]
 // authority metadata
 struct AuthorityData<C: Context> {
 	proposal: Option<(C::Digest, C::Signature)>,
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> Default for AuthorityData<C> {
 	fn default() -> Self {
 		AuthorityData {
@@ -270,12 +285,14 @@ impl<C: Context> Default for AuthorityData<C> {
 	}
 }
 
+#[cfg(feature = "std")]
 /// Type alias for the result of a statement import.
 pub type ImportResult<C> = Result<
 	Option<Summary<<C as Context>::Digest, <C as Context>::GroupId>>,
 	MisbehaviorFor<C>
 >;
 
+#[cfg(feature = "std")]
 /// Stores votes
 pub struct Table<C: Context> {
 	authority_data: HashMap<C::AuthorityId, AuthorityData<C>>,
@@ -284,6 +301,7 @@ pub struct Table<C: Context> {
 	includable_count: HashMap<C::GroupId, usize>,
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> Default for Table<C> {
 	fn default() -> Self {
 		Table {
@@ -295,6 +313,7 @@ impl<C: Context> Default for Table<C> {
 	}
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> Table<C> {
 	/// Produce a set of proposed candidates.
 	///
@@ -349,6 +368,16 @@ impl<C: Context> Table<C> {
 		})
 	}
 
+	/// Get a full attestation for a candidate, if it can be included.
+	pub fn attested_candidate(&self, digest: &C::Digest, context: &C) -> Option<AttestedCandidate<
+		C::GroupId, C::Candidate, C::AuthorityId, C::Signature,
+	>> {
+		self.candidate_votes.get(digest).and_then(|data| {
+			let v_threshold = context.requisite_votes(&data.group_id);
+			data.attested(v_threshold)
+		})
+	}
+
 	/// Import a signed statement. Signatures should be checked for validity, and the
 	/// sender should be checked to actually be an authority.
 	///
@@ -397,6 +426,40 @@ impl<C: Context> Table<C> {
 		self.candidate_votes.get(digest).map(|d| &d.candidate)
 	}
 
+	/// Get the statements backing all votes counted in the table, with candidate
+	/// statements ordered before any validity statements.
+	///
+	/// Importing these into an empty table with the same context reproduces the votes
+	/// of this one. Statements which were only evidence of misbehavior are not included.
+	pub fn statements(&self) -> Vec<SignedStatement<C::Candidate, C::Digest, C::AuthorityId, C::Signature>> {
+		let mut issued = Vec::new();
+		let mut votes = Vec::new();
+
+		for (digest, data) in &self.candidate_votes {
+			for (sender, vote) in &data.validity_votes {
+				let (statement, signature) = match *vote {
+					ValidityVote::Issued(ref s) => (Statement::Candidate(data.candidate.clone()), s),
+					ValidityVote::Valid(ref s) => (Statement::Valid(digest.clone()), s),
+					ValidityVote::Invalid(ref s) => (Statement::Invalid(digest.clone()), s),
+				};
+
+				let signed = SignedStatement {
+					statement,
+					signature: signature.clone(),
+					sender: sender.clone(),
+				};
+
+				match signed.statement {
+					Statement::Candidate(_) => issued.push(signed),
+					_ => votes.push(signed),
+				}
+			}
+		}
+
+		issued.extend(votes);
+		issued
+	}
+
 	/// Access all witnessed misbehavior.
 	pub fn get_misbehavior(&self)
 		-> &HashMap<C::AuthorityId, MisbehaviorFor<C>>
@@ -579,6 +642,7 @@ impl<C: Context> Table<C> {
 	}
 }
 
+#[cfg(feature = "std")]
 fn update_includable_count<G: Hash + Eq + Clone>(map: &mut HashMap<G, usize>, group_id: &G, was_includable: bool, is_includable: bool) {
 	if was_includable && !is_includable {
 		if let Entry::Occupied(mut entry) = map.entry(group_id.clone()) {
@@ -1001,6 +1065,7 @@ mod tests {
 
 		assert!(!table.detected_misbehavior.contains_key(&AuthorityId(1)));
 		assert!(!table.candidate_includable(&candidate_digest, &context));
+		assert!(table.attested_candidate(&candidate_digest, &context).is_none());
 		assert!(table.includable_count.is_empty());
 
 		let vote = SignedStatement {
@@ -1012,6 +1077,10 @@ mod tests {
 		table.import_statement(&context, vote);
 		assert!(!table.detected_misbehavior.contains_key(&AuthorityId(2)));
 		assert!(table.candidate_includable(&candidate_digest, &context));
+		assert_eq!(
+			table.attested_candidate(&candidate_digest, &context).map(|a| a.validity_votes.len()),
+			Some(2),
+		);
 		assert!(table.includable_count.get(&GroupId(2)).is_some());
 
 		// have the last validity guarantor note invalidity. now it is unincludable.
@@ -1089,4 +1158,49 @@ mod tests {
 		assert_eq!(summary.group_id, GroupId(2));
 		assert_eq!(summary.validity_votes, 2);
 	}
+
+	#[test]
+	fn statements_reproduce_votes() {
+		let context = TestContext {
+			authorities: {
+				let mut map = HashMap::new();
+				map.insert(AuthorityId(1), GroupId(2));
+				map.insert(AuthorityId(2), GroupId(2));
+				map.insert(AuthorityId(3), GroupId(2));
+				map
+			}
+		};
+
+		let mut table = create();
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Valid(Digest(100)),
+			signature: Signature(2),
+			sender: AuthorityId(2),
+		});
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Candidate(Candidate(2, 100)),
+			signature: Signature(1),
+			sender: AuthorityId(1),
+		});
+		table.import_statement(&context, SignedStatement {
+			statement: Statement::Valid(Digest(100)),
+			signature: Signature(3),
+			sender: AuthorityId(3),
+		});
+
+		let statements = table.statements();
+		assert_eq!(statements.len(), 2);
+		assert_eq!(statements[0].statement, Statement::Candidate(Candidate(2, 100)));
+
+		let mut restored = create();
+		for statement in statements {
+			restored.import_statement(&context, statement);
+		}
+
+		assert!(restored.candidate_includable(&Digest(100), &context));
+		assert_eq!(
+			restored.attested_candidate(&Digest(100), &context).map(|a| a.validity_votes.len()),
+			table.attested_candidate(&Digest(100), &context).map(|a| a.validity_votes.len()),
+		);
+	}
 }
@@ -13,9 +13,14 @@
 //! Each parachain is associated with two sets of authorities: those which can
 //! propose and attest to validity of candidates, and those who can only attest
 //! to availability.
+//!
+//! The statement types build without `std`, so that attestations can be checked on-chain.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate parity_codec as codec;
 extern crate substrate_primitives;
+extern crate sr_std as rstd;
 extern crate polkadot_primitives as primitives;
 
 #[macro_use]
@@ -23,26 +28,32 @@ extern crate parity_codec_derive;
 
 pub mod generic;
 
+#[cfg(feature = "std")]
 pub use generic::Table;
 
 use primitives::parachain::{
-	Id, CandidateReceipt, CandidateSignature as Signature, Statement as PrimitiveStatement,
+	Id, CandidateHash, CandidateReceipt, CandidateSignature as Signature,
+	Statement as PrimitiveStatement,
 };
-use primitives::{SessionKey, Hash};
+use primitives::SessionKey;
 
 /// Statements about candidates on the network.
-pub type Statement = generic::Statement<CandidateReceipt, Hash>;
+pub type Statement = generic::Statement<CandidateReceipt, CandidateHash>;
 
 /// Signed statements about candidates.
-pub type SignedStatement = generic::SignedStatement<CandidateReceipt, Hash, SessionKey, Signature>;
+pub type SignedStatement = generic::SignedStatement<CandidateReceipt, CandidateHash, SessionKey, Signature>;
 
 /// Kinds of misbehavior, along with proof.
-pub type Misbehavior = generic::Misbehavior<CandidateReceipt, Hash, SessionKey, Signature>;
+pub type Misbehavior = generic::Misbehavior<CandidateReceipt, CandidateHash, SessionKey, Signature>;
+
+/// A candidate along with the validity votes attesting to it.
+pub type AttestedCandidate = generic::AttestedCandidate<Id, CandidateReceipt, SessionKey, Signature>;
 
 /// A summary of import of a statement.
-pub type Summary = generic::Summary<Hash, Id>;
+pub type Summary = generic::Summary<CandidateHash, Id>;
 
 /// Context necessary to construct a table.
+#[cfg(feature = "std")]
 pub trait Context {
 	/// Whether a authority is a member of a group.
 	/// Members are meant to submit candidates and vote on validity.
@@ -52,14 +63,15 @@ pub trait Context {
 	fn requisite_votes(&self, group: &Id) -> usize;
 }
 
+#[cfg(feature = "std")]
 impl<C: Context> generic::Context for C {
 	type AuthorityId = SessionKey;
-	type Digest = Hash;
+	type Digest = CandidateHash;
 	type GroupId = Id;
 	type Signature = Signature;
 	type Candidate = CandidateReceipt;
 
-	fn candidate_digest(candidate: &CandidateReceipt) -> Hash {
+	fn candidate_digest(candidate: &CandidateReceipt) -> CandidateHash {
 		candidate.hash()
 	}
 
@@ -17,24 +17,18 @@
 //! Collator for polkadot
 
 extern crate adder;
-extern crate substrate_primitives;
 extern crate polkadot_parachain as parachain;
 extern crate polkadot_primitives as primitives;
-extern crate polkadot_collator as collator;
+extern crate polkadot_collator_template as collator_template;
 extern crate parking_lot;
-extern crate ctrlc;
-extern crate futures;
-extern crate exit_future;
 
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use adder::{HeadData as AdderHead, BlockData as AdderBody};
-use substrate_primitives::ed25519::Pair;
 use parachain::codec::{Encode, Decode};
-use primitives::parachain::{HeadData, BlockData, Id as ParaId, Message};
-use collator::{InvalidHead, ParachainContext, VersionInfo};
+use primitives::parachain::{HeadData, Id as ParaId};
+use collator_template::{BlockData, CollatorTemplate, InvalidHead, Message, ParachainContext, VersionInfo};
 use parking_lot::Mutex;
 
 const GENESIS: AdderHead = AdderHead {
@@ -98,43 +92,14 @@ impl ParachainContext for AdderContext {
 }
 
 fn main() {
-	let key = Arc::new(Pair::from_seed(&[1; 32]));
 	let id: ParaId = 100.into();
 
-	println!("Starting adder collator with genesis: ");
-
-	{
-		let encoded = GENESIS.encode();
-		println!("Dec: {:?}", encoded);
-		print!("Hex: 0x");
-		for byte in encoded {
-			print!("{:02x}", byte);
-		}
-
-		println!();
-	}
-
-	// can't use signal directly here because CtrlC takes only `Fn`.
-	let (exit_send, exit) = exit_future::signal();
-
-	let exit_send_cell = RefCell::new(Some(exit_send));
-	ctrlc::set_handler(move || {
-		if let Some(exit_send) = exit_send_cell.try_borrow_mut().expect("signal handler not reentrant; qed").take() {
-			exit_send.fire();
-		}
-	}).expect("Errror setting up ctrl-c handler");
-
 	let context = AdderContext {
 		db: Arc::new(Mutex::new(HashMap::new())),
 	};
 
-	let res = ::collator::run_collator(
-		context,
-		id,
-		exit,
-		key,
-		::std::env::args(),
-		VersionInfo {
+	let res = CollatorTemplate::new(context, id, "adder-collator")
+		.version(VersionInfo {
 			name: "<unkown>",
 			version: "<unknown>",
 			commit: "<unknown>",
@@ -142,8 +107,10 @@ fn main() {
 			description: "collator for adder parachain",
 			author: "parity technologies",
 			support_url: "https://github.com/paritytech/polkadot/issues/new",
-		}
-	);
+		})
+		.key_seed([1; 32])
+		.genesis_head(HeadData(GENESIS.encode()))
+		.run();
 
 	if let Err(e) = res {
 		println!("{}", e);
@@ -30,8 +30,8 @@ use std::sync::Arc;
 use client::{error::Result as ClientResult, BlockchainEvents, ChainHead, BlockBody};
 use client::block_builder::api::BlockBuilder;
 use client::blockchain::HeaderBackend;
-use client::runtime_api::Core;
-use primitives::ed25519;
+use client::runtime_api::{ApiExt, Core};
+use codec::Encode;
 use futures::prelude::*;
 use polkadot_primitives::{Block, BlockId};
 use polkadot_primitives::parachain::{CandidateReceipt, ParachainHost};
@@ -42,13 +42,13 @@ use tokio::runtime::TaskExecutor;
 use tokio::runtime::current_thread::Runtime as LocalRuntime;
 use tokio::timer::Interval;
 
-use super::{Network, Collators, TableRouter};
+use super::{Network, Collators, TableRouter, StatementSigner};
 
 /// Gets a list of the candidates in a block.
 pub(crate) fn fetch_candidates<P: BlockBody<Block>>(client: &P, block: &BlockId)
 	-> ClientResult<Option<impl Iterator<Item=CandidateReceipt>>>
 {
-	use codec::{Encode, Decode};
+	use codec::Decode;
 	use polkadot_runtime::{Call, ParachainsCall, UncheckedExtrinsic as RuntimeExtrinsic};
 
 	let extrinsics = client.block_body(block)?;
@@ -106,15 +106,15 @@ pub(crate) fn start<C, N, P>(
 	client: Arc<P>,
 	parachain_validation: Arc<::ParachainValidation<C, N, P>>,
 	thread_pool: TaskExecutor,
-	key: Arc<ed25519::Pair>,
+	key: Arc<StatementSigner>,
 	extrinsic_store: ExtrinsicStore,
 ) -> ServiceHandle
 	where
 		C: Collators + Send + Sync + 'static,
 		<C::Collation as IntoFuture>::Future: Send + 'static,
-		P: BlockchainEvents<Block> + ChainHead<Block> + BlockBody<Block>,
+		P: BlockchainEvents<Block> + ChainHead<Block> + BlockBody<Block> + ::RuntimeCodeHash,
 		P: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync + 'static,
-		P::Api: ParachainHost<Block> + Core<Block> + BlockBuilder<Block>,
+		P::Api: ParachainHost<Block> + Core<Block> + BlockBuilder<Block> + ApiExt<Block>,
 		N: Network + Send + Sync + 'static,
 		N::TableRouter: Send + 'static,
 		<<N::TableRouter as TableRouter>::FetchIncoming as IntoFuture>::Future: Send + 'static,
@@ -134,6 +134,24 @@ pub(crate) fn start<C, N, P>(
 				.for_each(move |notification| {
 					let parent_hash = notification.hash;
 					if notification.is_new_best {
+						// sessions can't be trusted to keep working across a runtime upgrade,
+						// so they are restarted before instantiating on the new block.
+						match client.runtime_code_hash(&BlockId::hash(parent_hash)) {
+							Ok(Some(code_hash)) => {
+								let restarted = validation.note_runtime_code(code_hash);
+								if restarted > 0 {
+									let version = client.runtime_api().version(&BlockId::hash(parent_hash))
+										.map(|version| format!("{} version {}", version.spec_name, version.spec_version))
+										.unwrap_or_else(|_| "an unknown version".into());
+									info!(target: "validation", "Relay chain runtime upgraded to {} at {:?}. \
+										Restarting {} parachain validation sessions, {} since start.",
+										version, parent_hash, restarted, validation.upgrade_restarts());
+								}
+							}
+							Ok(None) => warn!("No runtime code at {:?}", parent_hash),
+							Err(e) => warn!("Unable to fetch runtime code at {:?}: {:?}", parent_hash, e),
+						}
+
 						let res = client
 							.runtime_api()
 							.authorities(&BlockId::hash(parent_hash))
@@ -141,6 +159,7 @@ pub(crate) fn start<C, N, P>(
 							.and_then(|authorities| {
 								validation.get_or_instantiate(
 									parent_hash,
+									notification.header.number,
 									notification.header.parent_hash().clone(),
 									&authorities,
 									key.clone(),
@@ -151,6 +170,19 @@ pub(crate) fn start<C, N, P>(
 							warn!("Unable to start parachain validation on top of {:?}: {}",
 								parent_hash, e);
 						}
+
+						// candidates included in this block need no further work in any
+						// live session. this happens after instantiation, since the new
+						// session draws their outgoing messages from the parent session.
+						match fetch_candidates(&*client, &BlockId::hash(parent_hash)) {
+							Ok(Some(candidates)) => {
+								let included: Vec<_> = candidates.map(|c| c.hash()).collect();
+								validation.note_included(&included);
+							}
+							Ok(None) => {}
+							Err(e) => warn!("Failed to fetch block body for imported block {:?}: {:?}",
+								parent_hash, e),
+						}
 					}
 					Ok(())
 				})
@@ -0,0 +1,130 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Notifications of candidates backed locally.
+//!
+//! A candidate is backed once the local table holds enough validity votes for it
+//! to be included. Bridges, indexers and parachain nodes can subscribe to learn of
+//! backed candidates before they are included on-chain.
+//!
+//! Each subscriber has a bounded buffer. Subscribers which fall behind by a full
+//! buffer are dropped, so a slow consumer can't hold candidates in memory.
+
+use std::mem;
+use std::sync::Arc;
+
+use futures::sync::mpsc;
+use parking_lot::Mutex;
+use polkadot_primitives::parachain::BackedCandidate;
+
+/// Number of backed candidates buffered for a subscriber before it is dropped.
+pub const BACKED_CANDIDATES_BUFFER: usize = 256;
+
+/// Broadcasts backed candidates to any number of subscribers. Clones refer to the
+/// same set of subscribers.
+#[derive(Clone, Default)]
+pub struct BackedCandidates {
+	subscribers: Arc<Mutex<Vec<mpsc::Sender<BackedCandidate>>>>,
+}
+
+impl BackedCandidates {
+	/// Create a new broadcast with no subscribers.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Subscribe to all candidates backed from now on.
+	pub fn subscribe(&self) -> mpsc::Receiver<BackedCandidate> {
+		let (tx, rx) = mpsc::channel(BACKED_CANDIDATES_BUFFER);
+		self.subscribers.lock().push(tx);
+		rx
+	}
+
+	/// Send a backed candidate to all subscribers, dropping those which have gone away
+	/// or whose buffer is full.
+	pub fn notify(&self, candidate: BackedCandidate) {
+		let mut subscribers = self.subscribers.lock();
+		let live = mem::replace(&mut *subscribers, Vec::new()).into_iter()
+			.filter_map(|mut subscriber| match subscriber.try_send(candidate.clone()) {
+				Ok(()) => Some(subscriber),
+				Err(ref e) if e.is_full() => {
+					debug!(target: "validation", "Dropping subscriber lagging on backed candidates");
+					None
+				}
+				Err(_) => None,
+			})
+			.collect();
+
+		*subscribers = live;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::Stream;
+	use polkadot_primitives::parachain::{AttestedCandidate, CandidateReceipt, HeadData};
+
+	fn backed_candidate() -> BackedCandidate {
+		BackedCandidate {
+			relay_parent: [1; 32].into(),
+			candidate: AttestedCandidate {
+				candidate: CandidateReceipt {
+					parachain_index: 5.into(),
+					collator: [1; 32].into(),
+					signature: Default::default(),
+					head_data: HeadData(vec![1, 2, 3]),
+					balance_uploads: Vec::new(),
+					egress_queue_roots: Vec::new(),
+					fees: 0,
+					block_data_hash: [2; 32].into(),
+					ingress_watermark: 0,
+				},
+				validity_votes: Vec::new(),
+			},
+		}
+	}
+
+	#[test]
+	fn notifies_live_subscribers() {
+		let backed = BackedCandidates::new();
+		let dropped = backed.subscribe();
+		let live = backed.subscribe();
+		drop(dropped);
+
+		let candidate = backed_candidate();
+		backed.notify(candidate.clone());
+		assert_eq!(backed.subscribers.lock().len(), 1);
+
+		drop(backed);
+		assert_eq!(live.wait().map(|c| c.unwrap()).collect::<Vec<_>>(), vec![candidate]);
+	}
+
+	#[test]
+	fn drops_lagging_subscribers() {
+		let backed = BackedCandidates::new();
+		let _lagging = backed.subscribe();
+
+		// the channel holds one more message than its buffer for each sender.
+		for _ in 0..BACKED_CANDIDATES_BUFFER + 1 {
+			backed.notify(backed_candidate());
+		}
+		assert_eq!(backed.subscribers.lock().len(), 1);
+
+		backed.notify(backed_candidate());
+		assert!(backed.subscribers.lock().is_empty());
+	}
+}
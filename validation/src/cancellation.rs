@@ -0,0 +1,166 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hierarchical cancellation of background work.
+//!
+//! A `Cancellation` marks a scope which background work runs in. Scopes form a tree:
+//! cancelling a scope cancels every scope created from it with `child`, but not its
+//! parent. The service holds the root scope, with validation sessions as its children
+//! and the work on single candidates as children of the sessions.
+
+use std::mem;
+use std::sync::{Arc, Weak};
+
+use futures::prelude::*;
+use futures::task::{self, Task};
+use parking_lot::Mutex;
+
+#[derive(Default)]
+struct Scope {
+	cancelled: bool,
+	waiting: Vec<Task>,
+	children: Vec<Weak<Mutex<Scope>>>,
+}
+
+// cancel a scope and all of its live children.
+fn cancel_scope(scope: &Mutex<Scope>) {
+	let (waiting, children) = {
+		let mut scope = scope.lock();
+		if scope.cancelled { return }
+
+		scope.cancelled = true;
+		(
+			mem::replace(&mut scope.waiting, Vec::new()),
+			mem::replace(&mut scope.children, Vec::new()),
+		)
+	};
+
+	for task in waiting {
+		task.notify();
+	}
+
+	for child in children.into_iter().filter_map(|child| child.upgrade()) {
+		cancel_scope(&child);
+	}
+}
+
+/// A handle to a cancellable scope. Clones refer to the same scope.
+#[derive(Clone, Default)]
+pub struct Cancellation {
+	scope: Arc<Mutex<Scope>>,
+}
+
+impl Cancellation {
+	/// Create a new root scope, only cancelled explicitly.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Create a child scope, which is cancelled along with this scope.
+	/// The child of a cancelled scope starts out cancelled.
+	pub fn child(&self) -> Cancellation {
+		let child = Cancellation::new();
+
+		let mut scope = self.scope.lock();
+		if scope.cancelled {
+			child.scope.lock().cancelled = true;
+		} else {
+			scope.children.retain(|child| child.upgrade().is_some());
+			scope.children.push(Arc::downgrade(&child.scope));
+		}
+
+		child
+	}
+
+	/// Cancel this scope and all of its children.
+	pub fn cancel(&self) {
+		cancel_scope(&self.scope)
+	}
+
+	/// Whether this scope has been cancelled.
+	pub fn is_cancelled(&self) -> bool {
+		self.scope.lock().cancelled
+	}
+
+	/// A future which resolves once this scope is cancelled.
+	pub fn cancelled(&self) -> Cancelled {
+		Cancelled { scope: self.scope.clone() }
+	}
+
+	/// Run a future until it completes or this scope is cancelled, whichever comes first.
+	pub fn until_cancelled<F: Future>(&self, future: F) -> impl Future<Item=(),Error=()> {
+		future.select2(self.cancelled()).then(|_| Ok(()))
+	}
+}
+
+/// A future which resolves once a scope is cancelled.
+#[must_use = "futures do nothing unless polled"]
+pub struct Cancelled {
+	scope: Arc<Mutex<Scope>>,
+}
+
+impl Future for Cancelled {
+	type Item = ();
+	type Error = ();
+
+	fn poll(&mut self) -> Poll<(), ()> {
+		let mut scope = self.scope.lock();
+		if scope.cancelled {
+			return Ok(Async::Ready(()));
+		}
+
+		if !scope.waiting.iter().any(|task| task.will_notify_current()) {
+			scope.waiting.push(task::current());
+		}
+
+		Ok(Async::NotReady)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::future;
+
+	#[test]
+	fn cancels_children_but_not_parents() {
+		let service = Cancellation::new();
+		let session = service.child();
+		let candidate_a = session.child();
+		let candidate_b = session.child();
+
+		candidate_a.cancel();
+		assert!(candidate_a.is_cancelled());
+		assert!(!candidate_b.is_cancelled());
+		assert!(!session.is_cancelled());
+
+		service.cancel();
+		assert!(session.is_cancelled());
+		assert!(candidate_b.is_cancelled());
+		assert!(session.child().is_cancelled());
+	}
+
+	#[test]
+	fn cancelled_work_stops() {
+		let session = Cancellation::new();
+		let candidate = session.child();
+
+		let work = candidate.until_cancelled(future::empty::<(), ()>());
+		session.cancel();
+		assert_eq!(work.wait(), Ok(()));
+		assert_eq!(candidate.cancelled().wait(), Ok(()));
+	}
+}
@@ -19,14 +19,18 @@
 //! This module contains type definitions, a trait for a batch of collators, and a trait for
 //! attempting to fetch a collation repeatedly until a valid one is obtained.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use polkadot_primitives::{Block, Hash, AccountId, BlockId};
+use polkadot_primitives::{Block, BlockNumber, Hash, AccountId, BlockId};
 use polkadot_primitives::parachain::{Id as ParaId, Collation, Extrinsic, OutgoingMessage};
 use polkadot_primitives::parachain::{CandidateReceipt, ParachainHost};
+use polkadot_primitives::message_roots::message_queue_root;
+use polkadot_primitives::acceptance;
 use runtime_primitives::traits::ProvideRuntimeApi;
 use parachain::{wasm_executor::{self, ExternalitiesError}, MessageRef};
 use super::Incoming;
+use replay::{ReplayBundle, ReplayRecorder};
 
 use futures::prelude::*;
 
@@ -48,7 +52,7 @@ pub trait Collators: Clone {
 	/// will be passed to the `TableRouter` instance.
 	fn collate(&self, parachain: ParaId, relay_parent: Hash) -> Self::Collation;
 
-	/// Note a bad collator. TODO: take proof
+	/// Note a collator whose collation failed validation. TODO: take proof
 	fn note_bad_collator(&self, collator: AccountId);
 }
 
@@ -119,11 +123,15 @@ impl<C: Collators, P: ProvideRuntimeApi> Future for CollationFetch<C, P>
 					return Ok(Async::Ready((x, e)))
 				}
 				Err(e) => {
-					debug!("Failed to validate parachain due to API error: {}", e);
+					debug!("Failed to validate parachain collation: {}", e);
 
-					// just continue if we got a bad collation or failed to validate
+					// just continue if we got a bad collation or failed to validate.
+					// local API errors aren't the collator's fault.
 					self.live_fetch = None;
-					self.collators.note_bad_collator(x.receipt.collator)
+					match *e.kind() {
+						ErrorKind::Client(_) => {}
+						_ => self.collators.note_bad_collator(x.receipt.collator),
+					}
 				}
 			}
 		}
@@ -155,43 +163,62 @@ error_chain! {
 			description("Missing or extra egress route."),
 			display("Missing or extra egress route. (expected: {:?}, got {:?})", expected, got),
 		}
+		CollatorNotPermitted(id: ParaId, collator: AccountId) {
+			description("Collated by a collator not permitted for the parachain."),
+			display("Collator {:?} is not permitted to collate for parachain {:?}", collator, id),
+		}
 		WrongHeadData(expected: Vec<u8>, got: Vec<u8>) {
 			description("Parachain validation produced wrong head data."),
 			display("Parachain validation produced wrong head data (expected: {:?}, got {:?}", expected, got),
 		}
+		Unacceptable(reason: &'static str) {
+			description("Candidate would be rejected by the runtime."),
+			display("Candidate would be rejected by the runtime: {}", reason),
+		}
+		WrongIngressWatermark(expected: BlockNumber, got: BlockNumber) {
+			description("Candidate acknowledged the wrong ingress watermark."),
+			display("Candidate acknowledged the wrong ingress watermark (expected: {}, got {})", expected, got),
+		}
 	}
 }
 
-/// Compute a trie root for a set of messages.
-pub fn message_queue_root<A, I: IntoIterator<Item=A>>(messages: I) -> Hash
-	where A: AsRef<[u8]>
-{
-	::trie::ordered_trie_root::<primitives::Blake2Hasher, _, _>(messages)
-}
-
-/// Compute the set of egress roots for all given outgoing messages.
-pub fn egress_roots(mut outgoing: Vec<OutgoingMessage>) -> Vec<(ParaId, Hash)> {
-	// stable sort messages by parachain ID.
-	outgoing.sort_by_key(|msg| ParaId::from(msg.target));
-
-	let mut egress_roots = Vec::new();
-	{
-		let mut messages_iter = outgoing.iter().peekable();
-		while let Some(batch_target) = messages_iter.peek().map(|o| o.target) {
- 			// we borrow the iterator mutably to ensure it advances so the
-			// next iteration of the loop starts with `messages_iter` pointing to
-			// the next batch.
-			let messages_to = messages_iter
-				.clone()
-				.take_while(|o| o.target == batch_target)
-				.map(|o| { let _ = messages_iter.next(); &o.data[..] });
-
-			let computed_root = message_queue_root(messages_to);
-			egress_roots.push((batch_target, computed_root));
-		}
+/// Put incoming messages into the canonical order in which they are fed to validation:
+/// ascending by source parachain ID, with the messages from each source kept in
+/// queue order.
+///
+/// Returns `None` if any source parachain appears more than once, as there is
+/// no canonical way to merge its queues.
+pub fn canonical_ingress(mut incoming: Incoming) -> Option<Incoming> {
+	// stable sort leaves each queue intact.
+	incoming.sort_by_key(|&(source, _)| source);
+
+	if incoming.windows(2).any(|w| w[0].0 == w[1].0) {
+		None
+	} else {
+		Some(incoming)
 	}
+}
 
-	egress_roots
+/// Compute the ingress watermark acknowledged by a candidate which processed the given
+/// ingress at the relay parent. Collators include this in the receipt.
+pub fn ingress_watermark<P>(
+	client: &P,
+	relay_parent: &BlockId,
+	para_id: ParaId,
+	incoming: &Incoming,
+) -> Result<BlockNumber, Error> where
+	P: ProvideRuntimeApi,
+	P::Api: ParachainHost<Block>,
+{
+	let api = client.runtime_api();
+	let current = api.ingress_watermark(relay_parent, para_id)?;
+	let posted: HashMap<ParaId, BlockNumber> = api.ingress_posted(relay_parent, para_id)?
+		.ok_or_else(|| ErrorKind::InactiveParachain(para_id))?
+		.into_iter()
+		.collect();
+
+	let processed = incoming.iter().filter_map(|&(source, _)| posted.get(&source).cloned());
+	Ok(acceptance::ingress_watermark(current, processed))
 }
 
 fn check_extrinsic(
@@ -251,6 +278,7 @@ impl wasm_executor::Externalities for Externalities {
 		// TODO: https://github.com/paritytech/polkadot/issues/92
 		// check per-message and per-byte fees for the parachain.
 		let target: ParaId = message.target.into();
+		trace!(target: "validation", "Candidate posted message of {} bytes to {:?}", message.data.len(), target);
 		if target == self.parachain_index {
 			return Err(ExternalitiesError::CannotPostMessage("posted message to self"));
 		}
@@ -270,6 +298,8 @@ impl Externalities {
 		self,
 		candidate: &CandidateReceipt,
 	) -> Result<Extrinsic, Error> {
+		acceptance::check_outgoing_messages(&self.outgoing).map_err(ErrorKind::Unacceptable)?;
+
 		check_extrinsic(
 			self.outgoing,
 			&candidate.egress_queue_roots[..],
@@ -291,8 +321,21 @@ pub fn validate_collation<P>(
 	P: ProvideRuntimeApi,
 	P::Api: ParachainHost<Block>,
 {
-	use parachain::{IncomingMessage, ValidationParams};
+	validate_collation_recording(client, relay_parent, collation, incoming, None)
+}
 
+/// Check whether a given collation is valid, like `validate_collation`. Failures to
+/// execute the candidate are recorded for replay, if a recorder is given.
+pub fn validate_collation_recording<P>(
+	client: &P,
+	relay_parent: &BlockId,
+	collation: &Collation,
+	incoming: &Incoming,
+	recorder: Option<&ReplayRecorder>,
+) -> Result<Extrinsic, Error> where
+	P: ProvideRuntimeApi,
+	P::Api: ParachainHost<Block>,
+{
 	let api = client.runtime_api();
 	let para_id = collation.receipt.parachain_index;
 	let validation_code = api.parachain_code(relay_parent, para_id)?
@@ -301,8 +344,47 @@ pub fn validate_collation<P>(
 	let chain_head = api.parachain_head(relay_parent, para_id)?
 		.ok_or_else(|| ErrorKind::InactiveParachain(para_id))?;
 
+	if let Some(permitted) = api.permitted_collators(relay_parent, para_id)? {
+		let collator = collation.receipt.collator;
+		if !permitted.contains(&collator) {
+			return Err(ErrorKind::CollatorNotPermitted(para_id, collator).into());
+		}
+	}
+
+	// don't spend time executing candidates which could never be included.
+	let active_parachains = api.active_parachains(relay_parent)?;
+	acceptance::check_candidate(&collation.receipt, &active_parachains)
+		.map_err(|e| ErrorKind::Unacceptable(e.into()))?;
+
+	let watermark = ingress_watermark(client, relay_parent, para_id, incoming)?;
+	if collation.receipt.ingress_watermark != watermark {
+		return Err(ErrorKind::WrongIngressWatermark(watermark, collation.receipt.ingress_watermark).into());
+	}
+
+	let res = execute_collation(&validation_code, chain_head.clone(), collation, incoming);
+	if let (&Err(ref e), Some(recorder)) = (&res, recorder) {
+		let bundle = ReplayBundle::new(validation_code, chain_head, collation.clone(), incoming.clone(), e);
+		if !recorder.record(bundle) {
+			debug!(target: "validation", "Not recording failed candidate of parachain {:?}: queue full", para_id);
+		}
+	}
+
+	res
+}
+
+/// Execute a collation with the given validation code on the given parent head and
+/// check its outcome against the receipt. This is the part of validation which depends
+/// on nothing but its inputs, so it can be replayed.
+pub fn execute_collation(
+	validation_code: &[u8],
+	parent_head: Vec<u8>,
+	collation: &Collation,
+	incoming: &Incoming,
+) -> Result<Extrinsic, Error> {
+	use parachain::{IncomingMessage, ValidationParams};
+
 	let params = ValidationParams {
-		parent_head: chain_head,
+		parent_head,
 		block_data: collation.block_data.0.clone(),
 		ingress: incoming.iter()
 			.flat_map(|&(source, ref messages)| {
@@ -319,7 +401,7 @@ pub fn validate_collation<P>(
 		outgoing: Vec::new(),
 	};
 
-	match wasm_executor::validate_candidate(&validation_code, params, &mut ext) {
+	match wasm_executor::validate_candidate(validation_code, params, &mut ext) {
 		Ok(result) => {
 			if result.head_data == collation.receipt.head_data.0 {
 				ext.final_checks(&collation.receipt)
@@ -338,6 +420,31 @@ pub fn validate_collation<P>(
 mod tests {
 	use super::*;
 	use parachain::wasm_executor::Externalities as ExternalitiesTrait;
+	use polkadot_primitives::parachain::Message;
+	use polkadot_primitives::message_roots::egress_roots;
+
+	#[test]
+	fn canonical_ingress_orders_by_source() {
+		let incoming = vec![
+			(3.into(), vec![Message(vec![3]), Message(vec![1])]),
+			(1.into(), vec![Message(vec![2])]),
+			(2.into(), Vec::new()),
+		];
+
+		assert_eq!(canonical_ingress(incoming), Some(vec![
+			(1.into(), vec![Message(vec![2])]),
+			(2.into(), Vec::new()),
+			(3.into(), vec![Message(vec![3]), Message(vec![1])]),
+		]));
+
+		let duplicated = vec![
+			(1.into(), vec![Message(vec![1])]),
+			(2.into(), Vec::new()),
+			(1.into(), vec![Message(vec![2])]),
+		];
+
+		assert!(canonical_ingress(duplicated).is_none());
+	}
 
 	#[test]
 	fn compute_and_check_egress() {
@@ -393,4 +500,31 @@ mod tests {
 		assert!(ext.post_message(MessageRef { target: 1.into(), data: &[] }).is_ok());
 		assert!(ext.post_message(MessageRef { target: 5.into(), data: &[] }).is_err());
 	}
+
+	#[test]
+	fn final_checks_apply_message_limits() {
+		let data = vec![0; acceptance::MAX_MESSAGE_SIZE + 1];
+		let candidate = CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: Default::default(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(Vec::new()),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: vec![(1.into(), message_queue_root(&[&data]))],
+			fees: 0,
+			block_data_hash: Default::default(),
+			ingress_watermark: 0,
+		};
+
+		let mut ext = Externalities {
+			parachain_index: 5.into(),
+			outgoing: Vec::new(),
+		};
+		assert!(ext.post_message(MessageRef { target: 1.into(), data: &data }).is_ok());
+
+		match ext.final_checks(&candidate) {
+			Err(Error(ErrorKind::Unacceptable(_), _)) => {}
+			_ => panic!("oversized message accepted"),
+		}
+	}
 }
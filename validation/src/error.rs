@@ -30,6 +30,14 @@ error_chain! {
 			description("Duty Roster had invalid length"),
 			display("Invalid duty roster length: expected {}, got {}", expected, got),
 		}
+		InvalidScheduledValidator(index: u32, validators: usize) {
+			description("Scheduled core assignment refers to an unknown validator"),
+			display("Scheduled validator index {} out of bounds for {} validators", index, validators),
+		}
+		OutdatedRuntimeApi(required: u32) {
+			description("Runtime lacks the parachain host API needed for validation"),
+			display("Runtime doesn't provide version {} of the parachain host API", required),
+		}
 		NotValidator(id: AuthorityId) {
 			description("Local account ID not a validator at this block."),
 			display("Local account ID ({:?}) not a validator at this block.", id),
@@ -38,6 +46,34 @@ error_chain! {
 			description("Unexpected error while checking inherents"),
 			display("Unexpected error while checking inherents: {}", reason),
 		}
+		RemoteSigner(e: ::std::io::Error) {
+			description("Failed to communicate with remote signer"),
+			display("Failed to communicate with remote signer: {}", e),
+		}
+		SigningRefused {
+			description("Remote signer refused to sign statement"),
+			display("Remote signer refused to sign statement"),
+		}
+		BadRemoteSignature(id: AuthorityId) {
+			description("Remote signer produced an invalid signature"),
+			display("Remote signer produced a signature invalid for {:?}", id),
+		}
+		SnapshotRelayParent(expected: ::polkadot_primitives::Hash, got: ::polkadot_primitives::Hash) {
+			description("Attestation snapshot taken at a different relay parent"),
+			display("Attestation snapshot is for relay parent {}, expected {}", got, expected),
+		}
+		SnapshotBadSignature(sender: ::polkadot_primitives::SessionKey) {
+			description("Attestation snapshot contains a statement with an invalid signature"),
+			display("Attestation snapshot contains a statement from {:?} with an invalid signature", sender),
+		}
+		TooManyPendingSnapshots(held: usize) {
+			description("Too many attestation snapshots held for sessions not yet started"),
+			display("Already holding {} attestation snapshots for sessions not yet started", held),
+		}
+		ShuttingDown {
+			description("Node is shutting down"),
+			display("Not starting parachain validation: node is shutting down"),
+		}
 		PrematureDestruction {
 			description("Proposer destroyed before finishing proposing or evaluating"),
 			display("Proposer destroyed before finishing proposing or evaluating"),
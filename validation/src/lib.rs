@@ -40,7 +40,6 @@ extern crate parity_codec as codec;
 extern crate substrate_primitives as primitives;
 extern crate sr_primitives as runtime_primitives;
 extern crate substrate_client as client;
-extern crate substrate_trie as trie;
 
 extern crate exit_future;
 extern crate tokio;
@@ -60,27 +59,31 @@ extern crate futures;
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+extern crate parity_codec_derive;
+
 #[cfg(test)]
 extern crate substrate_keyring;
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{self, Duration, Instant};
 
 use client::{BlockchainEvents, ChainHead, BlockBody};
 use client::blockchain::HeaderBackend;
 use client::block_builder::api::BlockBuilder as BlockBuilderApi;
-use client::runtime_api::Core;
-use codec::Encode;
+use client::runtime_api::{ApiExt, Core};
 use extrinsic_store::Store as ExtrinsicStore;
 use parking_lot::Mutex;
 use polkadot_primitives::{Hash, Block, BlockId, BlockNumber, Header, SessionKey};
 use polkadot_primitives::parachain::{
-	Id as ParaId, Chain, DutyRoster, BlockData, Extrinsic as ParachainExtrinsic, CandidateReceipt,
+	Id as ParaId, Chain, DutyRoster, BlockData, Extrinsic as ParachainExtrinsic, CandidateHash, CandidateReceipt,
 	CandidateSignature, ParachainHost, AttestedCandidate, Statement as PrimitiveStatement, Message,
-	OutgoingMessage,
+	OutgoingMessage, CoreAssignment,
 };
-use primitives::{Ed25519AuthorityId as AuthorityId, ed25519};
+use primitives::{Ed25519AuthorityId as AuthorityId, Blake2Hasher, blake2_256, ed25519};
+use primitives::storage::{StorageKey, well_known_keys};
 use runtime_primitives::{traits::{ProvideRuntimeApi, Header as HeaderT}, ApplyError};
 use tokio::runtime::TaskExecutor;
 use tokio::timer::{Delay, Interval};
@@ -95,24 +98,45 @@ use inherents::InherentData;
 use runtime_aura::timestamp::TimestampInherentData;
 use aura::SlotDuration;
 
-pub use self::collation::{validate_collation, message_queue_root, egress_roots, Collators};
+pub use self::backed::BackedCandidates;
+pub use self::cancellation::{Cancellation, Cancelled};
+pub use self::collation::{validate_collation, canonical_ingress, ingress_watermark, Collators};
+pub use polkadot_primitives::message_roots::{message_queue_root, egress_roots, ingress_roots};
 pub use self::error::{ErrorKind, Error};
+pub use self::selection::{InclusionBudget, select_candidates};
+pub use self::signer::{
+	StatementSigner, RemoteSigner, SigningRequest, SigningPolicy, serve_signing_requests,
+};
+pub use self::shutdown::{Shutdown, InFlight};
 pub use self::shared_table::{
 	SharedTable, ParachainWork, PrimedParachainWork, Validated, Statement, SignedStatement,
-	GenericStatement,
+	GenericStatement, AttestationSnapshot,
 };
+pub use self::snapshot::AttestationSessions;
+pub use self::replay::{ReplayBundle, ReplayRecorder, load_replay};
 
 mod attestation_service;
+mod backed;
+mod cancellation;
 mod dynamic_inclusion;
 mod evaluation;
 mod error;
+mod replay;
+mod selection;
 mod shared_table;
+mod shutdown;
+mod signer;
+mod snapshot;
 
 pub mod collation;
 
 // block size limit.
 const MAX_TRANSACTIONS_SIZE: usize = 4 * 1024 * 1024;
 
+// the version of the parachain host API providing the scheduling, weights and
+// acceptance checks validation relies on.
+const PARACHAIN_HOST_API_VERSION: u32 = 2;
+
 /// Incoming messages; a series of sorted (ParaId, Message) pairs.
 pub type Incoming = Vec<(ParaId, Vec<Message>)>;
 
@@ -162,6 +186,20 @@ pub trait TableRouter: Clone {
 	/// and the `message_queue_root` function can be used to check that messages actually have
 	/// expected root.
 	fn fetch_incoming(&self, id: ParaId) -> Self::FetchIncoming;
+
+	/// Called when candidates have been included in a child block of the relay parent.
+	/// Any statements held back or gossip concerning these candidates can be dropped.
+	fn candidates_included(&self, candidates: &[CandidateHash]);
+
+	/// Called when the node is shutting down, after in-flight work has completed.
+	/// Locally signed statements should be circulated once more, and any state
+	/// worth keeping across a restart persisted. Also called after locally signed
+	/// statements are imported into the session from an attestation snapshot.
+	fn flush(&self);
+
+	/// Called once the validation session at the relay parent has ended. All work
+	/// of the session should be stopped, and its gossip dropped.
+	fn end_session(&self);
 }
 
 /// A long-lived network which can create parachain statement and BFT message routing processes on demand.
@@ -195,9 +233,7 @@ pub fn sign_table_statement(statement: &Statement, key: &ed25519::Pair, parent_h
 	// we sign using the primitive statement type because that's what the runtime
 	// expects. These types probably encode the same way so this clone could be optimized
 	// out in the future.
-	let mut encoded = PrimitiveStatement::from(statement.clone()).encode();
-	encoded.extend(parent_hash.as_ref());
-
+	let encoded = PrimitiveStatement::from(statement.clone()).localized_payload(parent_hash);
 	key.sign(&encoded).into()
 }
 
@@ -205,9 +241,7 @@ pub fn sign_table_statement(statement: &Statement, key: &ed25519::Pair, parent_h
 pub fn check_statement(statement: &Statement, signature: &CandidateSignature, signer: SessionKey, parent_hash: &Hash) -> bool {
 	use runtime_primitives::traits::Verify;
 
-	let mut encoded = PrimitiveStatement::from(statement.clone()).encode();
-	encoded.extend(parent_hash.as_ref());
-
+	let encoded = PrimitiveStatement::from(statement.clone()).localized_payload(parent_hash);
 	signature.verify(&encoded[..], &signer.into())
 }
 
@@ -257,6 +291,71 @@ pub fn make_group_info(
 	}
 }
 
+/// Compute group info out of the scheduled core assignments and a local authority set.
+///
+/// Validators not assigned to any core have a local duty on the relay chain.
+/// Cores without any assigned validators are not given a group.
+pub fn make_group_info_from_schedule(
+	scheduled: &[CoreAssignment],
+	authorities: &[AuthorityId],
+	local_id: AuthorityId,
+) -> Result<(HashMap<ParaId, GroupInfo>, LocalDuty), Error> {
+	if !authorities.contains(&local_id) {
+		bail!(ErrorKind::NotValidator(local_id))
+	}
+
+	let mut local_validation = Chain::Relay;
+	let mut map = HashMap::new();
+
+	for assignment in scheduled.iter().filter(|a| !a.group.is_empty()) {
+		let mut group = GroupInfo::default();
+		for &idx in &assignment.group {
+			let authority = match authorities.get(idx as usize) {
+				Some(authority) => authority,
+				None => bail!(ErrorKind::InvalidScheduledValidator(idx, authorities.len())),
+			};
+
+			if authority == &local_id {
+				local_validation = Chain::Parachain(assignment.para_id);
+			}
+
+			group.validity_guarantors.insert(authority.clone());
+		}
+
+		let validity_len = group.validity_guarantors.len();
+		group.needed_validity = validity_len / 2 + validity_len % 2;
+		map.insert(assignment.para_id, group);
+	}
+
+	Ok((map, LocalDuty { validation: local_validation }))
+}
+
+// ensure the runtime at the given block provides the parachain host API validation relies on.
+fn check_parachain_host_api<A: ApiExt<Block>>(api: &A, at: &BlockId) -> Result<(), Error> {
+	if api.has_api_with::<ParachainHost<Block>, _>(at, |version| version >= PARACHAIN_HOST_API_VERSION)? {
+		Ok(())
+	} else {
+		Err(ErrorKind::OutdatedRuntimeApi(PARACHAIN_HOST_API_VERSION).into())
+	}
+}
+
+/// Access to the hash of the runtime code at a block, which changes whenever the
+/// runtime is upgraded, even if the upgrade keeps the runtime version.
+pub trait RuntimeCodeHash {
+	/// Get the blake2-256 hash of the runtime code at the given block, if any.
+	fn runtime_code_hash(&self, at: &BlockId) -> client::error::Result<Option<Hash>>;
+}
+
+impl<B, E, RA> RuntimeCodeHash for client::Client<B, E, Block, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	fn runtime_code_hash(&self, at: &BlockId) -> client::error::Result<Option<Hash>> {
+		let code = self.storage(at, &StorageKey(well_known_keys::CODE.to_vec()))?;
+		Ok(code.map(|code| blake2_256(&code.0).into()))
+	}
+}
+
 /// Constructs parachain-agreement instances.
 struct ParachainValidation<C, N, P> {
 	/// The client instance.
@@ -272,13 +371,23 @@ struct ParachainValidation<C, N, P> {
 	/// Live agreements. Maps relay chain parent hashes to attestation
 	/// instances.
 	live_instances: Mutex<HashMap<Hash, Arc<AttestationTracker>>>,
+	/// Runtime code the live instances were started against.
+	runtime_versions: Mutex<RuntimeVersions>,
+	/// Number of live instances ended by runtime upgrades.
+	upgrade_restarts: AtomicUsize,
+	/// Coordinates a graceful shutdown.
+	shutdown: Shutdown,
+	/// Subscribers to candidates backed in live instances.
+	backed_candidates: BackedCandidates,
+	/// Attestation state of live instances, for export and import.
+	attestation_sessions: AttestationSessions,
 }
 
 impl<C, N, P> ParachainValidation<C, N, P> where
 	C: Collators + Send + 'static,
 	N: Network,
-	P: ProvideRuntimeApi + HeaderBackend<Block> + BlockBody<Block> + Send + Sync + 'static,
-	P::Api: ParachainHost<Block> + BlockBuilderApi<Block>,
+	P: ProvideRuntimeApi + HeaderBackend<Block> + BlockBody<Block> + RuntimeCodeHash + Send + Sync + 'static,
+	P::Api: ParachainHost<Block> + Core<Block> + BlockBuilderApi<Block> + ApiExt<Block>,
 	<C::Collation as IntoFuture>::Future: Send + 'static,
 	N::TableRouter: Send + 'static,
 	<<N::TableRouter as TableRouter>::FetchIncoming as IntoFuture>::Future: Send + 'static,
@@ -293,9 +402,10 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 	fn get_or_instantiate(
 		&self,
 		parent_hash: Hash,
+		parent_number: BlockNumber,
 		grandparent_hash: Hash,
 		authorities: &[AuthorityId],
-		sign_with: Arc<ed25519::Pair>,
+		sign_with: Arc<StatementSigner>,
 	)
 		-> Result<Arc<AttestationTracker>, Error>
 	{
@@ -304,6 +414,10 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 			return Ok(tracker.clone());
 		}
 
+		if self.shutdown.is_draining() {
+			bail!(ErrorKind::ShuttingDown)
+		}
+
 		let id = BlockId::hash(parent_hash);
 
 		// compute the parent candidates, if we know of them.
@@ -328,12 +442,13 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 			}).unwrap_or_default()
 		};
 
-		let duty_roster = self.client.runtime_api().duty_roster(&id)?;
+		check_parachain_host_api(&*self.client.runtime_api(), &id)?;
+		let scheduled = self.client.runtime_api().scheduled(&id)?;
 
-		let (group_info, local_duty) = make_group_info(
-			duty_roster,
+		let (group_info, local_duty) = make_group_info_from_schedule(
+			&scheduled,
 			authorities,
-			sign_with.public().into(),
+			sign_with.session_key(),
 		)?;
 
 		info!("Starting parachain attestation session on top of parent {:?}. Local parachain duty is {:?}",
@@ -343,13 +458,31 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 
 		debug!(target: "validation", "Active parachains: {:?}", active_parachains);
 
-		let table = Arc::new(SharedTable::new(group_info, sign_with.clone(), parent_hash, self.extrinsic_store.clone()));
+		let parachain_validators = self.client.runtime_api().parachain_validators(&id)?;
+		let runtime_code = self.client.runtime_code_hash(&id).ok().and_then(|hash| hash);
+
+		let table = Arc::new(SharedTable::new(
+			group_info,
+			parachain_validators,
+			sign_with.clone(),
+			parent_hash,
+			parent_number,
+			self.extrinsic_store.clone(),
+		).with_shutdown(self.shutdown.clone())
+			.with_backed_candidates(self.backed_candidates.clone()));
+
 		let router = self.network.communication_for(
 			table.clone(),
 			outgoing,
 		);
 
-		let drop_signal = match local_duty.validation {
+		let circulate_router = router.clone();
+		self.attestation_sessions.register(parent_hash, &table, move || circulate_router.flush());
+
+		let included_router = router.clone();
+		let flushed_router = router.clone();
+		let ended_router = router.clone();
+		let local_work = match local_duty.validation {
 			Chain::Parachain(id) => Some(self.launch_work(
 				parent_hash,
 				id,
@@ -361,17 +494,76 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 		let tracker = Arc::new(AttestationTracker {
 			table,
 			started: Instant::now(),
-			_drop_signal: drop_signal
+			on_included: Mutex::new(Box::new(move |candidates: &[CandidateHash]| {
+				included_router.candidates_included(candidates)
+			})),
+			on_flush: Mutex::new(Box::new(move || flushed_router.flush())),
+			on_end: Mutex::new(Box::new(move || ended_router.end_session())),
+			local_work,
 		});
 
 		live_instances.insert(parent_hash, tracker.clone());
+		self.runtime_versions.lock().sessions.insert(parent_hash, runtime_code);
 
 		Ok(tracker)
 	}
 
+	/// Note candidates included in a newly imported block.
+	///
+	/// Live sessions stop validating these candidates and drop any related
+	/// statements and gossip, without waiting for the sessions to be pruned.
+	fn note_included(&self, candidates: &[CandidateHash]) {
+		if candidates.is_empty() { return }
+
+		for tracker in self.live_instances.lock().values() {
+			tracker.table.note_included(candidates);
+			let mut on_included = tracker.on_included.lock();
+			(&mut **on_included)(candidates);
+		}
+	}
+
+	/// Note the hash of the runtime code at a new best block.
+	///
+	/// The runtime API may change meaning with a runtime upgrade, so when the code
+	/// changes, live instances whose relay parent runs other code are torn down,
+	/// to be restarted on the new runtime. Returns the number of instances torn down.
+	fn note_runtime_code(&self, code_hash: Hash) -> usize {
+		let outdated = self.runtime_versions.lock().note_best(code_hash);
+		if outdated.is_empty() { return 0 }
+
+		// dropping the trackers stops their work and ends their sessions.
+		let mut live_instances = self.live_instances.lock();
+		for parent_hash in &outdated {
+			live_instances.remove(parent_hash);
+			self.attestation_sessions.remove(parent_hash);
+		}
+
+		self.upgrade_restarts.fetch_add(outdated.len(), Ordering::Relaxed);
+		outdated.len()
+	}
+
+	/// The number of live instances ended by runtime upgrades.
+	fn upgrade_restarts(&self) -> usize {
+		self.upgrade_restarts.load(Ordering::Relaxed)
+	}
+
+	/// Flush the state of live sessions and the availability store, when shutting down.
+	fn flush(&self) {
+		for tracker in self.live_instances.lock().values() {
+			let mut on_flush = tracker.on_flush.lock();
+			(&mut **on_flush)();
+		}
+
+		if let Err(e) = self.extrinsic_store.flush() {
+			warn!(target: "validation", "Failed to flush availability store: {:?}", e);
+		}
+	}
+
 	/// Retain validation sessions matching predicate.
 	fn retain<F: FnMut(&Hash) -> bool>(&self, mut pred: F) {
-		self.live_instances.lock().retain(|k, _| pred(k))
+		self.live_instances.lock().retain(|k, _| pred(k));
+		self.runtime_versions.lock().sessions.retain(|k, _| pred(k));
+		self.attestation_sessions.retain(pred);
 	}
 
 	// launch parachain work asynchronously.
@@ -380,10 +572,10 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 		relay_parent: Hash,
 		validation_para: ParaId,
 		router: N::TableRouter,
-	) -> exit_future::Signal {
+	) -> Cancellation {
 		use extrinsic_store::Data;
 
-		let (signal, exit) = exit_future::signal();
+		let cancellation = Cancellation::new();
 
 		let fetch_incoming = router.fetch_incoming(validation_para)
 			.into_future()
@@ -405,6 +597,7 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 			});
 
 		let extrinsic_store = self.extrinsic_store.clone();
+		let in_flight = self.shutdown.in_flight();
 		let handled_work = collation_work.then(move |result| match result {
 			Ok((collation, extrinsic)) => {
 				let res = extrinsic_store.make_available(Data {
@@ -436,26 +629,80 @@ impl<C, N, P> ParachainValidation<C, N, P> where
 			}
 		});
 
-		let cancellable_work = handled_work.select(exit).then(|_| Ok(()));
+		// the work counts as in flight until it completes or is cancelled.
+		let cancellable_work = cancellation.until_cancelled(handled_work).then(move |_| {
+			drop(in_flight);
+			Ok(())
+		});
 
 		// spawn onto thread pool.
 		self.handle.spawn(cancellable_work);
-		signal
+		cancellation
+	}
+}
+
+// The runtime versions of live sessions, to end those running on a superseded runtime.
+#[derive(Default)]
+struct RuntimeVersions {
+	// hash of the runtime code at the best block.
+	best: Option<Hash>,
+	// hash of the runtime code at the relay parent of each live session,
+	// if it could be fetched.
+	sessions: HashMap<Hash, Option<Hash>>,
+}
+
+impl RuntimeVersions {
+	// note the runtime code at a new best block. if it differs from the code at
+	// the last best block, returns and forgets the relay parents of sessions which
+	// don't run this code.
+	fn note_best(&mut self, code_hash: Hash) -> Vec<Hash> {
+		let upgraded = self.best.map_or(false, |old| old != code_hash);
+		self.best = Some(code_hash);
+		if !upgraded { return Vec::new() }
+
+		let outdated: Vec<_> = self.sessions.iter()
+			.filter(|&(_, code)| *code != Some(code_hash))
+			.map(|(parent_hash, _)| *parent_hash)
+			.collect();
+
+		for parent_hash in &outdated {
+			self.sessions.remove(parent_hash);
+		}
+
+		outdated
 	}
 }
 
 /// Parachain validation for a single block.
 struct AttestationTracker {
-	_drop_signal: Option<exit_future::Signal>,
+	// the scope of the local collation work, if validating a parachain.
+	local_work: Option<Cancellation>,
 	table: Arc<SharedTable>,
 	started: Instant,
+	// notifies the table router of candidates included on-chain.
+	on_included: Mutex<Box<FnMut(&[CandidateHash]) + Send>>,
+	// flushes the table router on shutdown.
+	on_flush: Mutex<Box<FnMut() + Send>>,
+	// ends the session of the table router.
+	on_end: Mutex<Box<FnMut() + Send>>,
+}
+
+impl Drop for AttestationTracker {
+	fn drop(&mut self) {
+		if let Some(ref local_work) = self.local_work {
+			local_work.cancel();
+		}
+
+		let mut on_end = self.on_end.lock();
+		(&mut **on_end)();
+	}
 }
 
 /// Polkadot proposer factory.
 pub struct ProposerFactory<C, N, P, TxApi: PoolChainApi> {
 	parachain_validation: Arc<ParachainValidation<C, N, P>>,
 	transaction_pool: Arc<Pool<TxApi>>,
-	key: Arc<ed25519::Pair>,
+	key: Arc<StatementSigner>,
 	_service_handle: ServiceHandle,
 	aura_slot_duration: SlotDuration,
 }
@@ -463,24 +710,33 @@ pub struct ProposerFactory<C, N, P, TxApi: PoolChainApi> {
 impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 	C: Collators + Send + Sync + 'static,
 	<C::Collation as IntoFuture>::Future: Send + 'static,
-	P: BlockchainEvents<Block> + ChainHead<Block> + BlockBody<Block>,
+	P: BlockchainEvents<Block> + ChainHead<Block> + BlockBody<Block> + RuntimeCodeHash,
 	P: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync + 'static,
-	P::Api: ParachainHost<Block> + Core<Block> + BlockBuilderApi<Block>,
+	P::Api: ParachainHost<Block> + Core<Block> + BlockBuilderApi<Block> + ApiExt<Block>,
 	N: Network + Send + Sync + 'static,
 	N::TableRouter: Send + 'static,
 	<<N::TableRouter as TableRouter>::FetchIncoming as IntoFuture>::Future: Send + 'static,
 	TxApi: PoolChainApi,
 {
 	/// Create a new proposer factory.
+	///
+	/// Attestation statements are signed with `key`, which may delegate to a remote signer.
+	/// Draining `shutdown` stops new validation work, and flushes live sessions and the
+	/// extrinsic store once in-flight work has completed. Candidates reaching the backing
+	/// threshold are announced to the subscribers of `backed_candidates`, and the attestation
+	/// state of live sessions can be exported and imported through `attestation_sessions`.
 	pub fn new(
 		client: Arc<P>,
 		network: N,
 		collators: C,
 		transaction_pool: Arc<Pool<TxApi>>,
 		thread_pool: TaskExecutor,
-		key: Arc<ed25519::Pair>,
+		key: Arc<StatementSigner>,
 		extrinsic_store: ExtrinsicStore,
 		aura_slot_duration: SlotDuration,
+		shutdown: Shutdown,
+		backed_candidates: BackedCandidates,
+		attestation_sessions: AttestationSessions,
 	) -> Self {
 		let parachain_validation = Arc::new(ParachainValidation {
 			client: client.clone(),
@@ -489,8 +745,21 @@ impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 			handle: thread_pool.clone(),
 			extrinsic_store: extrinsic_store.clone(),
 			live_instances: Mutex::new(HashMap::new()),
+			runtime_versions: Mutex::new(RuntimeVersions::default()),
+			upgrade_restarts: AtomicUsize::new(0),
+			shutdown: shutdown.clone(),
+			backed_candidates,
+			attestation_sessions,
 		});
 
+		{
+			// the hook must not keep validation alive, as it is owned by the shutdown handle.
+			let parachain_validation = Arc::downgrade(&parachain_validation);
+			shutdown.on_flush(move || if let Some(validation) = parachain_validation.upgrade() {
+				validation.flush();
+			});
+		}
+
 		let service_handle = ::attestation_service::start(
 			client,
 			parachain_validation.clone(),
@@ -507,14 +776,20 @@ impl<C, N, P, TxApi> ProposerFactory<C, N, P, TxApi> where
 			aura_slot_duration,
 		}
 	}
+
+	/// The number of validation sessions ended by runtime upgrades, to be restarted
+	/// on the new runtime.
+	pub fn upgrade_restarts(&self) -> usize {
+		self.parachain_validation.upgrade_restarts()
+	}
 }
 
 impl<C, N, P, TxApi> consensus::Environment<Block> for ProposerFactory<C, N, P, TxApi> where
 	C: Collators + Send + 'static,
 	N: Network,
 	TxApi: PoolChainApi<Block=Block>,
-	P: ProvideRuntimeApi + HeaderBackend<Block> + BlockBody<Block> + Send + Sync + 'static,
-	P::Api: ParachainHost<Block> + BlockBuilderApi<Block>,
+	P: ProvideRuntimeApi + HeaderBackend<Block> + BlockBody<Block> + RuntimeCodeHash + Send + Sync + 'static,
+	P::Api: ParachainHost<Block> + Core<Block> + BlockBuilderApi<Block> + ApiExt<Block>,
 	<C::Collation as IntoFuture>::Future: Send + 'static,
 	N::TableRouter: Send + 'static,
 	<<N::TableRouter as TableRouter>::FetchIncoming as IntoFuture>::Future: Send + 'static,
@@ -532,6 +807,7 @@ impl<C, N, P, TxApi> consensus::Environment<Block> for ProposerFactory<C, N, P,
 		let sign_with = self.key.clone();
 		let tracker = self.parachain_validation.get_or_instantiate(
 			parent_hash,
+			parent_header.number,
 			parent_header.parent_hash().clone(),
 			authorities,
 			sign_with,
@@ -570,7 +846,7 @@ pub struct Proposer<C: Send + Sync, TxApi: PoolChainApi> where
 impl<C, TxApi> consensus::Proposer<Block> for Proposer<C, TxApi> where
 	TxApi: PoolChainApi<Block=Block>,
 	C: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync,
-	C::Api: ParachainHost<Block> + BlockBuilderApi<Block>,
+	C::Api: ParachainHost<Block> + BlockBuilderApi<Block> + ApiExt<Block>,
 {
 	type Error = Error;
 	type Create = Either<CreateProposal<C, TxApi>, future::FutureResult<Block, Error>>;
@@ -702,14 +978,20 @@ pub struct CreateProposal<C: Send + Sync, TxApi: PoolChainApi> {
 impl<C, TxApi> CreateProposal<C, TxApi> where
 	TxApi: PoolChainApi<Block=Block>,
 	C: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync,
-	C::Api: ParachainHost<Block> + BlockBuilderApi<Block>,
+	C::Api: ParachainHost<Block> + BlockBuilderApi<Block> + ApiExt<Block>,
 {
 	fn propose_with(&mut self, candidates: Vec<AttestedCandidate>) -> Result<Block, Error> {
 		use client::block_builder::BlockBuilder;
 		use runtime_primitives::traits::{Hash as HashT, BlakeTwo256};
 
+		let candidates = self.drop_rejected(candidates)?;
+
 		let mut inherent_data = self.inherent_data.take().expect("CreateProposal is not polled after finishing; qed");
+		let watermarks: Vec<(ParaId, BlockNumber)> = candidates.iter()
+			.map(|c| (c.parachain_index(), c.candidate.ingress_watermark))
+			.collect();
 		inherent_data.put_data(polkadot_runtime::PARACHAIN_INHERENT_IDENTIFIER, &candidates).map_err(ErrorKind::InherentError)?;
+		inherent_data.put_data(polkadot_runtime::INGRESS_INHERENT_IDENTIFIER, &watermarks).map_err(ErrorKind::InherentError)?;
 
 		let runtime_api = self.client.runtime_api();
 
@@ -771,12 +1053,47 @@ impl<C, TxApi> CreateProposal<C, TxApi> where
 
 		Ok(new_block)
 	}
+
+	// drop the candidates the runtime would reject, rather than authoring a block
+	// which is invalid because of them.
+	fn drop_rejected(&self, candidates: Vec<AttestedCandidate>) -> Result<Vec<AttestedCandidate>, Error> {
+		if candidates.is_empty() { return Ok(candidates) }
+
+		let header = Header::new(
+			self.parent_number + 1,
+			Default::default(),
+			Default::default(),
+			self.parent_hash,
+			Default::default(),
+		);
+
+		let runtime_api = self.client.runtime_api();
+		check_parachain_host_api(&*runtime_api, &self.parent_id)?;
+		runtime_api.initialise_block(&self.parent_id, &header)?;
+		let rejected = runtime_api.check_candidates(&self.parent_id, candidates.clone())?;
+
+		let mut keep = vec![true; candidates.len()];
+		for &(position, ref e) in &rejected {
+			match keep.get_mut(position as usize) {
+				Some(keep) => *keep = false,
+				None => continue,
+			}
+
+			warn!("Dropping candidate for parachain {:?} rejected by the runtime: {}",
+				candidates[position as usize].parachain_index(), e.as_str());
+		}
+
+		Ok(candidates.into_iter()
+			.zip(keep)
+			.filter_map(|(candidate, keep)| if keep { Some(candidate) } else { None })
+			.collect())
+	}
 }
 
 impl<C, TxApi> Future for CreateProposal<C, TxApi> where
 	TxApi: PoolChainApi<Block=Block>,
 	C: ProvideRuntimeApi + HeaderBackend<Block> + Send + Sync,
-	C::Api: ParachainHost<Block> + BlockBuilderApi<Block>,
+	C::Api: ParachainHost<Block> + BlockBuilderApi<Block> + ApiExt<Block>,
 {
 	type Item = Block;
 	type Error = Error;
@@ -787,8 +1104,19 @@ impl<C, TxApi> Future for CreateProposal<C, TxApi> where
 		let included = self.table.includable_count();
 		try_ready!(self.timing.poll(included));
 
-		// 2. propose
-		let proposed_candidates = self.table.proposed_set();
+		// 2. propose, with as many candidates as fit in the block.
+		let (budget, active_parachains) = {
+			let runtime_api = self.client.runtime_api();
+			check_parachain_host_api(&*runtime_api, &self.parent_id)?;
+			let weights = runtime_api.inclusion_weights(&self.parent_id)?;
+			(InclusionBudget::new(weights), runtime_api.active_parachains(&self.parent_id)?)
+		};
+		let proposed_candidates = select_candidates(
+			self.table.proposed_set(),
+			&budget,
+			&active_parachains,
+			self.parent_number + 1,
+		);
 
 		self.propose_with(proposed_candidates).map(Async::Ready)
 	}
@@ -799,9 +1127,14 @@ mod tests {
 	use super::*;
 	use substrate_keyring::Keyring;
 
+	/// An arbitrary candidate hash, for tests which don't need a real receipt.
+	pub(crate) fn candidate_hash(byte: u8) -> CandidateHash {
+		::codec::Decode::decode(&mut &[byte; 32][..]).expect("any 32 bytes are a candidate hash; qed")
+	}
+
 	#[test]
 	fn sign_and_check_statement() {
-		let statement: Statement = GenericStatement::Valid([1; 32].into());
+		let statement: Statement = GenericStatement::Valid(candidate_hash(1));
 		let parent_hash = [2; 32].into();
 
 		let sig = sign_table_statement(&statement, &Keyring::Alice.pair(), &parent_hash);
@@ -810,4 +1143,73 @@ mod tests {
 		assert!(!check_statement(&statement, &sig, Keyring::Alice.to_raw_public().into(), &[0xff; 32].into()));
 		assert!(!check_statement(&statement, &sig, Keyring::Bob.to_raw_public().into(), &parent_hash));
 	}
+
+	#[test]
+	fn runtime_upgrade_ends_outdated_sessions() {
+		let (old, new) = ([1; 32].into(), [2; 32].into());
+		let (on_old, on_new, unknown) = ([3; 32].into(), [4; 32].into(), [5; 32].into());
+
+		let mut versions = RuntimeVersions::default();
+		versions.sessions.insert(on_old, Some(old));
+		assert!(versions.note_best(old).is_empty());
+
+		// sessions on a fork which upgraded first are kept.
+		versions.sessions.insert(on_new, Some(new));
+		versions.sessions.insert(unknown, None);
+		assert!(versions.note_best(old).is_empty());
+		assert_eq!(versions.sessions.len(), 3);
+
+		let mut outdated = versions.note_best(new);
+		outdated.sort();
+		assert_eq!(outdated, vec![on_old, unknown]);
+		assert_eq!(versions.sessions.keys().collect::<Vec<_>>(), vec![&on_new]);
+
+		assert!(versions.note_best(new).is_empty());
+	}
+
+	#[test]
+	fn group_info_from_schedule() {
+		use polkadot_primitives::parachain::AssignmentKind;
+
+		let authorities: Vec<AuthorityId> = vec![
+			Keyring::Alice.to_raw_public().into(),
+			Keyring::Bob.to_raw_public().into(),
+			Keyring::Charlie.to_raw_public().into(),
+		];
+
+		let para_a: ParaId = 5.into();
+		let para_b: ParaId = 7.into();
+
+		let scheduled = vec![
+			CoreAssignment { core: 0, para_id: para_a, kind: AssignmentKind::Parachain, group: vec![0, 2] },
+			CoreAssignment { core: 1, para_id: para_b, kind: AssignmentKind::Parachain, group: vec![] },
+		];
+
+		let (groups, local_duty) = make_group_info_from_schedule(
+			&scheduled,
+			&authorities,
+			authorities[2].clone(),
+		).unwrap();
+
+		assert_eq!(local_duty.validation, Chain::Parachain(para_a));
+		assert_eq!(groups.len(), 1);
+		assert_eq!(groups[&para_a].needed_validity, 1);
+		assert!(groups[&para_a].validity_guarantors.contains(&authorities[0]));
+		assert!(!groups.contains_key(&para_b));
+
+		let (_, local_duty) = make_group_info_from_schedule(&scheduled, &authorities, authorities[1].clone()).unwrap();
+		assert_eq!(local_duty.validation, Chain::Relay);
+
+		let not_validator = make_group_info_from_schedule(
+			&scheduled,
+			&authorities,
+			Keyring::Dave.to_raw_public().into(),
+		);
+		assert!(not_validator.is_err());
+
+		let bad_schedule = vec![
+			CoreAssignment { core: 0, para_id: para_a, kind: AssignmentKind::Parachain, group: vec![3] },
+		];
+		assert!(make_group_info_from_schedule(&bad_schedule, &authorities, authorities[0].clone()).is_err());
+	}
 }
@@ -0,0 +1,255 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deterministic replay of failed candidate validations.
+//!
+//! Executing a candidate depends only on the validation code, the parent head, the
+//! collation and its ingress. When execution fails, these are bundled along with the
+//! outcome and written to a debug directory, so the failure can be re-run offline.
+//!
+//! Bundles are written from a dedicated thread, and only so many of them, so that a
+//! parachain failing every candidate can't fill the disk. Each bundle refers to its
+//! validation code by hash, and the code is written once next to the bundles.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use codec::{Decode, Encode};
+use parking_lot::Mutex;
+use polkadot_primitives::Hash;
+use polkadot_primitives::parachain::{Collation, Extrinsic};
+use primitives::blake2_256;
+
+use collation::{self, execute_collation};
+use super::Incoming;
+
+/// The number of bundles a recorder writes at most.
+pub const MAX_REPLAY_BUNDLES: usize = 64;
+
+// the number of bundles waiting to be written, beyond which more are dropped.
+const REPLAY_QUEUE: usize = 4;
+
+/// The inputs and outcome of a failed candidate validation.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReplayBundle {
+	/// The hash of the validation code.
+	pub code_hash: Hash,
+	/// The validation code of the parachain.
+	pub code: Vec<u8>,
+	/// The head data the candidate was built on.
+	pub parent_head: Vec<u8>,
+	/// The collation, including the candidate receipt and the PoV block.
+	pub collation: Collation,
+	/// The ingress fed to validation, in canonical order.
+	pub incoming: Incoming,
+	/// The recorded outcome of validation.
+	pub outcome: String,
+}
+
+impl ReplayBundle {
+	/// Bundle the inputs of a validation which failed with the given error.
+	pub fn new(
+		code: Vec<u8>,
+		parent_head: Vec<u8>,
+		collation: Collation,
+		incoming: Incoming,
+		outcome: &collation::Error,
+	) -> Self {
+		ReplayBundle {
+			code_hash: blake2_256(&code).into(),
+			code,
+			parent_head,
+			collation,
+			incoming,
+			outcome: outcome.to_string(),
+		}
+	}
+
+	/// Execute the candidate again.
+	pub fn replay(&self) -> Result<Extrinsic, collation::Error> {
+		execute_collation(&self.code, self.parent_head.clone(), &self.collation, &self.incoming)
+	}
+}
+
+// a bundle as written to disk, without its validation code.
+#[derive(Encode, Decode)]
+struct StoredBundle {
+	code_hash: Hash,
+	parent_head: Vec<u8>,
+	collation: Collation,
+	incoming: Incoming,
+	outcome: String,
+}
+
+fn code_path(dir: &Path, code_hash: &Hash) -> PathBuf {
+	dir.join(format!("{:?}.code", code_hash))
+}
+
+// write a bundle named after the hash of its candidate, along with its validation code
+// unless already written. returns `None` if the candidate was recorded already.
+fn write_bundle(dir: &Path, bundle: &ReplayBundle) -> io::Result<Option<PathBuf>> {
+	fs::create_dir_all(dir)?;
+
+	let path = dir.join(format!("{:?}.replay", bundle.collation.receipt.hash().0));
+	if path.exists() {
+		return Ok(None);
+	}
+
+	let code_path = code_path(dir, &bundle.code_hash);
+	if !code_path.exists() {
+		fs::write(&code_path, &bundle.code)?;
+	}
+
+	let stored = StoredBundle {
+		code_hash: bundle.code_hash,
+		parent_head: bundle.parent_head.clone(),
+		collation: bundle.collation.clone(),
+		incoming: bundle.incoming.clone(),
+		outcome: bundle.outcome.clone(),
+	};
+	fs::write(&path, stored.encode())?;
+	Ok(Some(path))
+}
+
+/// Writes replay bundles to a debug directory. Clones write to the same directory,
+/// sharing the limit of `MAX_REPLAY_BUNDLES`.
+#[derive(Clone, Debug)]
+pub struct ReplayRecorder {
+	queue: Arc<Mutex<mpsc::SyncSender<ReplayBundle>>>,
+}
+
+impl ReplayRecorder {
+	/// Record bundles in the given directory, which is created if missing.
+	/// This spawns the thread bundles are written from.
+	pub fn new(dir: PathBuf) -> io::Result<Self> {
+		let (tx, rx) = mpsc::sync_channel::<ReplayBundle>(REPLAY_QUEUE);
+
+		thread::Builder::new()
+			.name("validation-replay".into())
+			.spawn(move || {
+				let mut written = 0;
+
+				// ends once all recorders are dropped.
+				for bundle in rx {
+					let para_id = bundle.collation.receipt.parachain_index;
+					if written >= MAX_REPLAY_BUNDLES {
+						debug!(target: "validation", "Not recording failed candidate of parachain {:?}: limit reached", para_id);
+						continue;
+					}
+
+					match write_bundle(&dir, &bundle) {
+						Ok(Some(path)) => {
+							written += 1;
+							warn!(
+								target: "validation",
+								"Candidate of parachain {:?} failed validation, recorded for replay at {}",
+								para_id,
+								path.display()
+							);
+						}
+						Ok(None) => {}
+						Err(e) => warn!(target: "validation", "Failed to record validation replay: {}", e),
+					}
+				}
+			})?;
+
+		Ok(ReplayRecorder { queue: Arc::new(Mutex::new(tx)) })
+	}
+
+	/// Queue a bundle to be written, named after the hash of its candidate. Returns
+	/// `false` if the bundle was dropped, as too many are waiting to be written.
+	pub fn record(&self, bundle: ReplayBundle) -> bool {
+		self.queue.lock().try_send(bundle).is_ok()
+	}
+}
+
+/// Load a replay bundle written by a `ReplayRecorder`, along with its validation code.
+pub fn load_replay(path: &Path) -> io::Result<ReplayBundle> {
+	let invalid = |msg: &'static str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+	let encoded = fs::read(path)?;
+	let stored = StoredBundle::decode(&mut &encoded[..])
+		.ok_or_else(|| invalid("Invalid replay bundle"))?;
+
+	let dir = path.parent().unwrap_or_else(|| Path::new("."));
+	let code = fs::read(code_path(dir, &stored.code_hash))?;
+	if Hash::from(blake2_256(&code)) != stored.code_hash {
+		return Err(invalid("Validation code doesn't match the replay bundle"));
+	}
+
+	Ok(ReplayBundle {
+		code_hash: stored.code_hash,
+		code,
+		parent_head: stored.parent_head,
+		collation: stored.collation,
+		incoming: stored.incoming,
+		outcome: stored.outcome,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::{BlockData, CandidateReceipt, HeadData};
+
+	const ADDER_CODE: &[u8] = include_bytes!("../../parachain/tests/res/adder.wasm");
+
+	fn failed_bundle(block_data: Vec<u8>) -> ReplayBundle {
+		let collation = Collation {
+			block_data: BlockData(block_data),
+			receipt: CandidateReceipt {
+				parachain_index: 5.into(),
+				collator: [1; 32].into(),
+				signature: Default::default(),
+				head_data: HeadData(vec![1, 2, 3]),
+				balance_uploads: Vec::new(),
+				egress_queue_roots: Vec::new(),
+				fees: 0,
+				block_data_hash: [2; 32].into(),
+				ingress_watermark: 0,
+			},
+		};
+
+		let outcome = execute_collation(ADDER_CODE, vec![9, 9, 9], &collation, &Vec::new()).unwrap_err();
+		ReplayBundle::new(ADDER_CODE.to_vec(), vec![9, 9, 9], collation, Vec::new(), &outcome)
+	}
+
+	#[test]
+	fn record_load_and_replay() {
+		let dir = ::std::env::temp_dir().join(format!("polkadot-replay-{}", ::std::process::id()));
+		let first = failed_bundle(vec![1]);
+		let second = failed_bundle(vec![2]);
+
+		let path = write_bundle(&dir, &first).unwrap().unwrap();
+		assert_eq!(write_bundle(&dir, &first).unwrap(), None);
+		write_bundle(&dir, &second).unwrap().unwrap();
+
+		// the code of both is written once.
+		let code_files = fs::read_dir(&dir).unwrap()
+			.filter(|entry| entry.as_ref().unwrap().path().extension().map_or(false, |ext| ext == "code"))
+			.count();
+		assert_eq!(code_files, 1);
+
+		let loaded = load_replay(&path).unwrap();
+		assert_eq!(loaded, first);
+		assert_eq!(loaded.replay().unwrap_err().to_string(), first.outcome);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
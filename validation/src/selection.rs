@@ -0,0 +1,198 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Selection of the backed candidates to include in a relay chain block.
+//!
+//! Candidates are included within a budget on their total encoded size and
+//! on the weight the runtime assigns to checking them. When not everything
+//! fits, the parachain considered first rotates across the active parachains
+//! with the block number, so that no parachain is consistently left out.
+
+use codec::Encode;
+use polkadot_primitives::parachain::{AttestedCandidate, Id as ParaId, InclusionWeights};
+
+use super::MAX_TRANSACTIONS_SIZE;
+
+/// Budget on the candidates included in a single relay chain block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InclusionBudget {
+	/// Maximum total encoded size of the candidates, in bytes.
+	pub max_size: usize,
+	/// The weights of the candidates, as given by the runtime. The total weight
+	/// of the candidates is bounded by `weights.max_block`.
+	pub weights: InclusionWeights,
+}
+
+impl InclusionBudget {
+	/// Create a budget for the given runtime weights, allowing the candidates half
+	/// of the block's transaction space.
+	pub fn new(weights: InclusionWeights) -> Self {
+		InclusionBudget {
+			max_size: MAX_TRANSACTIONS_SIZE / 2,
+			weights,
+		}
+	}
+}
+
+/// Select the candidates that fit within the budget.
+///
+/// Candidates are considered starting at the parachain given by `rotation` among the
+/// `active_parachains`, wrapping around, and any candidate which would exceed the
+/// budget is skipped in favor of the following ones. The candidates are expected
+/// in order of parachain ID, and the selected ones keep that order, which is the
+/// order the runtime expects them in.
+pub fn select_candidates(
+	candidates: Vec<AttestedCandidate>,
+	budget: &InclusionBudget,
+	active_parachains: &[ParaId],
+	rotation: u64,
+) -> Vec<AttestedCandidate> {
+	if candidates.is_empty() { return candidates }
+
+	let start = if active_parachains.is_empty() {
+		0
+	} else {
+		let first = active_parachains[(rotation % active_parachains.len() as u64) as usize];
+		candidates.iter().position(|c| c.parachain_index() >= first).unwrap_or(0)
+	};
+
+	let mut selected = vec![false; candidates.len()];
+	let (mut size, mut weight) = (0, 0);
+
+	for i in (start..candidates.len()).chain(0..start) {
+		let candidate = &candidates[i];
+		let candidate_size = candidate.encode().len();
+		let candidate_weight = budget.weights.candidate(candidate);
+
+		if size + candidate_size > budget.max_size || weight + candidate_weight > budget.weights.max_block {
+			debug!(target: "validation", "Candidate for parachain {:?} does not fit in block, skipping",
+				candidate.parachain_index());
+			continue;
+		}
+
+		size += candidate_size;
+		weight += candidate_weight;
+		selected[i] = true;
+	}
+
+	candidates.into_iter()
+		.zip(selected)
+		.filter_map(|(candidate, selected)| if selected { Some(candidate) } else { None })
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::parachain::{CandidateReceipt, HeadData, ValidityAttestation};
+
+	const WEIGHTS: InclusionWeights = InclusionWeights {
+		candidate_base: 1_000,
+		validity_vote: 250,
+		egress_root: 10,
+		max_block: 100_000,
+	};
+
+	fn attested(id: u32, votes: usize) -> AttestedCandidate {
+		AttestedCandidate {
+			candidate: CandidateReceipt {
+				parachain_index: id.into(),
+				collator: Default::default(),
+				signature: Default::default(),
+				head_data: HeadData(vec![1, 2, 3]),
+				balance_uploads: Vec::new(),
+				egress_queue_roots: Vec::new(),
+				fees: 0,
+				block_data_hash: Default::default(),
+				ingress_watermark: 0,
+			},
+			validity_votes: (0..votes)
+				.map(|i| ([i as u8; 32].into(), ValidityAttestation::Implicit(Default::default())))
+				.collect(),
+		}
+	}
+
+	fn ids(candidates: &[AttestedCandidate]) -> Vec<ParaId> {
+		candidates.iter().map(|c| c.parachain_index()).collect()
+	}
+
+	fn active(n: u32) -> Vec<ParaId> {
+		(0..n).map(Into::into).collect()
+	}
+
+	fn weight_budget(max_block: u64) -> InclusionBudget {
+		InclusionBudget {
+			max_size: usize::max_value(),
+			weights: InclusionWeights { max_block, ..WEIGHTS },
+		}
+	}
+
+	#[test]
+	fn everything_within_budget_is_included() {
+		let candidates: Vec<_> = (0..4).map(|i| attested(i, 2)).collect();
+		let selected = select_candidates(candidates.clone(), &InclusionBudget::new(WEIGHTS), &active(4), 7);
+		assert_eq!(selected, candidates);
+	}
+
+	#[test]
+	fn weight_limit_rotates_across_parachains() {
+		let candidates: Vec<_> = (0..4).map(|i| attested(i, 1)).collect();
+		let budget = weight_budget(2 * WEIGHTS.candidate(&candidates[0]));
+
+		let selected: Vec<_> = (0..4)
+			.map(|rotation| ids(&select_candidates(candidates.clone(), &budget, &active(4), rotation)))
+			.collect();
+
+		assert_eq!(selected, vec![
+			vec![0.into(), 1.into()],
+			vec![1.into(), 2.into()],
+			vec![2.into(), 3.into()],
+			vec![0.into(), 3.into()],
+		]);
+	}
+
+	#[test]
+	fn rotation_follows_parachain_ids() {
+		// only some of the active parachains have candidates, so rotating over the
+		// candidates rather than the parachains would favor parachain 1.
+		let candidates = vec![attested(1, 1), attested(3, 1)];
+		let budget = weight_budget(WEIGHTS.candidate(&candidates[0]));
+
+		let selected: Vec<_> = (0..4)
+			.map(|rotation| ids(&select_candidates(candidates.clone(), &budget, &active(4), rotation)))
+			.collect();
+
+		assert_eq!(selected, vec![
+			vec![1.into()],
+			vec![1.into()],
+			vec![3.into()],
+			vec![3.into()],
+		]);
+	}
+
+	#[test]
+	fn oversized_candidate_skipped_for_smaller_ones() {
+		let candidates = vec![attested(0, 1), attested(1, 20), attested(2, 1)];
+		let small_size = candidates[0].encode().len();
+		let budget = InclusionBudget {
+			max_size: 2 * small_size,
+			weights: InclusionWeights { max_block: u64::max_value(), ..WEIGHTS },
+		};
+
+		let selected = select_candidates(candidates, &budget, &active(3), 1);
+		assert_eq!(ids(&selected), vec![0.into(), 2.into()]);
+	}
+}
@@ -21,10 +21,10 @@ use std::collections::HashMap;
 use futures::prelude::*;
 use futures::sync::oneshot;
 
-use polkadot_primitives::Hash;
+use polkadot_primitives::parachain::CandidateHash;
 
 /// Track includability of a set of candidates,
-pub(super) fn track<I: IntoIterator<Item=(Hash, bool)>>(candidates: I) -> (IncludabilitySender, Includable) {
+pub(super) fn track<I: IntoIterator<Item=(CandidateHash, bool)>>(candidates: I) -> (IncludabilitySender, Includable) {
 	let (tx, rx) = oneshot::channel();
 	let tracking: HashMap<_, _> = candidates.into_iter().collect();
 	let includable_count = tracking.values().filter(|x| **x).count();
@@ -45,7 +45,7 @@ pub(super) fn track<I: IntoIterator<Item=(Hash, bool)>>(candidates: I) -> (Inclu
 
 /// The sending end of the includability sender.
 pub(super) struct IncludabilitySender {
-	tracking: HashMap<Hash, bool>,
+	tracking: HashMap<CandidateHash, bool>,
 	includable_count: usize,
 	sender: Option<oneshot::Sender<()>>,
 }
@@ -55,7 +55,7 @@ impl IncludabilitySender {
 	/// returns `Err(Canceled)` if the other end has hung up.
 	///
 	/// returns `true` when this is completed and should be destroyed.
-	pub fn update_candidate(&mut self, candidate: Hash, includable: bool) -> bool {
+	pub fn update_candidate(&mut self, candidate: CandidateHash, includable: bool) -> bool {
 		use std::collections::hash_map::Entry;
 
 		match self.tracking.entry(candidate) {
@@ -106,12 +106,13 @@ impl Future for Includable {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use tests::candidate_hash;
 
 	#[test]
 	fn it_works() {
-		let hash1 = [1; 32].into();
-		let hash2 = [2; 32].into();
-		let hash3 = [3; 32].into();
+		let hash1 = candidate_hash(1);
+		let hash2 = candidate_hash(2);
+		let hash3 = candidate_hash(3);
 
 		let (mut sender, recv) = track([
 			(hash1, true),
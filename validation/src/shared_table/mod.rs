@@ -17,23 +17,26 @@
 //! Parachain statement table meant to be shared with a message router
 //! and a consensus proposer.
 
-use std::collections::hash_map::{HashMap, Entry};
+use std::collections::{HashSet, hash_map::{HashMap, Entry}};
 use std::sync::Arc;
 
-use extrinsic_store::{Data, Store as ExtrinsicStore};
+use extrinsic_store::{Data, SeenDigest, Store as ExtrinsicStore};
 use table::{self, Table, Context as TableContextTrait};
-use polkadot_primitives::{Block, BlockId, Hash, SessionKey};
+use polkadot_primitives::{Block, BlockId, BlockNumber, Hash, SessionKey};
 use polkadot_primitives::parachain::{
-	Id as ParaId, BlockData, Collation, Extrinsic, CandidateReceipt,
-	AttestedCandidate, ParachainHost
+	Id as ParaId, BlockData, CandidateHash, Collation, Extrinsic, CandidateReceipt,
+	AttestedCandidate, BackedCandidate, ParachainHost, ValidityAttestation,
 };
 
 use parking_lot::Mutex;
 use futures::{future, prelude::*};
 
-use super::{GroupInfo, Incoming, TableRouter};
+use super::{GroupInfo, Incoming, TableRouter, StatementSigner};
+use error::{Error, ErrorKind};
+use backed::BackedCandidates;
+use replay::ReplayRecorder;
+use shutdown::{InFlight, Shutdown};
 use self::includable::IncludabilitySender;
-use primitives::ed25519;
 use runtime_primitives::{traits::ProvideRuntimeApi};
 
 mod includable;
@@ -44,13 +47,16 @@ pub use table::generic::Statement as GenericStatement;
 
 struct TableContext {
 	parent_hash: Hash,
-	key: Arc<ed25519::Pair>,
+	parent_number: BlockNumber,
+	signer: Arc<StatementSigner>,
 	groups: HashMap<ParaId, GroupInfo>,
+	validators: Vec<SessionKey>,
 }
 
 impl table::Context for TableContext {
 	fn is_member_of(&self, authority: &SessionKey, group: &ParaId) -> bool {
-		self.groups.get(group).map_or(false, |g| g.validity_guarantors.contains(authority))
+		self.validators.contains(authority) &&
+			self.groups.get(group).map_or(false, |g| g.validity_guarantors.contains(authority))
 	}
 
 	fn requisite_votes(&self, group: &ParaId) -> usize {
@@ -60,17 +66,18 @@ impl table::Context for TableContext {
 
 impl TableContext {
 	fn local_id(&self) -> SessionKey {
-		self.key.public().into()
+		self.signer.session_key()
 	}
 
-	fn sign_statement(&self, statement: table::Statement) -> table::SignedStatement {
-		let signature = ::sign_table_statement(&statement, &self.key, &self.parent_hash).into();
-
-		table::SignedStatement {
+	fn sign_statement(&self, statement: table::Statement)
+		-> impl Future<Item=table::SignedStatement, Error=Error> + Send
+	{
+		let sender = self.local_id();
+		self.signer.sign_statement(&statement, &self.parent_hash, self.parent_number).map(move |signature| table::SignedStatement {
 			statement,
-			signature,
-			sender: self.local_id(),
-		}
+			signature: signature.into(),
+			sender,
+		})
 	}
 }
 
@@ -107,7 +114,19 @@ struct SharedTableInner {
 	table: Table<TableContext>,
 	trackers: Vec<IncludabilitySender>,
 	extrinsic_store: ExtrinsicStore,
-	validated: HashMap<Hash, ValidationWork>,
+	validated: HashMap<CandidateHash, ValidationWork>,
+	// candidates validated locally before a restart.
+	restored: HashSet<CandidateHash>,
+	// candidates validated locally which are noted in the persisted gossip digest.
+	persisted: HashSet<CandidateHash>,
+	// candidates already included in a child block.
+	included: HashSet<CandidateHash>,
+	// statements signed locally.
+	local_statements: Vec<SignedStatement>,
+	// candidates whose backing has been announced.
+	announced_backed: HashSet<CandidateHash>,
+	backed_candidates: BackedCandidates,
+	shutdown: Shutdown,
 }
 
 impl SharedTableInner {
@@ -138,6 +157,22 @@ impl SharedTableInner {
 
 		let digest = &summary.candidate;
 
+		if para_member && self.restored.remove(digest) {
+			if let Some(work) = self.restore_validation(context, digest) {
+				return Some(work);
+			}
+		}
+
+		// the candidate is already on-chain: count the statement, but don't do any more work.
+		if self.included.contains(digest) {
+			return None;
+		}
+
+		// no new work is started while shutting down.
+		if self.shutdown.is_draining() {
+			return None;
+		}
+
 		// TODO: consider a strategy based on the number of candidate votes as well.
 		let do_validation = para_member && match self.validated.entry(digest.clone()) {
 			Entry::Occupied(_) => false,
@@ -166,7 +201,7 @@ impl SharedTableInner {
 
 					Some(Work {
 						candidate_receipt: candidate.clone(),
-						fetch: fetch_block_data.join(fetch_incoming),
+						input: WorkInput::Fetch(fetch_block_data.join(fetch_incoming)),
 					})
 				}
 			}
@@ -177,17 +212,69 @@ impl SharedTableInner {
 		work.map(|work| ParachainWork {
 			extrinsic_store: self.extrinsic_store.clone(),
 			relay_parent: context.parent_hash.clone(),
-			work
+			work,
+			_in_flight: self.shutdown.in_flight(),
+		})
+	}
+
+	// produce work re-issuing the local statement on a candidate validated before a
+	// restart, provided that its data is still available. The candidate is not fetched
+	// or validated again, and signing is left to the work, off the table lock.
+	fn restore_validation<Fetch>(&mut self, context: &TableContext, digest: &CandidateHash)
+		-> Option<ParachainWork<Fetch>>
+	{
+		if self.validated.contains_key(digest) { return None }
+
+		let relay_parent = context.parent_hash;
+		let candidate_receipt = self.table.get_candidate(digest)?.clone();
+		let block_data = self.extrinsic_store.block_data(relay_parent, *digest)?;
+		let extrinsic = self.extrinsic_store.extrinsic(relay_parent, *digest)?;
+
+		debug!(target: "validation", "Restoring local validation of candidate {}", digest);
+		self.validated.insert(*digest, ValidationWork::InProgress);
+
+		Some(ParachainWork {
+			extrinsic_store: self.extrinsic_store.clone(),
+			relay_parent,
+			work: Work {
+				candidate_receipt,
+				input: WorkInput::Restored(block_data, extrinsic),
+			},
+			_in_flight: self.shutdown.in_flight(),
 		})
 	}
 
-	fn update_trackers(&mut self, candidate: &Hash, context: &TableContext) {
+	fn update_trackers(&mut self, candidate: &CandidateHash, context: &TableContext) {
 		let includable = self.table.candidate_includable(candidate, context);
 		for i in (0..self.trackers.len()).rev() {
 			if self.trackers[i].update_candidate(candidate.clone(), includable) {
 				self.trackers.swap_remove(i);
 			}
 		}
+
+		if includable && self.announced_backed.insert(*candidate) {
+			if let Some(attested) = self.table.attested_candidate(candidate, context) {
+				self.backed_candidates.notify(BackedCandidate {
+					relay_parent: context.parent_hash,
+					candidate: attested_to_primitive(attested),
+				});
+			}
+		}
+	}
+}
+
+// we transform the types of the attestations gathered from the table
+// into the type expected by the runtime. This may do signature
+// aggregation in the future.
+fn attested_to_primitive(attested: table::AttestedCandidate) -> AttestedCandidate {
+	use table::generic::ValidityAttestation as GAttestation;
+
+	AttestedCandidate {
+		candidate: attested.candidate,
+		validity_votes: attested.validity_votes.into_iter().map(|(a, v)| match v {
+			GAttestation::Implicit(s) => (a, ValidityAttestation::Implicit(s)),
+			GAttestation::Explicit(s) => (a, ValidityAttestation::Explicit(s)),
+		}).collect(),
 	}
 }
 
@@ -201,7 +288,7 @@ pub struct Validated {
 
 impl Validated {
 	/// Note that we've validated a candidate with given hash and it is bad.
-	pub fn known_bad(hash: Hash, block_data: BlockData) -> Self {
+	pub fn known_bad(hash: CandidateHash, block_data: BlockData) -> Self {
 		Validated {
 			statement: GenericStatement::Invalid(hash),
 			result: Validation::Invalid(block_data),
@@ -210,7 +297,7 @@ impl Validated {
 
 	/// Note that we've validated a candidate with given hash and it is good.
 	/// Extrinsic data required.
-	pub fn known_good(hash: Hash, block_data: BlockData, extrinsic: Extrinsic) -> Self {
+	pub fn known_good(hash: CandidateHash, block_data: BlockData, extrinsic: Extrinsic) -> Self {
 		Validated {
 			statement: GenericStatement::Valid(hash),
 			result: Validation::Valid(block_data, extrinsic),
@@ -251,6 +338,7 @@ pub struct ParachainWork<Fetch> {
 	work: Work<Fetch>,
 	relay_parent: Hash,
 	extrinsic_store: ExtrinsicStore,
+	_in_flight: InFlight,
 }
 
 impl<Fetch: Future> ParachainWork<Fetch> {
@@ -264,13 +352,29 @@ impl<Fetch: Future> ParachainWork<Fetch> {
 		where
 			P: Send + Sync + 'static,
 			P::Api: ParachainHost<Block>,
+	{
+		self.prime_recording(api, None)
+	}
+
+	/// Prime the parachain work with an API reference for extracting
+	/// chain information, recording failed executions for replay if a
+	/// recorder is given.
+	pub fn prime_recording<P: ProvideRuntimeApi>(self, api: Arc<P>, recorder: Option<ReplayRecorder>)
+		-> PrimedParachainWork<
+			Fetch,
+			impl Send + FnMut(&BlockId, &Collation, &Incoming) -> Result<Extrinsic, ()>,
+		>
+		where
+			P: Send + Sync + 'static,
+			P::Api: ParachainHost<Block>,
 	{
 		let validate = move |id: &_, collation: &_, incoming: &_| {
-			let res = ::collation::validate_collation(
+			let res = ::collation::validate_collation_recording(
 				&*api,
 				id,
 				collation,
 				incoming,
+				recorder.as_ref(),
 			);
 
 			match res {
@@ -295,7 +399,14 @@ impl<Fetch: Future> ParachainWork<Fetch> {
 
 struct Work<Fetch> {
 	candidate_receipt: CandidateReceipt,
-	fetch: Fetch
+	input: WorkInput<Fetch>,
+}
+
+enum WorkInput<Fetch> {
+	// fetch the data of the candidate and validate it.
+	Fetch(Fetch),
+	// the data of a candidate validated before a restart, which isn't validated again.
+	Restored(BlockData, Extrinsic),
 }
 
 /// Primed statement producer.
@@ -317,7 +428,12 @@ impl<Fetch, F, Err> Future for PrimedParachainWork<Fetch, F>
 		let work = &mut self.inner.work;
 		let candidate = &work.candidate_receipt;
 
-		let (block, incoming) = try_ready!(work.fetch.poll());
+		let (block, incoming) = match work.input {
+			WorkInput::Fetch(ref mut fetch) => try_ready!(fetch.poll()),
+			WorkInput::Restored(ref block_data, ref extrinsic) => return Ok(Async::Ready(
+				Validated::known_good(candidate.hash(), block_data.clone(), extrinsic.clone())
+			)),
+		};
 		let validation_res = (self.validate)(
 			&BlockId::hash(self.inner.relay_parent),
 			&Collation { block_data: block.clone(), receipt: candidate.clone() },
@@ -357,6 +473,22 @@ impl<Fetch, F, Err> Future for PrimedParachainWork<Fetch, F>
 	}
 }
 
+/// The attestation state of a validation session, which can be exported from one
+/// node and imported by another.
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub struct AttestationSnapshot {
+	/// The relay chain parent the session is localized to.
+	pub relay_parent: Hash,
+	/// Statements backing all votes counted by the table, candidates first.
+	pub statements: Vec<SignedStatement>,
+	/// Statements signed locally, in the order they were signed.
+	pub local_statements: Vec<SignedStatement>,
+	/// Data of the candidates validated as good locally.
+	pub available: Vec<(CandidateHash, BlockData, Extrinsic)>,
+	/// Candidates already included in a child block.
+	pub included: Vec<CandidateHash>,
+}
+
 /// A shared table object.
 pub struct SharedTable {
 	context: Arc<TableContext>,
@@ -375,25 +507,54 @@ impl Clone for SharedTable {
 impl SharedTable {
 	/// Create a new shared table.
 	///
-	/// Provide the key to sign with, and the parent hash of the relay chain
-	/// block being built.
+	/// Provide the validators eligible for parachain duty, the signer of local
+	/// statements, and the parent hash and number of the relay chain block being built.
+	/// Statements from validators outside of the eligible set are not counted.
 	pub fn new(
 		groups: HashMap<ParaId, GroupInfo>,
-		key: Arc<ed25519::Pair>,
+		validators: Vec<SessionKey>,
+		signer: Arc<StatementSigner>,
 		parent_hash: Hash,
+		parent_number: BlockNumber,
 		extrinsic_store: ExtrinsicStore,
 	) -> Self {
 		SharedTable {
-			context: Arc::new(TableContext { groups, key, parent_hash }),
+			context: Arc::new(TableContext { groups, validators, signer, parent_hash, parent_number }),
 			inner: Arc::new(Mutex::new(SharedTableInner {
 				table: Table::default(),
 				validated: HashMap::new(),
 				trackers: Vec::new(),
 				extrinsic_store,
+				restored: HashSet::new(),
+				persisted: HashSet::new(),
+				included: HashSet::new(),
+				local_statements: Vec::new(),
+				announced_backed: HashSet::new(),
+				backed_candidates: BackedCandidates::new(),
+				shutdown: Shutdown::new(),
 			}))
 		}
 	}
 
+	/// Track validation work started by the table with the given shutdown handle,
+	/// and don't start any more once it is draining.
+	pub fn with_shutdown(self, shutdown: Shutdown) -> Self {
+		self.inner.lock().shutdown = shutdown;
+		self
+	}
+
+	/// Announce candidates reaching the backing threshold in this table to the
+	/// subscribers of the given broadcast.
+	pub fn with_backed_candidates(self, backed_candidates: BackedCandidates) -> Self {
+		self.inner.lock().backed_candidates = backed_candidates;
+		self
+	}
+
+	/// Get the validators eligible for parachain duty.
+	pub fn parachain_validators(&self) -> &[SessionKey] {
+		&self.context.validators
+	}
+
 	/// Get the parent hash this table should hold statements localized to.
 	pub fn consensus_parent_hash(&self) -> &Hash {
 		&self.context.parent_hash
@@ -413,7 +574,7 @@ impl SharedTable {
 	///
 	/// This will return `Some` for any candidates that have been validated
 	/// locally.
-	pub(crate) fn extrinsic_data(&self, hash: &Hash) -> Option<Extrinsic> {
+	pub(crate) fn extrinsic_data(&self, hash: &CandidateHash) -> Option<Extrinsic> {
 		self.inner.lock().validated.get(hash).and_then(|x| match *x {
 			ValidationWork::Error(_) => None,
 			ValidationWork::InProgress => None,
@@ -422,6 +583,151 @@ impl SharedTable {
 		})
 	}
 
+	/// Note that candidates have been included in a child block of the relay parent.
+	///
+	/// Statements about these candidates are still imported, but no longer trigger
+	/// validation work. Data of candidates already validated is kept, as it is needed
+	/// to circulate their outgoing messages.
+	pub fn note_included(&self, candidates: &[CandidateHash]) {
+		self.inner.lock().included.extend(candidates.iter().cloned())
+	}
+
+	/// Whether a candidate has been noted as included.
+	pub fn is_included(&self, candidate: &CandidateHash) -> bool {
+		self.inner.lock().included.contains(candidate)
+	}
+
+	/// Whether a candidate has the validity votes needed to be included.
+	pub fn is_backed(&self, candidate: &CandidateHash) -> bool {
+		self.inner.lock().table.attested_candidate(candidate, &*self.context).is_some()
+	}
+
+	/// Restore the digest of gossip state persisted for this relay parent, if any,
+	/// returning the hashes of the gossip messages already seen.
+	///
+	/// Candidates validated locally before the digest was persisted will not be
+	/// validated again, as long as their data is still available.
+	pub fn restore_seen(&self) -> Vec<Hash> {
+		let mut inner = self.inner.lock();
+		match inner.extrinsic_store.seen(self.context.parent_hash) {
+			Some(digest) => {
+				inner.persisted.extend(digest.candidates.iter().cloned());
+				inner.restored.extend(digest.candidates);
+				digest.statements
+			}
+			None => Vec::new(),
+		}
+	}
+
+	/// Add to the persisted digest of gossip state for this relay parent: the hashes of
+	/// the given gossip messages, along with the candidates validated locally which
+	/// haven't been persisted yet.
+	pub fn persist_seen<I: IntoIterator<Item=Hash>>(&self, statements: I) {
+		let mut inner = self.inner.lock();
+		let candidates: Vec<_> = inner.validated.iter()
+			.filter_map(|(hash, work)| match *work {
+				ValidationWork::Done(Validation::Valid(_, _)) => Some(*hash),
+				_ => None,
+			})
+			.filter(|hash| !inner.persisted.contains(hash))
+			.collect();
+
+		let digest = SeenDigest {
+			statements: statements.into_iter().collect(),
+			candidates,
+		};
+
+		match inner.extrinsic_store.note_seen(self.context.parent_hash, &digest) {
+			Ok(()) => inner.persisted.extend(digest.candidates),
+			Err(e) => warn!(target: "validation", "Failed to persist gossip digest: {:?}", e),
+		}
+	}
+
+	/// Export the attestation state of this table.
+	pub fn export_snapshot(&self) -> AttestationSnapshot {
+		let inner = self.inner.lock();
+		let available = inner.validated.iter()
+			.filter_map(|(hash, work)| match *work {
+				ValidationWork::Done(Validation::Valid(ref block_data, ref extrinsic)) =>
+					Some((*hash, block_data.clone(), extrinsic.clone())),
+				_ => None,
+			})
+			.collect();
+
+		AttestationSnapshot {
+			relay_parent: self.context.parent_hash,
+			statements: inner.table.statements(),
+			local_statements: inner.local_statements.clone(),
+			available,
+			included: inner.included.iter().cloned().collect(),
+		}
+	}
+
+	/// Import attestation state exported by another table at the same relay parent.
+	///
+	/// All signatures are checked before anything is imported. Data of candidates
+	/// validated by the exporting node is made available, and those candidates are
+	/// not validated again. Block data not matching its candidate's receipt is skipped. Statements signed with the local key are kept as local
+	/// statements. No validation work is started by the import.
+	pub fn import_snapshot(&self, snapshot: AttestationSnapshot) -> Result<(), Error> {
+		let parent_hash = self.context.parent_hash;
+		if snapshot.relay_parent != parent_hash {
+			return Err(ErrorKind::SnapshotRelayParent(parent_hash, snapshot.relay_parent).into());
+		}
+
+		let bad_signature = snapshot.statements.iter()
+			.chain(snapshot.local_statements.iter())
+			.find(|signed| !::check_statement(&signed.statement, &signed.signature, signed.sender, &parent_hash));
+
+		if let Some(signed) = bad_signature {
+			return Err(ErrorKind::SnapshotBadSignature(signed.sender).into());
+		}
+
+		let mut inner = self.inner.lock();
+		for statement in snapshot.statements {
+			let summary = inner.table.import_statement(&*self.context, statement);
+			if let Some(summary) = summary {
+				inner.update_trackers(&summary.candidate, &*self.context);
+			}
+		}
+
+		for (candidate_hash, block_data, extrinsic) in snapshot.available {
+			let parachain_id = match inner.table.get_candidate(&candidate_hash) {
+				Some(candidate) if candidate.block_data_hash == block_data.hash() => candidate.parachain_index,
+				Some(_) => {
+					warn!(target: "validation", "Skipping imported block data not matching candidate {}", candidate_hash);
+					continue;
+				}
+				None => continue,
+			};
+
+			let data = Data {
+				relay_parent: parent_hash,
+				parachain_id,
+				candidate_hash,
+				block_data: block_data.clone(),
+				extrinsic: Some(extrinsic.clone()),
+			};
+
+			if let Err(e) = inner.extrinsic_store.make_available(data) {
+				warn!(target: "validation", "Failed to make imported candidate {} available: {:?}", candidate_hash, e);
+				continue;
+			}
+
+			inner.validated.insert(candidate_hash, ValidationWork::Done(Validation::Valid(block_data, extrinsic)));
+		}
+
+		let local_id = self.context.local_id();
+		for statement in snapshot.local_statements {
+			if statement.sender == local_id && !inner.local_statements.contains(&statement) {
+				inner.local_statements.push(statement);
+			}
+		}
+
+		inner.included.extend(snapshot.included);
+		Ok(())
+	}
+
 	/// Import a single statement with remote source, whose signature has already been checked.
 	///
 	/// The statement producer, if any, will produce only statements concerning the same candidate
@@ -460,27 +766,50 @@ impl SharedTable {
 	}
 
 	/// Sign and import the result of candidate validation.
+	///
+	/// Signing happens off the table lock. If it fails, the candidate is left
+	/// unvalidated, so that later statements on it may trigger validation again.
 	pub fn import_validated(&self, validated: Validated)
-		-> SignedStatement
+		-> impl Future<Item=SignedStatement, Error=Error> + Send
 	{
 		let digest = match validated.statement {
 			GenericStatement::Candidate(ref c) => c.hash(),
 			GenericStatement::Valid(h) | GenericStatement::Invalid(h) => h,
 		};
 
-		let signed_statement = self.context.sign_statement(validated.statement);
+		let Validated { statement, result } = validated;
+		let context = self.context.clone();
+		let inner = self.inner.clone();
 
-		let mut inner = self.inner.lock();
-		inner.table.import_statement(&*self.context, signed_statement.clone());
-		inner.validated.insert(digest, ValidationWork::Done(validated.result));
+		self.context.sign_statement(statement).then(move |res| {
+			let mut inner = inner.lock();
+			let signed_statement = match res {
+				Ok(signed_statement) => signed_statement,
+				Err(e) => {
+					warn!(target: "validation", "Failed to sign statement on candidate {}: {}", digest, e);
+					inner.validated.remove(&digest);
+					return Err(e);
+				}
+			};
 
-		signed_statement
+			inner.table.import_statement(&*context, signed_statement.clone());
+			inner.local_statements.push(signed_statement.clone());
+			inner.validated.insert(digest, ValidationWork::Done(result));
+			inner.update_trackers(&digest, &*context);
+
+			Ok(signed_statement)
+		})
+	}
+
+	/// Get all statements signed locally, in the order they were signed.
+	pub fn local_statements(&self) -> Vec<SignedStatement> {
+		self.inner.lock().local_statements.clone()
 	}
 
 	/// Execute a closure using a specific candidate.
 	///
 	/// Deadlocks if called recursively.
-	pub fn with_candidate<F, U>(&self, digest: &Hash, f: F) -> U
+	pub fn with_candidate<F, U>(&self, digest: &CandidateHash, f: F) -> U
 		where F: FnOnce(Option<&CandidateReceipt>) -> U
 	{
 		let inner = self.inner.lock();
@@ -489,22 +818,8 @@ impl SharedTable {
 
 	/// Get a set of candidates that can be proposed.
 	pub fn proposed_set(&self) -> Vec<AttestedCandidate> {
-		use table::generic::{ValidityAttestation as GAttestation};
-		use polkadot_primitives::parachain::ValidityAttestation;
-
-		// we transform the types of the attestations gathered from the table
-		// into the type expected by the runtime. This may do signature
-		// aggregation in the future.
 		let table_attestations = self.inner.lock().table.proposed_candidates(&*self.context);
-		table_attestations.into_iter()
-			.map(|attested| AttestedCandidate {
-				candidate: attested.candidate,
-				validity_votes: attested.validity_votes.into_iter().map(|(a, v)| match v {
-					GAttestation::Implicit(s) => (a, ValidityAttestation::Implicit(s)),
-					GAttestation::Explicit(s) => (a, ValidityAttestation::Explicit(s)),
-				}).collect(),
-			})
-			.collect()
+		table_attestations.into_iter().map(attested_to_primitive).collect()
 	}
 
 	/// Get the number of total parachains.
@@ -524,7 +839,7 @@ impl SharedTable {
 
 	/// Track includability  of a given set of candidate hashes.
 	pub fn track_includability<I>(&self, iterable: I) -> Includable
-		where I: IntoIterator<Item=Hash>
+		where I: IntoIterator<Item=CandidateHash>
 	{
 		let mut inner = self.inner.lock();
 
@@ -546,6 +861,7 @@ mod tests {
 	use super::*;
 	use substrate_keyring::Keyring;
 	use futures::future;
+	use codec::{Decode, Encode};
 
 	#[derive(Clone)]
 	struct DummyRouter;
@@ -565,6 +881,12 @@ mod tests {
 		fn fetch_incoming(&self, _para_id: ParaId) -> Self::FetchIncoming {
 			future::ok(Vec::new())
 		}
+
+		fn candidates_included(&self, _candidates: &[CandidateHash]) { }
+
+		fn flush(&self) { }
+
+		fn end_session(&self) { }
 	}
 
 	#[test]
@@ -586,8 +908,10 @@ mod tests {
 
 		let shared_table = SharedTable::new(
 			groups,
+			vec![local_id, validity_other],
 			local_key.clone(),
 			parent_hash,
+			0,
 			ExtrinsicStore::new_in_memory(),
 		);
 
@@ -600,6 +924,7 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
 		};
 
 		let candidate_statement = GenericStatement::Candidate(candidate);
@@ -636,8 +961,10 @@ mod tests {
 
 		let shared_table = SharedTable::new(
 			groups,
+			vec![local_id, validity_other],
 			local_key.clone(),
 			parent_hash,
+			0,
 			ExtrinsicStore::new_in_memory(),
 		);
 
@@ -650,6 +977,7 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
 		};
 
 		let candidate_statement = GenericStatement::Candidate(candidate);
@@ -667,6 +995,246 @@ mod tests {
 		).expect("should produce work");
 	}
 
+	#[test]
+	fn statement_from_ineligible_validator_is_ignored() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 1,
+		});
+
+		// the other group member is not among the parachain validators.
+		let shared_table = SharedTable::new(
+			groups,
+			vec![local_id],
+			local_key.clone(),
+			parent_hash,
+			0,
+			ExtrinsicStore::new_in_memory(),
+		);
+		assert_eq!(shared_table.parachain_validators(), &[local_id][..]);
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
+		};
+
+		let candidate_statement = GenericStatement::Candidate(candidate);
+
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		assert!(shared_table.import_remote_statement(&DummyRouter, signed_statement).is_none());
+		assert!(shared_table.proposed_set().is_empty());
+	}
+
+	#[test]
+	fn included_candidate_not_validated() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 2,
+		});
+
+		let shared_table = SharedTable::new(
+			groups,
+			vec![local_id, validity_other],
+			local_key.clone(),
+			parent_hash,
+			0,
+			ExtrinsicStore::new_in_memory(),
+		);
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
+		};
+		let hash = candidate.hash();
+
+		shared_table.note_included(&[hash]);
+		assert!(shared_table.is_included(&hash));
+
+		let candidate_statement = GenericStatement::Candidate(candidate);
+
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		assert!(shared_table.import_remote_statement(&DummyRouter, signed_statement).is_none());
+		assert!(shared_table.with_candidate(&hash, |c| c.is_some()));
+		assert!(!shared_table.inner.lock().validated.contains_key(&hash));
+	}
+
+	#[test]
+	fn no_work_dispatched_while_draining() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 2,
+		});
+
+		let shutdown = Shutdown::new();
+		let shared_table = SharedTable::new(
+			groups,
+			vec![local_id, validity_other],
+			local_key.clone(),
+			parent_hash,
+			0,
+			ExtrinsicStore::new_in_memory(),
+		).with_shutdown(shutdown.clone());
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
+		};
+
+		let hash = candidate.hash();
+		let candidate_statement = GenericStatement::Candidate(candidate);
+
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		assert_eq!(shutdown.drain(::std::time::Duration::from_secs(0)), 0);
+
+		assert!(shared_table.import_remote_statement(&DummyRouter, signed_statement).is_none());
+		assert!(shared_table.with_candidate(&hash, |c| c.is_some()));
+		assert!(!shared_table.inner.lock().validated.contains_key(&hash));
+	}
+
+	#[test]
+	fn restored_candidate_not_validated_again() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+		let store = ExtrinsicStore::new_in_memory();
+		let block_data = BlockData(vec![1, 2, 3]);
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 2,
+		});
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
+		};
+		let candidate_hash = candidate.hash();
+		let gossip_hash = [9; 32].into();
+
+		// validated and persisted before the restart.
+		{
+			let shared_table = SharedTable::new(groups.clone(), vec![local_id, validity_other], local_key.clone(), parent_hash, 0, store.clone());
+			shared_table.import_validated(Validated::known_good(candidate_hash, block_data.clone(), extrinsic.clone())).wait().unwrap();
+			shared_table.persist_seen(vec![gossip_hash]);
+		}
+
+		store.make_available(Data {
+			relay_parent: parent_hash,
+			parachain_id: para_id,
+			candidate_hash,
+			block_data,
+			extrinsic: Some(extrinsic),
+		}).unwrap();
+
+		let shared_table = SharedTable::new(groups, vec![local_id, validity_other], local_key.clone(), parent_hash, 0, store);
+		assert_eq!(shared_table.restore_seen(), vec![gossip_hash]);
+
+		let candidate_statement = GenericStatement::Candidate(candidate);
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		// the statement is re-issued by the work, without validating again.
+		let work = shared_table.import_remote_statement(&DummyRouter, signed_statement).unwrap();
+		assert!(shared_table.inner.lock().validated.get(&candidate_hash).unwrap().is_in_progress());
+
+		let validated = work.prime_with(|_, _, _| panic!("restored candidates are not validated again"))
+			.wait()
+			.unwrap();
+		assert_eq!(validated.statement, GenericStatement::Valid(candidate_hash));
+
+		shared_table.import_validated(validated).wait().unwrap();
+		assert!(shared_table.inner.lock().validated.get(&candidate_hash).unwrap().is_done());
+		assert!(shared_table.extrinsic_data(&candidate_hash).is_some());
+		assert_eq!(shared_table.includable_count(), 1);
+	}
+
 	#[test]
 	fn evaluate_makes_block_data_available() {
 		let store = ExtrinsicStore::new_in_memory();
@@ -683,6 +1251,7 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
 		};
 
 		let hash = candidate.hash();
@@ -690,10 +1259,11 @@ mod tests {
 		let producer: ParachainWork<future::FutureResult<_, ::std::io::Error>> = ParachainWork {
 			work: Work {
 				candidate_receipt: candidate,
-				fetch: future::ok((block_data.clone(), Vec::new())),
+				input: WorkInput::Fetch(future::ok((block_data.clone(), Vec::new()))),
 			},
 			relay_parent,
 			extrinsic_store: store.clone(),
+			_in_flight: Shutdown::new().in_flight(),
 		};
 
 		let validated = producer.prime_with(|_, _, _| Ok(Extrinsic { outgoing_messages: Vec::new() }))
@@ -723,6 +1293,7 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
 		};
 
 		let hash = candidate.hash();
@@ -730,10 +1301,11 @@ mod tests {
 		let producer = ParachainWork {
 			work: Work {
 				candidate_receipt: candidate,
-				fetch: future::ok::<_, ::std::io::Error>((block_data.clone(), Vec::new())),
+				input: WorkInput::Fetch(future::ok::<_, ::std::io::Error>((block_data.clone(), Vec::new()))),
 			},
 			relay_parent,
 			extrinsic_store: store.clone(),
+			_in_flight: Shutdown::new().in_flight(),
 		};
 
 		let validated = producer.prime_with(|_, _, _| Ok(Extrinsic { outgoing_messages: Vec::new() }))
@@ -765,8 +1337,10 @@ mod tests {
 
 		let shared_table = SharedTable::new(
 			groups,
+			vec![local_id, validity_other],
 			local_key.clone(),
 			parent_hash,
+			0,
 			ExtrinsicStore::new_in_memory(),
 		);
 
@@ -779,6 +1353,7 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
 		};
 
 		let hash = candidate.hash();
@@ -826,8 +1401,10 @@ mod tests {
 
 		let shared_table = SharedTable::new(
 			groups,
+			vec![local_id, validity_other],
 			local_key.clone(),
 			parent_hash,
+			0,
 			ExtrinsicStore::new_in_memory(),
 		);
 
@@ -840,6 +1417,7 @@ mod tests {
 			egress_queue_roots: Vec::new(),
 			fees: 1_000_000,
 			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
 		};
 
 		let hash = candidate.hash();
@@ -847,7 +1425,7 @@ mod tests {
 			candidate,
 			block_data,
 			extrinsic,
-		));
+		)).wait().expect("local signing never fails");
 
 		assert!(shared_table.inner.lock().validated.get(&hash).expect("validation has started").is_done());
 
@@ -858,4 +1436,213 @@ mod tests {
 
 		assert!(a.is_none());
 	}
+
+	#[test]
+	fn backed_candidate_announced_once() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 2,
+		});
+
+		let backed_candidates = BackedCandidates::new();
+		let backed = backed_candidates.subscribe();
+
+		let shared_table = SharedTable::new(
+			groups,
+			vec![local_id, validity_other],
+			local_key.clone(),
+			parent_hash,
+			0,
+			ExtrinsicStore::new_in_memory(),
+		).with_backed_candidates(backed_candidates);
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
+		};
+
+		let hash = candidate.hash();
+		let candidate_statement = GenericStatement::Candidate(candidate.clone());
+
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		assert!(shared_table.import_remote_statement(&DummyRouter, signed_statement.clone()).is_some());
+		assert!(!shared_table.is_backed(&hash));
+
+		// the local vote reaches the threshold.
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+		shared_table.import_validated(Validated::known_good(hash, BlockData(vec![1, 2, 3]), extrinsic)).wait().unwrap();
+		assert!(shared_table.is_backed(&hash));
+		assert!(shared_table.import_remote_statement(&DummyRouter, signed_statement).is_none());
+
+		drop(shared_table);
+		let backed: Vec<_> = backed.wait().map(|c| c.unwrap()).collect();
+		assert_eq!(backed.len(), 1);
+		assert_eq!(backed[0].relay_parent, parent_hash);
+		assert_eq!(backed[0].candidate.candidate, candidate);
+		assert_eq!(backed[0].candidate.validity_votes.len(), 2);
+	}
+
+	#[test]
+	fn snapshot_moves_attestation_state() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+		let block_data = BlockData(vec![1, 2, 3]);
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 2,
+		});
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: block_data.hash(),
+			ingress_watermark: 0,
+		};
+		let candidate_hash = candidate.hash();
+
+		let candidate_statement = GenericStatement::Candidate(candidate);
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		let old_table = SharedTable::new(
+			groups.clone(),
+			vec![local_id, validity_other],
+			local_key.clone(),
+			parent_hash,
+			0,
+			ExtrinsicStore::new_in_memory(),
+		);
+		old_table.import_remote_statement(&DummyRouter, signed_statement);
+		old_table.import_validated(Validated::known_good(candidate_hash, block_data.clone(), extrinsic)).wait().unwrap();
+		old_table.note_included(&[candidate_hash]);
+
+		let snapshot = old_table.export_snapshot();
+		assert_eq!(snapshot.statements.len(), 2);
+		let snapshot = AttestationSnapshot::decode(&mut &snapshot.encode()[..]).unwrap();
+
+		let store = ExtrinsicStore::new_in_memory();
+		let new_table = SharedTable::new(groups.clone(), vec![local_id, validity_other], local_key.clone(), parent_hash, 0, store.clone());
+
+		let mut elsewhere = snapshot.clone();
+		elsewhere.relay_parent = [1; 32].into();
+		assert!(new_table.import_snapshot(elsewhere).is_err());
+
+		let mut forged = snapshot.clone();
+		forged.statements[0].sender = local_id;
+		assert!(new_table.import_snapshot(forged).is_err());
+		assert_eq!(new_table.includable_count(), 0);
+
+		new_table.import_snapshot(snapshot).unwrap();
+		assert!(new_table.is_backed(&candidate_hash));
+		assert!(new_table.is_included(&candidate_hash));
+		assert_eq!(new_table.local_statements(), old_table.local_statements());
+		assert!(new_table.extrinsic_data(&candidate_hash).is_some());
+		assert_eq!(store.block_data(parent_hash, candidate_hash), Some(block_data));
+	}
+
+	#[test]
+	fn snapshot_skips_mismatched_block_data() {
+		let mut groups = HashMap::new();
+
+		let para_id = ParaId::from(1);
+		let local_id = Keyring::Alice.to_raw_public().into();
+		let local_key = Arc::new(Keyring::Alice.pair());
+
+		let validity_other = Keyring::Bob.to_raw_public().into();
+		let validity_other_key = Keyring::Bob.pair();
+		let parent_hash = Default::default();
+		let block_data = BlockData(vec![1, 2, 3]);
+		let extrinsic = Extrinsic { outgoing_messages: Vec::new() };
+
+		groups.insert(para_id, GroupInfo {
+			validity_guarantors: [local_id, validity_other].iter().cloned().collect(),
+			needed_validity: 2,
+		});
+
+		let candidate = CandidateReceipt {
+			parachain_index: para_id,
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3, 4]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees: 1_000_000,
+			block_data_hash: block_data.hash(),
+			ingress_watermark: 0,
+		};
+		let candidate_hash = candidate.hash();
+
+		let candidate_statement = GenericStatement::Candidate(candidate);
+		let signature = ::sign_table_statement(&candidate_statement, &validity_other_key, &parent_hash);
+		let signed_statement = ::table::generic::SignedStatement {
+			statement: candidate_statement,
+			signature: signature.into(),
+			sender: validity_other,
+		};
+
+		let old_table = SharedTable::new(
+			groups.clone(),
+			vec![local_id, validity_other],
+			local_key.clone(),
+			parent_hash,
+			0,
+			ExtrinsicStore::new_in_memory(),
+		);
+		old_table.import_remote_statement(&DummyRouter, signed_statement);
+		old_table.import_validated(Validated::known_good(candidate_hash, block_data, extrinsic)).wait().unwrap();
+
+		// the data isn't covered by the signatures, so can be swapped.
+		let mut snapshot = old_table.export_snapshot();
+		assert_eq!(snapshot.available.len(), 1);
+		snapshot.available[0].1 = BlockData(vec![4, 5, 6]);
+
+		let store = ExtrinsicStore::new_in_memory();
+		let new_table = SharedTable::new(groups, vec![local_id, validity_other], local_key, parent_hash, 0, store.clone());
+		new_table.import_snapshot(snapshot).unwrap();
+
+		assert!(new_table.is_backed(&candidate_hash));
+		assert!(new_table.extrinsic_data(&candidate_hash).is_none());
+		assert_eq!(store.block_data(parent_hash, candidate_hash), None);
+	}
 }
@@ -0,0 +1,151 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Graceful shutdown of parachain validation.
+//!
+//! When the node is asked to stop, no new validation sessions or validation work
+//! are started, and work already in flight is given a grace period to complete.
+//! Afterwards, registered flush hooks run, circulating local statements once more
+//! and persisting state, before the node drops its sessions.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+#[derive(Default)]
+struct Inner {
+	draining: AtomicBool,
+	in_flight: Mutex<usize>,
+	finished: Condvar,
+	flush_hooks: Mutex<Vec<Box<Fn() + Send>>>,
+}
+
+/// Handle for coordinating a graceful shutdown. Clones refer to the same shutdown.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+	inner: Arc<Inner>,
+}
+
+impl Shutdown {
+	/// Create a new shutdown handle.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Whether a shutdown has begun, in which case no new work should be started.
+	pub fn is_draining(&self) -> bool {
+		self.inner.draining.load(Ordering::SeqCst)
+	}
+
+	/// Note a piece of in-flight work, which shutdown waits for until the returned
+	/// guard is dropped.
+	pub fn in_flight(&self) -> InFlight {
+		*self.inner.in_flight.lock() += 1;
+		InFlight { inner: self.inner.clone() }
+	}
+
+	/// Register a hook to be called once in-flight work has completed, or the grace
+	/// period has expired.
+	pub fn on_flush<F: Fn() + Send + 'static>(&self, hook: F) {
+		self.inner.flush_hooks.lock().push(Box::new(hook));
+	}
+
+	/// Stop accepting new work, wait up to `grace_period` for in-flight work to
+	/// complete, and run the flush hooks.
+	///
+	/// Returns the number of pieces of work which did not complete in time.
+	pub fn drain(&self, grace_period: Duration) -> usize {
+		self.inner.draining.store(true, Ordering::SeqCst);
+
+		let deadline = Instant::now() + grace_period;
+		let unfinished = {
+			let mut in_flight = self.inner.in_flight.lock();
+			while *in_flight > 0 {
+				if self.inner.finished.wait_until(&mut in_flight, deadline).timed_out() {
+					break
+				}
+			}
+
+			*in_flight
+		};
+
+		for hook in self.inner.flush_hooks.lock().iter() {
+			hook();
+		}
+
+		unfinished
+	}
+}
+
+/// Guard for a piece of in-flight work. See `Shutdown::in_flight`.
+pub struct InFlight {
+	inner: Arc<Inner>,
+}
+
+impl Drop for InFlight {
+	fn drop(&mut self) {
+		let mut in_flight = self.inner.in_flight.lock();
+		*in_flight -= 1;
+		if *in_flight == 0 {
+			self.inner.finished.notify_all();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::AtomicUsize;
+	use std::thread;
+
+	#[test]
+	fn drain_waits_for_in_flight_work_then_flushes() {
+		let shutdown = Shutdown::new();
+		let flushed = Arc::new(AtomicUsize::new(0));
+
+		{
+			let flushed = flushed.clone();
+			shutdown.on_flush(move || { flushed.fetch_add(1, Ordering::SeqCst); });
+		}
+
+		let work = shutdown.in_flight();
+		assert!(!shutdown.is_draining());
+
+		let handle = {
+			let shutdown = shutdown.clone();
+			thread::spawn(move || shutdown.drain(Duration::from_secs(10)))
+		};
+
+		while !shutdown.is_draining() { thread::yield_now() }
+		assert_eq!(flushed.load(Ordering::SeqCst), 0);
+
+		drop(work);
+		assert_eq!(handle.join().unwrap(), 0);
+		assert_eq!(flushed.load(Ordering::SeqCst), 1);
+	}
+
+	#[test]
+	fn drain_gives_up_after_grace_period() {
+		let shutdown = Shutdown::new();
+		let _work = shutdown.in_flight();
+		let _other_work = shutdown.in_flight();
+
+		assert_eq!(shutdown.drain(Duration::from_millis(10)), 2);
+		assert!(shutdown.is_draining());
+	}
+}
@@ -0,0 +1,595 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Signing of attestation statements.
+//!
+//! By default statements are signed with the session key held in the node's keystore.
+//! Validators may instead delegate signing to a separate process, for example one backed by
+//! an HSM, using a `RemoteSigner`.
+//!
+//! The remote signer is spoken to over a TCP connection. Requests aren't authenticated, so
+//! the signing process only accepts connections on loopback addresses. Each request
+//! is an encoded `SigningRequest` and each response an encoded `Option<CandidateSignature>`,
+//! both prefixed with their length as an encoded `u32`. The signing process should
+//! apply a `SigningPolicy` and refuse requests which could lead to conflicting statements.
+//!
+//! Requests to the remote signer are made from a dedicated thread, so signing never blocks
+//! the caller.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use codec::{Decode, Encode};
+use futures::prelude::*;
+use futures::future;
+use futures::sync::oneshot;
+use parking_lot::Mutex;
+use polkadot_primitives::{BlockNumber, Hash, SessionKey};
+use polkadot_primitives::parachain::{CandidateHash, CandidateSignature};
+use primitives::ed25519;
+use table::generic::Statement as GenericStatement;
+
+use super::Statement;
+use error::{Error, ErrorKind};
+
+/// Largest message accepted from the other side of a remote signing connection.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The number of times a request to the remote signer is made before giving up.
+/// The connection is re-established between attempts.
+const REMOTE_SIGNING_ATTEMPTS: usize = 3;
+
+/// The most relay chain parents at a single block number a signing policy signs at.
+/// Statements at further forks are refused, bounding the history kept.
+const MAX_PARENTS_PER_NUMBER: usize = 16;
+
+/// A future resolving to the signature on a statement.
+pub type SigningFuture = Box<Future<Item=CandidateSignature, Error=Error> + Send>;
+
+/// Something which can sign attestation statements on behalf of the local validator.
+pub trait StatementSigner: Send + Sync {
+	/// The session key statements are signed with.
+	fn session_key(&self) -> SessionKey;
+
+	/// Sign a statement localized to the given relay chain parent, with the given number.
+	fn sign_statement(&self, statement: &Statement, parent_hash: &Hash, parent_number: BlockNumber) -> SigningFuture;
+}
+
+impl StatementSigner for ed25519::Pair {
+	fn session_key(&self) -> SessionKey {
+		self.public().into()
+	}
+
+	fn sign_statement(&self, statement: &Statement, parent_hash: &Hash, _: BlockNumber) -> SigningFuture {
+		Box::new(future::ok(::sign_table_statement(statement, self, parent_hash)))
+	}
+}
+
+/// A request to sign a statement, as sent to a remote signer.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct SigningRequest {
+	/// The statement to sign.
+	pub statement: Statement,
+	/// The relay chain parent the statement is localized to.
+	pub parent_hash: Hash,
+	/// The block number of the relay chain parent.
+	pub parent_number: BlockNumber,
+}
+
+type SigningResponse = io::Result<Option<CandidateSignature>>;
+
+/// A statement signer delegating to a remote signing process.
+///
+/// Only the public session key is needed locally.
+pub struct RemoteSigner {
+	session_key: SessionKey,
+	requests: Mutex<mpsc::Sender<(SigningRequest, oneshot::Sender<SigningResponse>)>>,
+}
+
+impl RemoteSigner {
+	/// Create a new remote signer, signing with the given session key through the
+	/// signing process listening on `addr`. The connection is established lazily.
+	pub fn new(addr: SocketAddr, session_key: SessionKey) -> io::Result<Self> {
+		let (tx, rx) = mpsc::channel();
+
+		thread::Builder::new()
+			.name("remote-signer".into())
+			.spawn(move || {
+				let mut connection = SigningConnection {
+					addr,
+					timeout: Duration::from_secs(2),
+					stream: None,
+				};
+
+				// ends once the signer is dropped.
+				for (request, response) in rx {
+					let _ = response.send(connection.request_with_retries(&request));
+				}
+			})?;
+
+		Ok(RemoteSigner {
+			session_key,
+			requests: Mutex::new(tx),
+		})
+	}
+}
+
+impl StatementSigner for RemoteSigner {
+	fn session_key(&self) -> SessionKey {
+		self.session_key
+	}
+
+	fn sign_statement(&self, statement: &Statement, parent_hash: &Hash, parent_number: BlockNumber) -> SigningFuture {
+		let request = SigningRequest { statement: statement.clone(), parent_hash: *parent_hash, parent_number };
+		let (tx, rx) = oneshot::channel();
+		if self.requests.lock().send((request.clone(), tx)).is_err() {
+			return Box::new(future::err(ErrorKind::RemoteSigner(signer_gone()).into()));
+		}
+
+		let session_key = self.session_key;
+		Box::new(rx.then(move |response| -> Result<CandidateSignature, Error> {
+			let signature = response.unwrap_or_else(|_| Err(signer_gone()))
+				.map_err(ErrorKind::RemoteSigner)?
+				.ok_or(ErrorKind::SigningRefused)?;
+
+			if !::check_statement(&request.statement, &signature, session_key, &request.parent_hash) {
+				bail!(ErrorKind::BadRemoteSignature(session_key));
+			}
+
+			Ok(signature)
+		}))
+	}
+}
+
+fn signer_gone() -> io::Error {
+	io::Error::new(io::ErrorKind::BrokenPipe, "Remote signer thread has exited")
+}
+
+// the connection to the signing process, owned by the remote signer thread.
+struct SigningConnection {
+	addr: SocketAddr,
+	timeout: Duration,
+	stream: Option<TcpStream>,
+}
+
+impl SigningConnection {
+	fn request_with_retries(&mut self, request: &SigningRequest) -> SigningResponse {
+		let mut attempt = 1;
+		loop {
+			match self.request(request) {
+				Err(ref e) if attempt < REMOTE_SIGNING_ATTEMPTS => {
+					debug!(target: "validation", "Signing request failed, retrying: {}", e);
+					attempt += 1;
+				}
+				res => return res,
+			}
+		}
+	}
+
+	fn request(&mut self, request: &SigningRequest) -> SigningResponse {
+		if self.stream.is_none() {
+			let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+			stream.set_read_timeout(Some(self.timeout))?;
+			stream.set_write_timeout(Some(self.timeout))?;
+			self.stream = Some(stream);
+		}
+
+		let res = {
+			let conn = self.stream.as_mut().expect("connection established above; qed");
+			write_message(conn, &request.encode()).and_then(|_| read_message(conn))
+		};
+
+		match res {
+			Ok(response) => Decode::decode(&mut &response[..])
+				.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed signing response")),
+			Err(e) => {
+				// reconnect on the next request.
+				self.stream = None;
+				Err(e)
+			}
+		}
+	}
+}
+
+#[derive(Default, Clone, Encode, Decode)]
+struct SignedAtParent {
+	proposed: Option<CandidateHash>,
+	valid: Vec<CandidateHash>,
+	invalid: Vec<CandidateHash>,
+}
+
+impl SignedAtParent {
+	// note a statement as signed, or `false` if it conflicts with those signed before.
+	fn note(&mut self, statement: &Statement) -> bool {
+		match *statement {
+			GenericStatement::Candidate(ref candidate) => {
+				let digest = candidate.hash();
+				if self.proposed.map_or(false, |p| p != digest) || self.invalid.contains(&digest) {
+					return false;
+				}
+
+				self.proposed = Some(digest);
+				if !self.valid.contains(&digest) { self.valid.push(digest) }
+			}
+			GenericStatement::Valid(digest) => {
+				if self.invalid.contains(&digest) { return false }
+				if !self.valid.contains(&digest) { self.valid.push(digest) }
+			}
+			GenericStatement::Invalid(digest) => {
+				if self.valid.contains(&digest) { return false }
+				if !self.invalid.contains(&digest) { self.invalid.push(digest) }
+			}
+		}
+
+		true
+	}
+}
+
+// the statements signed at recent relay chain parents, by block number.
+#[derive(Default, Clone)]
+struct History {
+	signed: BTreeMap<BlockNumber, HashMap<Hash, SignedAtParent>>,
+	// the highest block number statements were forgotten at.
+	forgotten: Option<BlockNumber>,
+}
+
+impl Encode for History {
+	fn encode_to<T: ::codec::Output>(&self, dest: &mut T) {
+		let signed: Vec<(BlockNumber, Vec<(Hash, SignedAtParent)>)> = self.signed.iter()
+			.map(|(number, parents)| (*number, parents.iter().map(|(h, s)| (*h, s.clone())).collect()))
+			.collect();
+
+		self.forgotten.encode_to(dest);
+		signed.encode_to(dest);
+	}
+}
+
+impl Decode for History {
+	fn decode<I: ::codec::Input>(input: &mut I) -> Option<Self> {
+		let forgotten = Decode::decode(input)?;
+		let signed: Vec<(BlockNumber, Vec<(Hash, SignedAtParent)>)> = Decode::decode(input)?;
+
+		Some(History {
+			signed: signed.into_iter().map(|(number, parents)| (number, parents.into_iter().collect())).collect(),
+			forgotten,
+		})
+	}
+}
+
+/// Policy for a signing process, refusing to sign statements which conflict with
+/// statements signed before.
+///
+/// At most one candidate may be proposed per relay chain parent, and no candidate may be
+/// declared both valid and invalid. Statements are remembered at the most recent relay chain
+/// block numbers only, and nothing is signed at or below a number which has been forgotten.
+/// The history may be kept on disk, so that it survives restarts.
+pub struct SigningPolicy {
+	history: History,
+	max_numbers: usize,
+	path: Option<PathBuf>,
+}
+
+impl SigningPolicy {
+	/// Create a new policy, remembering statements at up to `max_numbers` relay chain
+	/// block numbers in memory.
+	pub fn new(max_numbers: usize) -> Self {
+		SigningPolicy {
+			history: History::default(),
+			max_numbers,
+			path: None,
+		}
+	}
+
+	/// Create a policy keeping its history in the file at `path`, loading the history
+	/// stored there by earlier runs.
+	pub fn open(path: PathBuf, max_numbers: usize) -> io::Result<Self> {
+		let history = match fs::read(&path) {
+			Ok(encoded) => History::decode(&mut &encoded[..])
+				.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Corrupt signing history"))?,
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => History::default(),
+			Err(e) => return Err(e),
+		};
+
+		Ok(SigningPolicy { history, max_numbers, path: Some(path) })
+	}
+
+	/// Check whether a statement may be signed, noting it as signed if so. Statements
+	/// are only signed once they are stored, if the history is kept on disk.
+	pub fn check_and_note(&mut self, statement: &Statement, parent_hash: &Hash, parent_number: BlockNumber) -> bool {
+		if self.history.forgotten.map_or(false, |forgotten| parent_number <= forgotten) {
+			return false;
+		}
+
+		let mut history = self.history.clone();
+		{
+			let at_number = history.signed.entry(parent_number).or_insert_with(HashMap::new);
+			if !at_number.contains_key(parent_hash) && at_number.len() >= MAX_PARENTS_PER_NUMBER {
+				return false;
+			}
+
+			if !at_number.entry(*parent_hash).or_insert_with(Default::default).note(statement) {
+				return false;
+			}
+		}
+
+		while history.signed.len() > self.max_numbers {
+			let oldest = *history.signed.keys().next().expect("more entries than the limit; qed");
+			history.signed.remove(&oldest);
+			history.forgotten = Some(history.forgotten.map_or(oldest, |f| ::std::cmp::max(f, oldest)));
+		}
+
+		if !history.signed.contains_key(&parent_number) {
+			return false;
+		}
+
+		if let Some(ref path) = self.path {
+			if let Err(e) = store_history(path, &history) {
+				warn!(target: "validation", "Failed to store signing history to {}: {}", path.display(), e);
+				return false;
+			}
+		}
+
+		self.history = history;
+		true
+	}
+}
+
+// replace the stored history atomically, so a crash never leaves it partially written.
+fn store_history(path: &PathBuf, history: &History) -> io::Result<()> {
+	let temp = path.with_extension("tmp");
+	fs::write(&temp, history.encode())?;
+	fs::rename(&temp, path)
+}
+
+/// Serve signing requests from a validator node, signing with the given key
+/// whatever the policy permits.
+///
+/// Connections are handled one at a time, so requests are checked against the policy
+/// in the order they are received. Requests aren't authenticated, so listeners on
+/// addresses other than loopback are refused. This only returns if the listener is
+/// refused or accepting a connection fails.
+pub fn serve_signing_requests(
+	listener: TcpListener,
+	key: &ed25519::Pair,
+	policy: &mut SigningPolicy,
+) -> io::Result<()> {
+	let local = listener.local_addr()?;
+	if !local.ip().is_loopback() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("Refusing to serve unauthenticated signing requests on non-loopback address {}", local),
+		));
+	}
+
+	loop {
+		let (stream, peer) = listener.accept()?;
+		debug!(target: "validation", "Accepted signing connection from {}", peer);
+
+		if let Err(e) = handle_signing_connection(stream, key, policy) {
+			debug!(target: "validation", "Signing connection from {} closed: {}", peer, e);
+		}
+	}
+}
+
+fn handle_signing_connection(
+	mut stream: TcpStream,
+	key: &ed25519::Pair,
+	policy: &mut SigningPolicy,
+) -> io::Result<()> {
+	loop {
+		let message = read_message(&mut stream)?;
+		let response = SigningRequest::decode(&mut &message[..]).and_then(|request| {
+			if policy.check_and_note(&request.statement, &request.parent_hash, request.parent_number) {
+				Some(::sign_table_statement(&request.statement, key, &request.parent_hash))
+			} else {
+				warn!(target: "validation", "Refusing to sign conflicting statement {:?}", request.statement);
+				None
+			}
+		});
+
+		write_message(&mut stream, &response.encode())?;
+	}
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+	writer.write_all(&(message.len() as u32).encode())?;
+	writer.write_all(message)?;
+	writer.flush()
+}
+
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+	let mut len = [0u8; 4];
+	reader.read_exact(&mut len)?;
+
+	let len = u32::decode(&mut &len[..]).expect("4 bytes always decode to a u32; qed") as usize;
+	if len > MAX_MESSAGE_SIZE {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Signing message too large"));
+	}
+
+	let mut message = vec![0; len];
+	reader.read_exact(&mut message)?;
+	Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use substrate_keyring::Keyring;
+	use polkadot_primitives::parachain::CandidateReceipt;
+	use tests::candidate_hash;
+
+	fn candidate(fees: u64) -> CandidateReceipt {
+		CandidateReceipt {
+			parachain_index: 5.into(),
+			collator: [1; 32].into(),
+			signature: Default::default(),
+			head_data: ::polkadot_primitives::parachain::HeadData(vec![1, 2, 3]),
+			balance_uploads: Vec::new(),
+			egress_queue_roots: Vec::new(),
+			fees,
+			block_data_hash: [2; 32].into(),
+			ingress_watermark: 0,
+		}
+	}
+
+	#[test]
+	fn policy_rejects_conflicting_statements() {
+		let mut policy = SigningPolicy::new(2);
+		let parent_a = [1; 32].into();
+		let parent_b = [2; 32].into();
+
+		let first = candidate(1);
+		let second = candidate(2);
+
+		assert!(policy.check_and_note(&GenericStatement::Candidate(first.clone()), &parent_a, 1));
+		assert!(policy.check_and_note(&GenericStatement::Candidate(first.clone()), &parent_a, 1));
+		assert!(!policy.check_and_note(&GenericStatement::Candidate(second.clone()), &parent_a, 1));
+		assert!(!policy.check_and_note(&GenericStatement::Invalid(first.hash()), &parent_a, 1));
+
+		assert!(policy.check_and_note(&GenericStatement::Invalid(second.hash()), &parent_a, 1));
+		assert!(!policy.check_and_note(&GenericStatement::Valid(second.hash()), &parent_a, 1));
+
+		// statements at other relay parents don't conflict, even at the same number.
+		assert!(policy.check_and_note(&GenericStatement::Candidate(second.clone()), &parent_b, 1));
+	}
+
+	#[test]
+	fn policy_refuses_forgotten_block_numbers() {
+		let mut policy = SigningPolicy::new(2);
+		let parent_a = [1; 32].into();
+		let valid = GenericStatement::Valid(candidate_hash(9));
+
+		assert!(policy.check_and_note(&GenericStatement::Invalid(candidate(1).hash()), &parent_a, 5));
+		assert!(policy.check_and_note(&valid, &[2; 32].into(), 6));
+
+		// statements below the remembered numbers would be forgotten straight away.
+		assert!(!policy.check_and_note(&valid, &[3; 32].into(), 4));
+
+		// the lowest number is forgotten once the limit is reached, and nothing is
+		// signed at or below it afterwards.
+		assert!(policy.check_and_note(&valid, &[4; 32].into(), 7));
+		assert!(!policy.check_and_note(&GenericStatement::Valid(candidate(1).hash()), &parent_a, 5));
+		assert!(!policy.check_and_note(&valid, &[5; 32].into(), 3));
+		assert!(policy.check_and_note(&valid, &[6; 32].into(), 6));
+	}
+
+	#[test]
+	fn policy_bounds_forks_per_number() {
+		let mut policy = SigningPolicy::new(2);
+		let valid = GenericStatement::Valid(candidate_hash(9));
+
+		for i in 0..MAX_PARENTS_PER_NUMBER {
+			assert!(policy.check_and_note(&valid, &[i as u8; 32].into(), 1));
+		}
+
+		assert!(!policy.check_and_note(&valid, &[0xff; 32].into(), 1));
+		assert!(policy.check_and_note(&valid, &[0; 32].into(), 1));
+	}
+
+	#[test]
+	fn policy_history_survives_restart() {
+		let path = ::std::env::temp_dir().join(format!("polkadot-signing-history-{}", ::std::process::id()));
+		let _ = fs::remove_file(&path);
+		let parent_hash = [1; 32].into();
+		let first = candidate(1);
+
+		{
+			let mut policy = SigningPolicy::open(path.clone(), 2).unwrap();
+			assert!(policy.check_and_note(&GenericStatement::Candidate(first.clone()), &parent_hash, 1));
+			assert!(policy.check_and_note(&GenericStatement::Valid(first.hash()), &[2; 32].into(), 2));
+			assert!(policy.check_and_note(&GenericStatement::Valid(first.hash()), &[3; 32].into(), 3));
+		}
+
+		let mut policy = SigningPolicy::open(path.clone(), 2).unwrap();
+		assert!(!policy.check_and_note(&GenericStatement::Invalid(first.hash()), &[2; 32].into(), 2));
+		assert!(!policy.check_and_note(&GenericStatement::Candidate(candidate(2)), &parent_hash, 1));
+		assert!(policy.check_and_note(&GenericStatement::Valid(first.hash()), &[2; 32].into(), 2));
+
+		fs::write(&path, &[1u8, 2, 3][..]).unwrap();
+		assert!(SigningPolicy::open(path.clone(), 2).is_err());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn refuses_to_serve_on_non_loopback_address() {
+		let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+		let res = serve_signing_requests(listener, &Keyring::Alice.pair(), &mut SigningPolicy::new(16));
+		assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	}
+
+	#[test]
+	fn remote_signer_signs_within_policy() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		thread::spawn(move || {
+			let key = Keyring::Alice.pair();
+			let mut policy = SigningPolicy::new(16);
+			let _ = serve_signing_requests(listener, &key, &mut policy);
+		});
+
+		let signer = RemoteSigner::new(addr, Keyring::Alice.to_raw_public().into()).unwrap();
+		let parent_hash = [1; 32].into();
+		let first = candidate(1);
+
+		let statement = GenericStatement::Candidate(first.clone());
+		let signature = signer.sign_statement(&statement, &parent_hash, 1).wait().unwrap();
+		assert!(::check_statement(&statement, &signature, signer.session_key(), &parent_hash));
+		assert_eq!(signature, Keyring::Alice.pair().sign_statement(&statement, &parent_hash, 1).wait().unwrap());
+
+		// the signing process refuses to contradict itself.
+		match signer.sign_statement(&GenericStatement::Invalid(first.hash()), &parent_hash, 1).wait() {
+			Err(Error(ErrorKind::SigningRefused, _)) => {}
+			other => panic!("expected refusal, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn remote_signer_rejects_signature_from_other_key() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		thread::spawn(move || {
+			let key = Keyring::Bob.pair();
+			let mut policy = SigningPolicy::new(16);
+			let _ = serve_signing_requests(listener, &key, &mut policy);
+		});
+
+		let signer = RemoteSigner::new(addr, Keyring::Alice.to_raw_public().into()).unwrap();
+		match signer.sign_statement(&GenericStatement::Valid(candidate_hash(3)), &[1; 32].into(), 1).wait() {
+			Err(Error(ErrorKind::BadRemoteSignature(_), _)) => {}
+			other => panic!("expected bad signature, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn remote_signer_fails_without_signing_process() {
+		let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+		let signer = RemoteSigner::new(addr, Keyring::Alice.to_raw_public().into()).unwrap();
+		match signer.sign_statement(&GenericStatement::Valid(candidate_hash(3)), &[1; 32].into(), 1).wait() {
+			Err(Error(ErrorKind::RemoteSigner(_), _)) => {}
+			other => panic!("expected failure to reach signer, got {:?}", other),
+		}
+	}
+}
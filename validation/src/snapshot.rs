@@ -0,0 +1,177 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export and import of the attestation state of live validation sessions.
+//!
+//! The state of a session can be dumped to debug a stuck session, or moved to
+//! another node to migrate a validator to new hardware mid-session. Snapshots
+//! imported before the session has started on the receiving node are held until it
+//! starts. Locally signed statements are circulated again once imported, since the
+//! exporting node may have stopped before its peers received them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use polkadot_primitives::Hash;
+
+use error::{Error, ErrorKind};
+use shared_table::{AttestationSnapshot, SharedTable};
+
+/// The maximum number of snapshots held for sessions which have not started yet.
+pub const MAX_PENDING_SNAPSHOTS: usize = 16;
+
+// circulates the locally signed statements of a session.
+type Circulate = Arc<Mutex<Box<FnMut() + Send>>>;
+
+struct LiveSession {
+	table: SharedTable,
+	circulate: Circulate,
+}
+
+#[derive(Default)]
+struct Sessions {
+	live: HashMap<Hash, LiveSession>,
+	pending: HashMap<Hash, AttestationSnapshot>,
+}
+
+/// A handle to the attestation state of live validation sessions. Clones refer
+/// to the same sessions.
+#[derive(Clone, Default)]
+pub struct AttestationSessions {
+	inner: Arc<Mutex<Sessions>>,
+}
+
+impl AttestationSessions {
+	/// Create a new handle, with no sessions.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Export the attestation state of the session at the given relay parent, if live.
+	pub fn export(&self, relay_parent: &Hash) -> Option<AttestationSnapshot> {
+		self.inner.lock().live.get(relay_parent).map(|session| session.table.export_snapshot())
+	}
+
+	/// Import attestation state exported by another node.
+	///
+	/// Returns `true` if the snapshot was imported into a live session, and `false`
+	/// if it is held until the session at its relay parent starts. Held snapshots
+	/// are dropped if their relay parent is pruned first. At most
+	/// `MAX_PENDING_SNAPSHOTS` snapshots are held, and a snapshot for another relay
+	/// parent is refused while that many are.
+	pub fn import(&self, snapshot: AttestationSnapshot) -> Result<bool, Error> {
+		let (table, circulate) = {
+			let mut inner = self.inner.lock();
+			let live = inner.live.get(&snapshot.relay_parent)
+				.map(|session| (session.table.clone(), session.circulate.clone()));
+
+			match live {
+				Some(live) => live,
+				None => {
+					let held = inner.pending.len();
+					if held >= MAX_PENDING_SNAPSHOTS && !inner.pending.contains_key(&snapshot.relay_parent) {
+						return Err(ErrorKind::TooManyPendingSnapshots(held).into());
+					}
+
+					inner.pending.insert(snapshot.relay_parent, snapshot);
+					return Ok(false);
+				}
+			}
+		};
+
+		let has_local = !snapshot.local_statements.is_empty();
+		table.import_snapshot(snapshot)?;
+		if has_local {
+			(&mut **circulate.lock())();
+		}
+
+		Ok(true)
+	}
+
+	/// Note a session starting at the given relay parent, importing any snapshot
+	/// held for it. `circulate` should gossip the locally signed statements of the
+	/// session, and is called after importing a snapshot with any.
+	pub(crate) fn register<F>(&self, relay_parent: Hash, table: &SharedTable, circulate: F)
+		where F: FnMut() + Send + 'static
+	{
+		let circulate: Circulate = Arc::new(Mutex::new(Box::new(circulate)));
+		let snapshot = {
+			let mut inner = self.inner.lock();
+			inner.live.insert(relay_parent, LiveSession {
+				table: table.clone(),
+				circulate: circulate.clone(),
+			});
+			inner.pending.remove(&relay_parent)
+		};
+
+		if let Some(snapshot) = snapshot {
+			let has_local = !snapshot.local_statements.is_empty();
+			match table.import_snapshot(snapshot) {
+				Ok(()) => {
+					info!("Imported attestation snapshot for relay parent {}", relay_parent);
+					if has_local {
+						(&mut **circulate.lock())();
+					}
+				}
+				Err(e) => warn!(target: "validation", "Failed to import attestation snapshot: {}", e),
+			}
+		}
+	}
+
+	/// Retain sessions, and held snapshots, at relay parents matching the predicate.
+	pub(crate) fn retain<F: FnMut(&Hash) -> bool>(&self, mut pred: F) {
+		let mut inner = self.inner.lock();
+		inner.live.retain(|k, _| pred(k));
+		inner.pending.retain(|k, _| pred(k));
+	}
+
+	/// Forget the live session at a relay parent. Held snapshots are kept.
+	pub(crate) fn remove(&self, relay_parent: &Hash) {
+		self.inner.lock().live.remove(relay_parent);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn snapshot(relay_parent: Hash) -> AttestationSnapshot {
+		AttestationSnapshot {
+			relay_parent,
+			statements: Vec::new(),
+			local_statements: Vec::new(),
+			available: Vec::new(),
+			included: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn held_snapshots_are_bounded() {
+		let sessions = AttestationSessions::new();
+		for i in 0..MAX_PENDING_SNAPSHOTS {
+			assert_eq!(sessions.import(snapshot([i as u8; 32].into())).unwrap(), false);
+		}
+
+		assert!(sessions.import(snapshot([0xff; 32].into())).is_err());
+
+		// replacing a held snapshot is fine.
+		assert_eq!(sessions.import(snapshot([0; 32].into())).unwrap(), false);
+
+		sessions.retain(|relay_parent| *relay_parent != Hash::from([0; 32]));
+		assert_eq!(sessions.import(snapshot([0xff; 32].into())).unwrap(), false);
+	}
+}